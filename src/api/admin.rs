@@ -1,8 +1,14 @@
-//! Admin API handlers (stats, users, settings)
-use crate::{auth, crypto, db};
+//! Admin API handlers (stats, logs, users, settings)
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{auth, config, crypto, db, logging, maintenance};
+use crate::crypto::{hex_encode, sha256};
 use crate::api::{Request, Response};
-use crate::api::json::parse_json;
-use crate::api::utils::{get_token, require_admin, valid_email, valid_password, valid_role};
+use crate::api::errors::ErrorCode;
+use crate::api::json::{parse_json, JsonSerializer as Json};
+use crate::api::utils::{get_token, require_admin, valid_email, valid_role};
 use crate::db::{Document, Value};
 
 // ── Stats ────────────────────────────────────────────────────────────────────
@@ -18,10 +24,161 @@ pub fn stats(req: &Request) -> Response {
     ))
 }
 
+/// Per-collection document counts and sizes, the state of db.bin and how
+/// many operations were logged as slow.
+pub fn stats_detailed(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let db = db::get();
+    let collections: Vec<String> = db
+        .collection_stats()
+        .iter()
+        .map(|s| format!(
+            r#"{{"name":{},"documents":{},"bytes":{},"fields":{}}}"#,
+            Json::wrap_string(&s.name), s.documents, s.bytes, s.fields
+        ))
+        .collect();
+    let or_null = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+    Response::ok(&format!(
+        r#"{{"collections":[{}],"file_bytes":{},"last_sync":{},"slow_operations":{}}}"#,
+        collections.join(","),
+        or_null(db.file_size().map(|n| n.to_string())),
+        or_null(db.last_sync().map(|ts| ts.to_string())),
+        db.slow_operations()
+    ))
+}
+
 pub fn backup(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
-    let path = db::get().backup();
-    Response::ok(&format!(r#"{{"backup":"{}"}}"#, path))
+    match db::get().backup() {
+        Some(path) => {
+            let name = Path::new(&path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let download = auth::sign_url("GET", &format!("/api/admin/backups/{}", name), db::now() + auth::SIGNED_URL_TTL);
+            Response::ok(&format!(
+                r#"{{"backup":{},"download":{}}}"#,
+                Json::wrap_string(&path),
+                Json::wrap_string(&download)
+            ))
+        }
+        None => Response::bad_request("In-memory database has no file to back up"),
+    }
+}
+
+/// `POST /api/admin/sign` with `{path, method?, expires_in?}`: a URL that
+/// reaches `path` without a session until it expires, for handing a
+/// download or upload to something that should not see the admin token.
+/// `method` defaults to GET and `expires_in` to 15 minutes, at most a day.
+pub fn sign(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let Some(path) = json.get("path").and_then(Value::as_str).filter(|p| p.starts_with('/')) else {
+        return Response::bad_request("path must be an absolute URL path");
+    };
+    let method = json.get("method").and_then(Value::as_str).unwrap_or("GET").to_ascii_uppercase();
+    let ttl = match json.get("expires_in") {
+        None => auth::SIGNED_URL_TTL,
+        Some(Value::Int(secs)) if (1..=auth::MAX_SIGNED_URL_TTL).contains(secs) => *secs,
+        Some(_) => return Response::bad_request(&format!("expires_in must be 1 to {} seconds", auth::MAX_SIGNED_URL_TTL)),
+    };
+    let expires = db::now() + ttl;
+    Response::ok(&format!(
+        r#"{{"url":{},"method":{},"expires":{}}}"#,
+        Json::wrap_string(&auth::sign_url(&method, path, expires)),
+        Json::wrap_string(&method),
+        expires
+    ))
+}
+
+/// Write db.bin now, e.g. before a filesystem snapshot: `{bytes, synced_at}`.
+pub fn flush(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    if config::db_in_memory() {
+        return Response::bad_request("In-memory database has no file to flush");
+    }
+    match db::get().flush() {
+        Some((bytes, synced_at)) => {
+            logging::info("db", &format!("flushed {} bytes", bytes));
+            Response::ok(&format!(r#"{{"bytes":{},"synced_at":{}}}"#, bytes, synced_at))
+        }
+        None => Response::error(500, ErrorCode::WriteFailed, "Failed to write the database file", None),
+    }
+}
+
+/// Turn maintenance mode on or off with `{"enabled":bool}`; an empty body toggles it.
+pub fn maintenance(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let on = match json.get("enabled") {
+        Some(Value::Bool(b)) => *b,
+        Some(_) => return Response::bad_request("enabled must be a boolean"),
+        None => !maintenance::enabled(),
+    };
+    maintenance::set(on);
+    logging::warn("maintenance", if on { "maintenance mode enabled" } else { "maintenance mode disabled" });
+    Response::ok(&format!(r#"{{"maintenance":{}}}"#, on))
+}
+
+// ── Logs ─────────────────────────────────────────────────────────────────────
+
+/// Most lines `GET /api/admin/logs` returns.
+const MAX_LOG_LINES: usize = 1000;
+
+/// How long `?follow` waits for new lines before answering with none.
+const FOLLOW_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// `GET /api/admin/logs?lines=100&level=error`: the last lines of the log
+/// file, oldest first, with the `offset` to follow from. `?follow&offset=N`
+/// long-polls until lines arrive past `offset` (or [`FOLLOW_TIMEOUT`]).
+pub fn logs(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let Some(path) = logging::log_path().map(PathBuf::from) else {
+        return Response::bad_request("Logging is disabled");
+    };
+    let level = req.query_param("level").filter(|l| !l.is_empty());
+    if level.as_deref().is_some_and(|l| !["info", "warn", "error"].iter().any(|k| l.eq_ignore_ascii_case(k))) {
+        return Response::bad_request("level must be info, warn or error");
+    }
+    let level = level.as_deref();
+    let end = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+    let result = if req.query_param("follow").is_some() {
+        let offset = match req.query_param("offset") {
+            Some(v) => match v.parse::<u64>() {
+                Ok(offset) => offset,
+                Err(_) => return Response::bad_request("offset must be a byte position"),
+            },
+            None => end,
+        };
+        follow(&path, offset, level)
+    } else {
+        let lines = match req.query_param("lines") {
+            Some(v) => match v.parse::<usize>() {
+                Ok(n) if n > 0 => n.min(MAX_LOG_LINES),
+                _ => return Response::bad_request("lines must be a positive number"),
+            },
+            None => 100,
+        };
+        logging::tail(&path, lines, level).map(|lines| (lines, end))
+    };
+    match result {
+        Ok((lines, offset)) => {
+            let lines: Vec<String> = lines.iter().map(|l| Json::wrap_string(l)).collect();
+            Response::ok(&format!(r#"{{"lines":[{}],"offset":{}}}"#, lines.join(","), offset))
+        }
+        Err(_) => Response::not_found(),
+    }
+}
+
+fn follow(path: &Path, offset: u64, level: Option<&str>) -> std::io::Result<(Vec<String>, u64)> {
+    let deadline = Instant::now() + FOLLOW_TIMEOUT;
+    let mut offset = offset;
+    loop {
+        let (lines, next) = logging::read_from(path, offset, level)?;
+        if !lines.is_empty() || Instant::now() >= deadline {
+            return Ok((lines, next));
+        }
+        offset = next;
+        thread::sleep(Duration::from_millis(250));
+    }
 }
 
 // ── Users ────────────────────────────────────────────────────────────────────
@@ -36,31 +193,28 @@ pub fn list_users(req: &Request) -> Response {
 pub fn create_user(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
     let json = parse_json(&req.body);
-    let email = json.get("email").and_then(|v| v.as_str()).unwrap_or("");
+    let email = &auth::normalize_email(json.get("email").and_then(|v| v.as_str()).unwrap_or(""));
     let password = json.get("password").and_then(|v| v.as_str()).unwrap_or("");
     let role = json.get("role").and_then(|v| v.as_str()).unwrap_or("user");
 
     if !valid_email(email) {
-        return Response::bad_request("Invalid email");
+        return Response::invalid(ErrorCode::InvalidEmail);
     }
-    if !valid_password(password) {
-        return Response::bad_request("Password must be at least 8 characters");
+    if let Err(msg) = auth::password_strength(password) {
+        return Response::invalid_because(ErrorCode::WeakPassword, &msg);
     }
     if !valid_role(role) {
-        return Response::bad_request("Invalid role");
-    }
-    if db::get().find_by("_users", "email", email).is_some() {
-        return Response::bad_request("Email already registered");
+        return Response::invalid(ErrorCode::InvalidRole);
     }
-
     let mut doc = Document::new();
     doc.insert("email".into(), Value::String(email.into()));
     doc.insert("password".into(), Value::String(crypto::hash_password(password)));
     doc.insert("role".into(), Value::String(role.into()));
 
-    match db::get().insert("_users", doc) {
-        Some(id) => Response::created(&format!(r#"{{"id":"{}"}}"#, id)),
-        None => Response::bad_request("Failed to create user"),
+    match db::get().try_insert("_users", doc) {
+        Ok(id) => Response::created(&format!(r#"{{"id":"{}"}}"#, id)),
+        Err(db::WriteError::Unique(field)) if field == "email" => Response::invalid(ErrorCode::EmailTaken),
+        Err(_) => Response::invalid(ErrorCode::WriteFailed),
     }
 }
 
@@ -68,24 +222,19 @@ pub fn update_user(req: &Request, id: &str) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
     let json = parse_json(&req.body);
 
-    let email = json.get("email").and_then(|v| v.as_str()).map(|s| s.to_string());
+    let email = json.get("email").and_then(|v| v.as_str()).map(auth::normalize_email);
     let role = json.get("role").and_then(|v| v.as_str()).map(|s| s.to_string());
     let password = json.get("password").and_then(|v| v.as_str()).map(|s| s.to_string());
 
     if let Some(email) = &email {
         if !valid_email(email) {
-            return Response::bad_request("Invalid email");
-        }
-        if let Some(existing) = db::get().find_by("_users", "email", email) {
-            if existing.get("id").and_then(|v| v.as_str()) != Some(id) {
-                return Response::bad_request("Email already registered");
-            }
+            return Response::invalid(ErrorCode::InvalidEmail);
         }
     }
 
     if let Some(role) = &role {
         if !valid_role(role) {
-            return Response::bad_request("Invalid role");
+            return Response::invalid(ErrorCode::InvalidRole);
         }
     }
 
@@ -97,8 +246,8 @@ pub fn update_user(req: &Request, id: &str) -> Response {
         updates.insert("role".into(), Value::String(role));
     }
     if let Some(password) = password {
-        if !valid_password(&password) {
-            return Response::bad_request("Password must be at least 8 characters");
+        if let Err(msg) = auth::password_strength(&password) {
+            return Response::invalid_because(ErrorCode::WeakPassword, &msg);
         }
         updates.insert("password".into(), Value::String(crypto::hash_password(&password)));
     }
@@ -107,10 +256,11 @@ pub fn update_user(req: &Request, id: &str) -> Response {
         return Response::bad_request("No updates provided");
     }
 
-    if db::get().update("_users", id, updates) {
-        Response::ok(&format!(r#"{{"id":"{}","updated":true}}"#, id))
-    } else {
-        Response::not_found()
+    match db::get().try_update("_users", id, updates) {
+        Ok(true) => Response::ok(&format!(r#"{{"id":"{}","updated":true}}"#, id)),
+        Ok(false) => Response::not_found(),
+        Err(db::WriteError::Unique(field)) if field == "email" => Response::invalid(ErrorCode::EmailTaken),
+        Err(e) => Response::write_error(&e),
     }
 }
 
@@ -133,16 +283,21 @@ pub fn get_settings(req: &Request) -> Response {
     let settings = db::get().find_all("_settings");
     if let Some(doc) = settings.first() {
         if let Some(id) = doc.get("id").and_then(|v| v.as_str()) {
+            let etag = settings_etag(doc);
             return Response::ok(&format!(
-                r#"{{"id":"{}","settings":{}}}"#,
+                r#"{{"id":"{}","etag":{},"settings":{}}}"#,
                 id,
+                Json::wrap_string(&etag),
                 db::doc_to_json_for_collection("_settings", doc)
-            ));
+            )).with_header("ETag", &etag);
         }
     }
     Response::ok(r#"{"id":"","settings":{}}"#)
 }
 
+/// Update the settings singleton. With `If-Match`, the write only goes through
+/// when the stored settings still carry that ETag, compared under the same
+/// lock as the write; otherwise 409. `If-Match` needs the document's `id`.
 pub fn update_settings(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
     let json = parse_json(&req.body);
@@ -153,9 +308,31 @@ pub fn update_settings(req: &Request) -> Response {
         return Response::bad_request("No settings provided");
     }
 
+    let expected = req.headers.get("if-match");
+    if expected.is_some() && id.is_empty() {
+        return Response::bad_request("If-Match needs the id of the settings to update");
+    }
+
     if !id.is_empty() {
-        if db::get().update("_settings", id, updates.clone()) {
-            return Response::ok(&format!(r#"{{"id":"{}","updated":true}}"#, id));
+        let matches = |doc: &Document| match expected {
+            Some(expected) => {
+                let etag = settings_etag(doc);
+                expected.trim() == "*" || expected.split(',').any(|e| e.trim() == etag)
+            }
+            None => true,
+        };
+        match db::get().update_if("_settings", id, updates.clone(), matches) {
+            Ok(db::ConditionalUpdate::Updated) => {
+                let etag = db::get().find_one("_settings", id).map(|d| settings_etag(&d)).unwrap_or_default();
+                return Response::ok(&format!(r#"{{"id":"{}","updated":true,"etag":{}}}"#, id, Json::wrap_string(&etag)))
+                    .with_header("ETag", &etag);
+            }
+            // A tag for settings that are gone cannot match anything
+            Ok(_) if expected.is_some() => {
+                return Response::conflict("Settings were changed by someone else; reload and try again");
+            }
+            Ok(_) => {}
+            Err(e) => return Response::bad_request(&e.to_string()),
         }
     }
 
@@ -165,6 +342,22 @@ pub fn update_settings(req: &Request) -> Response {
     }
 }
 
+/// Quoted ETag from the `updated` timestamp plus a digest of the contents,
+/// so two saves within the same second still get distinct tags.
+fn settings_etag(doc: &Document) -> String {
+    let mut keys: Vec<&String> = doc.keys().collect();
+    keys.sort();
+    let canonical: String = keys
+        .iter()
+        .map(|k| format!("{}={};", k, db::value_to_json(&doc[*k])))
+        .collect();
+    let updated = match doc.get("updated") {
+        Some(Value::Int(ts)) => *ts,
+        _ => 0,
+    };
+    format!("\"{}-{}\"", updated, &hex_encode(&sha256(canonical.as_bytes()))[..12])
+}
+
 fn filter_settings(doc: &Document) -> Document {
     let mut out = Document::new();
     for key in [
@@ -176,6 +369,7 @@ fn filter_settings(doc: &Document) -> Document {
         "og_image",
         "twitter_card",
         "canonical_url",
+        "favicon",
         "nginx_hostname",
         "nginx_internal_ip",
         "dev_network_name",
@@ -196,3 +390,21 @@ fn filter_settings(doc: &Document) -> Document {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_etag_tracks_content_and_timestamp() {
+        let mut doc = Document::new();
+        doc.insert("page_title".into(), Value::String("A".into()));
+        doc.insert("updated".into(), Value::Int(100));
+        let original = settings_etag(&doc);
+        assert!(original.starts_with("\"100-") && original.ends_with('"'));
+        assert_eq!(settings_etag(&doc.clone()), original);
+
+        doc.insert("page_title".into(), Value::String("B".into()));
+        assert_ne!(settings_etag(&doc), original);
+    }
+}
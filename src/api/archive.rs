@@ -0,0 +1,282 @@
+//! Project export and import as a portable archive
+//!
+//! An archive is a single JSON object, since request and response bodies are
+//! text: the project's files (base64) plus its `dev-{name}_*` collections
+//! with schema and documents. User documents travel with their password
+//! hashes; sessions travel as schema only, since their documents are live
+//! tokens. Ports are not carried over; an import allocates fresh ones on
+//! the receiving instance.
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::{config, urlpath};
+use crate::api::{Request, Response};
+use crate::api::collections::{parse_extra_fields, parse_schema};
+use crate::api::json::{parse_value, JsonSerializer as Json};
+use crate::api::projects::{self, ProjectInfo};
+use crate::api::utils::require_admin;
+use crate::crypto::{base64_decode, base64_encode};
+use crate::db::{self, Document, Schema, Value};
+
+/// `format` of an archive; changes if the layout changes incompatibly.
+const FORMAT: &str = "rpw-project/1";
+
+/// Project collections whose documents never leave or enter an instance.
+const SCHEMA_ONLY: [&str; 1] = ["sessions"];
+
+/// `GET /api/projects/{name}/export`: the project as an archive download.
+pub fn export(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    if !projects::project_names().iter().any(|p| p == name) {
+        return Response::not_found();
+    }
+    match export_project(&config::root_dir(), name) {
+        Ok(archive) => Response::ok(&archive)
+            .with_header("Content-Disposition", &format!("attachment; filename=\"{}.rpw.json\"", name)),
+        Err(e) => Response::bad_request(&e),
+    }
+}
+
+/// `POST /api/projects/import` with an archive as the body: recreate the
+/// project under its archived name with fresh ports.
+pub fn import(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    match import_project(&config::root_dir(), &req.body) {
+        Ok(info) => Response::created(&format!(
+            r#"{{"name":{},"dev_port":{},"prod_port":{}}}"#,
+            Json::wrap_string(&info.name),
+            info.dev_port,
+            info.prod_port
+        )),
+        Err(e) => Response::bad_request(&e),
+    }
+}
+
+fn export_project(root: &Path, name: &str) -> Result<String, String> {
+    let dir = root.join("projects").join(name);
+    let mut paths = Vec::new();
+    collect_files(&dir, "", &mut paths).map_err(|e| format!("Failed to read project files: {}", e))?;
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let data = fs::read(dir.join(&path)).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        files.push(format!(r#"{{"path":{},"data":"{}"}}"#, Json::wrap_string(&path), base64_encode(&data)));
+    }
+
+    let collections: Vec<String> = db::PROJECT_COLLECTIONS
+        .iter()
+        .filter_map(|suffix| {
+            let collection = projects::project_collection(name, suffix);
+            let schema = db::get().schema(&collection)?;
+            let docs: Vec<String> = if SCHEMA_ONLY.contains(suffix) {
+                Vec::new()
+            } else {
+                db::get().find_all(&collection).into_iter().map(|doc| db::value_to_json(&Value::Object(doc))).collect()
+            };
+            Some(format!(
+                r#"{{"suffix":"{}","fields":{},"max_docs":{},"extra_fields":"{}","documents":[{}]}}"#,
+                suffix,
+                schema_json(&schema),
+                schema.max_docs,
+                schema.extra_fields.as_str(),
+                docs.join(",")
+            ))
+        })
+        .collect();
+
+    Ok(format!(
+        r#"{{"format":"{}","name":{},"exported":{},"files":[{}],"collections":[{}]}}"#,
+        FORMAT,
+        Json::wrap_string(name),
+        db::now(),
+        files.join(","),
+        collections.join(",")
+    ))
+}
+
+/// Relative paths of the regular files below `dir`, sorted. Symlinks are
+/// skipped so an export never reaches outside the project.
+fn collect_files(dir: &Path, prefix: &str, out: &mut Vec<String>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+        let path = format!("{}{}", prefix, name);
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", path), out)?;
+        } else if ty.is_file() {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `fields` in the shape `POST /api/collections` accepts.
+fn schema_json(schema: &Schema) -> String {
+    let fields: Vec<String> = schema
+        .fields
+        .iter()
+        .map(|(name, ty, default)| {
+            let mut field = format!(r#"{{"name":{},"type":"{}""#, Json::wrap_string(name), ty);
+            if let Some(default) = default {
+                field.push_str(&format!(r#","default":{}"#, db::value_to_json(default)));
+            }
+            if schema.unique.contains(name) {
+                field.push_str(r#","unique":true"#);
+            }
+            field + "}"
+        })
+        .collect();
+    format!("[{}]", fields.join(","))
+}
+
+/// A parsed and validated archive, ready to be written.
+struct Archive {
+    name: String,
+    files: Vec<(String, Vec<u8>)>,
+    collections: Vec<(String, Schema, Vec<Document>)>,
+}
+
+fn import_project(root: &Path, body: &str) -> Result<ProjectInfo, String> {
+    // Validate everything before touching the disk or the database
+    let archive = parse_archive(body)?;
+    let project = projects::reserve(root, &archive.name)?;
+
+    if let Err(e) = write_files(&project.dir, &archive.files) {
+        projects::release(project);
+        return Err(format!("Failed to write project files: {}", e));
+    }
+    for (suffix, schema, docs) in archive.collections {
+        let collection = projects::project_collection(&archive.name, &suffix);
+        if let Err(e) = db::get().restore_collection(&collection, schema, docs) {
+            projects::release(project);
+            return Err(format!("Failed to import {}: {}", collection, e));
+        }
+    }
+    projects::ensure_project_collections(&archive.name);
+    Ok(projects::activate(project))
+}
+
+fn parse_archive(body: &str) -> Result<Archive, String> {
+    let Some(Value::Object(json)) = parse_value(body) else {
+        return Err("Archive must be a JSON object".into());
+    };
+    if json.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        return Err(format!("Unsupported archive format (expected {})", FORMAT));
+    }
+    let name = json.get("name").and_then(Value::as_str).ok_or("Archive has no project name")?.to_string();
+
+    let mut files = Vec::new();
+    for file in json.get("files").and_then(Value::as_array).ok_or("Archive has no files")? {
+        let file = file.as_object().ok_or("Invalid file entry")?;
+        let path = file.get("path").and_then(Value::as_str).unwrap_or("");
+        if !valid_file_path(path) {
+            return Err(format!("Invalid file path: {}", path));
+        }
+        let data = file.get("data").and_then(Value::as_str).and_then(base64_decode);
+        files.push((path.to_string(), data.ok_or_else(|| format!("Invalid data for {}", path))?));
+    }
+
+    let mut collections = Vec::new();
+    for collection in json.get("collections").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default() {
+        let collection = collection.as_object().ok_or("Invalid collection entry")?;
+        let suffix = collection.get("suffix").and_then(Value::as_str).unwrap_or("");
+        if !db::PROJECT_COLLECTIONS.contains(&suffix) {
+            return Err(format!("Unknown project collection: {}", suffix));
+        }
+        let mut schema = parse_schema(collection.get("fields"))?;
+        schema.max_docs = match collection.get("max_docs") {
+            Some(Value::Int(n)) => usize::try_from(*n).map_err(|_| format!("Invalid max_docs in {}", suffix))?,
+            _ => 0,
+        };
+        schema.extra_fields = parse_extra_fields(collection.get("extra_fields")).map_err(|e| format!("{} in {}", e, suffix))?;
+        // Sessions in an archive from elsewhere would be valid logins here
+        let documents = if SCHEMA_ONLY.contains(&suffix) { None } else { collection.get("documents") };
+        let docs = documents
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|doc| doc.as_object().cloned().ok_or_else(|| format!("Invalid document in {}", suffix)))
+            .collect::<Result<_, _>>()?;
+        collections.push((suffix.to_string(), schema, docs));
+    }
+    Ok(Archive { name, files, collections })
+}
+
+/// Archived paths are plain relative paths that normalize to themselves:
+/// no `..`, no leading or duplicate slashes, no escapes.
+fn valid_file_path(path: &str) -> bool {
+    let absolute = format!("/{}", path);
+    !path.is_empty() && !path.ends_with('/') && urlpath::normalize(&absolute).as_deref() == Some(absolute.as_str())
+}
+
+fn write_files(dir: &Path, files: &[(String, Vec<u8>)]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (path, data) in files {
+        let target = dir.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_file_path() {
+        for path in ["index.html", "css/site.css", "img/logo 2.png"] {
+            assert!(valid_file_path(path), "{}", path);
+        }
+        for path in ["", "/etc/passwd", "../x", "a/../../x", "a//b", "dir/", "a%2fb", "a\\b", "./x"] {
+            assert!(!valid_file_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_export_and_import_round_trip() {
+        db::init_in_memory("archive-test");
+        let root = std::env::temp_dir().join(format!("rpw-archive-{}", std::process::id()));
+        fs::create_dir_all(root.join("projects/_template/img")).unwrap();
+        fs::write(root.join("projects/_template/index.html"), "<h1>Hello World</h1>").unwrap();
+        let logo: Vec<u8> = (0..=255).collect();
+        fs::write(root.join("projects/_template/img/logo.png"), &logo).unwrap();
+
+        projects::create_project_in(&root, "arc-shop").unwrap();
+        let mut user = Document::new();
+        user.insert("email".into(), Value::String("buyer@example.com".into()));
+        let user_id = db::get().insert("dev-arc-shop_users", user).unwrap();
+        let mut session = Document::new();
+        session.insert("token".into(), Value::String("live-session-token".into()));
+        db::get().insert("dev-arc-shop_sessions", session).unwrap();
+        let archive = export_project(&root, "arc-shop").unwrap();
+        assert!(!archive.contains("live-session-token"));
+        assert_eq!(projects::delete_project_in(&root, "arc-shop"), Ok(true));
+
+        let info = import_project(&root, &archive).unwrap();
+        assert_eq!(info.name, "arc-shop");
+        let dir = root.join("projects/arc-shop");
+        assert_eq!(fs::read_to_string(dir.join("index.html")).unwrap(), "<h1>arc-shop</h1>");
+        assert_eq!(fs::read(dir.join("img/logo.png")).unwrap(), logo);
+        let user = db::get().find_one("dev-arc-shop_users", &user_id).unwrap();
+        assert_eq!(user.get("email").and_then(Value::as_str), Some("buyer@example.com"));
+        let schema = db::get().schema("dev-arc-shop_users").unwrap();
+        assert!(schema.fields.iter().any(|(name, ty, _)| name == "email" && ty == "string"));
+        assert!(db::get().schema("dev-arc-shop_sessions").is_some());
+        assert!(db::get().find_all("dev-arc-shop_sessions").is_empty());
+        assert!(projects::project_names().contains(&"arc-shop".to_string()));
+        assert_eq!(import_project(&root, &archive).err().as_deref(), Some("Project already exists"));
+
+        // A rejected archive leaves nothing behind
+        assert_eq!(projects::delete_project_in(&root, "arc-shop"), Ok(true));
+        let evil = archive.replacen(r#""path":"img/logo.png""#, r#""path":"../../evil.png""#, 1);
+        assert_eq!(import_project(&root, &evil).err().as_deref(), Some("Invalid file path: ../../evil.png"));
+        assert!(!projects::project_names().contains(&"arc-shop".to_string()));
+        assert!(!dir.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,5 +1,9 @@
-use crate::{auth, db};
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::{auth, config, db};
 use crate::api::{Request, Response};
+use crate::api::errors::ErrorCode;
+use crate::ratelimit::{self, Decision, SlidingWindow};
 use crate::api::json::parse_json;
 use crate::api::utils::{get_token};
 
@@ -16,16 +20,44 @@ pub fn register(req: &Request) -> Response {
             result.user_id.unwrap_or_default()
         ))
     } else {
-        Response::bad_request(&result.error.unwrap_or_default())
+        let code = result.error.unwrap_or(ErrorCode::WriteFailed);
+        Response::invalid_because(code, result.message.as_deref().unwrap_or(code.message()))
     }
 }
 
+/// Ten login attempts per client and email every five minutes; a successful
+/// login clears the counter.
+fn login_limiter() -> &'static SlidingWindow<String> {
+    static LIMITER: OnceLock<SlidingWindow<String>> = OnceLock::new();
+    ratelimit::shared(&LIMITER, 10, Duration::from_secs(300))
+}
+
 pub fn login(req: &Request) -> Response {
     let json = parse_json(&req.body);
     let email = json.get("email").and_then(|v| v.as_str()).unwrap_or("");
     let password = json.get("password").and_then(|v| v.as_str()).unwrap_or("");
 
+    let key = format!("{}|{}", req.client_ip, email.to_lowercase());
+    if let Decision::RetryAfter(wait) = login_limiter().check(key.clone()) {
+        return Response::too_many_requests(wait);
+    }
+
     let result = auth::login(email, password);
+    if result.success {
+        login_limiter().clear(&key);
+        Response::ok(&format!(
+            r#"{{"token":"{}","user_id":"{}"}}"#,
+            result.token.unwrap_or_default(),
+            result.user_id.unwrap_or_default()
+        ))
+    } else {
+        Response::invalid(result.error.unwrap_or(ErrorCode::InvalidCredentials))
+    }
+}
+
+/// Swap the caller's token for a new one; the old token is revoked immediately.
+pub fn refresh(req: &Request) -> Response {
+    let result = auth::refresh(&get_token(req));
     if result.success {
         Response::ok(&format!(
             r#"{{"token":"{}","user_id":"{}"}}"#,
@@ -33,7 +65,7 @@ pub fn login(req: &Request) -> Response {
             result.user_id.unwrap_or_default()
         ))
     } else {
-        Response::bad_request(&result.error.unwrap_or_default())
+        Response::unauthorized()
     }
 }
 
@@ -43,6 +75,39 @@ pub fn logout(req: &Request) -> Response {
     Response::ok(r#"{"success":true}"#)
 }
 
+/// `POST /api/auth/change-password` with `{current_password, new_password}`.
+/// Wrong guesses at the current password count against the login limit.
+pub fn change_password(req: &Request) -> Response {
+    let json = parse_json(&req.body);
+    let current = json.get("current_password").and_then(|v| v.as_str()).unwrap_or("");
+    let new = json.get("new_password").and_then(|v| v.as_str()).unwrap_or("");
+
+    let token = get_token(req);
+    let key = format!("{}|change-password|{}", req.client_ip, token);
+    if let Decision::RetryAfter(wait) = login_limiter().check(key.clone()) {
+        return Response::too_many_requests(wait);
+    }
+
+    let result = auth::change_password(&token, current, new, config::password_change_ends_sessions());
+    if result.success {
+        login_limiter().clear(&key);
+        return Response::ok(r#"{"success":true}"#);
+    }
+    match result.error {
+        Some(ErrorCode::InvalidSession) => Response::unauthorized(),
+        Some(ErrorCode::InvalidCredentials) => {
+            Response::error(400, ErrorCode::InvalidCredentials, "Current password is incorrect", Some("current_password"))
+        }
+        Some(ErrorCode::WeakPassword) => Response::error(
+            400,
+            ErrorCode::WeakPassword,
+            result.message.as_deref().unwrap_or(ErrorCode::WeakPassword.message()),
+            Some("new_password"),
+        ),
+        code => Response::invalid(code.unwrap_or(ErrorCode::WriteFailed)),
+    }
+}
+
 pub fn me(req: &Request) -> Response {
     let token = get_token(req);
     match auth::get_user(&token) {
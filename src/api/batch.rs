@@ -0,0 +1,92 @@
+//! `POST /api/batch`: several writes applied together or not at all
+use crate::config;
+use crate::api::{Request, Response};
+use crate::api::collections::check_size;
+use crate::api::json::{parse_value, JsonSerializer as Json};
+use crate::api::utils::{current_user_id, is_private_collection, require_admin};
+use crate::db::{self, Op, OpResult, Value};
+
+/// Most operations one batch may carry.
+const MAX_BATCH_OPS: usize = 100;
+
+/// Body: `[{"op":"insert"|"update"|"delete","collection":..,"id":..,"doc":{..}}, ...]`.
+/// Every op is checked like its single-document route first, then all run
+/// in one [`db::Database::transaction`]. Answers `{"results":[{"op","id"}]}`,
+/// or `400` naming the first failing op when nothing was applied.
+pub fn run(req: &Request) -> Response {
+    let Some(user) = current_user_id(req) else { return Response::unauthorized(); };
+    let Some(Value::Array(items)) = parse_value(&req.body) else {
+        return Response::bad_request("Body must be an array of operations");
+    };
+    if items.is_empty() || items.len() > MAX_BATCH_OPS {
+        return Response::bad_request(&format!("A batch holds 1 to {} operations", MAX_BATCH_OPS));
+    }
+    let mut ops = Vec::with_capacity(items.len());
+    for (n, item) in items.iter().enumerate() {
+        match parse_op(req, item) {
+            Ok(op) => ops.push(op),
+            Err(ParseFailure::Forbidden) => return Response::unauthorized(),
+            Err(ParseFailure::TooLarge(res)) => return res,
+            Err(ParseFailure::Invalid(msg)) => return Response::bad_request(&format!("op {}: {}", n, msg)),
+        }
+    }
+    match db::get().transaction(ops, Some(&user)) {
+        Ok(results) => {
+            let json: Vec<String> = results
+                .iter()
+                .map(|result| {
+                    let (op, id) = match result {
+                        OpResult::Inserted(id) => ("insert", id),
+                        OpResult::Updated(id) => ("update", id),
+                        OpResult::Deleted(id) => ("delete", id),
+                    };
+                    format!(r#"{{"op":"{}","id":{}}}"#, op, Json::wrap_string(id))
+                })
+                .collect();
+            Response::ok(&format!(r#"{{"results":[{}]}}"#, json.join(",")))
+        }
+        Err(e) => Response::bad_request(&e),
+    }
+}
+
+enum ParseFailure {
+    Forbidden,
+    Invalid(String),
+    /// A document over MAX_DOCUMENT_BYTES, answered with 413 as the single routes do
+    TooLarge(Response),
+}
+
+fn parse_op(req: &Request, item: &Value) -> Result<Op, ParseFailure> {
+    let obj = item.as_object().ok_or(ParseFailure::Invalid("operation must be an object".into()))?;
+    let field = |key: &str| obj.get(key).and_then(Value::as_str).map(str::to_string);
+    let collection = field("collection").ok_or(ParseFailure::Invalid("collection is required".into()))?;
+    if is_private_collection(&collection) && !require_admin(req) {
+        return Err(ParseFailure::Forbidden);
+    }
+    let id = || field("id").filter(|id| db::valid_doc_id(id)).ok_or(ParseFailure::Invalid("a valid id is required".into()));
+    let doc = || {
+        let doc = obj.get("doc").and_then(Value::as_object).cloned().ok_or(ParseFailure::Invalid("doc must be an object".into()))?;
+        match db::get().schema(&collection) {
+            Some(schema) => schema.check(&doc).map(|()| doc).map_err(ParseFailure::Invalid),
+            None => Ok(doc),
+        }
+    };
+    let limit = config::max_document_bytes();
+    match field("op").as_deref() {
+        Some("insert") => {
+            let doc = doc()?;
+            check_size(&doc, limit).map_err(ParseFailure::TooLarge)?;
+            Ok(Op::Insert { doc, collection })
+        }
+        Some("update") => {
+            let (id, doc) = (id()?, doc()?);
+            // Measure what would be stored: the existing document with the updates applied
+            let mut merged = db::get().find_one(&collection, &id).unwrap_or_default();
+            merged.extend(doc.clone());
+            check_size(&merged, limit).map_err(ParseFailure::TooLarge)?;
+            Ok(Op::Update { id, doc, collection })
+        }
+        Some("delete") => Ok(Op::Delete { id: id()?, collection }),
+        _ => Err(ParseFailure::Invalid("op must be insert, update or delete".into())),
+    }
+}
@@ -1,26 +1,59 @@
+use crate::{config, logging};
 use crate::api::{Request, Response};
+use crate::api::errors::ErrorCode;
 use crate::api::json::{parse_json, JsonSerializer as Json};
-use crate::api::utils::{is_private_collection, require_admin, require_auth};
-use crate::db::{self, Value};
+use crate::api::utils::{current_user_id, is_private_collection, require_admin, require_auth};
+use crate::db::{self, CollectionKind, Database, Document, RenameError, Value};
 
+/// User collections, sorted. `?prefix=dev-` narrows the list; `?after=&limit=`
+/// pages it like documents, adding `next_cursor`.
 pub fn list_collections(req: &Request) -> Response {
     if !require_auth(req) { return Response::unauthorized(); }
-    let cols = db::get().list_collections();
-    Response::ok(&format!(r#"{{"collections":[{}]}}"#,
-        cols.iter().map(|c| Json::wrap_string(c)).collect::<Vec<_>>().join(",")
-    ))
+    let prefix = req.query_param("prefix").unwrap_or_default();
+    names_response(req, db::get().collections_with_prefix(&prefix, false))
 }
 
+/// System (`_`) collections, with the same `?prefix=` and paging.
 pub fn list_system_collections(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
-    let cols = db::get().list_all_collections();
-    let system: Vec<String> = cols
-        .into_iter()
-        .filter(|c| c.starts_with('_'))
-        .collect();
-    Response::ok(&format!(r#"{{"collections":[{}]}}"#,
-        system.iter().map(|c| Json::wrap_string(c)).collect::<Vec<_>>().join(",")
-    ))
+    let prefix = req.query_param("prefix").unwrap_or_default();
+    let system = db::get().collections_with_prefix(&prefix, true).into_iter().filter(|c| Database::collection_kind(c) == CollectionKind::System).collect();
+    names_response(req, system)
+}
+
+fn names_response(req: &Request, names: Vec<String>) -> Response {
+    let after = req.query_param("after").filter(|a| !a.is_empty());
+    let limit = req.query_param("limit");
+    let (names, cursor) = if after.is_some() || limit.is_some() {
+        let limit = match parse_limit(limit) {
+            Ok(limit) => limit,
+            Err(res) => return res,
+        };
+        let (page, next) = page_names(names, after.as_deref(), limit);
+        (page, Some(next))
+    } else {
+        (names, None)
+    };
+    let json = names.iter().map(|c| Json::wrap_string(c)).collect::<Vec<_>>().join(",");
+    match cursor {
+        Some(next) => Response::ok(&format!(
+            r#"{{"collections":[{}],"next_cursor":{}}}"#,
+            json,
+            next.as_deref().map_or("null".to_string(), Json::wrap_string)
+        )),
+        None => Response::ok(&format!(r#"{{"collections":[{}]}}"#, json)),
+    }
+}
+
+/// Up to `limit` of the sorted `names` after `after` (which need not exist,
+/// so a deleted cursor still works), and the cursor for the next page.
+fn page_names(names: Vec<String>, after: Option<&str>, limit: usize) -> (Vec<String>, Option<String>) {
+    let mut page: Vec<String> = names.into_iter().filter(|n| after.is_none_or(|a| n.as_str() > a)).take(limit + 1).collect();
+    let next = (page.len() > limit).then(|| {
+        page.truncate(limit);
+        page.last().cloned().unwrap_or_default()
+    });
+    (page, next)
 }
 
 pub fn create_collection(req: &Request) -> Response {
@@ -28,22 +61,54 @@ pub fn create_collection(req: &Request) -> Response {
     let json = parse_json(&req.body);
     let name = json.get("name").and_then(|v| v.as_str()).unwrap_or("");
 
-    if name.is_empty() || name.starts_with('_') {
+    if !db::valid_collection_name(name) || Database::collection_kind(name) == CollectionKind::System {
         return Response::bad_request("Invalid collection name");
     }
 
-    let fields: Vec<(String, String)> = json.get("fields")
+    let mut schema = match parse_schema(json.get("fields")) {
+        Ok(schema) => schema,
+        Err(msg) => return Response::bad_request(&msg),
+    };
+    schema.max_docs = match parse_max_docs(json.get("max_docs")) {
+        Ok(max) => max,
+        Err(msg) => return Response::bad_request(msg),
+    };
+    schema.extra_fields = match parse_extra_fields(json.get("extra_fields")) {
+        Ok(mode) => mode,
+        Err(msg) => return Response::bad_request(&msg),
+    };
+    db::get().create_collection_with_schema(name, schema);
+    Response::created(&format!(r#"{{"name":"{}"}}"#, name))
+}
+
+/// Schema from a `fields` array of `{name, type, default, unique}` objects,
+/// as accepted by `POST /api/collections`. Type defaults to `string`.
+pub fn parse_schema(fields: Option<&Value>) -> Result<db::Schema, String> {
+    let declared: Vec<(db::SchemaField, bool)> = fields
         .and_then(|v| match v { Value::Array(arr) => Some(arr), _ => None })
         .map(|arr| arr.iter().filter_map(|f| {
             let obj = f.as_object()?;
             let name = obj.get("name")?.as_str()?;
-            let typ = obj.get("type")?.as_str().unwrap_or("string");
-            Some((name.to_string(), typ.to_string()))
+            let typ = obj.get("type").and_then(|t| t.as_str()).unwrap_or("string");
+            let unique = matches!(obj.get("unique"), Some(Value::Bool(true)));
+            Some(((name.to_string(), typ.to_string(), obj.get("default").cloned()), unique))
         }).collect())
         .unwrap_or_default();
+    let unique: Vec<String> = declared.iter().filter(|(_, unique)| *unique).map(|((name, _, _), _)| name.clone()).collect();
+    let fields: Vec<db::SchemaField> = declared.into_iter().map(|(field, _)| field).collect();
 
-    db::get().create_collection(name, fields);
-    Response::created(&format!(r#"{{"name":"{}"}}"#, name))
+    if let Some((field, typ, _)) = fields.iter().find(|(_, typ, _)| !db::FIELD_TYPES.contains(&typ.as_str())) {
+        return Err(format!(
+            "Unknown type '{}' for field '{}' (expected one of: {})", typ, field, db::FIELD_TYPES.join(", ")
+        ));
+    }
+    if let Some((field, typ, _)) = fields.iter().find(|(_, typ, default)| {
+        default.as_ref().is_some_and(|value| !db::value_matches_type(value, typ))
+    }) {
+        return Err(format!("Default for field '{}' must be of type {}", field, typ));
+    }
+
+    Ok(db::Schema { fields, unique, max_docs: 0, extra_fields: db::ExtraFields::Off })
 }
 
 pub fn delete_collection(req: &Request, name: &str) -> Response {
@@ -55,24 +120,167 @@ pub fn delete_collection(req: &Request, name: &str) -> Response {
     }
 }
 
+/// `POST /api/collections/{name}/quota` with `{max_docs}`: change how many
+/// documents the collection may hold (0 for unlimited).
+pub fn set_quota(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let max_docs = match json.get("max_docs").map(|v| parse_max_docs(Some(v))) {
+        Some(Ok(max)) => max,
+        Some(Err(msg)) => return Response::bad_request(msg),
+        None => return Response::bad_request("max_docs is required"),
+    };
+    if !db::get().set_max_docs(name, max_docs) {
+        return Response::not_found();
+    }
+    Response::ok(&format!(r#"{{"name":"{}","max_docs":{}}}"#, name, max_docs))
+}
+
+/// `POST /api/collections/{name}/extra-fields` with `{mode}`: choose what
+/// writes carrying undeclared fields get (`off`, `lenient` or `strict`).
+pub fn set_extra_fields(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let mode = match json.get("mode").map(|v| parse_extra_fields(Some(v))) {
+        Some(Ok(mode)) => mode,
+        Some(Err(msg)) => return Response::bad_request(&msg),
+        None => return Response::bad_request("mode is required"),
+    };
+    if !db::get().set_extra_fields(name, mode) {
+        return Response::not_found();
+    }
+    Response::ok(&format!(r#"{{"name":"{}","extra_fields":"{}"}}"#, name, mode.as_str()))
+}
+
+/// An extra-field mode: absent or `null` is `off`.
+pub fn parse_extra_fields(value: Option<&Value>) -> Result<db::ExtraFields, String> {
+    match value {
+        None | Some(Value::Null) => Ok(db::ExtraFields::Off),
+        Some(v) => v.as_str().and_then(db::ExtraFields::parse).ok_or_else(|| {
+            let modes: Vec<&str> = db::ExtraFields::ALL.iter().map(|mode| mode.as_str()).collect();
+            format!("extra_fields must be one of: {}", modes.join(", "))
+        }),
+    }
+}
+
+/// A collection quota: absent or `null` is 0 (unlimited).
+fn parse_max_docs(value: Option<&Value>) -> Result<usize, &'static str> {
+    match value {
+        None | Some(Value::Null) => Ok(0),
+        Some(Value::Int(n)) if (0..=u32::MAX as i64).contains(n) => Ok(*n as usize),
+        Some(_) => Err("max_docs must be a non-negative integer"),
+    }
+}
+
+/// Rename a collection, keeping its documents. Collections derived from a
+/// project are tied to it and cannot be renamed in or out of that namespace.
+pub fn rename_collection(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let new_name = json.get("new_name").and_then(|v| v.as_str()).unwrap_or("");
+    if [name, new_name].iter().any(|n| Database::collection_kind(n) == CollectionKind::Project) {
+        return Response::bad_request("Project collections cannot be renamed");
+    }
+    match db::get().rename_collection(name, new_name) {
+        Ok(()) => Response::ok(&format!(r#"{{"name":"{}","renamed":true}}"#, new_name)),
+        Err(RenameError::NotFound) => Response::not_found(),
+        Err(RenameError::Exists) => Response::conflict("Collection already exists"),
+        Err(RenameError::Invalid(msg)) => Response::bad_request(msg),
+    }
+}
+
+/// Page size for `?after=`/`?limit=` when no limit is given, and its ceiling.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// `?limit=` for a page: [`DEFAULT_PAGE_SIZE`] when absent, at most [`MAX_PAGE_SIZE`].
+fn parse_limit(limit: Option<String>) -> Result<usize, Response> {
+    match limit.map(|l| l.parse::<usize>()) {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(Ok(n)) if n > 0 => Ok(n.min(MAX_PAGE_SIZE)),
+        Some(_) => Err(Response::bad_request("limit must be a positive integer")),
+    }
+}
+
+/// All documents as an array, or with `?after=<id>&limit=N` one page as
+/// `{"docs":[...],"next_cursor":id|null}`.
 pub fn list_documents(req: &Request, collection: &str) -> Response {
     if !require_auth(req) { return Response::unauthorized(); }
     if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
-    let docs = db::get().find_all(collection);
+    let after = req.query_param("after").filter(|a| !a.is_empty());
+    let limit = req.query_param("limit");
+    // `cursor` is Some(next_cursor) when a page was asked for
+    let (mut docs, cursor) = if after.is_some() || limit.is_some() {
+        let limit = match parse_limit(limit) {
+            Ok(limit) => limit,
+            Err(res) => return res,
+        };
+        let page = match db::get().find_after(collection, after.as_deref(), limit) {
+            Some(page) => page,
+            None if after.is_some() => return Response::bad_request("Unknown cursor"),
+            None => db::Page::default(),
+        };
+        (page.docs, Some(page.next_cursor))
+    } else {
+        (db::get().find_all(collection), None)
+    };
+    // ?mine=true scopes the list to the caller's documents; admins always see everything
+    if req.query_param("mine").as_deref() == Some("true") && !require_admin(req) {
+        let user = current_user_id(req).unwrap_or_default();
+        docs.retain(|d| d.get(db::OWNER_FIELD).and_then(Value::as_str) == Some(user.as_str()));
+    }
     let json: Vec<String> = docs.iter().map(|d| db::doc_to_json_for_collection(collection, d)).collect();
-    Response::ok(&format!("[{}]", json.join(",")))
+    match cursor {
+        Some(next) => Response::ok(&format!(
+            r#"{{"docs":[{}],"next_cursor":{}}}"#,
+            json.join(","),
+            next.as_deref().map_or("null".to_string(), Json::wrap_string)
+        )),
+        None => Response::ok(&format!("[{}]", json.join(","))),
+    }
 }
 
 pub fn create_document(req: &Request, collection: &str) -> Response {
-    if !require_auth(req) { return Response::unauthorized(); }
+    let Some(user) = current_user_id(req) else { return Response::unauthorized(); };
     if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
     let doc = parse_json(&req.body);
-    match db::get().insert(collection, doc) {
-        Some(id) => Response::created(&format!(r#"{{"id":"{}"}}"#, id)),
-        None => Response::bad_request("Failed to create document"),
+    let unknown = match check_schema(collection, &doc) {
+        Ok(unknown) => unknown,
+        Err(e) => return Response::bad_request(&e),
+    };
+    if let Err(res) = check_size(&doc, config::max_document_bytes()) {
+        return res;
+    }
+    // ?ttl=<seconds> makes the document expire; the sweeper deletes it later
+    let inserted = match req.query_param("ttl") {
+        Some(ttl) => match ttl.parse::<i64>() {
+            Ok(secs) if secs > 0 => db::get().insert_with_ttl(collection, doc, Some(&user), secs),
+            _ => return Response::bad_request("ttl must be a positive number of seconds"),
+        },
+        None => db::get().try_insert_owned(collection, doc, &user),
+    };
+    match inserted {
+        Ok(id) => Response::created(&with_unknown_fields(format!(r#"{{"id":"{}"}}"#, id), collection, &unknown)),
+        Err(e) => Response::write_error(&e),
     }
 }
 
+/// `GET /api/collections/{name}/distinct?field=category`: the field's
+/// distinct values, sorted, as `{"values":[...]}`. Read rules match listing.
+pub fn distinct(req: &Request, collection: &str) -> Response {
+    if !require_auth(req) { return Response::unauthorized(); }
+    if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
+    let Some(field) = req.query_param("field").filter(|f| !f.is_empty()) else {
+        return Response::bad_request("field is required");
+    };
+    // Password hashes never leave the server, not even deduplicated
+    if collection == "_users" && field == "password" {
+        return Response::bad_request("field cannot be listed");
+    }
+    let values: Vec<String> = db::get().distinct(collection, &field).iter().map(db::value_to_json).collect();
+    Response::ok(&format!(r#"{{"values":[{}]}}"#, values.join(",")))
+}
+
 pub fn get_document(req: &Request, collection: &str, id: &str) -> Response {
     if !require_auth(req) { return Response::unauthorized(); }
     if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
@@ -82,17 +290,90 @@ pub fn get_document(req: &Request, collection: &str, id: &str) -> Response {
     }
 }
 
+/// Update a document, or create it at the client's id when it does not exist yet.
 pub fn update_document(req: &Request, collection: &str, id: &str, body: &str) -> Response {
-    if !require_auth(req) { return Response::unauthorized(); }
+    let Some(user) = current_user_id(req) else { return Response::unauthorized(); };
     if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
+    if db::get().schema(collection).is_none() {
+        return Response::not_found();
+    }
+    if !db::valid_doc_id(id) {
+        return Response::bad_request("Invalid document id");
+    }
     let updates = parse_json(body);
-    if db::get().update(collection, id, updates) {
-        Response::ok(&format!(r#"{{"id":"{}","updated":true}}"#, id))
-    } else {
-        Response::not_found()
+    let unknown = match check_schema(collection, &updates) {
+        Ok(unknown) => unknown,
+        Err(e) => return Response::bad_request(&e),
+    };
+    // Measure what would be stored: the existing document with the updates applied
+    let mut merged = db::get().find_one(collection, id).unwrap_or_default();
+    merged.extend(updates.clone());
+    if let Err(res) = check_size(&merged, config::max_document_bytes()) {
+        return res;
+    }
+    match db::get().upsert(collection, id, updates, Some(&user)) {
+        Ok(true) => Response::created(&with_unknown_fields(format!(r#"{{"id":"{}","created":true}}"#, id), collection, &unknown)),
+        Ok(false) => Response::ok(&with_unknown_fields(format!(r#"{{"id":"{}","updated":true}}"#, id), collection, &unknown)),
+        Err(e) => Response::write_error(&e),
     }
 }
 
+/// `PATCH /api/collections/{name}/{id}`: apply the body as a JSON Merge Patch
+/// (RFC 7386), so `{"meta":{"views":4},"draft":null}` changes one nested
+/// field and removes another. The document must exist.
+pub fn patch_document(req: &Request, collection: &str, id: &str) -> Response {
+    if !require_auth(req) { return Response::unauthorized(); }
+    if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
+    let Some(existing) = db::get().find_one(collection, id) else { return Response::not_found() };
+    let patch = parse_json(&req.body);
+    let merged = db::patched(&existing, patch.clone());
+    // Only the fields this patch sets are worth a hint, not ones stored earlier
+    let unknown: Vec<String> = match check_schema(collection, &merged) {
+        Ok(unknown) => unknown.into_iter().filter(|field| patch.get(field).is_some_and(|v| !matches!(v, Value::Null))).collect(),
+        Err(e) => return Response::bad_request(&e),
+    };
+    if let Err(res) = check_size(&merged, config::max_document_bytes()) {
+        return res;
+    }
+    match db::get().patch(collection, id, patch) {
+        Ok(true) => Response::ok(&with_unknown_fields(format!(r#"{{"id":"{}","updated":true}}"#, id), collection, &unknown)),
+        Ok(false) => Response::not_found(),
+        Err(e) => Response::write_error(&e),
+    }
+}
+
+/// Check `doc` against the collection's schema. For a lenient schema, the
+/// undeclared fields it carries; otherwise none.
+fn check_schema(collection: &str, doc: &Document) -> Result<Vec<String>, String> {
+    let Some(schema) = db::get().schema(collection) else { return Ok(Vec::new()) };
+    schema.check(doc)?;
+    Ok(match schema.extra_fields {
+        db::ExtraFields::Lenient => schema.unknown_fields(doc),
+        _ => Vec::new(),
+    })
+}
+
+/// `body` (a JSON object) with `"_unknown_fields"` added when a lenient
+/// collection stored fields its schema does not declare; those get logged.
+fn with_unknown_fields(body: String, collection: &str, unknown: &[String]) -> String {
+    if unknown.is_empty() {
+        return body;
+    }
+    logging::warn("collections", &format!("{} stored undeclared field(s): {}", collection, unknown.join(", ")));
+    let names: Vec<String> = unknown.iter().map(|field| Json::wrap_string(field)).collect();
+    format!(r#"{},"_unknown_fields":[{}]}}"#, body.strip_suffix('}').unwrap_or(&body), names.join(","))
+}
+
+/// 413 when `doc` would take more than `limit` bytes in `db.bin`.
+pub fn check_size(doc: &Document, limit: usize) -> Result<(), Response> {
+    let size = db::serialized_size(doc);
+    if size > limit {
+        let message = format!("Document is {} bytes; the limit is {}", size, limit);
+        return Err(Response::error(413, ErrorCode::DocumentTooLarge, &message, None));
+    }
+    Ok(())
+}
+
 pub fn delete_document(req: &Request, collection: &str, id: &str) -> Response {
     if !require_auth(req) { return Response::unauthorized(); }
     if is_private_collection(collection) && !require_admin(req) { return Response::unauthorized(); }
@@ -102,3 +383,34 @@ pub fn delete_document(req: &Request, collection: &str, id: &str) -> Response {
         Response::not_found()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_size_limit() {
+        let mut doc = Document::new();
+        doc.insert("text".into(), Value::String("x".repeat(100)));
+        let size = db::serialized_size(&doc);
+        assert!(check_size(&doc, size).is_ok());
+        let res = check_size(&doc, size - 1).err().unwrap();
+        assert_eq!(res.status, 413);
+        assert!(res.body.contains(r#""code":"document_too_large""#));
+    }
+
+    #[test]
+    fn test_page_names_walks_sorted_names() {
+        let names: Vec<String> = (0..5).map(|n| format!("dev-p{}_users", n)).collect();
+        let (first, next) = page_names(names.clone(), None, 2);
+        assert_eq!(first, ["dev-p0_users", "dev-p1_users"]);
+        assert_eq!(next.as_deref(), Some("dev-p1_users"));
+        let (second, next) = page_names(names.clone(), next.as_deref(), 2);
+        assert_eq!(second, ["dev-p2_users", "dev-p3_users"]);
+        let (last, next) = page_names(names.clone(), next.as_deref(), 2);
+        assert_eq!(last, ["dev-p4_users"]);
+        assert!(next.is_none());
+        // A cursor that was deleted meanwhile still lands in place
+        assert_eq!(page_names(names, Some("dev-p2"), 10).0, ["dev-p2_users", "dev-p3_users", "dev-p4_users"]);
+    }
+}
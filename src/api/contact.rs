@@ -1,4 +1,8 @@
+use std::sync::OnceLock;
+use std::time::Duration;
 use crate::api::{Request, Response};
+use crate::api::errors::ErrorCode;
+use crate::ratelimit::{self, Decision, SlidingWindow};
 use crate::api::json::parse_json;
 use crate::api::utils::valid_email;
 use crate::db::{self, Value};
@@ -10,7 +14,16 @@ const MAX_MESSAGE_LEN: usize = 2000;
 const MIN_ELAPSED_SECS: i64 = 3;
 const MAX_ELAPSED_SECS: i64 = 60 * 60;
 
+/// Five contact submissions per client every ten minutes.
+fn contact_limiter() -> &'static SlidingWindow<String> {
+    static LIMITER: OnceLock<SlidingWindow<String>> = OnceLock::new();
+    ratelimit::shared(&LIMITER, 5, Duration::from_secs(600))
+}
+
 pub fn submit(req: &Request) -> Response {
+    if let Decision::RetryAfter(wait) = contact_limiter().check(req.client_ip.clone()) {
+        return Response::too_many_requests(wait);
+    }
     let payload = parse_json(&req.body);
     let company = payload.get("company").and_then(|v| v.as_str()).unwrap_or("").trim();
     let name = payload.get("name").and_then(|v| v.as_str()).unwrap_or("").trim();
@@ -21,7 +34,7 @@ pub fn submit(req: &Request) -> Response {
     if !company.is_empty() {
         return Response::bad_request("Bot detected");
     }
-    if elapsed < MIN_ELAPSED_SECS || elapsed > MAX_ELAPSED_SECS {
+    if !(MIN_ELAPSED_SECS..=MAX_ELAPSED_SECS).contains(&elapsed) {
         return Response::bad_request("Please wait a moment before submitting");
     }
 
@@ -32,7 +45,7 @@ pub fn submit(req: &Request) -> Response {
         return Response::bad_request("Name is too long");
     }
     if email.len() > MAX_EMAIL_LEN || !valid_email(email) {
-        return Response::bad_request("Invalid email");
+        return Response::invalid(ErrorCode::InvalidEmail);
     }
     if message.len() > MAX_MESSAGE_LEN {
         return Response::bad_request("Message is too long");
@@ -0,0 +1,150 @@
+//! Machine-readable error codes for API responses
+//!
+//! Every API error body is `{"error":"<message>","code":"<code>"}`, plus
+//! `"field"` when one input is at fault. `error` stays a plain string so
+//! clients that only show the message keep working. A client that sends
+//! `Accept: application/json; errors=nested` gets the same error as
+//! `{"error":{"code","message","field"}}` instead (see `Response::nested`).
+
+/// Stable identifiers clients can branch on; the message may change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    /// Any other rejected input; the message says what
+    BadRequest,
+    InvalidJson,
+    Unauthorized,
+    NotFound,
+    Conflict,
+    RateLimited,
+    InvalidEmail,
+    WeakPassword,
+    InvalidRole,
+    EmailTaken,
+    InvalidCredentials,
+    InvalidSession,
+    SessionExpired,
+    /// A unique field's value belongs to another document
+    DuplicateValue,
+    /// The write was valid but could not be stored
+    WriteFailed,
+    /// A document over the per-document size limit (413)
+    DocumentTooLarge,
+    /// The collection holds its `max_docs` already (429)
+    QuotaExceeded,
+    /// A server setting is invalid; nothing the client can fix (500)
+    Misconfigured,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 18] = [
+        ErrorCode::BadRequest, ErrorCode::InvalidJson, ErrorCode::Unauthorized, ErrorCode::NotFound,
+        ErrorCode::Conflict, ErrorCode::RateLimited, ErrorCode::InvalidEmail, ErrorCode::WeakPassword,
+        ErrorCode::InvalidRole, ErrorCode::EmailTaken, ErrorCode::InvalidCredentials, ErrorCode::InvalidSession,
+        ErrorCode::SessionExpired, ErrorCode::DuplicateValue, ErrorCode::WriteFailed, ErrorCode::DocumentTooLarge,
+        ErrorCode::QuotaExceeded, ErrorCode::Misconfigured,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::InvalidJson => "invalid_json",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InvalidEmail => "invalid_email",
+            ErrorCode::WeakPassword => "weak_password",
+            ErrorCode::InvalidRole => "invalid_role",
+            ErrorCode::EmailTaken => "email_taken",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::InvalidSession => "invalid_session",
+            ErrorCode::SessionExpired => "session_expired",
+            ErrorCode::DuplicateValue => "duplicate_value",
+            ErrorCode::WriteFailed => "write_failed",
+            ErrorCode::DocumentTooLarge => "document_too_large",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::Misconfigured => "misconfigured",
+        }
+    }
+
+    /// Default message, used when the handler has nothing more specific.
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad request",
+            ErrorCode::InvalidJson => "Invalid JSON",
+            ErrorCode::Unauthorized => "Unauthorized",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::Conflict => "Conflict",
+            ErrorCode::RateLimited => "Too many requests",
+            ErrorCode::InvalidEmail => "Invalid email",
+            ErrorCode::WeakPassword => "Password is too weak",
+            ErrorCode::InvalidRole => "Invalid role",
+            ErrorCode::EmailTaken => "Email already registered",
+            ErrorCode::InvalidCredentials => "Invalid credentials",
+            ErrorCode::InvalidSession => "Invalid session",
+            ErrorCode::SessionExpired => "Session expired",
+            ErrorCode::DuplicateValue => "Duplicate value",
+            ErrorCode::WriteFailed => "Failed to save",
+            ErrorCode::DocumentTooLarge => "Document too large",
+            ErrorCode::QuotaExceeded => "Collection is full",
+            ErrorCode::Misconfigured => "Server is misconfigured",
+        }
+    }
+
+    /// The request field a validation code is about.
+    pub fn field(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::InvalidEmail | ErrorCode::EmailTaken => Some("email"),
+            ErrorCode::WeakPassword => Some("password"),
+            ErrorCode::InvalidRole => Some("role"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Response;
+    use crate::db;
+
+    #[test]
+    fn test_codes_are_distinct_snake_case() {
+        let codes: Vec<&str> = ErrorCode::ALL.iter().map(|c| c.as_str()).collect();
+        for (i, code) in codes.iter().enumerate() {
+            assert!(code.chars().all(|c| c.is_ascii_lowercase() || c == '_'), "{}", code);
+            assert!(!codes[i + 1..].contains(code), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_error_bodies() {
+        let res = Response::invalid(ErrorCode::InvalidEmail);
+        assert_eq!(res.status, 400);
+        assert_eq!(res.body, r#"{"error":"Invalid email","code":"invalid_email","field":"email"}"#);
+
+        let res = Response::write_error(&db::WriteError::Unique("sku".into()));
+        assert!(res.body.contains(r#""code":"duplicate_value","field":"sku""#), "{}", res.body);
+        // Only the variant decides: a hook's look-alike message stays a plain 400
+        let res = Response::write_error(&db::WriteError::Other("Duplicate value for unique field 'sku'".into()));
+        assert!(res.body.contains(r#""code":"bad_request""#), "{}", res.body);
+        let res = Response::write_error(&db::WriteError::Quota(3));
+        assert_eq!(res.status, 429);
+        assert!(res.body.contains(r#""code":"quota_exceeded""#), "{}", res.body);
+
+        let res = Response::bad_request(r#"Unknown field "x""#);
+        assert_eq!(res.body, r#"{"error":"Unknown field \"x\"","code":"bad_request"}"#);
+        assert!(Response::unauthorized().body.contains(r#""code":"unauthorized""#));
+    }
+
+    #[test]
+    fn test_nested_envelope() {
+        let res = Response::invalid(ErrorCode::InvalidEmail).nested();
+        assert_eq!(res.status, 400);
+        assert_eq!(res.body, r#"{"error":{"code":"invalid_email","message":"Invalid email","field":"email"}}"#);
+        let res = Response::bad_request(r#"Unknown field "x""#).nested();
+        assert_eq!(res.body, r#"{"error":{"code":"bad_request","message":"Unknown field \"x\""}}"#);
+        // Success bodies are not errors and stay as they are
+        assert_eq!(Response::ok(r#"{"error":"none"}"#).nested().body, r#"{"error":"none"}"#);
+    }
+}
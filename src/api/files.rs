@@ -0,0 +1,311 @@
+//! Admin file browsing and editing under projects/ and public/
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::{config, logging, realtime, urlpath};
+use crate::api::{Request, Response};
+use crate::api::json::{parse_json, JsonSerializer as Json};
+use crate::api::utils::require_admin;
+
+/// Top-level directories the file API may reach, relative to the root.
+const ROOTS: &[&str] = &["projects", "public"];
+
+/// Largest file `GET /api/admin/files/content` returns.
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// Largest file `PUT /api/admin/files` writes.
+const MAX_WRITE_BYTES: usize = 1024 * 1024;
+
+const OUTSIDE_ROOTS: &str = "Path must be inside projects/ or public/";
+
+/// `GET /api/admin/files?dir=projects/foo`: entries of a directory,
+/// directories first, with sizes and modification times (Unix seconds).
+pub fn list(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let dir = req.query_param("dir").unwrap_or_default();
+    let path = match resolve(&config::root_dir(), &dir) {
+        Ok(path) => path,
+        Err(res) => return res,
+    };
+    if !path.is_dir() {
+        return Response::bad_request("Not a directory");
+    }
+    Response::ok(&format!(
+        r#"{{"dir":{},"entries":{}}}"#,
+        Json::wrap_string(dir.trim_matches('/')),
+        listing(&path)
+    ))
+}
+
+/// `GET /api/admin/files/content?path=projects/foo/index.html`: a small
+/// UTF-8 file's contents for preview.
+pub fn content(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let rel = req.query_param("path").unwrap_or_default();
+    let path = match resolve(&config::root_dir(), &rel) {
+        Ok(path) => path,
+        Err(res) => return res,
+    };
+    let Ok(meta) = fs::metadata(&path) else { return Response::not_found() };
+    if !meta.is_file() {
+        return Response::bad_request("Not a file");
+    }
+    if meta.len() > MAX_PREVIEW_BYTES {
+        return Response::bad_request("File too large to preview");
+    }
+    let Ok(text) = fs::read(&path).map(String::from_utf8) else { return Response::not_found() };
+    let Ok(text) = text else { return Response::bad_request("Not a text file") };
+    Response::ok(&format!(
+        r#"{{"path":{},"size":{},"content":{}}}"#,
+        Json::wrap_string(rel.trim_matches('/')),
+        meta.len(),
+        Json::wrap_string(&text)
+    ))
+}
+
+/// `PUT /api/admin/files` with `{path, content}`: create or overwrite a text
+/// file. The parent directory must already exist.
+pub fn write(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let json = parse_json(&req.body);
+    let rel = json.get("path").and_then(|v| v.as_str()).unwrap_or("");
+    let Some(content) = json.get("content").and_then(|v| v.as_str()) else {
+        return Response::bad_request("content must be a string");
+    };
+    if let Err(msg) = check_text(content) {
+        return Response::bad_request(msg);
+    }
+    let path = match resolve_new(&config::root_dir(), rel) {
+        Ok(path) => path,
+        Err(res) => return res,
+    };
+    if path.is_dir() {
+        return Response::bad_request("Path is a directory");
+    }
+    let created = !path.exists();
+    if let Err(e) = fs::write(&path, content) {
+        logging::error("files", &format!("write {} failed: {}", rel, e));
+        return Response::bad_request("Failed to write file");
+    }
+    changed(rel, "file.written");
+    let body = format!(r#"{{"path":{},"size":{}}}"#, Json::wrap_string(rel.trim_matches('/')), content.len());
+    if created { Response::created(&body) } else { Response::ok(&body) }
+}
+
+/// `POST /api/admin/files/dir` with `{path}`: create a directory (and any
+/// missing parents below the root).
+pub fn create_dir(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let rel = parse_json(&req.body).get("path").and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let rel = rel.trim_matches('/');
+    let (top, rest) = rel.split_once('/').unwrap_or((rel, ""));
+    if !ROOTS.contains(&top) || rest.is_empty() {
+        return Response::bad_request(OUTSIDE_ROOTS);
+    }
+    // Create one level at a time so every step is confined like a new file
+    let mut sofar = top.to_string();
+    for segment in rest.split('/') {
+        sofar = format!("{}/{}", sofar, segment);
+        let path = match resolve_new(&config::root_dir(), &sofar) {
+            Ok(path) => path,
+            Err(res) => return res,
+        };
+        if path.is_dir() {
+            continue;
+        }
+        if path.exists() || fs::create_dir(&path).is_err() {
+            return Response::bad_request("Failed to create directory");
+        }
+    }
+    changed(rel, "dir.created");
+    Response::created(&format!(r#"{{"path":{}}}"#, Json::wrap_string(rel)))
+}
+
+/// `DELETE /api/admin/files?path=...`: delete a file or an empty directory.
+/// The roots themselves cannot be deleted.
+pub fn delete(req: &Request) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let rel = req.query_param("path").unwrap_or_default();
+    if !rel.trim_matches('/').contains('/') {
+        return Response::bad_request(OUTSIDE_ROOTS);
+    }
+    let path = match resolve(&config::root_dir(), &rel) {
+        Ok(path) => path,
+        Err(res) => return res,
+    };
+    let (result, kind) = if path.is_dir() {
+        (fs::remove_dir(&path), "dir.deleted")
+    } else {
+        (fs::remove_file(&path), "file.deleted")
+    };
+    if result.is_err() {
+        return Response::bad_request("Failed to delete (directories must be empty)");
+    }
+    changed(&rel, kind);
+    Response::ok(r#"{"deleted":true}"#)
+}
+
+/// Written files must fit the limit and be text: valid UTF-8 (guaranteed by
+/// the JSON string) without NUL bytes.
+fn check_text(content: &str) -> Result<(), &'static str> {
+    if content.len() > MAX_WRITE_BYTES {
+        return Err("File exceeds 1 MB");
+    }
+    if content.contains('\0') {
+        return Err("Only text files can be written");
+    }
+    Ok(())
+}
+
+/// Tell realtime clients a file changed so open previews can reload.
+fn changed(rel: &str, kind: &str) {
+    logging::info("files", &format!("{} {}", kind, rel));
+    realtime::broadcast(&format!(
+        r#"{{"type":"{}","path":{}}}"#,
+        kind,
+        Json::wrap_string(rel.trim_matches('/'))
+    ));
+}
+
+/// Resolve `projects/...` or `public/...` to an existing path below `base`
+/// with the same guard as static serving. `rel` comes from an already
+/// decoded query value, so it is taken literally: `%41` names `%41`.
+/// Traversal or another top-level directory is a 400, a missing path (or
+/// a symlink out) a 404.
+fn resolve(base: &Path, rel: &str) -> Result<PathBuf, Response> {
+    let rel = rel.trim_matches('/');
+    let (top, rest) = rel.split_once('/').unwrap_or((rel, ""));
+    if !ROOTS.contains(&top) || rest.split('/').any(|segment| segment == ".." || segment.contains(['\\', '\0'])) {
+        return Err(Response::bad_request(OUTSIDE_ROOTS));
+    }
+    urlpath::resolve_normalized(&base.join(top), rest).ok_or_else(Response::not_found)
+}
+
+/// Like [`resolve`], for a path that may not exist yet: the parent must
+/// resolve inside a root and the last segment must be a plain name, taken
+/// literally like the rest. An existing entry must resolve on its own, so
+/// a symlink out is refused.
+fn resolve_new(base: &Path, rel: &str) -> Result<PathBuf, Response> {
+    let rel = rel.trim_matches('/');
+    let Some((parent, name)) = rel.rsplit_once('/') else {
+        return Err(Response::bad_request(OUTSIDE_ROOTS));
+    };
+    if matches!(name, "" | "." | "..") || name.contains(['/', '\\', '\0']) {
+        return Err(Response::bad_request(OUTSIDE_ROOTS));
+    }
+    let dir = resolve(base, parent)?;
+    if !dir.is_dir() {
+        return Err(Response::bad_request("Parent is not a directory"));
+    }
+    let target = dir.join(name);
+    if fs::symlink_metadata(&target).is_ok() {
+        return resolve(base, rel).map_err(|_| Response::bad_request(OUTSIDE_ROOTS));
+    }
+    Ok(target)
+}
+
+fn listing(dir: &Path) -> String {
+    let mut entries: Vec<(bool, String, u64, u64)> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .filter_map(|entry| {
+                    let meta = entry.metadata().ok()?;
+                    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                    Some((meta.is_dir(), entry.file_name().to_string_lossy().into_owned(), meta.len(), modified))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    let json: Vec<String> = entries
+        .iter()
+        .map(|(is_dir, name, size, modified)| format!(
+            r#"{{"name":{},"type":"{}","size":{},"modified":{}}}"#,
+            Json::wrap_string(name),
+            if *is_dir { "dir" } else { "file" },
+            if *is_dir { 0 } else { *size },
+            modified
+        ))
+        .collect();
+    format!("[{}]", json.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::json::parse_value;
+    use crate::db::Value;
+
+    fn field<'a>(entry: &'a Value, key: &str) -> Option<&'a Value> {
+        entry.as_object()?.get(key)
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("rpw-files-{}-{}", name, std::process::id()));
+        fs::create_dir_all(base.join("projects/foo/css")).unwrap();
+        fs::write(base.join("projects/foo/index.html"), "<h1>foo</h1>").unwrap();
+        fs::write(base.join(".env.local"), "SECRET_KEY=x").unwrap();
+        base
+    }
+
+    #[test]
+    fn test_lists_directory() {
+        let base = fixture("list");
+        let dir = resolve(&base, "projects/foo/").ok().unwrap();
+        let entries = parse_value(&listing(&dir)).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(field(&entries[0], "name").and_then(Value::as_str), Some("css"));
+        assert_eq!(field(&entries[0], "type").and_then(Value::as_str), Some("dir"));
+        assert_eq!(field(&entries[1], "name").and_then(Value::as_str), Some("index.html"));
+        assert!(matches!(field(&entries[1], "size"), Some(Value::Int(12))));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_traversal_and_other_roots() {
+        let base = fixture("reject");
+        for rel in ["projects/../.env.local", "projects/foo/../../.env.local", ".env.local", "data/db.bin", ""] {
+            assert_eq!(resolve(&base, rel).err().map(|r| r.status), Some(400), "{}", rel);
+        }
+        // Escapes are names here, not traversal: the query was decoded once already
+        for rel in ["projects/missing", "projects/foo/%2e%2e/%2e%2e/.env.local"] {
+            assert_eq!(resolve(&base, rel).err().map(|r| r.status), Some(404), "{}", rel);
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_names_with_percent_signs_are_taken_literally() {
+        let base = fixture("percent");
+        fs::write(base.join("projects/foo/100%.txt"), "full").unwrap();
+        fs::create_dir(base.join("projects/foo/%2541")).unwrap();
+        fs::create_dir(base.join("projects/foo/%41")).unwrap();
+        let file = resolve(&base, "projects/foo/100%.txt").ok().unwrap();
+        assert_eq!(fs::read_to_string(file).unwrap(), "full");
+        let dir = resolve(&base, "projects/foo/%2541").ok().unwrap();
+        assert_eq!(dir.file_name().unwrap(), "%2541");
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_new_file_stays_inside_root() {
+        let base = fixture("write");
+        let path = resolve_new(&base, "projects/foo/css/site.css").ok().unwrap();
+        fs::write(&path, "body{}").unwrap();
+        assert!(base.join("projects/foo/css/site.css").is_file());
+        assert!(resolve_new(&base, "projects/foo/index.html").is_ok());
+
+        std::os::unix::fs::symlink(base.join(".env.local"), base.join("projects/foo/leak")).unwrap();
+        for rel in ["projects/foo/leak", "projects/../.env.local", "projects/foo/..", ".env.local", "projects", "data/new.txt", "projects/foo/a\\b", "projects/foo/."] {
+            assert_eq!(resolve_new(&base, rel).err().map(|r| r.status), Some(400), "{}", rel);
+        }
+        assert_eq!(resolve_new(&base, "projects/nope/x.css").err().map(|r| r.status), Some(404));
+        let literal = resolve_new(&base, "projects/foo/%2541.txt").ok().unwrap();
+        assert_eq!(literal, base.join("projects/foo").canonicalize().unwrap().join("%2541.txt"));
+        assert!(resolve_new(&base, "projects/foo/100%.txt").is_ok());
+        assert_eq!(check_text("a\0b"), Err("Only text files can be written"));
+        assert!(check_text(&"x".repeat(MAX_WRITE_BYTES + 1)).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -1,18 +1,89 @@
 //! Zero-dependency JSON parser and builder
 use crate::db::{Value, Document};
+use crate::handler::MAX_BODY_BYTES;
 
 // ── Parser ───────────────────────────────────────────────────────────────────
 
+/// Bounds on untrusted JSON, so a deeply nested or huge payload is refused
+/// instead of exhausting the stack or memory.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    /// Deepest nesting of objects and arrays
+    pub max_depth: usize,
+    /// Largest input in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_depth: 64, max_bytes: MAX_BODY_BYTES }
+    }
+}
+
+/// Why JSON input was refused. Displays as a message fit for a client.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// Syntax error at a 1-based line and column, with what was expected there
+    Syntax { line: usize, column: usize, expected: &'static str },
+    TooLarge,
+    TooDeep,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Syntax { line, column, expected } => {
+                write!(f, "Invalid JSON at line {}, column {}: expected {}", line, column, expected)
+            }
+            ParseError::TooLarge => f.write_str("JSON body too large"),
+            ParseError::TooDeep => f.write_str("JSON nested too deeply"),
+        }
+    }
+}
+
+/// Parse a JSON object body into a Document.
+/// Well-formed input goes through the recursive parser (nested objects and
+/// arrays included); anything else falls back to the lenient flat parser.
 pub fn parse_json(input: &str) -> Document {
+    match parse_value(input) {
+        Some(Value::Object(obj)) => obj,
+        _ => parse_flat(input),
+    }
+}
+
+/// Parse any JSON value within the default [`Limits`]. Returns None on
+/// malformed input or input over the limits.
+pub fn parse_value(input: &str) -> Option<Value> {
+    parse_json_strict(input).ok()
+}
+
+/// Parse any JSON value within the default [`Limits`], saying where and why
+/// malformed input failed.
+pub fn parse_json_strict(input: &str) -> Result<Value, ParseError> {
+    parse_value_with(input, Limits::default())
+}
+
+/// Parse any JSON value, enforcing `limits` while parsing.
+pub fn parse_value_with(input: &str, limits: Limits) -> Result<Value, ParseError> {
+    if input.len() > limits.max_bytes {
+        return Err(ParseError::TooLarge);
+    }
+    let mut parser = Parser { bytes: input.as_bytes(), pos: 0, depth: limits.max_depth, error: None };
+    let value = parser.value().and_then(|value| {
+        parser.skip_ws();
+        if parser.pos == parser.bytes.len() { Some(value) } else { parser.fail("end of input") }
+    });
+    value.ok_or_else(|| parser.error.take().unwrap_or(ParseError::TooDeep))
+}
+
+fn parse_flat(input: &str) -> Document {
     let mut doc = Document::new();
     let input = input.trim();
     if !input.starts_with('{') { return doc; }
     
     // Very basic parser for flat objects (zero-dep)
     let inner = input.trim_start_matches('{').trim_end_matches('}');
-    let mut parts = inner.split(',');
-    
-    while let Some(part) = parts.next() {
+    for part in inner.split(',') {
         if let Some((k, v)) = part.split_once(':') {
             let key = k.trim().trim_matches('"').to_string();
             let val_str = v.trim();
@@ -37,6 +108,253 @@ pub fn parse_json(input: &str) -> Document {
     doc
 }
 
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Nesting levels still allowed
+    depth: usize,
+    /// First failure; inner failures win over the outer ones they cause
+    error: Option<ParseError>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Record a syntax error at the current position and fail.
+    fn fail<T>(&mut self, expected: &'static str) -> Option<T> {
+        if self.error.is_none() {
+            let before = &self.bytes[..self.pos.min(self.bytes.len())];
+            let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+            let column = String::from_utf8_lossy(&before[line_start..]).chars().count() + 1;
+            let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+            self.error = Some(ParseError::Syntax { line, column, expected });
+        }
+        None
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Option<()> {
+        self.skip_ws();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Some(())
+        } else {
+            self.fail(expected)
+        }
+    }
+
+    fn literal(&mut self, word: &'static str, value: Value) -> Option<Value> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Some(value)
+        } else {
+            self.fail(word)
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b'{' | b'[') if self.depth == 0 => {
+                self.error.get_or_insert(ParseError::TooDeep);
+                None
+            }
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => self.fail("a value"),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Option<Value>) -> Option<Value> {
+        self.depth -= 1;
+        let value = parse(self);
+        self.depth += 1;
+        value
+    }
+
+    fn object(&mut self) -> Option<Value> {
+        self.expect(b'{', "'{'")?;
+        let mut obj = Document::new();
+        self.skip_ws();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Some(Value::Object(obj));
+        }
+        loop {
+            self.skip_ws();
+            if self.peek() != Some(b'"') {
+                return self.fail("a string key");
+            }
+            let key = self.string()?;
+            self.expect(b':', "':' after key")?;
+            let value = self.value()?;
+            obj.insert(key, value);
+            self.skip_ws();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => { self.pos += 1; return Some(Value::Object(obj)); }
+                _ => return self.fail("',' or '}'"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Value> {
+        self.expect(b'[', "'['")?;
+        let mut arr = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Some(Value::Array(arr));
+        }
+        loop {
+            arr.push(self.value()?);
+            self.skip_ws();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => { self.pos += 1; return Some(Value::Array(arr)); }
+                _ => return self.fail("',' or ']'"),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.peek() != Some(b'"') {
+            return self.fail("a string");
+        }
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(b) = self.peek() else { return self.fail("closing quote") };
+            self.pos += 1;
+            match b {
+                b'"' => return String::from_utf8(out).ok().or_else(|| self.fail("valid UTF-8")),
+                b'\\' => {
+                    let esc = self.peek();
+                    self.pos += 1;
+                    match esc {
+                        Some(b'"') => out.push(b'"'),
+                        Some(b'\\') => out.push(b'\\'),
+                        Some(b'/') => out.push(b'/'),
+                        Some(b'b') => out.push(0x08),
+                        Some(b'f') => out.push(0x0C),
+                        Some(b'n') => out.push(b'\n'),
+                        Some(b'r') => out.push(b'\r'),
+                        Some(b't') => out.push(b'\t'),
+                        Some(b'u') => {
+                            let ch = self.unicode_escape()?;
+                            let mut buf = [0u8; 4];
+                            out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                        }
+                        _ => {
+                            self.pos -= 1;
+                            return self.fail("an escape such as \\n, \\\" or \\u0041");
+                        }
+                    }
+                }
+                0x00..=0x1F => {
+                    self.pos -= 1;
+                    return self.fail("an escaped control character");
+                }
+                _ => out.push(b),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let Some(code) = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        else {
+            return self.fail("four hex digits");
+        };
+        self.pos += 4;
+        Some(code)
+    }
+
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if (0xD800..0xDC00).contains(&high) {
+            // Surrogate pair: expect a following \uDC00-\uDFFF
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return self.fail("a low surrogate");
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return self.fail("a low surrogate");
+            }
+            return char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00));
+        }
+        char::from_u32(high).or_else(|| self.fail("a valid code point"))
+    }
+
+    /// JSON number grammar: `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`.
+    /// Integers that fit are `Int`; fractions, exponents and integers
+    /// beyond i64 are `Float`.
+    fn number(&mut self) -> Option<Value> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return self.fail("a digit"),
+        }
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits("a digit after '.'")?;
+            integer = false;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.required_digits("a digit in the exponent")?;
+            integer = false;
+        }
+        if matches!(self.peek(), Some(b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-')) {
+            return self.fail("the end of the number");
+        }
+        // The grammar above only admits ASCII
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+        match text.parse::<i64>() {
+            Ok(n) if integer => Some(Value::Int(n)),
+            _ => text.parse::<f64>().ok().map(Value::Float),
+        }
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self, expected: &'static str) -> Option<()> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return self.fail(expected);
+        }
+        self.digits();
+        Some(())
+    }
+}
+
 // ── Builder (New "Better Tool") ──────────────────────────────────────────────
 
 pub struct JsonSerializer;
@@ -51,6 +369,7 @@ impl JsonSerializer {
                 '\n' => escaped.push_str("\\n"),
                 '\r' => escaped.push_str("\\r"),
                 '\t' => escaped.push_str("\\t"),
+                c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
                 _ => escaped.push(c),
             }
         }
@@ -65,3 +384,119 @@ impl JsonSerializer {
         format!("\"{}\":\"{}\"", key, Self::escape(value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nested() {
+        let doc = parse_json(r#"{"name":"a, b","tags":["x","y"],"meta":{"n":1,"ok":true},"none":null}"#);
+        assert_eq!(doc.get("name").and_then(|v| v.as_str()), Some("a, b"));
+        match doc.get("tags") {
+            Some(Value::Array(items)) => assert_eq!(items.len(), 2),
+            other => panic!("expected array, got {:?}", other),
+        }
+        let meta = doc.get("meta").and_then(|v| v.as_object()).expect("meta object");
+        assert!(matches!(meta.get("n"), Some(Value::Int(1))));
+        assert!(matches!(doc.get("none"), Some(Value::Null)));
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        let value = parse_value(r#""line\n\"q\" \u00e9 \ud83e\udd80""#).unwrap();
+        assert_eq!(value.as_str(), Some("line\n\"q\" é 🦀"));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(parse_value(r#"{"a":1"#).is_none());
+        assert!(parse_value(r#"{"a":1} x"#).is_none());
+        assert!(parse_value(r#"["a",]"#).is_none());
+    }
+
+    fn syntax_error(input: &str) -> (usize, usize, &'static str) {
+        match parse_json_strict(input) {
+            Err(ParseError::Syntax { line, column, expected }) => (line, column, expected),
+            other => panic!("expected a syntax error for {:?}, got {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn test_strict_reports_trailing_commas() {
+        assert_eq!(syntax_error(r#"["a",]"#), (1, 6, "a value"));
+        assert_eq!(syntax_error("{\n  \"a\": 1,\n}"), (3, 1, "a string key"));
+    }
+
+    #[test]
+    fn test_strict_reports_unterminated_strings() {
+        assert_eq!(syntax_error(r#"{"name":"bob"#), (1, 13, "closing quote"));
+        assert_eq!(syntax_error("{\"a\":\"line\nbreak\"}"), (1, 11, "an escaped control character"));
+        assert_eq!(syntax_error(r#"{"a":"\q"}"#), (1, 8, "an escape such as \\n, \\\" or \\u0041"));
+    }
+
+    #[test]
+    fn test_strict_reports_bad_numbers_and_tokens() {
+        assert_eq!(syntax_error(r#"{"n":1.2.3}"#), (1, 9, "the end of the number"));
+        assert_eq!(syntax_error(r#"{"n":--1}"#), (1, 7, "a digit"));
+        assert_eq!(syntax_error(r#"{"n":01}"#), (1, 7, "the end of the number"));
+        assert_eq!(syntax_error(r#"{"n":1.}"#), (1, 8, "a digit after '.'"));
+        assert_eq!(syntax_error(r#"{"n":1e+}"#), (1, 9, "a digit in the exponent"));
+        assert_eq!(syntax_error(r#"{"n":+1}"#), (1, 6, "a value"));
+        assert_eq!(syntax_error(r#"{"ok":tru}"#), (1, 7, "true"));
+        assert_eq!(syntax_error(r#"{"a" 1}"#), (1, 6, "':' after key"));
+        assert_eq!(syntax_error(r#"{"a":1} x"#), (1, 9, "end of input"));
+        assert_eq!(syntax_error(""), (1, 1, "a value"));
+        let message = parse_json_strict(r#"{"a":1 "b":2}"#).unwrap_err().to_string();
+        assert_eq!(message, "Invalid JSON at line 1, column 8: expected ',' or '}'");
+        // Lenient parsing still accepts what strict mode refuses
+        assert!(matches!(parse_json("{a:1}").get("a"), Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn test_number_grammar() {
+        let num = |text: &str| parse_value(text).unwrap_or_else(|| panic!("{} should parse", text));
+        assert!(matches!(num("-42"), Value::Int(-42)));
+        assert!(matches!(num("0"), Value::Int(0)));
+        assert!(matches!(num("-0"), Value::Int(0)));
+        assert!(matches!(num("9223372036854775807"), Value::Int(i64::MAX)));
+        assert!(matches!(num("-9223372036854775808"), Value::Int(i64::MIN)));
+        assert!(matches!(num("9223372036854775808"), Value::Float(f) if f == 9_223_372_036_854_775_808_f64));
+        assert!(matches!(num("-123456789012345678901234567890"), Value::Float(f) if f < -1.2e29));
+        assert!(matches!(num("-273.15"), Value::Float(f) if f == -273.15));
+        assert!(matches!(num("6.022e23"), Value::Float(f) if f == 6.022e23));
+        assert!(matches!(num("1e-9"), Value::Float(f) if f == 1e-9));
+        assert!(matches!(num("2E+3"), Value::Float(f) if f == 2000.0));
+        assert!(matches!(num("1.0"), Value::Float(f) if f == 1.0));
+
+        let doc = parse_json(r#"{"t":-273.15,"n":6.022e23,"e":1e-9,"i":-7}"#);
+        assert!(matches!(doc.get("t"), Some(Value::Float(f)) if *f == -273.15));
+        assert!(matches!(doc.get("n"), Some(Value::Float(f)) if *f == 6.022e23));
+        assert!(matches!(doc.get("e"), Some(Value::Float(f)) if *f == 1e-9));
+        assert!(matches!(doc.get("i"), Some(Value::Int(-7))));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let nested = |n: usize| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        let limits = Limits::default();
+        assert!(parse_value_with(&nested(limits.max_depth), limits).is_ok());
+        assert_eq!(parse_value_with(&nested(limits.max_depth + 1), limits).err(), Some(ParseError::TooDeep));
+
+        // Far past the limit must fail cleanly, not overflow the stack
+        let bomb = format!(r#"{{"a":{}}}"#, "[".repeat(100_000));
+        assert_eq!(parse_value_with(&bomb, limits).err(), Some(ParseError::TooDeep));
+
+        // Brackets inside strings do not count
+        let quoted = format!(r#"{{"s":"{}\"{}"}}"#, "[".repeat(100), "{".repeat(100));
+        assert!(parse_value_with(&quoted, limits).is_ok());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let limits = Limits { max_depth: 64, max_bytes: 16 };
+        assert!(parse_value_with(r#"{"a":"short"}"#, limits).is_ok());
+        let big = r#"{"a":"this is too long"}"#;
+        assert_eq!(parse_value_with(big, limits).err(), Some(ParseError::TooLarge));
+    }
+}
@@ -1,10 +1,15 @@
 //! API routing and JSON handling
 pub mod admin;
+pub mod archive;
 pub mod auth;
+pub mod batch;
 pub mod collections;
 pub mod contact;
+pub mod errors;
+pub mod files;
 pub mod json;
 pub mod ollama;
+pub mod openapi;
 pub mod projects;
 pub mod tools;
 pub mod utils;
@@ -12,55 +17,157 @@ pub mod utils;
 // Note: admin is now a single file instead of a subdirectory
 
 use std::collections::HashMap;
+use std::time::Duration;
+use crate::db::WriteError;
 use crate::logging;
+use errors::ErrorCode;
+use json::JsonSerializer as Json;
 
 pub struct Request {
     pub method: String,
     pub path: String,
+    /// Raw query string (without the `?`)
+    pub query: String,
     pub headers: HashMap<String, String>,
     pub body: String,
+    pub request_id: String,
+    pub client_ip: String,
+    /// Whether the URL carries a valid signature from [`crate::auth::sign_url`],
+    /// which stands in for an admin session
+    pub signed: bool,
 }
 
 pub struct Response {
     pub status: u16,
     pub body: String,
+    pub headers: Vec<(String, String)>,
+    /// Set by [`Response::error`], so an error can be told apart without
+    /// reading its body
+    pub code: Option<ErrorCode>,
+}
+
+impl Request {
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        utils::query_param(&self.query, key)
+    }
 }
 
 impl Response {
     pub fn json(status: u16, data: &str) -> Self {
-        Self { status, body: data.to_string() }
+        Self { status, body: data.to_string(), headers: Vec::new(), code: None }
+    }
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
     }
     pub fn ok(data: &str) -> Self { Self::json(200, data) }
     pub fn created(data: &str) -> Self { Self::json(201, data) }
-    pub fn bad_request(msg: &str) -> Self { Self::json(400, &format!(r#"{{"error":"{}"}}"#, msg)) }
-    pub fn unauthorized() -> Self { Self::json(401, r#"{"error":"Unauthorized"}"#) }
-    pub fn not_found() -> Self { Self::json(404, r#"{"error":"Not found"}"#) }
+    /// Error body `{"error":message,"code":code[,"field":field]}`; see
+    /// [`Response::nested`] for the other envelope.
+    pub fn error(status: u16, code: ErrorCode, message: &str, field: Option<&str>) -> Self {
+        let field = field.map(|f| format!(r#","field":{}"#, Json::wrap_string(f))).unwrap_or_default();
+        let body = format!(r#"{{"error":{},"code":"{}"{}}}"#, Json::wrap_string(message), code.as_str(), field);
+        Self { code: Some(code), ..Self::json(status, &body) }
+    }
+    /// 400 for a known validation failure, with its default message and field.
+    pub fn invalid(code: ErrorCode) -> Self { Self::error(400, code, code.message(), code.field()) }
+    /// Like [`Response::invalid`] with a more specific message.
+    pub fn invalid_because(code: ErrorCode, message: &str) -> Self { Self::error(400, code, message, code.field()) }
+    pub fn bad_request(msg: &str) -> Self { Self::error(400, ErrorCode::BadRequest, msg, None) }
+    pub fn unauthorized() -> Self { Self::error(401, ErrorCode::Unauthorized, "Unauthorized", None) }
+    pub fn not_found() -> Self { Self::error(404, ErrorCode::NotFound, "Not found", None) }
+    pub fn conflict(msg: &str) -> Self { Self::error(409, ErrorCode::Conflict, msg, None) }
+    pub fn too_many_requests(retry_after: Duration) -> Self {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Self::error(429, ErrorCode::RateLimited, "Too many requests", None).with_header("Retry-After", &secs.max(1).to_string())
+    }
+    /// An error from [`Response::error`] in the nested envelope
+    /// `{"error":{"code":code,"message":message[,"field":field]}}`. Any
+    /// other response is returned as is.
+    pub fn nested(self) -> Self {
+        let Some(code) = self.code else { return self };
+        let flat = json::parse_json(&self.body);
+        let message = flat.get("error").and_then(|v| v.as_str()).unwrap_or_default();
+        let field = flat.get("field").and_then(|v| v.as_str())
+            .map(|f| format!(r#","field":{}"#, Json::wrap_string(f)))
+            .unwrap_or_default();
+        let body = format!(r#"{{"error":{{"code":"{}","message":{}{}}}}}"#, code.as_str(), Json::wrap_string(message), field);
+        Self { body, ..self }
+    }
+    /// 400 for a failed database write: a unique violation becomes
+    /// `duplicate_value` naming the field, a full collection a 429
+    /// `quota_exceeded`, anything else `bad_request`.
+    pub fn write_error(err: &WriteError) -> Self {
+        match err {
+            WriteError::Quota(_) => Self::error(429, ErrorCode::QuotaExceeded, &err.to_string(), None),
+            WriteError::Unique(field) => Self::error(400, ErrorCode::DuplicateValue, &err.to_string(), Some(field)),
+            WriteError::Other(msg) => Self::bad_request(msg),
+        }
+    }
 }
 
 /// Route API request
 pub fn handle(req: &Request) -> Response {
+    negotiated(req, dispatch(req))
+}
+
+/// `response` in the error envelope `req` asked for: flat by default, so
+/// existing clients keep their top-level `error` string, or nested.
+pub fn negotiated(req: &Request, response: Response) -> Response {
+    if crate::negotiate::nested_errors(req.headers.get("accept").map(String::as_str)) {
+        response.nested()
+    } else {
+        response
+    }
+}
+
+fn dispatch(req: &Request) -> Response {
+    // Bodies must be valid JSON within the parser limits; handlers then
+    // parse them leniently knowing they are well-formed
+    if !req.body.trim().is_empty() {
+        if let Err(e) = json::parse_json_strict(&req.body) {
+            logging::info("api", &format!("{} {} -> 400 ({})", req.method, req.path, e));
+            return Response::error(400, ErrorCode::InvalidJson, &e.to_string(), None);
+        }
+    }
     let path_parts: Vec<&str> = req.path.trim_start_matches("/api/").split('/').collect();
 
     let response = match (req.method.as_str(), path_parts.as_slice()) {
+        ("GET", ["openapi.json"]) => openapi::spec(req),
+
         // Auth routes
         ("POST", ["auth", "register"]) => auth::register(req),
         ("POST", ["auth", "login"]) => auth::login(req),
+        ("POST", ["auth", "refresh"]) => auth::refresh(req),
         ("POST", ["auth", "logout"]) => auth::logout(req),
+        ("POST", ["auth", "change-password"]) => auth::change_password(req),
         ("GET", ["auth", "me"]) => auth::me(req),
 
         // Collection routes
         ("GET", ["collections"]) => collections::list_collections(req),
         ("POST", ["collections"]) => collections::create_collection(req),
         ("DELETE", ["collections", name]) => collections::delete_collection(req, name),
+        ("POST", ["collections", name, "rename"]) => collections::rename_collection(req, name),
+        ("POST", ["collections", name, "quota"]) => collections::set_quota(req, name),
+        ("POST", ["collections", name, "extra-fields"]) => collections::set_extra_fields(req, name),
         ("GET", ["collections", name]) => collections::list_documents(req, name),
         ("POST", ["collections", name]) => collections::create_document(req, name),
+        ("GET", ["collections", name, "distinct"]) => collections::distinct(req, name),
         ("GET", ["collections", name, id]) => collections::get_document(req, name, id),
         ("PUT", ["collections", name, id]) => collections::update_document(req, name, id, &req.body),
+        ("PATCH", ["collections", name, id]) => collections::patch_document(req, name, id),
         ("DELETE", ["collections", name, id]) => collections::delete_document(req, name, id),
 
+        ("POST", ["batch"]) => batch::run(req),
+
         // Admin routes
         ("GET", ["admin", "stats"]) => admin::stats(req),
+        ("GET", ["admin", "stats", "detailed"]) => admin::stats_detailed(req),
         ("POST", ["admin", "backup"]) => admin::backup(req),
+        ("POST", ["admin", "sign"]) => admin::sign(req),
+        ("POST", ["admin", "flush"]) => admin::flush(req),
+        ("POST", ["admin", "maintenance"]) => admin::maintenance(req),
+        ("GET", ["admin", "logs"]) => admin::logs(req),
         ("GET", ["admin", "collections", "system"]) => collections::list_system_collections(req),
         ("GET", ["admin", "users"]) => admin::list_users(req),
         ("POST", ["admin", "users"]) => admin::create_user(req),
@@ -68,16 +175,35 @@ pub fn handle(req: &Request) -> Response {
         ("DELETE", ["admin", "users", id]) => admin::delete_user(req, id),
         ("GET", ["admin", "settings"]) => admin::get_settings(req),
         ("PUT", ["admin", "settings"]) => admin::update_settings(req),
+        ("GET", ["admin", "files"]) => files::list(req),
+        ("GET", ["admin", "files", "content"]) => files::content(req),
+        ("PUT", ["admin", "files"]) => files::write(req),
+        ("DELETE", ["admin", "files"]) => files::delete(req),
+        ("POST", ["admin", "files", "dir"]) => files::create_dir(req),
         ("POST", ["admin", "chat"]) => ollama::chat(req),
         ("POST", ["contact"]) => contact::submit(req),
 
         // Projects routes
         ("GET", ["projects"]) => projects::list_projects(req),
         ("POST", ["projects"]) => projects::create_project(req),
+        ("POST", ["projects", "import"]) => archive::import(req),
         ("DELETE", ["projects", name]) => projects::delete_project(req, name),
+        ("GET", ["projects", name, "export"]) => archive::export(req, name),
+        ("PUT", ["projects", name, "proxy"]) => projects::update_proxy_settings(req, name),
+        ("POST", ["projects", name, "rebuild"]) => projects::rebuild_project(req, name),
 
         _ => Response::not_found(),
     };
+    // Public routes ignore a header they cannot use; one that needed
+    // credentials says why it refused
+    let response = if response.code == Some(ErrorCode::Unauthorized)
+        && crate::auth::credentials(&req.headers) == crate::auth::Credentials::Malformed
+    {
+        Response::error(401, ErrorCode::Unauthorized, "Malformed Authorization header (expected Bearer <token>)", None)
+            .with_header("WWW-Authenticate", "Bearer")
+    } else {
+        response
+    };
     logging::info("api", &format!("{} {} -> {}", req.method, req.path, response.status));
     response
 }
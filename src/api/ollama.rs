@@ -1,21 +1,30 @@
 //! Ollama API proxy
 use std::io::{Read, Write};
 use std::net::TcpStream;
+use std::sync::Arc;
 use std::time::Duration;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use crate::logging;
 use crate::api::{Request, Response};
+use crate::api::errors::ErrorCode;
 use crate::api::utils::{require_admin, load_env};
 use crate::api::tools;
-use crate::api::json::JsonSerializer as Json;
+use crate::api::json::{parse_value, JsonSerializer as Json};
+use crate::db::{self, Value};
 
 pub fn chat(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
     
     let ollama_url = load_env("OLLAMA_HOST").unwrap_or_else(|| "http://localhost:11434".to_string());
-    let host_port = ollama_url.trim_start_matches("http://").trim_start_matches("https://");
-    let (host, port) = if let Some((h, p)) = host_port.split_once(':') {
-        (h, p)
-    } else {
-        (host_port, "11434")
+    let url = match OllamaUrl::parse(&ollama_url) {
+        Ok(url) => url,
+        // The server's configuration is at fault, not the request
+        Err(e) => {
+            let message = format!("Invalid OLLAMA_HOST: {}", e);
+            logging::error("ollama", &message);
+            return Response::error(500, ErrorCode::Misconfigured, &message, None);
+        }
     };
 
     // Prepare the initial request to Ollama with tools
@@ -35,10 +44,10 @@ pub fn chat(req: &Request) -> Response {
         }
     }
 
-    match forward_to_ollama(host, port, &ollama_req_body) {
+    match forward_with_retry(&url, &ollama_req_body, &req.request_id) {
         Ok(res_body) => {
             if res_body.contains("\"tool_calls\":") {
-                handle_tool_calls(host, port, &ollama_req_body, &res_body)
+                handle_tool_calls(&ollama_req_body, &res_body)
             } else {
                 Response::ok(&res_body)
             }
@@ -60,7 +69,7 @@ fn ensure_system_prompt(body: &str) -> String {
         Some(pos) => msg_pos + pos + 1,
         None => return body.to_string(),
     };
-    let system_msg = r#"{"role":"system","content":"You have tool access: list_collections (returns project collections plus system collections), list_project_collections (project collections only), list_system_collections (internal collections starting with _), list_projects, create_project, find_free_ports, and search_docs. Use tools when asked about collections, projects, ports, or docs. System collections include _users, _settings, and _sessions. Always respond with strict JSON only (no markdown, no extra text). Use shape {\"answer\":string,\"data\":object,\"error\":string|null}."},"#;
+    let system_msg = r#"{"role":"system","content":"You have tool access: list_collections (returns project collections plus system collections), list_project_collections (project collections only), list_system_collections (internal collections starting with _), get_documents (read documents from a collection; _users and _sessions are off limits), list_projects, create_project, delete_project, find_free_ports, and search_docs. Use tools when asked about collections, projects, ports, or docs. System collections include _users, _settings, and _sessions. Always respond with strict JSON only (no markdown, no extra text). Use shape {\"answer\":string,\"data\":object,\"error\":string|null}."},"#;
     let mut out = String::with_capacity(body.len() + system_msg.len());
     out.push_str(&body[..bracket_pos]);
     out.push_str(system_msg);
@@ -68,82 +77,404 @@ fn ensure_system_prompt(body: &str) -> String {
     out
 }
 
-fn handle_tool_calls(_host: &str, _port: &str, _original_req: &str, ollama_res: &str) -> Response {
-    if let Some(tc_start) = ollama_res.find("\"tool_calls\":") {
-        if let Some(name_start) = ollama_res[tc_start..].find("\"name\":\"") {
-            let name_pos = tc_start + name_start + 8;
-            if let Some(name_end) = ollama_res[name_pos..].find('"') {
-                let tool_name = &ollama_res[name_pos..name_pos + name_end];
-                
-                let mut args = "";
-                if let Some(args_start) = ollama_res[name_pos..].find("\"arguments\":") {
-                    let args_pos = name_pos + args_start + 12;
-                    if let Some(arg_val_start) = ollama_res[args_pos..].find('{') {
-                        let mut brace_count = 0;
-                        let mut arg_val_end = 0;
-                        for (i, c) in ollama_res[args_pos + arg_val_start..].chars().enumerate() {
-                            if c == '{' { brace_count += 1; }
-                            if c == '}' { brace_count -= 1; }
-                            if brace_count == 0 {
-                                arg_val_end = i + 1;
-                                break;
-                            }
-                        }
-                        args = &ollama_res[args_pos + arg_val_start .. args_pos + arg_val_start + arg_val_end];
-                    }
-                }
-
-                let tool_result = tools::call_tool(tool_name, args);
-                let answer = match tool_name {
-                    "list_collections" => "Here are your collections.",
-                    "list_project_collections" => "Here are your project collections.",
-                    "list_system_collections" => "Here are your system collections.",
-                    "list_projects" => "Here are your projects.",
-                    "create_project" => "Project created.",
-                    "find_free_ports" => "Here is a free dev/prod port pair.",
-                    "search_docs" => "Here are the matching docs.",
-                    _ => "Here is the result.",
-                };
-                let msg_content = format!(
-                    "{{\"answer\":{},\"data\":{},\"error\":null}}",
-                    Json::wrap_string(answer),
-                    tool_result
-                );
-                let inner_msg = format!("{{{},{}}}",
-                    Json::key_string("role", "assistant"),
-                    Json::key_string("content", &msg_content)
-                );
-                let response = format!("{{\"message\":{}}}", inner_msg);
-                
-                Response::ok(&response)
-            } else { Response::ok(ollama_res) }
-        } else { Response::ok(ollama_res) }
-    } else { Response::ok(ollama_res) }
-}
-
-fn forward_to_ollama(host: &str, port: &str, body: &str) -> Result<String, String> {
-    let addr = format!("{}:{}", host, port);
-    let mut stream = TcpStream::connect(&addr)
-        .map_err(|e| format!("Failed to connect to Ollama at {}: {}", addr, e))?;
+fn handle_tool_calls(_original_req: &str, ollama_res: &str) -> Response {
+    let calls = extract_tool_calls(ollama_res);
+    if calls.is_empty() {
+        return Response::ok(ollama_res);
+    }
+
+    let (answer, data) = if let [(tool_name, args)] = calls.as_slice() {
+        (tool_answer(tool_name), tools::call_tool(tool_name, args))
+    } else {
+        let results = calls
+            .iter()
+            .map(|(name, args)| format!(
+                "{{\"tool\":{},\"data\":{}}}",
+                Json::wrap_string(name),
+                tools::call_tool(name, args)
+            ))
+            .collect::<Vec<_>>()
+            .join(",");
+        ("Here are the results.", format!("{{\"results\":[{}]}}", results))
+    };
+
+    let msg_content = format!(
+        "{{\"answer\":{},\"data\":{},\"error\":null}}",
+        Json::wrap_string(answer),
+        data
+    );
+    let inner_msg = format!("{{{},{}}}",
+        Json::key_string("role", "assistant"),
+        Json::key_string("content", &msg_content)
+    );
+    Response::ok(&format!("{{\"message\":{}}}", inner_msg))
+}
+
+fn tool_answer(tool_name: &str) -> &'static str {
+    match tool_name {
+        "list_collections" => "Here are your collections.",
+        "list_project_collections" => "Here are your project collections.",
+        "list_system_collections" => "Here are your system collections.",
+        "get_documents" => "Here are the documents.",
+        "list_projects" => "Here are your projects.",
+        "create_project" => "Project created.",
+        "delete_project" => "Project deleted.",
+        "find_free_ports" => "Here is a free dev/prod port pair.",
+        "search_docs" => "Here are the matching docs.",
+        _ => "Here is the result.",
+    }
+}
+
+/// Pull `(name, arguments_json)` pairs out of `message.tool_calls[].function`.
+/// Arguments may arrive as an object or as a JSON-encoded string.
+fn extract_tool_calls(ollama_res: &str) -> Vec<(String, String)> {
+    let Some(response) = parse_value(ollama_res) else { return Vec::new(); };
+    let calls = response
+        .as_object()
+        .and_then(|r| r.get("message"))
+        .and_then(Value::as_object)
+        .and_then(|m| m.get("tool_calls"))
+        .and_then(Value::as_array);
+    let Some(calls) = calls else { return Vec::new(); };
+
+    calls.iter().filter_map(|call| {
+        let function = call.as_object()?.get("function")?.as_object()?;
+        let name = function.get("name")?.as_str()?.to_string();
+        let args = match function.get("arguments") {
+            Some(Value::String(encoded)) => encoded.clone(),
+            Some(value) => db::value_to_json(value),
+            None => "{}".to_string(),
+        };
+        Some((name, args))
+    }).collect()
+}
+
+/// Parsed `OLLAMA_HOST` value: scheme, host, port and optional base path.
+#[derive(Debug, PartialEq)]
+struct OllamaUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl OllamaUrl {
+    fn parse(url: &str) -> Result<Self, String> {
+        let url = url.trim();
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if url.contains("://") {
+            return Err("unsupported scheme".into());
+        } else {
+            (false, url)
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+
+        // Bracketed IPv6 literals keep their colons: [::1]:11434
+        let (host, port) = if let Some(inner) = authority.strip_prefix('[') {
+            let end = inner.find(']').ok_or("unterminated IPv6 host")?;
+            let port = inner[end + 1..].strip_prefix(':');
+            (&inner[..end], port)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((h, p)) => (h, Some(p)),
+                None => (authority, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err("missing host".into());
+        }
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| format!("invalid port: {}", p))?,
+            None if tls => 443,
+            None => 11434,
+        };
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn chat_path(&self) -> String {
+        format!("{}/api/chat", self.path)
+    }
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Failure talking to Ollama. Only connection failures are retried;
+/// once Ollama answered (even with an HTTP error) the call is final.
+#[derive(Debug)]
+enum OllamaError {
+    Connect(String),
+    Request(String),
+    Http(u16, String),
+}
+
+impl std::fmt::Display for OllamaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OllamaError::Connect(msg) | OllamaError::Request(msg) => write!(f, "{}", msg),
+            OllamaError::Http(status, body) => write!(f, "HTTP {}: {}", status, body.trim()),
+        }
+    }
+}
+
+/// Bounded exponential backoff for cold-starting Ollama instances.
+/// Configured via OLLAMA_RETRY_ATTEMPTS and OLLAMA_RETRY_DELAY_MS, clamped
+/// so a misconfigured value cannot keep a request waiting for minutes.
+struct RetryPolicy {
+    attempts: u32,
+    base_delay: Duration,
+}
+
+impl RetryPolicy {
+    const MAX_ATTEMPTS: u32 = 10;
+    const MAX_DELAY: Duration = Duration::from_secs(5);
+    /// All waits of one request together.
+    const MAX_TOTAL_DELAY: Duration = Duration::from_secs(20);
+
+    fn new(attempts: u32, base_delay: Duration) -> Self {
+        Self { attempts: attempts.clamp(1, Self::MAX_ATTEMPTS), base_delay: base_delay.min(Self::MAX_DELAY) }
+    }
+
+    fn from_env() -> Self {
+        let attempts = load_env("OLLAMA_RETRY_ATTEMPTS")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3);
+        let base_ms = load_env("OLLAMA_RETRY_DELAY_MS")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(250);
+        Self::new(attempts, Duration::from_millis(base_ms))
+    }
+
+    /// `attempt` is zero-based: the number of calls already made minus one.
+    fn should_retry(&self, err: &OllamaError, attempt: u32) -> bool {
+        matches!(err, OllamaError::Connect(_))
+            && attempt + 1 < self.attempts
+            && self.waited(attempt + 1) <= Self::MAX_TOTAL_DELAY
+    }
+
+    /// Time spent sleeping before the first `retries` retries.
+    fn waited(&self, retries: u32) -> Duration {
+        (0..retries).map(|attempt| self.delay(attempt)).sum()
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor).min(Self::MAX_DELAY)
+    }
+}
+
+fn forward_with_retry(url: &OllamaUrl, body: &str, request_id: &str) -> Result<String, OllamaError> {
+    let policy = RetryPolicy::from_env();
+    let mut attempt = 0;
+    loop {
+        match forward_to_ollama(url, body, request_id) {
+            Err(e) if policy.should_retry(&e, attempt) => {
+                std::thread::sleep(policy.delay(attempt));
+                attempt += 1;
+            }
+            Err(OllamaError::Connect(msg)) if attempt > 0 => {
+                return Err(OllamaError::Connect(format!("{} (gave up after {} attempts)", msg, attempt + 1)));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn connect(url: &OllamaUrl) -> Result<Box<dyn ReadWrite>, OllamaError> {
+    let addr = format!("{}:{}", url.host, url.port);
+    let stream = TcpStream::connect((url.host.as_str(), url.port))
+        .map_err(|e| OllamaError::Connect(format!("Failed to connect to Ollama at {}: {}", addr, e)))?;
 
     stream.set_read_timeout(Some(Duration::from_secs(30))).ok();
     stream.set_write_timeout(Some(Duration::from_secs(10))).ok();
 
+    if !url.tls {
+        return Ok(Box::new(stream));
+    }
+    let server_name = ServerName::try_from(url.host.clone())
+        .map_err(|_| OllamaError::Request(format!("Invalid TLS server name: {}", url.host)))?;
+    let tls = rustls::ClientConnection::new(Arc::new(tls_client_config()), server_name)
+        .map_err(|e| OllamaError::Request(format!("TLS init failed: {}", e)))?;
+    Ok(Box::new(rustls::StreamOwned::new(tls, stream)))
+}
+
+/// Client config trusting the system CA bundle (or `SSL_CERT_FILE` when set).
+fn tls_client_config() -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    let candidates = [
+        std::env::var("SSL_CERT_FILE").unwrap_or_default(),
+        "/etc/ssl/certs/ca-certificates.crt".to_string(),
+        "/etc/pki/tls/certs/ca-bundle.crt".to_string(),
+        "/etc/ssl/cert.pem".to_string(),
+    ];
+    for path in candidates.iter().filter(|p| !p.is_empty()) {
+        if let Ok(file) = std::fs::File::open(path) {
+            let mut reader = std::io::BufReader::new(file);
+            for cert in rustls_pemfile::certs(&mut reader).flatten() {
+                let _ = roots.add(cert);
+            }
+            break;
+        }
+    }
+    ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+fn forward_to_ollama(url: &OllamaUrl, body: &str, request_id: &str) -> Result<String, OllamaError> {
+    let mut stream = connect(url)?;
+
     let request = format!(
-        "POST /api/chat HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        host, body.len(), body
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n{}",
+        url.chat_path(), url.host, body.len(), request_id, body
     );
 
     stream.write_all(request.as_bytes())
-        .map_err(|e| format!("Failed to write to Ollama: {}", e))?;
+        .map_err(|e| OllamaError::Request(format!("Failed to write to Ollama: {}", e)))?;
 
-    let mut response = String::new();
-    stream.read_to_string(&mut response)
-        .map_err(|e| format!("Failed to read from Ollama: {}", e))?;
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 4096];
+    loop {
+        match stream.read(&mut tmp) {
+            Ok(0) => break,
+            Ok(n) => buf.extend_from_slice(&tmp[..n]),
+            // TLS peers frequently close without close_notify
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(OllamaError::Request(format!("Failed to read from Ollama: {}", e))),
+        }
+    }
+    let response = String::from_utf8_lossy(&buf).to_string();
 
-    if let Some(body_start) = response.find("\r\n\r\n") {
-        Ok(response[body_start + 4..].to_string())
-    } else {
-        Ok(response)
+    let status = response
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(200);
+    let body = match response.find("\r\n\r\n") {
+        Some(body_start) => response[body_start + 4..].to_string(),
+        None => response,
+    };
+    if status >= 400 {
+        return Err(OllamaError::Http(status, body));
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_only_connection_errors() {
+        let policy = RetryPolicy { attempts: 3, base_delay: Duration::from_millis(100) };
+        let refused = OllamaError::Connect("refused".into());
+        assert!(policy.should_retry(&refused, 0));
+        assert!(policy.should_retry(&refused, 1));
+        assert!(!policy.should_retry(&refused, 2));
+        assert!(!policy.should_retry(&OllamaError::Http(500, "boom".into()), 0));
+        assert!(!policy.should_retry(&OllamaError::Request("write".into()), 0));
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_and_caps() {
+        let policy = RetryPolicy { attempts: 10, base_delay: Duration::from_millis(100) };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(20), RetryPolicy::MAX_DELAY);
+        assert_eq!(policy.delay(40), RetryPolicy::MAX_DELAY);
+    }
+
+    #[test]
+    fn test_retry_attempts_and_total_delay_are_clamped() {
+        let policy = RetryPolicy::new(1000, Duration::from_secs(60));
+        assert_eq!(policy.attempts, RetryPolicy::MAX_ATTEMPTS);
+        assert_eq!(policy.base_delay, RetryPolicy::MAX_DELAY);
+        assert_eq!(RetryPolicy::new(0, Duration::ZERO).attempts, 1);
+        // Four 5 s waits reach the 20 s budget; a fifth would pass it
+        let refused = OllamaError::Connect("refused".into());
+        assert!(policy.should_retry(&refused, 3));
+        assert!(!policy.should_retry(&refused, 4));
+    }
+
+    #[test]
+    fn test_extract_tool_calls_with_braces_in_args() {
+        let res = r#"{"message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"search_docs","arguments":{"query":"use {% include %} tags"}}}]}}"#;
+        let calls = extract_tool_calls(res);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "search_docs");
+        let args = parse_value(&calls[0].1).unwrap();
+        assert_eq!(
+            args.as_object().and_then(|a| a.get("query")).and_then(Value::as_str),
+            Some("use {% include %} tags")
+        );
+    }
+
+    #[test]
+    fn test_extract_tool_calls_string_encoded_args() {
+        let res = r#"{"message":{"tool_calls":[{"function":{"name":"create_project","arguments":"{\"name\":\"shop\"}"}},{"function":{"name":"list_projects"}}]}}"#;
+        let calls = extract_tool_calls(res);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], ("create_project".to_string(), r#"{"name":"shop"}"#.to_string()));
+        assert_eq!(calls[1], ("list_projects".to_string(), "{}".to_string()));
+    }
+
+    #[test]
+    fn test_extract_tool_calls_ignores_non_json() {
+        assert!(extract_tool_calls(r#"not json "tool_calls":"#).is_empty());
+    }
+
+    #[test]
+    fn test_parse_http_default_port() {
+        let url = OllamaUrl::parse("http://localhost").unwrap();
+        assert!(!url.tls);
+        assert_eq!(url.host, "localhost");
+        assert_eq!(url.port, 11434);
+        assert_eq!(url.chat_path(), "/api/chat");
+    }
+
+    #[test]
+    fn test_parse_https_default_port() {
+        let url = OllamaUrl::parse("https://ollama.example.com/").unwrap();
+        assert!(url.tls);
+        assert_eq!(url.host, "ollama.example.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.chat_path(), "/api/chat");
+    }
+
+    #[test]
+    fn test_parse_host_with_path() {
+        let url = OllamaUrl::parse("https://gpu.example.com/ollama/").unwrap();
+        assert_eq!(url.host, "gpu.example.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.chat_path(), "/ollama/api/chat");
+    }
+
+    #[test]
+    fn test_parse_explicit_port() {
+        let url = OllamaUrl::parse("http://10.0.0.5:8080").unwrap();
+        assert_eq!(url.host, "10.0.0.5");
+        assert_eq!(url.port, 8080);
+        let url = OllamaUrl::parse("https://[::1]:8443/v1").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.chat_path(), "/v1/api/chat");
+        assert!(OllamaUrl::parse("http://host:notaport").is_err());
+        assert!(OllamaUrl::parse("ftp://host").is_err());
     }
 }
@@ -0,0 +1,254 @@
+//! OpenAPI 3 description of the HTTP API, generated from a static route table
+use crate::api::{Request, Response};
+use crate::api::errors::ErrorCode;
+use crate::api::json::JsonSerializer as Json;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Public,
+    User,
+    Admin,
+}
+
+struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    access: Access,
+    request: Option<&'static str>,
+    status: u16,
+    response: &'static str,
+}
+
+// ── Schemas ──────────────────────────────────────────────────────────────────
+
+const CREDENTIALS: &str = r#"{"type":"object","required":["email","password"],"properties":{"email":{"type":"string"},"password":{"type":"string"}}}"#;
+const PASSWORD_CHANGE: &str = r#"{"type":"object","required":["current_password","new_password"],"properties":{"current_password":{"type":"string"},"new_password":{"type":"string"}}}"#;
+const SESSION: &str = r#"{"type":"object","properties":{"token":{"type":"string"},"user_id":{"type":"string"}}}"#;
+const SUCCESS: &str = r#"{"type":"object","properties":{"success":{"type":"boolean"}}}"#;
+const DOCUMENT: &str = r#"{"type":"object","additionalProperties":true,"properties":{"id":{"type":"string"},"created":{"type":"integer"},"updated":{"type":"integer"}}}"#;
+const DOCUMENTS: &str = r#"{"type":"array","items":{"type":"object","additionalProperties":true}}"#;
+const DISTINCT: &str = r#"{"type":"object","properties":{"values":{"type":"array","items":{}}}}"#;
+const CREATED_ID: &str = r#"{"type":"object","properties":{"id":{"type":"string"}}}"#;
+const UPDATED: &str = r#"{"type":"object","properties":{"id":{"type":"string"},"updated":{"type":"boolean"}}}"#;
+const DELETED: &str = r#"{"type":"object","properties":{"deleted":{"type":"boolean"}}}"#;
+const COLLECTION_NAMES: &str = r#"{"type":"object","properties":{"collections":{"type":"array","items":{"type":"string"}},"next_cursor":{"type":"string","nullable":true,"description":"Present when ?after= or ?limit= was given"}}}"#;
+const NEW_COLLECTION: &str = r#"{"type":"object","required":["name"],"properties":{"name":{"type":"string"},"fields":{"type":"array","items":{"type":"object","properties":{"name":{"type":"string"},"type":{"type":"string","enum":["string","int","float","bool","array","object"]},"default":{"description":"Stored when a new document omits the field"},"unique":{"type":"boolean","description":"Reject values another document already has"}}}},"max_docs":{"type":"integer","description":"Refuse inserts past this many documents (0 = unlimited)"},"extra_fields":{"type":"string","enum":["off","lenient","strict"],"description":"What writes with undeclared fields get: stored silently, stored with an _unknown_fields hint, or refused"}}}"#;
+const NAMED: &str = r#"{"type":"object","properties":{"name":{"type":"string"}}}"#;
+const EXTRA_FIELDS: &str = r#"{"type":"object","required":["mode"],"properties":{"name":{"type":"string"},"mode":{"type":"string","enum":["off","lenient","strict"]},"extra_fields":{"type":"string"}}}"#;
+const QUOTA: &str = r#"{"type":"object","required":["max_docs"],"properties":{"name":{"type":"string"},"max_docs":{"type":"integer","description":"0 = unlimited"}}}"#;
+const RENAME: &str = r#"{"type":"object","required":["new_name"],"properties":{"new_name":{"type":"string"}}}"#;
+const RENAMED: &str = r#"{"type":"object","properties":{"name":{"type":"string"},"renamed":{"type":"boolean"}}}"#;
+const STATS: &str = r#"{"type":"object","properties":{"collections":{"type":"integer"},"users":{"type":"integer"}}}"#;
+const DETAILED_STATS: &str = r#"{"type":"object","properties":{"collections":{"type":"array","items":{"type":"object","properties":{"name":{"type":"string"},"documents":{"type":"integer"},"bytes":{"type":"integer"},"fields":{"type":"integer"}}}},"file_bytes":{"type":"integer","nullable":true},"last_sync":{"type":"integer","nullable":true},"slow_operations":{"type":"integer","description":"Operations slower than DB_SLOW_MS since startup"}}}"#;
+const FLUSHED: &str = r#"{"type":"object","properties":{"bytes":{"type":"integer","description":"Size of db.bin"},"synced_at":{"type":"integer","description":"Unix seconds"}}}"#;
+const BACKUP: &str = r#"{"type":"object","properties":{"backup":{"type":"string"},"download":{"type":"string","description":"Signed URL of the file, good for 15 minutes"}}}"#;
+const SIGN: &str = r#"{"type":"object","required":["path"],"properties":{"path":{"type":"string","description":"URL path, query included"},"method":{"type":"string","default":"GET"},"expires_in":{"type":"integer","default":900,"maximum":86400}}}"#;
+const SIGNED: &str = r#"{"type":"object","properties":{"url":{"type":"string"},"method":{"type":"string"},"expires":{"type":"integer","description":"Unix seconds"}}}"#;
+const NEW_USER: &str = r#"{"type":"object","required":["email","password"],"properties":{"email":{"type":"string"},"password":{"type":"string"},"role":{"type":"string","enum":["admin","user"]}}}"#;
+const USER_UPDATE: &str = r#"{"type":"object","properties":{"email":{"type":"string"},"password":{"type":"string"},"role":{"type":"string","enum":["admin","user"]}}}"#;
+const SETTINGS: &str = r#"{"type":"object","properties":{"id":{"type":"string"},"settings":{"type":"object","additionalProperties":true}}}"#;
+const MAINTENANCE_TOGGLE: &str = r#"{"type":"object","properties":{"enabled":{"type":"boolean","description":"Omit to toggle"}}}"#;
+const MAINTENANCE: &str = r#"{"type":"object","properties":{"maintenance":{"type":"boolean"}}}"#;
+const LOG_LINES: &str = r#"{"type":"object","properties":{"lines":{"type":"array","items":{"type":"string"}},"offset":{"type":"integer","description":"Byte position to pass back as ?offset= with ?follow"}}}"#;
+const FILE_LISTING: &str = r#"{"type":"object","properties":{"dir":{"type":"string"},"entries":{"type":"array","items":{"type":"object","properties":{"name":{"type":"string"},"type":{"type":"string","enum":["dir","file"]},"size":{"type":"integer"},"modified":{"type":"integer"}}}}}}"#;
+const FILE_CONTENT: &str = r#"{"type":"object","properties":{"path":{"type":"string"},"size":{"type":"integer"},"content":{"type":"string"}}}"#;
+const FILE_WRITE: &str = r#"{"type":"object","required":["path","content"],"properties":{"path":{"type":"string"},"content":{"type":"string","description":"UTF-8 text, at most 1 MB"}}}"#;
+const FILE_WRITTEN: &str = r#"{"type":"object","properties":{"path":{"type":"string"},"size":{"type":"integer"}}}"#;
+const PATH: &str = r#"{"type":"object","required":["path"],"properties":{"path":{"type":"string"}}}"#;
+const BATCH: &str = r#"{"type":"array","maxItems":100,"items":{"type":"object","required":["op","collection"],"properties":{"op":{"type":"string","enum":["insert","update","delete"]},"collection":{"type":"string"},"id":{"type":"string","description":"Required for update and delete"},"doc":{"type":"object","description":"Required for insert and update"}}}}"#;
+const BATCH_RESULTS: &str = r#"{"type":"object","properties":{"results":{"type":"array","items":{"type":"object","properties":{"op":{"type":"string"},"id":{"type":"string"}}}}}}"#;
+const ANY_OBJECT: &str = r#"{"type":"object","additionalProperties":true}"#;
+const CHAT: &str = r#"{"type":"object","properties":{"model":{"type":"string"},"messages":{"type":"array","items":{"type":"object","properties":{"role":{"type":"string"},"content":{"type":"string"}}}}}}"#;
+const CHAT_REPLY: &str = r#"{"type":"object","properties":{"message":{"type":"object","properties":{"role":{"type":"string"},"content":{"type":"string"}}}}}"#;
+const PROJECTS: &str = r#"{"type":"array","items":{"type":"string"}}"#;
+const PROJECT_CREATED: &str = r#"{"type":"object","properties":{"name":{"type":"string"},"success":{"type":"boolean"}}}"#;
+const PROJECT_ARCHIVE: &str = r#"{"type":"object","required":["format","name","files"],"properties":{"format":{"type":"string","enum":["rpw-project/1"]},"name":{"type":"string"},"exported":{"type":"integer"},"files":{"type":"array","items":{"type":"object","properties":{"path":{"type":"string"},"data":{"type":"string","format":"byte"}}}},"collections":{"type":"array","items":{"type":"object","properties":{"suffix":{"type":"string","enum":["users","sessions","settings"]},"fields":{"type":"array","items":{"type":"object"}},"max_docs":{"type":"integer"},"extra_fields":{"type":"string","enum":["off","lenient","strict"]},"documents":{"type":"array","items":{"type":"object"}}}}}}}"#;
+const PROJECT_IMPORTED: &str = r#"{"type":"object","properties":{"name":{"type":"string"},"dev_port":{"type":"integer"},"prod_port":{"type":"integer"}}}"#;
+const PROJECT_REBUILT: &str = r#"{"type":"object","properties":{"name":{"type":"string"},"rebuilt":{"type":"array","items":{"type":"string"},"description":"Pages that changed, relative to the project"}}}"#;
+const PROXY_SETTINGS_UPDATE: &str = r#"{"type":"object","properties":{"rewrite_host":{"type":"boolean","description":"Send the upstream's own address as Host"},"access":{"type":"string","enum":["public","admin","restricted"]},"allow_roles":{"type":"array","items":{"type":"string"}},"allow_users":{"type":"array","items":{"type":"string"},"description":"User ids or emails"}}}"#;
+const PROXY_SETTINGS: &str = r#"{"type":"object","properties":{"project":{"type":"string"},"rewrite_host":{"type":"boolean"},"access":{"type":"string"},"allow_roles":{"type":"array","items":{"type":"string"}},"allow_users":{"type":"array","items":{"type":"string"}}}}"#;
+const CONTACT: &str = r#"{"type":"object","required":["name","email","message","elapsed"],"properties":{"name":{"type":"string"},"email":{"type":"string"},"message":{"type":"string"},"elapsed":{"type":"integer"},"company":{"type":"string","description":"Honeypot; must be empty"}}}"#;
+
+// ── Route table ──────────────────────────────────────────────────────────────
+
+const fn ep(
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    access: Access,
+    request: Option<&'static str>,
+    status: u16,
+    response: &'static str,
+) -> Endpoint {
+    Endpoint { method, path, summary, access, request, status, response }
+}
+
+const ENDPOINTS: &[Endpoint] = &[
+    ep("POST", "/api/auth/register", "Register a user (the first user becomes admin)", Access::Public, Some(CREDENTIALS), 201, SESSION),
+    ep("POST", "/api/auth/login", "Log in and receive a session token", Access::Public, Some(CREDENTIALS), 200, SESSION),
+    ep("POST", "/api/auth/refresh", "Rotate the session token", Access::User, None, 200, SESSION),
+    ep("POST", "/api/auth/logout", "End the current session", Access::User, None, 200, SUCCESS),
+    ep("POST", "/api/auth/change-password", "Change your password; other sessions end unless PASSWORD_CHANGE_ENDS_SESSIONS=0", Access::User, Some(PASSWORD_CHANGE), 200, SUCCESS),
+    ep("GET", "/api/auth/me", "Current user", Access::User, None, 200, DOCUMENT),
+    ep("GET", "/api/collections", "List project collections, sorted (?prefix=dev- filters; ?after=&limit= pages)", Access::User, None, 200, COLLECTION_NAMES),
+    ep("POST", "/api/collections", "Create a collection", Access::Admin, Some(NEW_COLLECTION), 201, NAMED),
+    ep("GET", "/api/collections/{name}", "List documents in a collection (?mine=true keeps the caller's own; ?after=&limit= returns {docs,next_cursor})", Access::User, None, 200, DOCUMENTS),
+    ep("POST", "/api/collections/{name}", "Create a document (?ttl=<seconds> sets _expires_at for the sweeper)", Access::User, Some(ANY_OBJECT), 201, CREATED_ID),
+    ep("DELETE", "/api/collections/{name}", "Delete a collection", Access::Admin, None, 200, DELETED),
+    ep("POST", "/api/collections/{name}/rename", "Rename a collection, keeping its documents", Access::Admin, Some(RENAME), 200, RENAMED),
+    ep("POST", "/api/collections/{name}/quota", "Set the document cap; inserts past it get 429 quota_exceeded (0 = unlimited)", Access::Admin, Some(QUOTA), 200, QUOTA),
+    ep("POST", "/api/collections/{name}/extra-fields", "Choose what writes with undeclared fields get: off, lenient (stored, answered with _unknown_fields) or strict (400)", Access::Admin, Some(EXTRA_FIELDS), 200, EXTRA_FIELDS),
+    ep("GET", "/api/collections/{name}/distinct", "Distinct values of a field, sorted (?field=category)", Access::User, None, 200, DISTINCT),
+    ep("GET", "/api/collections/{name}/{id}", "Get a document", Access::User, None, 200, DOCUMENT),
+    ep("PUT", "/api/collections/{name}/{id}", "Update a document, or create it at this id (201)", Access::User, Some(ANY_OBJECT), 200, UPDATED),
+    ep("PATCH", "/api/collections/{name}/{id}", "Apply a JSON Merge Patch: nested objects merge, null removes a field", Access::User, Some(ANY_OBJECT), 200, UPDATED),
+    ep("DELETE", "/api/collections/{name}/{id}", "Delete a document", Access::User, None, 200, DELETED),
+    ep("POST", "/api/batch", "Apply several writes atomically: all succeed or none are applied (400 names the failing op)", Access::User, Some(BATCH), 200, BATCH_RESULTS),
+    ep("GET", "/api/admin/stats", "Collection and user counts", Access::Admin, None, 200, STATS),
+    ep("GET", "/api/admin/stats/detailed", "Per-collection document counts and sizes, db.bin size and last sync", Access::Admin, None, 200, DETAILED_STATS),
+    ep("POST", "/api/admin/backup", "Write an encrypted database backup", Access::Admin, None, 200, BACKUP),
+    ep("POST", "/api/admin/sign", "Sign a URL that admits one method on one path without a session until it expires", Access::Admin, Some(SIGN), 200, SIGNED),
+    ep("POST", "/api/admin/flush", "Write db.bin to disk now (before a filesystem snapshot)", Access::Admin, None, 200, FLUSHED),
+    ep("POST", "/api/admin/maintenance", "Enable, disable or toggle maintenance mode", Access::Admin, Some(MAINTENANCE_TOGGLE), 200, MAINTENANCE),
+    ep("GET", "/api/admin/logs", "Last log lines (?lines=100&level=error), or long-poll for new ones (?follow&offset=N)", Access::Admin, None, 200, LOG_LINES),
+    ep("GET", "/api/admin/collections/system", "List system collections, sorted (?prefix= filters; ?after=&limit= pages)", Access::Admin, None, 200, COLLECTION_NAMES),
+    ep("GET", "/api/admin/users", "List users", Access::Admin, None, 200, DOCUMENTS),
+    ep("POST", "/api/admin/users", "Create a user", Access::Admin, Some(NEW_USER), 201, CREATED_ID),
+    ep("PUT", "/api/admin/users/{id}", "Update a user", Access::Admin, Some(USER_UPDATE), 200, UPDATED),
+    ep("DELETE", "/api/admin/users/{id}", "Delete a user", Access::Admin, None, 200, DELETED),
+    ep("GET", "/api/admin/settings", "Site settings", Access::Admin, None, 200, SETTINGS),
+    ep("PUT", "/api/admin/settings", "Update site settings", Access::Admin, Some(ANY_OBJECT), 200, UPDATED),
+    ep("GET", "/api/admin/files", "List a directory under projects/ or public/ (?dir=projects/foo)", Access::Admin, None, 200, FILE_LISTING),
+    ep("GET", "/api/admin/files/content", "Preview a text file up to 64 KiB (?path=projects/foo/index.html)", Access::Admin, None, 200, FILE_CONTENT),
+    ep("PUT", "/api/admin/files", "Create or overwrite a text file (201 when created)", Access::Admin, Some(FILE_WRITE), 200, FILE_WRITTEN),
+    ep("DELETE", "/api/admin/files", "Delete a file or empty directory (?path=projects/foo/old.css)", Access::Admin, None, 200, DELETED),
+    ep("POST", "/api/admin/files/dir", "Create a directory", Access::Admin, Some(PATH), 201, PATH),
+    ep("POST", "/api/admin/chat", "Chat with the Ollama assistant (tool calls run server-side)", Access::Admin, Some(CHAT), 200, CHAT_REPLY),
+    ep("GET", "/api/projects", "List projects", Access::Admin, None, 200, PROJECTS),
+    ep("POST", "/api/projects", "Create a project from the template", Access::Admin, Some(NAMED), 201, PROJECT_CREATED),
+    ep("DELETE", "/api/projects/{name}", "Delete a project and its data", Access::Admin, None, 200, DELETED),
+    ep("POST", "/api/projects/{name}/rebuild", "Re-render a project's pages with the current nav and footer", Access::Admin, None, 200, PROJECT_REBUILT),
+    ep("GET", "/api/projects/{name}/export", "Export a project's files and collections as an archive", Access::Admin, None, 200, PROJECT_ARCHIVE),
+    ep("POST", "/api/projects/import", "Recreate a project from an archive with fresh ports", Access::Admin, Some(PROJECT_ARCHIVE), 201, PROJECT_IMPORTED),
+    ep("PUT", "/api/projects/{name}/proxy", "Update a project's reverse proxy settings", Access::Admin, Some(PROXY_SETTINGS_UPDATE), 200, PROXY_SETTINGS),
+    ep("POST", "/api/contact", "Submit a contact form message", Access::Public, Some(CONTACT), 201, CREATED_ID),
+    ep("GET", "/api/openapi.json", "This document", Access::Public, None, 200, r#"{"type":"object"}"#),
+];
+
+// ── Generation ───────────────────────────────────────────────────────────────
+
+pub fn spec(_req: &Request) -> Response {
+    Response::ok(&document()).with_header("Cache-Control", "public, max-age=3600")
+}
+
+pub fn document() -> String {
+    let mut paths: Vec<(&str, Vec<String>)> = Vec::new();
+    for endpoint in ENDPOINTS {
+        let op = format!("{}:{}", Json::wrap_string(&endpoint.method.to_lowercase()), operation(endpoint));
+        match paths.iter_mut().find(|(path, _)| *path == endpoint.path) {
+            Some((_, ops)) => ops.push(op),
+            None => paths.push((endpoint.path, vec![op])),
+        }
+    }
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|(path, ops)| format!("{}:{{{}}}", Json::wrap_string(path), ops.join(",")))
+        .collect();
+
+    format!(
+        r#"{{"openapi":"3.0.3","info":{{"title":"Rust Pure Web API","version":{}}},"components":{{"securitySchemes":{{"bearerAuth":{{"type":"http","scheme":"bearer"}}}},"schemas":{{"Error":{}}}}},"paths":{{{}}}}}"#,
+        Json::wrap_string(env!("CARGO_PKG_VERSION")),
+        error_schema(),
+        paths.join(",")
+    )
+}
+
+/// `{error, code, field?}`; `code` lists every [`ErrorCode`].
+fn error_schema() -> String {
+    let codes: Vec<String> = ErrorCode::ALL.iter().map(|c| Json::wrap_string(c.as_str())).collect();
+    format!(
+        r#"{{"type":"object","description":"Default envelope. With Accept: application/json; errors=nested the same fields come as {{\"error\":{{\"code\",\"message\",\"field\"}}}}","required":["error","code"],"properties":{{"error":{{"type":"string"}},"code":{{"type":"string","enum":[{}]}},"field":{{"type":"string"}}}}}}"#,
+        codes.join(",")
+    )
+}
+
+fn operation(endpoint: &Endpoint) -> String {
+    // Tag by the first segment after /api: auth, collections, admin, ...
+    let tag = match endpoint.path.split('/').nth(2) {
+        Some(seg) if !seg.contains('.') => seg,
+        _ => "meta",
+    };
+    let mut fields = vec![
+        Json::key_string("summary", endpoint.summary),
+        format!(r#""tags":[{}]"#, Json::wrap_string(tag)),
+    ];
+    if endpoint.access != Access::Public {
+        fields.push(r#""security":[{"bearerAuth":[]}]"#.to_string());
+    }
+
+    let params: Vec<String> = endpoint
+        .path
+        .split('/')
+        .filter_map(|seg| seg.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| format!(
+            r#"{{"name":{},"in":"path","required":true,"schema":{{"type":"string"}}}}"#,
+            Json::wrap_string(name)
+        ))
+        .collect();
+    if !params.is_empty() {
+        fields.push(format!(r#""parameters":[{}]"#, params.join(",")));
+    }
+
+    if let Some(schema) = endpoint.request {
+        fields.push(format!(
+            r#""requestBody":{{"required":true,"content":{{"application/json":{{"schema":{}}}}}}}"#,
+            schema
+        ));
+    }
+
+    let mut responses = vec![format!(
+        r#""{}":{{"description":"Success","content":{{"application/json":{{"schema":{}}}}}}}"#,
+        endpoint.status, endpoint.response
+    )];
+    if endpoint.request.is_some() {
+        responses.push(error_response(400, "Invalid request"));
+    }
+    match endpoint.access {
+        Access::Public => {}
+        Access::User => responses.push(error_response(401, "Missing or invalid token")),
+        Access::Admin => responses.push(error_response(401, "Admin token required")),
+    }
+    if endpoint.path.contains('{') {
+        responses.push(error_response(404, "Not found"));
+    }
+    fields.push(format!(r#""responses":{{{}}}"#, responses.join(",")));
+
+    format!("{{{}}}", fields.join(","))
+}
+
+fn error_response(status: u16, description: &str) -> String {
+    format!(
+        r##""{}":{{"description":{},"content":{{"application/json":{{"schema":{{"$ref":"#/components/schemas/Error"}}}}}}}}"##,
+        status,
+        Json::wrap_string(description)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::json::parse_value;
+
+    #[test]
+    fn test_openapi_is_json_with_login_path() {
+        let doc = parse_value(&document()).expect("valid JSON");
+        let root = doc.as_object().unwrap();
+        assert!(root.get("openapi").and_then(|v| v.as_str()).unwrap().starts_with("3."));
+        let paths = root.get("paths").and_then(|v| v.as_object()).unwrap();
+        let login = paths.get("/api/auth/login").and_then(|v| v.as_object()).expect("login path");
+        assert!(login.contains_key("post"));
+        let doc_path = paths.get("/api/collections/{name}/{id}").and_then(|v| v.as_object()).unwrap();
+        assert_eq!(doc_path.len(), 4);
+    }
+}
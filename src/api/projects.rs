@@ -1,8 +1,9 @@
 //! Projects API handlers
 use std::fs;
-use crate::config;
+use std::path::{Path, PathBuf};
+use crate::{config, logging, template};
 use crate::api::{Request, Response};
-use crate::api::json::parse_json;
+use crate::api::json::{parse_json, JsonSerializer as Json};
 use crate::api::utils::require_admin;
 use crate::ports;
 use crate::crypto::hash_password;
@@ -10,27 +11,8 @@ use crate::db::{self, Document, Value};
 
 pub fn list_projects(req: &Request) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
-    
-    let projects_dir = config::root_dir().join("projects");
-    if !projects_dir.exists() {
-        return Response::ok("[]");
-    }
-
-    let mut projects = Vec::new();
-    if let Ok(entries) = fs::read_dir(projects_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name != "_template" {
-                        projects.push(format!("\"{}\"", name));
-                    }
-                }
-            }
-        }
-    }
-    projects.sort();
-    Response::ok(&format!("[{}]", projects.join(",")))
+    let names: Vec<String> = project_names().iter().map(|name| Json::wrap_string(name)).collect();
+    Response::ok(&format!("[{}]", names.join(",")))
 }
 
 pub fn create_project(req: &Request) -> Response {
@@ -38,118 +20,353 @@ pub fn create_project(req: &Request) -> Response {
     
     let json = parse_json(&req.body);
     let name = json.get("name").and_then(|v| v.as_str()).unwrap_or("");
-    
-    if name.is_empty() || name.starts_with('_') || name.contains('/') || name.contains('.') {
-        return Response::bad_request("Invalid project name");
+
+    match create_project_named(name) {
+        Ok(info) => Response::created(&format!(r#"{{"name":"{}","success":true}}"#, info.name)),
+        Err(e) => Response::bad_request(&e),
     }
+}
 
-    let root = config::root_dir();
-    let projects_dir = root.join("projects");
-    let template_dir = projects_dir.join("_template");
-    let target_dir = projects_dir.join(name);
+/// Result of a successful project creation
+pub struct ProjectInfo {
+    pub name: String,
+    pub dev_port: u16,
+    pub prod_port: u16,
+}
 
-    if target_dir.exists() {
-        return Response::bad_request("Project already exists");
-    }
+/// Create a project from the template: register it, clone files, assign
+/// dev/prod ports, create the derived collections and bake the nav/footer
+/// into index.html. Shared by the HTTP API and the AI agent tool.
+pub fn create_project_named(name: &str) -> Result<ProjectInfo, String> {
+    create_project_in(&config::root_dir(), name)
+}
+
+pub fn create_project_in(root: &Path, name: &str) -> Result<ProjectInfo, String> {
+    let template_dir = root.join("projects").join("_template");
+    let project = reserve(root, name)?;
 
     if !template_dir.exists() {
-        return Response::bad_request("Template not found");
+        release(project);
+        return Err("Template not found".into());
     }
 
-    // Assign dev/prod ports
-    let settings = db::get().find_all("_settings");
-    let (dev_start, dev_end, prod_start, prod_end) = if let Some(doc) = settings.first() {
-        (
-            match doc.get("dev_port_start") { Some(Value::Int(v)) => *v as u16, _ => 3501 },
-            match doc.get("dev_port_end") { Some(Value::Int(v)) => *v as u16, _ => 3599 },
-            match doc.get("prod_port_start") { Some(Value::Int(v)) => *v as u16, _ => 3601 },
-            match doc.get("prod_port_end") { Some(Value::Int(v)) => *v as u16, _ => 3699 },
-        )
-    } else {
-        (3501, 3599, 3601, 3699)
-    };
+    if let Err(e) = copy_dir(&template_dir, &project.dir) {
+        release(project);
+        return Err(format!("Failed to clone template: {}", e));
+    }
 
-    let (dev_port, prod_port) = match ports::find_free_port_pair(dev_start, dev_end, prod_start, prod_end) {
-        Some(pair) => pair,
-        None => return Response::bad_request("No free ports available"),
-    };
+    if config::seeding_enabled() {
+        ensure_default_dev_user();
+    }
+    ensure_project_collections(name);
 
-    if let Err(e) = copy_dir(&template_dir, &target_dir) {
-        return Response::bad_request(&format!("Failed to clone template: {}", e));
+    if let Err(e) = render_files(&project.dir, name, config::admin_panel_path().as_deref()) {
+        logging::warn("projects", &format!("rendering {} failed: {}", name, e));
     }
 
-    ensure_default_dev_user();
-    ensure_project_collections(name);
+    Ok(activate(project))
+}
 
-    let mut port_doc = Document::new();
-    port_doc.insert("project".into(), Value::String(name.to_string()));
-    port_doc.insert("dev_port".into(), Value::Int(dev_port as i64));
-    port_doc.insert("prod_port".into(), Value::Int(prod_port as i64));
-    port_doc.insert("created".into(), Value::Int(db::now()));
-    let _ = db::get().insert("_ports", port_doc);
+/// `POST /api/projects/{name}/rebuild`: re-render the project's pages with
+/// the current nav, footer and admin link, so template fixes reach projects
+/// created before them.
+pub fn rebuild_project(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+    let dir = config::root_dir().join("projects").join(name);
+    if !project_names().iter().any(|p| p == name) || !dir.is_dir() {
+        return Response::not_found();
+    }
+    match render_files(&dir, name, config::admin_panel_path().as_deref()) {
+        Ok(files) => {
+            let files: Vec<String> = files.iter().map(|f| Json::wrap_string(f)).collect();
+            Response::ok(&format!(r#"{{"name":{},"rebuilt":[{}]}}"#, Json::wrap_string(name), files.join(",")))
+        }
+        Err(e) => Response::bad_request(&format!("Failed to rebuild project: {}", e)),
+    }
+}
 
-    let index_path = target_dir.join("index.html");
-    if let Ok(mut content) = fs::read_to_string(&index_path) {
-        let project_nav = format!(
-            r#"<nav class="fixed top-0 w-full border-b border-border bg-background/80 backdrop-blur-sm z-50">
+const PROJECT_NAV: &str = r#"<nav class="fixed top-0 w-full border-b border-border bg-background/80 backdrop-blur-sm z-50">
         <div class="max-w-5xl mx-auto px-6 h-16 flex items-center justify-between">
-            <a href="/projects/{name}/" class="font-semibold flex items-center gap-2">
+            <a href="/projects/{{ name }}/" class="font-semibold flex items-center gap-2">
                 <span class="text-xl">🦀</span>
-                <span>{name}</span>
+                <span>{{ name }}</span>
             </a>
             <div class="flex items-center gap-4 text-sm text-muted-foreground">
-                <span>Project: {name}</span>
+                <span>Project: {{ name }}</span>
                 <a href="/docs" class="hover:text-foreground transition-colors font-medium text-blue-400">Docs</a>
                 <a href="/" class="hover:text-foreground transition-colors">Home</a>
             </div>
         </div>
-    </nav>"#,
-            name = name
-        );
+    </nav>"#;
 
-        let project_footer = format!(
-            r#"<footer class="py-8 px-6 border-t border-border">
+const PROJECT_FOOTER: &str = r#"<footer class="py-8 px-6 border-t border-border">
         <div class="max-w-5xl mx-auto flex flex-col md:flex-row items-center justify-between gap-4 text-sm text-muted-foreground">
-            <p>© 2024 Project {name}. All rights reserved.</p>
+            <p>© 2024 Project {{ name }}. All rights reserved.</p>
             <div class="flex items-center gap-4">
                 <p>Built with Rust 🦀</p>
-                <a href="/_admin" class="hover:text-foreground transition-colors">Admin</a>
+                {{ admin_link }}
             </div>
         </div>
-    </footer>"#,
-            name = name
-        );
+    </footer>"#;
 
-        content = content.replace("{% include \"components/nav.html\" %}", &project_nav);
-        content = content.replace("{% include \"components/footer.html\" %}", &project_footer);
-        content = content.replace("Hello World", name);
-        let _ = fs::write(&index_path, content);
+/// Render every `.html` file below `dir` with [`render_page`], writing the
+/// ones that changed. Returns their paths relative to `dir`, sorted.
+fn render_files(dir: &Path, name: &str, admin_path: Option<&str>) -> std::io::Result<Vec<String>> {
+    let mut changed = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+            let content = fs::read_to_string(&path)?;
+            let rendered = render_page(&content, name, admin_path);
+            if rendered != content {
+                fs::write(&path, rendered)?;
+                let rel = path.strip_prefix(dir).unwrap_or(&path);
+                changed.push(rel.to_string_lossy().into_owned());
+            }
+        }
     }
+    changed.sort();
+    Ok(changed)
+}
+
+/// Substitute a project page: the nav and footer includes (or blocks a
+/// previous render left behind) become the project's own markup, and the
+/// template's "Hello World" its name. Rendering twice changes nothing.
+fn render_page(content: &str, name: &str, admin_path: Option<&str>) -> String {
+    let mut ctx = template::Context::new();
+    ctx.set("name", name);
+    let admin_link = admin_path.map(|path| format!(
+        r#"<a href="{}" class="hover:text-foreground transition-colors">Admin</a>"#,
+        template::escape_html(path)
+    ));
+    ctx.set_raw("admin_link", &admin_link.unwrap_or_default());
+    let content = replace_block(content, "nav", &ctx.apply(PROJECT_NAV));
+    let content = replace_block(&content, "footer", &ctx.apply(PROJECT_FOOTER));
+    content.replace("Hello World", name)
+}
 
-    Response::created(&format!(r#"{{"name":"{}","success":true}}"#, name))
+/// Replace `{% include "components/{key}.html" %}` and any block between
+/// `<!-- project:{key} -->` markers with `markup`, wrapped in those markers.
+fn replace_block(content: &str, key: &str, markup: &str) -> String {
+    let (open, close) = (format!("<!-- project:{} -->", key), format!("<!-- /project:{} -->", key));
+    let block = format!("{}{}{}", open, markup, close);
+    let mut out = content.replace(&format!("{{% include \"components/{}.html\" %}}", key), &block);
+    let mut from = 0;
+    while let Some(start) = out[from..].find(&open).map(|i| from + i) {
+        let Some(end) = out[start..].find(&close).map(|i| start + i + close.len()) else { break };
+        out.replace_range(start..end, &block);
+        from = start + block.len();
+    }
+    out
+}
+
+/// A project claimed in the registry (status `creating`) with its ports
+/// picked. Finish with [`activate`], or undo with [`release`].
+pub struct Reservation {
+    registry_id: String,
+    pub name: String,
+    pub dir: PathBuf,
+    pub dev_port: u16,
+    pub prod_port: u16,
+}
+
+/// Claim `name` in the registry and pick a free dev/prod port pair.
+pub fn reserve(root: &Path, name: &str) -> Result<Reservation, String> {
+    if !valid_project_name(name) {
+        return Err("Invalid project name".into());
+    }
+    let dir = root.join("projects").join(name);
+    if dir.exists() {
+        return Err("Project already exists".into());
+    }
+
+    // Claim the name first; the unique index refuses a second registration
+    let mut entry = Document::new();
+    entry.insert("name".into(), Value::String(name.to_string()));
+    entry.insert("created".into(), Value::Int(db::now()));
+    entry.insert("status".into(), Value::String("creating".into()));
+    let Ok(registry_id) = db::get().try_insert(REGISTRY, entry) else {
+        return Err("Project already exists".into());
+    };
+
+    // Assign dev/prod ports
+    let settings = db::get().find_all("_settings");
+    let (dev_start, dev_end, prod_start, prod_end) = if let Some(doc) = settings.first() {
+        (
+            match doc.get("dev_port_start") { Some(Value::Int(v)) => *v as u16, _ => 3501 },
+            match doc.get("dev_port_end") { Some(Value::Int(v)) => *v as u16, _ => 3599 },
+            match doc.get("prod_port_start") { Some(Value::Int(v)) => *v as u16, _ => 3601 },
+            match doc.get("prod_port_end") { Some(Value::Int(v)) => *v as u16, _ => 3699 },
+        )
+    } else {
+        (3501, 3599, 3601, 3699)
+    };
+
+    let Some((dev_port, prod_port)) = ports::find_free_port_pair(dev_start, dev_end, prod_start, prod_end) else {
+        db::get().delete(REGISTRY, &registry_id);
+        return Err("No free ports available".into());
+    };
+
+    Ok(Reservation { registry_id, name: name.to_string(), dir, dev_port, prod_port })
+}
+
+/// Record the reserved ports in `_ports` and mark the project `active`.
+pub fn activate(project: Reservation) -> ProjectInfo {
+    let mut port_doc = Document::new();
+    port_doc.insert("project".into(), Value::String(project.name.clone()));
+    port_doc.insert("dev_port".into(), Value::Int(project.dev_port as i64));
+    port_doc.insert("prod_port".into(), Value::Int(project.prod_port as i64));
+    port_doc.insert("created".into(), Value::Int(db::now()));
+    let _ = db::get().insert("_ports", port_doc);
+
+    let mut active = Document::new();
+    active.insert("dev_port".into(), Value::Int(project.dev_port as i64));
+    active.insert("prod_port".into(), Value::Int(project.prod_port as i64));
+    active.insert("status".into(), Value::String("active".into()));
+    db::get().update(REGISTRY, &project.registry_id, active);
+
+    ProjectInfo { name: project.name, dev_port: project.dev_port, prod_port: project.prod_port }
+}
+
+/// Undo a failed setup: the folder, any derived collections and the entry.
+pub fn release(project: Reservation) {
+    let _ = fs::remove_dir_all(&project.dir);
+    cleanup_project_collections(&project.name);
+    db::get().delete(REGISTRY, &project.registry_id);
 }
 
 pub fn delete_project(req: &Request, name: &str) -> Response {
     if !require_admin(req) { return Response::unauthorized(); }
-    
-    if name.starts_with('_') || name.contains('/') || name.contains('.') {
-        return Response::bad_request("Invalid project name");
+
+    match delete_project_named(name) {
+        Ok(true) => Response::ok(r#"{"deleted":true}"#),
+        Ok(false) => Response::not_found(),
+        Err(e) => Response::bad_request(&e),
     }
+}
+
+/// Who may reach a project through the reverse proxy (`access` on `_ports`)
+const PROXY_ACCESS: [&str; 3] = ["public", "admin", "restricted"];
 
-    let target_dir = config::root_dir().join("projects").join(name);
-    if !target_dir.exists() || !target_dir.is_dir() {
+/// Update a project's reverse proxy settings, stored on its `_ports` entry.
+/// Body: `{"rewrite_host": bool, "access": "public"|"admin"|"restricted",
+/// "allow_roles": [..], "allow_users": [..]}`; omitted keys are left unchanged.
+pub fn update_proxy_settings(req: &Request, name: &str) -> Response {
+    if !require_admin(req) { return Response::unauthorized(); }
+
+    let db = db::get();
+    let Some(id) = db.find_by("_ports", "project", name)
+        .and_then(|doc| doc.get("id").and_then(Value::as_str).map(String::from)) else {
         return Response::not_found();
+    };
+
+    let json = parse_json(&req.body);
+    let mut updates = Document::new();
+    match json.get("rewrite_host") {
+        Some(Value::Bool(on)) => { updates.insert("rewrite_host".into(), Value::Bool(*on)); }
+        Some(_) => return Response::bad_request("rewrite_host must be a boolean"),
+        None => {}
+    }
+    match json.get("access") {
+        Some(Value::String(access)) if PROXY_ACCESS.contains(&access.as_str()) => {
+            updates.insert("access".into(), Value::String(access.clone()));
+        }
+        Some(_) => return Response::bad_request("access must be public, admin or restricted"),
+        None => {}
     }
+    for key in ["allow_roles", "allow_users"] {
+        match json.get(key) {
+            Some(Value::Array(items)) if items.iter().all(|v| v.as_str().is_some()) => {
+                updates.insert(key.into(), Value::Array(items.clone()));
+            }
+            Some(_) => return Response::bad_request(&format!("{} must be an array of strings", key)),
+            None => {}
+        }
+    }
+    db.update("_ports", &id, updates);
 
-    if let Err(e) = fs::remove_dir_all(target_dir) {
-        return Response::bad_request(&format!("Failed to delete project: {}", e));
+    let doc = db.find_one("_ports", &id).unwrap_or_default();
+    let list = |key: &str| doc.get(key).map_or("[]".to_string(), db::value_to_json);
+    Response::ok(&format!(
+        r#"{{"project":"{}","rewrite_host":{},"access":"{}","allow_roles":{},"allow_users":{}}}"#,
+        name,
+        matches!(doc.get("rewrite_host"), Some(Value::Bool(true))),
+        doc.get("access").and_then(Value::as_str).unwrap_or("admin"),
+        list("allow_roles"),
+        list("allow_users")
+    ))
+}
+
+/// Delete a project: its folder, ports, derived collections and registry
+/// entry. A folder without an entry (or the reverse) is still cleaned up.
+/// Returns Ok(false) when neither exists.
+pub fn delete_project_named(name: &str) -> Result<bool, String> {
+    delete_project_in(&config::root_dir(), name)
+}
+
+pub fn delete_project_in(root: &Path, name: &str) -> Result<bool, String> {
+    if !valid_project_name(name) {
+        return Err("Invalid project name".into());
+    }
+
+    let target_dir = root.join("projects").join(name);
+    let entry = db::get().find_by(REGISTRY, "name", name);
+    if entry.is_none() && !target_dir.is_dir() {
+        return Ok(false);
+    }
+
+    if target_dir.is_dir() {
+        if let Err(e) = fs::remove_dir_all(target_dir) {
+            return Err(format!("Failed to delete project: {}", e));
+        }
     }
 
     // Cleanup ports assignment
     cleanup_project_ports(name);
     cleanup_project_collections(name);
+    if let Some(id) = entry.as_ref().and_then(|doc| doc.get("id")).and_then(Value::as_str) {
+        db::get().delete(REGISTRY, id);
+    }
+
+    Ok(true)
+}
+
+/// The registry collection: which projects exist. Folders, `_ports` rows
+/// and `dev-{name}_*` collections are derived from it.
+const REGISTRY: &str = "_projects";
+
+/// Registered project names, sorted.
+pub fn project_names() -> Vec<String> {
+    let mut names: Vec<String> = db::get()
+        .find_all(REGISTRY)
+        .iter()
+        .filter_map(|doc| doc.get("name").and_then(Value::as_str).map(str::to_string))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Project names map directly to folders under projects/ and into the derived
+/// `dev-{name}_*` collection names, so both must pass `valid_collection_name`;
+/// the reserved `_template` style is rejected too.
+pub fn valid_project_name(name: &str) -> bool {
+    db::valid_collection_name(name)
+        && !name.starts_with('_')
+        && db::PROJECT_COLLECTIONS.iter().all(|suffix| db::valid_collection_name(&project_collection(name, suffix)))
+}
 
-    Response::ok(r#"{"deleted":true}"#)
+/// Name of a project's derived collection, e.g. `dev-shop_users`.
+pub fn project_collection(project: &str, suffix: &str) -> String {
+    format!("dev-{}_{}", project, suffix)
 }
 
 fn cleanup_project_ports(project: &str) {
@@ -182,10 +399,11 @@ fn ensure_default_dev_user() {
     let _ = db.insert("_users", doc);
 }
 
-fn ensure_project_collections(project: &str) {
-    let users = format!("dev-{}_users", project);
-    let sessions = format!("dev-{}_sessions", project);
-    let settings = format!("dev-{}_settings", project);
+/// Create whichever of the project's derived collections are missing or empty.
+pub fn ensure_project_collections(project: &str) {
+    let users = project_collection(project, "users");
+    let sessions = project_collection(project, "sessions");
+    let settings = project_collection(project, "settings");
 
     if db::get().find_all(&users).is_empty() {
         db::get().create_collection(&users, vec![
@@ -215,16 +433,12 @@ fn ensure_project_collections(project: &str) {
 }
 
 fn cleanup_project_collections(project: &str) {
-    let users = format!("dev-{}_users", project);
-    let sessions = format!("dev-{}_sessions", project);
-    let settings = format!("dev-{}_settings", project);
-    let _ = db::get().delete_collection(&users);
-    let _ = db::get().delete_collection(&sessions);
-    let _ = db::get().delete_collection(&settings);
+    for suffix in db::PROJECT_COLLECTIONS {
+        let _ = db::get().delete_collection(&project_collection(project, suffix));
+    }
 }
 
-
-fn copy_dir(src: &std::path::Path, dst: &std::path::Path) -> std::io::Result<()> {
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
     fs::create_dir_all(dst)?;
     for entry in fs::read_dir(src)? {
         let entry = entry?;
@@ -237,3 +451,83 @@ fn copy_dir(src: &std::path::Path, dst: &std::path::Path) -> std::io::Result<()>
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_project_name() {
+        assert!(valid_project_name("shop"));
+        assert!(valid_project_name("my-site2"));
+        assert!(!valid_project_name(""));
+        assert!(!valid_project_name("_template"));
+        assert!(!valid_project_name("../etc"));
+        assert!(!valid_project_name("a/b"));
+        assert!(!valid_project_name("site.com"));
+        assert!(!valid_project_name("my shop"));
+        assert!(!valid_project_name("café"));
+        // dev-{name}_sessions must still fit the collection name limit
+        assert!(valid_project_name(&"p".repeat(db::MAX_COLLECTION_NAME - "dev-_sessions".len())));
+        assert!(!valid_project_name(&"p".repeat(db::MAX_COLLECTION_NAME - "dev-_sessions".len() + 1)));
+    }
+
+    #[test]
+    fn test_registry_follows_create_and_delete() {
+        db::init_in_memory("projects-test");
+        let root = std::env::temp_dir().join(format!("rpw-projects-{}", std::process::id()));
+        fs::create_dir_all(root.join("projects/_template")).unwrap();
+        fs::write(root.join("projects/_template/index.html"), "<h1>Hello World</h1>").unwrap();
+        let registered = || project_names().contains(&"reg-shop".to_string());
+
+        let info = create_project_in(&root, "reg-shop").unwrap();
+        let entry = db::get().find_by(REGISTRY, "name", "reg-shop").unwrap();
+        assert_eq!(entry.get("status").and_then(Value::as_str), Some("active"));
+        assert!(matches!(entry.get("dev_port"), Some(Value::Int(p)) if *p == info.dev_port as i64));
+        assert!(registered());
+        assert!(db::get().find_by("_ports", "project", "reg-shop").is_some());
+
+        // The folder is derived: losing it neither unregisters the project
+        // nor frees the name, and delete still cleans up the rest
+        fs::remove_dir_all(root.join("projects/reg-shop")).unwrap();
+        assert!(registered());
+        assert_eq!(create_project_in(&root, "reg-shop").err().as_deref(), Some("Project already exists"));
+        assert_eq!(delete_project_in(&root, "reg-shop"), Ok(true));
+        assert!(!registered());
+        assert!(db::get().find_by("_ports", "project", "reg-shop").is_none());
+        assert!(db::get().schema("dev-reg-shop_users").is_none());
+        assert_eq!(delete_project_in(&root, "reg-shop"), Ok(false));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_render_page_is_idempotent_and_follows_admin_path() {
+        let old = "{% include \"components/nav.html\" %}\n<h1>Hello World</h1>\n{% include \"components/footer.html\" %}";
+        let page = render_page(old, "shop", Some("/_admin"));
+        assert!(page.contains("Project: shop") && page.contains("<h1>shop</h1>"));
+        assert!(page.contains(r#"<a href="/_admin""#));
+        assert!(!page.contains("{% include"));
+        assert_eq!(render_page(&page, "shop", Some("/_admin")), page);
+
+        let moved = render_page(&page, "shop", Some("/ops"));
+        assert!(moved.contains(r#"<a href="/ops""#) && !moved.contains("/_admin"));
+        assert_eq!(moved.matches("<!-- project:footer -->").count(), 1);
+        assert!(!render_page(&page, "shop", None).contains(">Admin</a>"));
+    }
+
+    #[test]
+    fn test_rebuild_renders_pages_with_old_placeholders() {
+        let dir = std::env::temp_dir().join(format!("rpw-rebuild-{}", std::process::id()));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("index.html"), "{% include \"components/nav.html\" %}<main></main>").unwrap();
+        fs::write(dir.join("docs/intro.html"), "<p>no includes</p>").unwrap();
+        fs::write(dir.join("notes.txt"), "{% include \"components/nav.html\" %}").unwrap();
+
+        assert_eq!(render_files(&dir, "blog", Some("/_admin")).unwrap(), ["index.html"]);
+        let index = fs::read_to_string(dir.join("index.html")).unwrap();
+        assert!(index.starts_with("<!-- project:nav --><nav") && index.contains("Project: blog"));
+        assert!(fs::read_to_string(dir.join("notes.txt")).unwrap().starts_with("{% include"));
+        assert!(render_files(&dir, "blog", Some("/_admin")).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,19 @@
 //! AI Agent Tools execution
 use std::fs;
+use std::path::{Component, Path, PathBuf};
 use crate::db;
 use crate::config;
 use crate::api::json::JsonSerializer as Json;
 use crate::api::json::parse_json;
+use crate::api::projects;
 use crate::ports;
-use crate::db::Value;
+use crate::db::{CollectionKind, Database, Value};
+
+/// Collections the agent may never read (password hashes, session tokens),
+/// along with every project's copy of them
+const HIDDEN_COLLECTIONS: [&str; 2] = ["_users", "_sessions"];
+const DEFAULT_DOC_LIMIT: usize = 20;
+const MAX_DOC_LIMIT: usize = 100;
 
 /// Define available tools for Ollama
 pub fn get_tools_json() -> String {
@@ -46,6 +54,27 @@ pub fn get_tools_json() -> String {
                 }
             }
         },
+        {
+            "type": "function",
+            "function": {
+                "name": "get_documents",
+                "description": "Read documents from a collection",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "collection": {
+                            "type": "string",
+                            "description": "Collection name"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Maximum number of documents to return (default 20, max 100)"
+                        }
+                    },
+                    "required": ["collection"]
+                }
+            }
+        },
         {
             "type": "function",
             "function": {
@@ -75,6 +104,23 @@ pub fn get_tools_json() -> String {
                 }
             }
         },
+        {
+            "type": "function",
+            "function": {
+                "name": "delete_project",
+                "description": "Delete a project folder along with its ports and collections",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "Project folder name"
+                        }
+                    },
+                    "required": ["name"]
+                }
+            }
+        },
         {
             "type": "function",
             "function": {
@@ -113,14 +159,52 @@ pub fn call_tool(name: &str, args: &str) -> String {
         "list_collections" => list_collections(),
         "list_project_collections" => list_project_collections(),
         "list_system_collections" => list_system_collections(),
+        "get_documents" => get_documents(args),
         "list_projects" => list_projects(),
         "create_project" => create_project(args),
+        "delete_project" => delete_project(args),
         "find_free_ports" => find_free_ports(),
         "search_docs" => search_docs(args),
         _ => format!("{{\"error\":\"Unknown tool: {}\"}}", name),
     }
 }
 
+/// Resolve a root-relative path for tool filesystem access.
+/// Every tool read/write goes through here: absolute paths, `..` and
+/// anything outside `config::tool_allowed_dirs()` are rejected, and so is
+/// a path that a symlink carries out of its allowed directory.
+pub fn safe_path(rel: &str) -> Option<PathBuf> {
+    let rel = Path::new(rel);
+    if !is_plain_relative(rel) {
+        return None;
+    }
+    let root = config::root_dir();
+    let dir = config::tool_allowed_dirs()
+        .into_iter()
+        .find(|dir| is_plain_relative(Path::new(dir)) && rel.starts_with(dir))?;
+    let dir = root.join(dir);
+    let path = root.join(rel);
+    (contained(&root, &dir) && contained(&dir, &path)).then_some(path)
+}
+
+/// Whether `path` stays inside `dir` once both are canonicalized, as
+/// [`crate::urlpath::resolve_normalized`] checks. A path that does not
+/// exist yet is judged by its nearest existing ancestor; a `dir` that does
+/// not exist at all holds nothing a symlink could lead out of.
+fn contained(dir: &Path, path: &Path) -> bool {
+    let Ok(dir) = dir.canonicalize() else {
+        return fs::symlink_metadata(dir).is_err();
+    };
+    path.ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+        .is_some_and(|resolved| resolved.starts_with(&dir))
+}
+
+fn is_plain_relative(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
 fn list_collections() -> String {
     let db = db::get();
     let collections = db.list_collections();
@@ -132,7 +216,7 @@ fn list_collections() -> String {
     let system = db
         .list_all_collections()
         .iter()
-        .filter(|name| name.starts_with('_'))
+        .filter(|name| Database::collection_kind(name) == CollectionKind::System)
         .map(|name| Json::wrap_string(name))
         .collect::<Vec<_>>()
         .join(",");
@@ -155,33 +239,65 @@ fn list_system_collections() -> String {
     let collections = db.list_all_collections();
     let list = collections
         .iter()
-        .filter(|name| name.starts_with('_'))
+        .filter(|name| Database::collection_kind(name) == CollectionKind::System)
         .map(|name| Json::wrap_string(name))
         .collect::<Vec<_>>()
         .join(",");
     format!("{{\"system_collections\":[{}]}}", list)
 }
 
-fn list_projects() -> String {
-    let projects_dir = config::root_dir().join("projects");
-    if !projects_dir.exists() {
-        return "{\"projects\":[]}".to_string();
+fn get_documents(args_json: &str) -> String {
+    let json = parse_json(args_json);
+    let collection = json.get("collection").and_then(|v| v.as_str()).unwrap_or("");
+
+    if collection.is_empty() {
+        return "{\"error\":\"Missing collection parameter\"}".to_string();
     }
-    let mut projects = Vec::new();
-    if let Ok(entries) = fs::read_dir(projects_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name != "_template" {
-                        projects.push(name.to_string());
-                    }
-                }
-            }
-        }
+    if hidden(collection) {
+        return "{\"error\":\"Access denied\"}".to_string();
+    }
+    let db = db::get();
+    if !db.list_all_collections().iter().any(|c| c == collection) {
+        return format!("{{\"error\":{}}}", Json::wrap_string(&format!("Unknown collection: {}", collection)));
     }
-    projects.sort();
-    let list = projects
+
+    let limit = doc_limit(json.get("limit"));
+    let docs = db.find_all(collection);
+    let list = docs
+        .iter()
+        .take(limit)
+        .map(|doc| db::doc_to_json_for_collection(collection, doc))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"collection\":{},\"total\":{},\"documents\":[{}]}}",
+        Json::wrap_string(collection), docs.len(), list
+    )
+}
+
+/// `_users` itself or a project's `dev-{name}_users`, and the same for sessions.
+fn hidden(collection: &str) -> bool {
+    HIDDEN_COLLECTIONS.iter().any(|hidden| {
+        collection == *hidden
+            || (Database::collection_kind(collection) == CollectionKind::Project && collection.ends_with(hidden))
+    })
+}
+
+fn doc_limit(value: Option<&Value>) -> usize {
+    let requested = match value {
+        Some(Value::Int(n)) => *n,
+        Some(Value::Float(f)) => *f as i64,
+        Some(Value::String(s)) => s.trim().parse::<i64>().unwrap_or(DEFAULT_DOC_LIMIT as i64),
+        _ => DEFAULT_DOC_LIMIT as i64,
+    };
+    if requested <= 0 {
+        return DEFAULT_DOC_LIMIT;
+    }
+    (requested as usize).min(MAX_DOC_LIMIT)
+}
+
+fn list_projects() -> String {
+    let list = projects::project_names()
         .iter()
         .map(|name| Json::wrap_string(name))
         .collect::<Vec<_>>()
@@ -193,40 +309,32 @@ fn create_project(args_json: &str) -> String {
     let json = parse_json(args_json);
     let name = json.get("name").and_then(|v| v.as_str()).unwrap_or("");
 
-    if name.is_empty() || name.starts_with('_') || name.contains('/') || name.contains('.') {
-        return "{\"error\":\"Invalid project name\"}".to_string();
+    if safe_path(&format!("projects/{}", name)).is_none() {
+        return "{\"error\":\"Access denied\"}".to_string();
     }
 
-    let root = config::root_dir();
-    let projects_dir = root.join("projects");
-    let template_dir = projects_dir.join("_template");
-    let target_dir = projects_dir.join(name);
-
-    if target_dir.exists() {
-        return "{\"error\":\"Project already exists\"}".to_string();
+    match projects::create_project_named(name) {
+        Ok(info) => format!(
+            "{{\"name\":{},\"dev_port\":{},\"prod_port\":{},\"success\":true}}",
+            Json::wrap_string(&info.name), info.dev_port, info.prod_port
+        ),
+        Err(e) => format!("{{\"error\":{}}}", Json::wrap_string(&e)),
     }
-    if !template_dir.exists() {
-        return "{\"error\":\"Template not found\"}".to_string();
-    }
-    if let Err(e) = copy_dir(&template_dir, &target_dir) {
-        return format!("{{\"error\":\"Failed to clone template: {}\"}}", e);
-    }
-
-    format!("{{\"name\":{},\"success\":true}}", Json::wrap_string(name))
 }
 
-fn copy_dir(src: &std::path::Path, dst: &std::path::Path) -> std::io::Result<()> {
-    fs::create_dir_all(dst)?;
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_dir(&entry.path(), &dst.join(entry.file_name()))?;
-        } else {
-            fs::copy(entry.path(), dst.join(entry.file_name()))?;
-        }
+fn delete_project(args_json: &str) -> String {
+    let json = parse_json(args_json);
+    let name = json.get("name").and_then(|v| v.as_str()).unwrap_or("");
+
+    if safe_path(&format!("projects/{}", name)).is_none() {
+        return "{\"error\":\"Access denied\"}".to_string();
+    }
+
+    match projects::delete_project_named(name) {
+        Ok(true) => "{\"deleted\":true}".to_string(),
+        Ok(false) => "{\"error\":\"Project not found\"}".to_string(),
+        Err(e) => format!("{{\"error\":{}}}", Json::wrap_string(&e)),
     }
-    Ok(())
 }
 
 fn find_free_ports() -> String {
@@ -249,24 +357,30 @@ fn find_free_ports() -> String {
 }
 
 fn search_docs(args_json: &str) -> String {
-    // Basic JSON extraction without dependencies
-    let query = if let Some(start) = args_json.find("\"query\":") {
-        let rest = &args_json[start + 8..];
-        let rest = rest.trim_start_matches(':').trim_start_matches(' ').trim_start_matches('"');
-        if let Some(end) = rest.find('"') {
-            &rest[..end]
-        } else { "" }
-    } else { "" };
+    let json = parse_json(args_json);
+    let query = json.get("query").and_then(|v| v.as_str()).unwrap_or("");
 
     if query.is_empty() {
         return "{{\"error\":\"Missing query parameter\"}}".to_string();
     }
 
-    let docs_dir = config::templates_dir().join("docs");
+    let docs_dir = match safe_path("public/templates/docs") {
+        Some(p) => p,
+        None => return "{\"error\":\"Access denied\"}".to_string(),
+    };
+    format!("{{\"results\":[{}]}}", matching_docs(&docs_dir, query).join(","))
+}
+
+/// `{"file","preview"}` for each file directly in `dir` mentioning `query`.
+/// Entries leading out of `dir` through a symlink are skipped.
+fn matching_docs(dir: &Path, query: &str) -> Vec<String> {
     let mut results = Vec::new();
 
-    if let Ok(entries) = fs::read_dir(docs_dir) {
+    if let Ok(entries) = fs::read_dir(dir) {
         for entry in entries.flatten() {
+            if !contained(dir, &entry.path()) {
+                continue;
+            }
             if let Ok(content) = fs::read_to_string(entry.path()) {
                 if content.to_lowercase().contains(&query.to_lowercase()) {
                     if let Some(name) = entry.file_name().to_str() {
@@ -275,8 +389,7 @@ fn search_docs(args_json: &str) -> String {
                             .take(100)
                             .collect::<String>()
                             .replace('"', "'")
-                            .replace('\n', " ")
-                            .replace('\r', " ");
+                            .replace(['\n', '\r'], " ");
                         let item = format!(
                             "{{{},{}}}",
                             Json::key_string("file", name),
@@ -288,6 +401,76 @@ fn search_docs(args_json: &str) -> String {
             }
         }
     }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_are_hidden_in_every_project() {
+        for name in ["_users", "_sessions", "dev-shop_users", "dev-shop_sessions"] {
+            assert!(hidden(name), "{}", name);
+        }
+        for name in ["_settings", "dev-shop_settings", "users", "my_users", "dev-_users"] {
+            assert!(!hidden(name), "{}", name);
+        }
+        assert_eq!(get_documents(r#"{"collection":"dev-shop_users"}"#), r#"{"error":"Access denied"}"#);
+    }
 
-    format!("{{\"results\":[{}]}}", results.join(","))
+    #[test]
+    fn test_doc_limit() {
+        assert_eq!(doc_limit(None), DEFAULT_DOC_LIMIT);
+        assert_eq!(doc_limit(Some(&Value::Int(5))), 5);
+        assert_eq!(doc_limit(Some(&Value::String("7".into()))), 7);
+        assert_eq!(doc_limit(Some(&Value::Int(0))), DEFAULT_DOC_LIMIT);
+        assert_eq!(doc_limit(Some(&Value::Int(-3))), DEFAULT_DOC_LIMIT);
+        assert_eq!(doc_limit(Some(&Value::Int(5000))), MAX_DOC_LIMIT);
+        assert_eq!(doc_limit(Some(&Value::Bool(true))), DEFAULT_DOC_LIMIT);
+    }
+
+    #[test]
+    fn test_safe_path_allows_configured_dirs() {
+        assert!(safe_path("projects/demo/index.html").is_some());
+        assert!(safe_path("public/templates/docs/intro.html").is_some());
+    }
+
+    #[test]
+    fn test_safe_path_rejects_traversal() {
+        assert!(safe_path("../etc/passwd").is_none());
+        assert!(safe_path("projects/../../etc/passwd").is_none());
+        assert!(safe_path("projects/demo/../../.env.local").is_none());
+        assert!(safe_path("/etc/passwd").is_none());
+        assert!(safe_path("").is_none());
+    }
+
+    #[test]
+    fn test_symlinks_out_of_a_directory_are_refused() {
+        let base = std::env::temp_dir().join(format!("rpw-tools-{}", std::process::id()));
+        let docs = base.join("docs");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(docs.join("intro.html"), "welcome aboard").unwrap();
+        fs::write(base.join("secret.txt"), "welcome SECRET_KEY=x").unwrap();
+        std::os::unix::fs::symlink(base.join("secret.txt"), docs.join("leak.html")).unwrap();
+        std::os::unix::fs::symlink(&base, docs.join("up")).unwrap();
+
+        assert!(contained(&docs, &docs.join("intro.html")));
+        assert!(contained(&docs, &docs.join("new/page.html")));
+        assert!(!contained(&docs, &docs.join("leak.html")));
+        assert!(!contained(&docs, &docs.join("up/new.html")));
+        assert!(contained(&docs.join("missing"), &docs.join("missing/x")));
+        let found = matching_docs(&docs, "welcome");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].contains("intro.html"));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_safe_path_rejects_outside_allowlist() {
+        assert!(safe_path(".env.local").is_none());
+        assert!(safe_path("data/db.bin").is_none());
+        assert!(safe_path("projects-evil/x").is_none());
+    }
 }
@@ -1,21 +1,22 @@
 use crate::auth;
 use crate::api::Request;
 use crate::config;
+use crate::db::{CollectionKind, Database};
 
 // Re-export validation helpers from auth (single source of truth)
-pub use crate::auth::{valid_email, valid_password, valid_role};
+pub use crate::auth::{valid_email, valid_role};
 
 pub fn load_env(key: &str) -> Option<String> {
     config::load_env(key)
 }
 
 pub fn get_token(req: &Request) -> String {
-    req.headers.get("authorization")
-        .map(|h| h.trim_start_matches("Bearer ").to_string())
-        .or_else(|| req.headers.get("cookie")
-            .and_then(|c| c.split(';').find(|p| p.trim().starts_with("token=")))
-            .map(|p| p.trim().trim_start_matches("token=").to_string()))
-        .unwrap_or_default()
+    auth::request_token(&req.headers)
+}
+
+/// Id of the user behind the request's token, if it is valid.
+pub fn current_user_id(req: &Request) -> Option<String> {
+    auth::validate_token(&get_token(req))
 }
 
 pub fn require_auth(req: &Request) -> bool {
@@ -23,9 +24,55 @@ pub fn require_auth(req: &Request) -> bool {
 }
 
 pub fn require_admin(req: &Request) -> bool {
-    auth::is_admin(&get_token(req))
+    req.signed || auth::is_admin(&get_token(req))
 }
 
 pub fn is_private_collection(name: &str) -> bool {
-    name.starts_with('_')
+    Database::collection_kind(name) == CollectionKind::System
+}
+
+/// First value of `key` in a query string, percent-decoded.
+pub fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then(|| url_decode(v))
+    })
+}
+
+/// Decode `%XX` escapes and `+`; malformed escapes are kept literally.
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+            (None, b'+') => out.push(b' '),
+            (None, b) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_param_decodes_values() {
+        let query = "mine=true&q=caf%C3%A9+bar&bad=100%";
+        assert_eq!(query_param(query, "mine").as_deref(), Some("true"));
+        assert_eq!(query_param(query, "q").as_deref(), Some("café bar"));
+        assert_eq!(query_param(query, "bad").as_deref(), Some("100%"));
+        assert_eq!(query_param(query, "missing"), None);
+    }
 }
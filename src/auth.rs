@@ -1,40 +1,53 @@
 //! Authentication system - register, login, sessions
-use crate::crypto::{hash_password, verify_password, random_hex};
+use crate::crypto::{constant_time_eq, hash_password, hex_encode, hmac_sha256, needs_rehash, verify_password, random_hex};
+use crate::api::errors::ErrorCode;
+use crate::config::{self, PasswordPolicy};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 use crate::db::{self, Document, Value};
+use crate::logging;
 
 const SESSION_DURATION: i64 = 86400 * 7; // 7 days
 
+/// Lifetime of a signed URL when the caller does not ask for one
+pub const SIGNED_URL_TTL: i64 = 15 * 60;
+/// Longest lifetime a signed URL can be given
+pub const MAX_SIGNED_URL_TTL: i64 = 86400;
+
+/// Refused when the policy enables the blocklist; see [`common_passwords`].
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
 pub struct AuthResult {
     pub success: bool,
     pub token: Option<String>,
     pub user_id: Option<String>,
-    pub error: Option<String>,
+    pub error: Option<ErrorCode>,
+    /// Replaces the code's default message when set
+    pub message: Option<String>,
 }
 
 impl AuthResult {
     fn ok(token: String, user_id: String) -> Self {
-        Self { success: true, token: Some(token), user_id: Some(user_id), error: None }
+        Self { success: true, token: Some(token), user_id: Some(user_id), error: None, message: None }
     }
-    fn err(msg: &str) -> Self {
-        Self { success: false, token: None, user_id: None, error: Some(msg.into()) }
+    fn err(code: ErrorCode) -> Self {
+        Self { success: false, token: None, user_id: None, error: Some(code), message: None }
+    }
+    fn weak_password(message: String) -> Self {
+        Self { message: Some(message), ..Self::err(ErrorCode::WeakPassword) }
     }
 }
 
 /// Register new user
 pub fn register(email: &str, password: &str) -> AuthResult {
     let db = db::get();
+    let email = &normalize_email(email);
 
-    // Check if email exists
-    if db.find_by("_users", "email", email).is_some() {
-        return AuthResult::err("Email already registered");
-    }
-
-    // Validate
     if !valid_email(email) {
-        return AuthResult::err("Invalid email");
+        return AuthResult::err(ErrorCode::InvalidEmail);
     }
-    if !valid_password(password) {
-        return AuthResult::err("Password must be at least 8 characters");
+    if let Err(message) = password_strength(password) {
+        return AuthResult::weak_password(message);
     }
 
     // Create user (first user is admin)
@@ -44,12 +57,14 @@ pub fn register(email: &str, password: &str) -> AuthResult {
     doc.insert("password".into(), Value::String(hash_password(password)));
     doc.insert("role".into(), Value::String(if is_first { "admin" } else { "user" }.into()));
 
-    match db.insert("_users", doc) {
-        Some(user_id) => {
+    // `_users.email` is unique, so a taken address is refused here
+    match db.try_insert("_users", doc) {
+        Ok(user_id) => {
             let token = create_session(&user_id);
             AuthResult::ok(token, user_id)
         }
-        None => AuthResult::err("Failed to create user"),
+        Err(db::WriteError::Unique(field)) if field == "email" => AuthResult::err(ErrorCode::EmailTaken),
+        Err(_) => AuthResult::err(ErrorCode::WriteFailed),
     }
 }
 
@@ -57,29 +72,71 @@ pub fn register(email: &str, password: &str) -> AuthResult {
 pub fn login(email: &str, password: &str) -> AuthResult {
     let db = db::get();
 
-    let user = match db.find_by("_users", "email", email) {
+    let user = match db.find_by("_users", "email", &normalize_email(email)) {
         Some(u) => u,
-        None => return AuthResult::err("Invalid credentials"),
+        None => return AuthResult::err(ErrorCode::InvalidCredentials),
     };
 
     let stored_hash = match user.get("password").and_then(|v| v.as_str()) {
         Some(h) => h,
-        None => return AuthResult::err("Invalid credentials"),
+        None => return AuthResult::err(ErrorCode::InvalidCredentials),
     };
 
     if !verify_password(password, stored_hash) {
-        return AuthResult::err("Invalid credentials");
+        return AuthResult::err(ErrorCode::InvalidCredentials);
     }
 
     let user_id = match user.get("id").and_then(|v| v.as_str()) {
         Some(id) => id.to_string(),
-        None => return AuthResult::err("User corrupt"),
+        None => return AuthResult::err(ErrorCode::InvalidCredentials),
     };
 
+    // Move the hash to the configured scheme while the password is at hand
+    if needs_rehash(stored_hash) {
+        let mut update = Document::new();
+        update.insert("password".into(), Value::String(hash_password(password)));
+        db.update("_users", &user_id, update);
+    }
+
     let token = create_session(&user_id);
     AuthResult::ok(token, user_id)
 }
 
+/// Change the password of the user signed in with `token`, who must know
+/// the current one. With `end_other_sessions` every other session of theirs
+/// is logged out, so a token someone else holds stops working; `token`
+/// itself stays valid.
+pub fn change_password(token: &str, current: &str, new: &str, end_other_sessions: bool) -> AuthResult {
+    let db = db::get();
+    let Some(user_id) = validate_token(token) else {
+        return AuthResult::err(ErrorCode::InvalidSession);
+    };
+    let stored_hash = db.find_one("_users", &user_id).and_then(|u| u.get("password").and_then(|v| v.as_str()).map(str::to_string));
+    if !stored_hash.is_some_and(|hash| verify_password(current, &hash)) {
+        return AuthResult::err(ErrorCode::InvalidCredentials);
+    }
+    if let Err(message) = password_strength(new) {
+        return AuthResult::weak_password(message);
+    }
+
+    let mut update = Document::new();
+    update.insert("password".into(), Value::String(hash_password(new)));
+    if !db.update("_users", &user_id, update) {
+        return AuthResult::err(ErrorCode::WriteFailed);
+    }
+    if end_other_sessions {
+        for session in db.find_all("_sessions") {
+            let field = |name: &str| session.get(name).and_then(|v| v.as_str());
+            if field("user_id") == Some(user_id.as_str()) && field("token") != Some(token) {
+                if let Some(id) = field("id") {
+                    db.delete("_sessions", id);
+                }
+            }
+        }
+    }
+    AuthResult::ok(token.to_string(), user_id)
+}
+
 /// Create session token
 fn create_session(user_id: &str) -> String {
     let db = db::get();
@@ -91,31 +148,84 @@ fn create_session(user_id: &str) -> String {
     doc.insert("token".into(), Value::String(token.clone()));
     doc.insert("expires".into(), Value::Int(expires));
 
-    db.insert("_sessions", doc);
+    // `_expires_at` lets the sweeper drop sessions nobody presents again
+    let _ = db.insert_with_ttl("_sessions", doc, None, SESSION_DURATION);
     token
 }
 
 /// Validate session token, return user_id if valid
 pub fn validate_token(token: &str) -> Option<String> {
+    check_session(token, config::session_idle_timeout())
+}
+
+/// [`validate_token`] with an idle limit: a session unused for
+/// `idle_timeout` seconds ends even before it expires, and using it
+/// records `last_used` (see [`touch_interval`]).
+fn check_session(token: &str, idle_timeout: Option<i64>) -> Option<String> {
     let db = db::get();
     let session = db.find_by("_sessions", "token", token)?;
+    let id = session.get("id").and_then(|v| v.as_str())?;
 
     let expires = match session.get("expires") {
         Some(Value::Int(e)) => *e,
         _ => return None,
     };
+    // Sessions that were never touched have been idle since they began
+    let last_used = match session.get("last_used").or_else(|| session.get("created")) {
+        Some(Value::Int(t)) => *t,
+        _ => 0,
+    };
+    let idle = db::now() - last_used;
 
-    if expires < db::now() {
-        // Expired, delete session
-        if let Some(Value::String(id)) = session.get("id") {
-            db.delete("_sessions", id);
-        }
+    if expired(expires) || idle_timeout.is_some_and(|limit| idle >= limit) {
+        db.delete("_sessions", id);
         return None;
     }
+    if idle_timeout.is_some_and(|limit| idle >= touch_interval(limit)) {
+        let mut touch = Document::new();
+        touch.insert("last_used".into(), Value::Int(db::now()));
+        db.update("_sessions", id, touch);
+    }
 
     session.get("user_id").and_then(|v| v.as_str()).map(|s| s.to_string())
 }
 
+/// How stale `last_used` may get before a request rewrites it. Every write
+/// syncs db.bin, so sessions are touched at most every tenth of the idle
+/// window and never more than every five minutes; a session can end up to
+/// that much before a full window has passed since its last request.
+fn touch_interval(idle_timeout: i64) -> i64 {
+    (idle_timeout / 10).clamp(1, 300)
+}
+
+/// A session is good until its `expires` second, the same instant the
+/// sweeper deletes it through `_expires_at`.
+fn expired(expires: i64) -> bool {
+    db::now() >= expires
+}
+
+/// Rotate a session: the old token stops working and a new one with a fresh
+/// expiry is issued. Taking the old session is atomic, so a token can only be
+/// refreshed once.
+pub fn refresh(token: &str) -> AuthResult {
+    let db = db::get();
+    let Some(session) = db.take_by("_sessions", "token", token) else {
+        return AuthResult::err(ErrorCode::InvalidSession);
+    };
+    match session.get("expires") {
+        Some(Value::Int(expires)) if !expired(*expires) => {}
+        _ => return AuthResult::err(ErrorCode::SessionExpired),
+    }
+    let Some(user_id) = session.get("user_id").and_then(|v| v.as_str()) else {
+        return AuthResult::err(ErrorCode::InvalidSession);
+    };
+    if db.find_one("_users", user_id).is_none() {
+        return AuthResult::err(ErrorCode::InvalidSession);
+    }
+    let token = create_session(user_id);
+    AuthResult::ok(token, user_id.to_string())
+}
+
 /// Get current user from token
 pub fn get_user(token: &str) -> Option<Document> {
     let user_id = validate_token(token)?;
@@ -136,6 +246,94 @@ pub fn logout(token: &str) -> bool {
     false
 }
 
+/// What a request presents to authenticate.
+#[derive(Debug, PartialEq)]
+pub enum Credentials {
+    Missing,
+    Token(String),
+    /// An `Authorization` header that is not `Bearer <token>`
+    Malformed,
+}
+
+/// Credentials of a request: a `Bearer` `Authorization` header, else the
+/// `token` cookie. Any other `Authorization` header (say Basic, added by a
+/// proxy in front) is `Malformed` only when there is no cookie to fall
+/// back to.
+pub fn credentials(headers: &HashMap<String, String>) -> Credentials {
+    let header = headers.get("authorization");
+    if let Some(token) = header.and_then(|h| bearer(h)) {
+        return Credentials::Token(token.to_string());
+    }
+    match headers.get("cookie").and_then(|c| cookie(c, "token")) {
+        Some(token) if !token.is_empty() => Credentials::Token(token.to_string()),
+        _ if header.is_some() => Credentials::Malformed,
+        _ => Credentials::Missing,
+    }
+}
+
+/// Session token of a request, empty unless it presents a valid one.
+pub fn request_token(headers: &HashMap<String, String>) -> String {
+    match credentials(headers) {
+        Credentials::Token(token) => token,
+        Credentials::Missing | Credentials::Malformed => String::new(),
+    }
+}
+
+/// Token of an `Authorization: Bearer <token>` header; the scheme is
+/// case-insensitive. `None` for any other scheme, a bare token or an empty
+/// one.
+pub fn bearer(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    let token = token.trim_start();
+    let valid = scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() && !token.contains(char::is_whitespace);
+    valid.then_some(token)
+}
+
+/// Value of the cookie called exactly `name` in a `Cookie` header, unquoted.
+/// Pairs are split on `;` and trimmed; segments without `=` (a stray
+/// `Secure` or `HttpOnly`) are skipped, and the first match wins.
+pub fn cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| {
+            let value = value.trim();
+            value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+        })
+}
+
+/// `path` (which may carry a query) with `expires` and `sig` appended,
+/// letting a `method` request reach it without a session until the Unix
+/// second `expires`. `sig` is an HMAC-SHA256 over the method, the path and the
+/// expiry, so none of them can be changed without invalidating it.
+pub fn sign_url(method: &str, path: &str, expires: i64) -> String {
+    let separator = if path.contains('?') { '&' } else { '?' };
+    format!("{}{}expires={}&sig={}", path, separator, expires, url_signature(method, path, expires))
+}
+
+/// Whether a request for `path` with `query` carries a valid signature
+/// from [`sign_url`] that has not expired. The query's other parameters
+/// were part of what was signed and must appear as they were.
+pub fn verify_signed_url(method: &str, path: &str, query: &str) -> bool {
+    let (mut expires, mut sig, mut rest) = (None, None, Vec::new());
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some(("expires", value)) => expires = value.parse::<i64>().ok(),
+            Some(("sig", value)) => sig = Some(value),
+            _ => rest.push(pair),
+        }
+    }
+    let (Some(expires), Some(sig)) = (expires, sig) else { return false };
+    let signed = if rest.is_empty() { path.to_string() } else { format!("{}?{}", path, rest.join("&")) };
+    !expired(expires) && constant_time_eq(url_signature(method, &signed, expires).as_bytes(), sig.as_bytes())
+}
+
+fn url_signature(method: &str, path: &str, expires: i64) -> String {
+    let message = format!("{} {}\n{}", method.to_ascii_uppercase(), path, expires);
+    hex_encode(&hmac_sha256(config::url_signing_key(), message.as_bytes()))
+}
+
 /// Check if user has admin role
 pub fn is_admin(token: &str) -> bool {
     get_user(token)
@@ -143,16 +341,353 @@ pub fn is_admin(token: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Reject `_users` writes with an unknown role, whichever code path performs them.
+pub fn register_hooks() {
+    let db = db::get();
+    db.on_before_insert("_users", Box::new(check_role));
+    db.on_before_update("_users", Box::new(check_role));
+}
+
+fn check_role(doc: &mut Document) -> Result<(), String> {
+    match doc.get("role") {
+        Some(Value::String(role)) if valid_role(role) => Ok(()),
+        None => Ok(()),
+        _ => Err("Invalid role".into()),
+    }
+}
+
 // ── Validation helpers (single source of truth) ─────────────────────────────
 
+/// Emails are stored and looked up lowercased, so `User@Example.com` and
+/// `user@example.com` are one account.
+pub fn normalize_email(email: &str) -> String {
+    email.to_lowercase()
+}
+
+/// Lowercase `_users` emails stored before they were normalized. One that
+/// would collide with another account is left alone and logged, for an
+/// admin to resolve. Returns how many were changed.
+pub fn normalize_stored_emails() -> usize {
+    let db = db::get();
+    let mut changed = 0;
+    for user in db.find_all("_users") {
+        let (Some(id), Some(email)) = (user.get("id").and_then(|v| v.as_str()), user.get("email").and_then(|v| v.as_str())) else {
+            continue;
+        };
+        let normalized = normalize_email(email);
+        if normalized == email {
+            continue;
+        }
+        let mut update = Document::new();
+        update.insert("email".into(), Value::String(normalized));
+        match db.try_update("_users", id, update) {
+            Ok(true) => changed += 1,
+            Ok(false) => {}
+            Err(e) => logging::warn("auth", &format!("left email of user {} as is: {}", id, e)),
+        }
+    }
+    changed
+}
+
+/// Deliberately loose: one `@` with something before it, a dotted domain
+/// without a leading or trailing dot, and no whitespace anywhere.
 pub fn valid_email(email: &str) -> bool {
-    email.len() >= 3 && email.contains('@')
+    let Some((local, domain)) = email.split_once('@') else { return false };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace)
+}
+
+/// Check a new password against the configured [`PasswordPolicy`]. The error
+/// says what is missing, e.g. "Password must contain a digit and a symbol".
+pub fn password_strength(password: &str) -> Result<(), String> {
+    check_password(password, &PasswordPolicy::from_env())
+}
+
+fn check_password(password: &str, policy: &PasswordPolicy) -> Result<(), String> {
+    if password.chars().count() < policy.min_length {
+        return Err(format!("Password must be at least {} characters", policy.min_length));
+    }
+    let missing: Vec<&str> = policy
+        .require
+        .iter()
+        .filter(|class| !password.chars().any(|c| class.matches(c)))
+        .map(|class| class.describe())
+        .collect();
+    match missing.as_slice() {
+        [] => {}
+        [one] => return Err(format!("Password must contain {}", one)),
+        [rest @ .., last] => return Err(format!("Password must contain {} and {}", rest.join(", "), last)),
+    }
+    if policy.blocklist && common_passwords().contains(&password.to_lowercase()) {
+        return Err("Password is too common".into());
+    }
+    Ok(())
 }
 
-pub fn valid_password(password: &str) -> bool {
-    password.len() >= 8
+/// The bundled blocklist, lowercased, parsed on first use.
+fn common_passwords() -> &'static HashSet<String> {
+    static LIST: OnceLock<HashSet<String>> = OnceLock::new();
+    LIST.get_or_init(|| {
+        COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect()
+    })
 }
 
 pub fn valid_role(role: &str) -> bool {
     role == "admin" || role == "user"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::MockClock;
+    use crate::config::CharClass;
+    use crate::crypto::PasswordHasher;
+    use std::time::Duration;
+
+    fn policy(min_length: usize, require: Vec<CharClass>) -> PasswordPolicy {
+        PasswordPolicy { min_length, require, blocklist: false }
+    }
+
+    #[test]
+    fn test_password_length_rule() {
+        let p = policy(10, vec![]);
+        assert_eq!(check_password("aaaaaaaaa", &p), Err("Password must be at least 10 characters".into()));
+        assert_eq!(check_password("aaaaaaaaaa", &p), Ok(()));
+        // Characters, not bytes
+        assert!(check_password("ééééé", &policy(6, vec![])).is_err());
+    }
+
+    #[test]
+    fn test_password_class_rules() {
+        let p = policy(8, vec![CharClass::Letter, CharClass::Digit]);
+        assert_eq!(check_password("aaaaaaaa", &p), Err("Password must contain a digit".into()));
+        assert_eq!(check_password("12345678", &p), Err("Password must contain a letter".into()));
+
+        let p = policy(8, vec![CharClass::Lower, CharClass::Upper, CharClass::Digit, CharClass::Symbol]);
+        assert_eq!(
+            check_password("abcdefgh", &p),
+            Err("Password must contain an uppercase letter, a digit and a symbol".into())
+        );
+        assert_eq!(check_password("ABCDEFG1!", &p), Err("Password must contain a lowercase letter".into()));
+        assert_eq!(check_password("Abcdef1!", &p), Ok(()));
+    }
+
+    #[test]
+    fn test_password_blocklist() {
+        let off = policy(8, vec![CharClass::Letter, CharClass::Digit]);
+        let on = PasswordPolicy { blocklist: true, ..policy(8, vec![CharClass::Letter, CharClass::Digit]) };
+        assert_eq!(check_password("Password123", &off), Ok(()));
+        assert_eq!(check_password("Password123", &on), Err("Password is too common".into()));
+        assert_eq!(check_password("QWERTY123", &on), Err("Password is too common".into()));
+        assert_eq!(check_password("violet-harbor-42", &on), Ok(()));
+        assert!(!common_passwords().iter().any(|p| p.starts_with('#')));
+    }
+
+    #[test]
+    fn test_valid_email() {
+        for ok in ["a@b.co", "first.last+tag@mail.example.com", "o'neil@x.io", "u@sub-domain.co.uk"] {
+            assert!(valid_email(ok), "{}", ok);
+        }
+        for bad in ["@", "a@", "@b.co", "a@b", "a@@b.co", "a@b@c.co", "a@.b.co", "a@b.co.", "a b@c.co", "a@b.co\n", ""] {
+            assert!(!valid_email(bad), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_session_expires_exactly_at_boundary() {
+        db::init_in_memory("auth-test");
+        let clock = MockClock::install(1_700_000_000);
+        let token = create_session("clock-user");
+        clock.advance(Duration::from_secs(SESSION_DURATION as u64 - 1));
+        assert_eq!(validate_token(&token).as_deref(), Some("clock-user"));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(validate_token(&token), None);
+        // Refused and removed, like the sweeper would at this instant
+        assert!(db::get().find_by("_sessions", "token", &token).is_none());
+    }
+
+    #[test]
+    fn test_change_password() {
+        db::init_in_memory("auth-test");
+        // A one-round hash keeps the checks of the current password cheap
+        let cheap = |password: &str| Value::String(PasswordHasher::Pbkdf2 { iterations: 1 }.hash(password));
+        let mut user = Document::new();
+        user.insert("email".into(), Value::String(format!("change-{}@example.com", random_hex(4))));
+        user.insert("password".into(), cheap("Original-Pass-1"));
+        let user_id = db::get().insert("_users", user).unwrap();
+        let stored = || db::get().find_one("_users", &user_id).unwrap().get("password").and_then(|v| v.as_str()).map(str::to_string);
+        let (token, other) = (create_session(&user_id), create_session(&user_id));
+        let kept = |token: &str| validate_token(token).is_some();
+        let original = stored();
+
+        let wrong = change_password(&token, "Not-The-Pass-1", "Replacement-Pass-2", true);
+        assert_eq!(wrong.error, Some(ErrorCode::InvalidCredentials));
+        let weak = change_password(&token, "Original-Pass-1", "short", true);
+        assert_eq!(weak.error, Some(ErrorCode::WeakPassword));
+        assert_eq!(stored(), original);
+        assert!(kept(&other), "a refused change ends no sessions");
+        let nobody = change_password("no-such-token", "Original-Pass-1", "Replacement-Pass-2", true);
+        assert_eq!(nobody.error, Some(ErrorCode::InvalidSession));
+
+        assert!(change_password(&token, "Original-Pass-1", "Replacement-Pass-2", true).success);
+        assert_ne!(stored(), original);
+        assert!(kept(&token));
+        assert!(!kept(&other));
+
+        // Without ending sessions, the others stay signed in
+        let mut reset = Document::new();
+        reset.insert("password".into(), cheap("Replacement-Pass-2"));
+        db::get().update("_users", &user_id, reset);
+        let another = create_session(&user_id);
+        assert!(change_password(&token, "Replacement-Pass-2", "Third-Pass-3", false).success);
+        assert!(kept(&another));
+    }
+
+    #[test]
+    fn test_emails_are_case_insensitive() {
+        db::init_in_memory("auth-test");
+        let tag = random_hex(4);
+        let registered = register(&format!("Case-{}@Example.com", tag), "Original-Pass-1");
+        assert!(registered.success);
+        let user = db::get().find_one("_users", registered.user_id.as_deref().unwrap()).unwrap();
+        assert_eq!(user.get("email").and_then(|v| v.as_str()), Some(format!("case-{}@example.com", tag).as_str()));
+
+        let twin = register(&format!("case-{}@example.COM", tag), "Original-Pass-1");
+        assert_eq!(twin.error, Some(ErrorCode::EmailTaken));
+        let login = login(&format!("CASE-{}@EXAMPLE.COM", tag), "Original-Pass-1");
+        assert_eq!(login.user_id, registered.user_id);
+    }
+
+    #[test]
+    fn test_stored_emails_are_lowercased_unless_taken() {
+        db::init_in_memory("auth-test");
+        let tag = random_hex(4);
+        let insert = |email: String| {
+            let mut user = Document::new();
+            user.insert("email".into(), Value::String(email));
+            db::get().insert("_users", user).unwrap()
+        };
+        let email = |id: &str| db::get().find_one("_users", id).unwrap().get("email").and_then(|v| v.as_str()).map(str::to_string);
+        let upper = insert(format!("Legacy-{}@Example.com", tag));
+        let taken = insert(format!("twin-{}@example.com", tag));
+        let twin = insert(format!("Twin-{}@example.com", tag));
+
+        normalize_stored_emails();
+        assert_eq!(email(&upper), Some(format!("legacy-{}@example.com", tag)));
+        assert_eq!(email(&taken), Some(format!("twin-{}@example.com", tag)));
+        assert_eq!(email(&twin), Some(format!("Twin-{}@example.com", tag)));
+    }
+
+    #[test]
+    fn test_idle_session_ends_before_it_expires() {
+        db::init_in_memory("auth-test");
+        let clock = MockClock::install(1_700_000_000);
+        let token = create_session("idle-user");
+        assert_eq!(check_session(&token, None).as_deref(), Some("idle-user"));
+        clock.advance(Duration::from_secs(3600));
+        // Without an idle limit an hour of silence is fine
+        assert_eq!(check_session(&token, None).as_deref(), Some("idle-user"));
+        assert_eq!(check_session(&token, Some(3600)), None);
+        assert!(db::get().find_by("_sessions", "token", &token).is_none());
+    }
+
+    #[test]
+    fn test_activity_keeps_a_session_alive() {
+        db::init_in_memory("auth-test");
+        let clock = MockClock::install(1_700_000_000);
+        let token = create_session("busy-user");
+        let last_used = || db::get().find_by("_sessions", "token", &token).and_then(|s| s.get("last_used").cloned());
+
+        // Used every 10 minutes for three hours under a one-hour limit
+        for _ in 0..18 {
+            clock.advance(Duration::from_secs(600));
+            assert_eq!(check_session(&token, Some(3600)).as_deref(), Some("busy-user"));
+        }
+        assert!(matches!(last_used(), Some(Value::Int(t)) if t == 1_700_000_000 + 18 * 600));
+
+        // Requests closer together than the touch interval write nothing
+        clock.advance(Duration::from_secs(60));
+        check_session(&token, Some(3600));
+        assert!(matches!(last_used(), Some(Value::Int(t)) if t == 1_700_000_000 + 18 * 600));
+        assert_eq!(touch_interval(3600), 300);
+        assert_eq!(touch_interval(600), 60);
+        assert_eq!(touch_interval(5), 1);
+    }
+
+    #[test]
+    fn test_cookie_matches_the_exact_name() {
+        assert_eq!(cookie("token=abc", "token"), Some("abc"));
+        assert_eq!(cookie("theme=dark; token=abc; lang=is", "token"), Some("abc"));
+        assert_eq!(cookie("csrf_token=decoy; token=real", "token"), Some("real"));
+        assert_eq!(cookie("csrf_token=decoy; xtoken=also", "token"), None);
+        assert_eq!(cookie("  token = abc ;theme=dark", "token"), Some("abc"));
+        assert_eq!(cookie("Secure; SameSite=Lax; token=\"abc\"", "token"), Some("abc"));
+        assert_eq!(cookie("token=first; token=second", "token"), Some("first"));
+        assert_eq!(cookie("", "token"), None);
+
+        let headers = HashMap::from([("cookie".to_string(), "csrf_token=decoy; token=real".to_string())]);
+        assert_eq!(request_token(&headers), "real");
+        assert_eq!(request_token(&HashMap::new()), "");
+    }
+
+    #[test]
+    fn test_signed_urls() {
+        let clock = MockClock::install(1_700_000_000);
+        let url = sign_url("GET", "/api/admin/files/content?path=public/app.js", db::now() + 60);
+        let (path, query) = url.split_once('?').unwrap();
+        assert!(query.starts_with("path=public/app.js&expires=1700000060&sig="));
+        assert!(verify_signed_url("GET", path, query));
+
+        // Another file, another method or a later expiry is not what was signed
+        assert!(!verify_signed_url("GET", path, &query.replace("app.js", "db.bin")));
+        assert!(!verify_signed_url("GET", "/api/admin/files", query));
+        assert!(!verify_signed_url("PUT", path, query));
+        assert!(!verify_signed_url("GET", path, &query.replace("expires=1700000060", "expires=1800000000")));
+        assert!(!verify_signed_url("GET", path, "path=public/app.js"));
+
+        clock.advance(Duration::from_secs(59));
+        assert!(verify_signed_url("GET", path, query));
+        clock.advance(Duration::from_secs(1));
+        assert!(!verify_signed_url("GET", path, query));
+    }
+
+    #[test]
+    fn test_bearer_is_parsed_strictly() {
+        assert_eq!(bearer("Bearer abc123"), Some("abc123"));
+        assert_eq!(bearer("bearer abc123"), Some("abc123"));
+        assert_eq!(bearer("BEARER  abc123 "), Some("abc123"));
+        assert_eq!(bearer("abc123"), None);
+        assert_eq!(bearer("Basic dXNlcjpwYXNz"), None);
+        assert_eq!(bearer("Bearer"), None);
+        assert_eq!(bearer("Bearer "), None);
+        assert_eq!(bearer("Bearer a b"), None);
+        assert_eq!(bearer(""), None);
+    }
+
+    #[test]
+    fn test_credentials_tell_missing_from_malformed() {
+        let with = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        assert_eq!(credentials(&with(&[])), Credentials::Missing);
+        assert_eq!(credentials(&with(&[("cookie", "theme=dark")])), Credentials::Missing);
+        assert_eq!(credentials(&with(&[("cookie", "token=")])), Credentials::Missing);
+        assert_eq!(credentials(&with(&[("authorization", "bearer abc")])), Credentials::Token("abc".into()));
+        assert_eq!(credentials(&with(&[("cookie", "token=abc")])), Credentials::Token("abc".into()));
+        assert_eq!(credentials(&with(&[("authorization", "abc")])), Credentials::Malformed);
+        assert_eq!(credentials(&with(&[("authorization", "")])), Credentials::Malformed);
+        // A header that is not Bearer falls back to the cookie
+        assert_eq!(credentials(&with(&[("authorization", "Basic dXNlcjpwYXNz"), ("cookie", "token=abc")])), Credentials::Token("abc".into()));
+        assert_eq!(credentials(&with(&[("authorization", "abc"), ("cookie", "token=")])), Credentials::Malformed);
+    }
+}
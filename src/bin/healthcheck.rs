@@ -1,5 +1,6 @@
 //! Zero-dependency integration test runner
 //! Run after deployment: ./target/release/healthcheck [host:port]
+#![allow(clippy::collapsible_if)]
 
 use std::collections::HashMap;
 use std::fs;
@@ -31,6 +32,14 @@ fn main() {
         Ok(())
     }, &mut passed, &mut failed);
 
+    test(&host, "Responses carry security headers", || {
+        let res = http_get(&host, "/")?;
+        assert_contains(&res, "X-Content-Type-Options: nosniff")?;
+        assert_contains(&res, "X-Frame-Options: ")?;
+        assert_contains(&res, "Content-Security-Policy: ")?;
+        Ok(())
+    }, &mut passed, &mut failed);
+
     test(&host, "GET /_admin returns 200", || {
         let res = http_get(&host, "/_admin")?;
         assert_status(&res, 200)?;
@@ -52,8 +61,73 @@ fn main() {
         Ok(())
     }, &mut passed, &mut failed);
 
+    test(&host, "404 honors Accept: application/json vs text/html", || {
+        let json = http_get_with_headers(&host, "/nonexistent-page-12345", &[("Accept", "application/json")])?;
+        assert_status(&json, 404)?;
+        assert_contains(&json, "Content-Type: application/json")?;
+        assert_contains(get_body(&json), r#""error":"Not Found""#)?;
+        let html = http_get_with_headers(&host, "/nonexistent-page-12345", &[("Accept", "text/html")])?;
+        assert_status(&html, 404)?;
+        assert_contains(&html, "Content-Type: text/html")?;
+        assert_contains(&html, "<!DOCTYPE html>")?;
+        let api = http_get_with_headers(&host, "/api/nonexistent", &[("Accept", "text/html")])?;
+        assert_status(&api, 404)?;
+        assert_contains(&api, "Content-Type: application/json")?;
+        Ok(())
+    }, &mut passed, &mut failed);
+
     // ── Auth API ─────────────────────────────────────────────────────────────
 
+    test(&host, "GET /api/openapi.json is public and lists auth routes", || {
+        let res = http_get(&host, "/api/openapi.json")?;
+        assert_status(&res, 200)?;
+        assert_contains(&res, "Cache-Control: public")?;
+        assert_contains(&res, "\"/api/auth/login\"")?;
+        Ok(())
+    }, &mut passed, &mut failed);
+
+    test(&host, "Paths are normalized before routing", || {
+        assert_status(&http_get(&host, "//styles.css")?, 200)?;
+        assert_status(&http_get(&host, "/docs/")?, 200)?;
+        assert_status(&http_get(&host, "/docsfoo")?, 404)?;
+        assert_status(&http_get(&host, "/%2e%2e/Cargo.toml")?, 400)?;
+        assert_status(&http_get(&host, "/a%2f..%2fCargo.toml")?, 400)
+    }, &mut passed, &mut failed);
+
+    test(&host, "Static files carry an ETag, answer 304 and gzip", || {
+        let res = http_get(&host, "/styles.css")?;
+        assert_status(&res, 200)?;
+        let etag = res.lines()
+            .find_map(|l| l.strip_prefix("ETag: "))
+            .ok_or("Missing ETag header")?
+            .to_string();
+        let cached = http_get_with_headers(&host, "/styles.css", &[("If-None-Match", &etag)])?;
+        assert_status(&cached, 304)?;
+        let packed = http_get_with_headers(&host, "/styles.css", &[("Accept-Encoding", "gzip")])?;
+        assert_status(&packed, 200)?;
+        assert_contains(&packed, "Content-Encoding: gzip")
+    }, &mut passed, &mut failed);
+
+    test(&host, "X-Request-Id is echoed back", || {
+        let res = http_get_with_headers(&host, "/api/openapi.json", &[("X-Request-Id", "healthcheck-trace-42")])?;
+        assert_status(&res, 200)?;
+        assert_contains(&res, "X-Request-Id: healthcheck-trace-42")?;
+        Ok(())
+    }, &mut passed, &mut failed);
+
+    test(&host, "POST /api/contact is rate limited with Retry-After", || {
+        // Invalid submissions still count; the sixth inside the window is refused
+        for _ in 0..6 {
+            let res = http_post(&host, "/api/contact", "{}")?;
+            if res.starts_with("HTTP/1.1 429") {
+                assert_contains(&res, "Retry-After: ")?;
+                return Ok(());
+            }
+            assert_status(&res, 400)?;
+        }
+        Err("Expected a 429 after repeated submissions".into())
+    }, &mut passed, &mut failed);
+
     test(&host, "GET /api/auth/me without token returns 401", || {
         let res = http_get(&host, "/api/auth/me")?;
         assert_status(&res, 401)?;
@@ -67,6 +141,18 @@ fn main() {
         Ok(())
     }, &mut passed, &mut failed);
 
+    test(&host, "Validation errors carry a code and field", || {
+        let res = http_post(&host, "/api/auth/register", r#"{"email":"not-an-email","password":"longenough"}"#)?;
+        assert_status(&res, 400)?;
+        assert_contains(&res, r#""code":"invalid_email","field":"email""#)
+    }, &mut passed, &mut failed);
+
+    test(&host, "Weak passwords are refused with what is missing", || {
+        let res = http_post(&host, "/api/auth/register", r#"{"email":"weak@example.com","password":"aaaaaaaa"}"#)?;
+        assert_status(&res, 400)?;
+        assert_contains(&res, r#""error":"Password must contain a digit","code":"weak_password""#)
+    }, &mut passed, &mut failed);
+
     // Test login with env credentials if available
     let email = env.get("ADMIN_EMAIL").cloned().unwrap_or_default();
     let password = env.get("ADMIN_PASSWORD").cloned().unwrap_or_default();
@@ -117,6 +203,22 @@ fn main() {
             Ok(())
         }, &mut passed, &mut failed);
 
+        let e = email.clone();
+        let p = password.clone();
+        test(&host, "POST /api/auth/refresh rotates the token", || {
+            let body = format!(r#"{{"email":"{}","password":"{}"}}"#, e, p);
+            let login = http_post(&host, "/api/auth/login", &body)?;
+            let old = extract_json_value(&login, "token").ok_or("login failed")?;
+            let res = http_post_auth(&host, "/api/auth/refresh", "", &old)?;
+            assert_status(&res, 200)?;
+            let new = extract_json_value(&res, "token").ok_or("Missing token")?;
+            assert_status(&http_get_auth(&host, "/api/auth/me", &old)?, 401)?;
+            assert_status(&http_post_auth(&host, "/api/auth/refresh", "", &old)?, 401)?;
+            assert_status(&http_get_auth(&host, "/api/auth/me", &new)?, 200)?;
+            let _ = http_post_auth(&host, "/api/auth/logout", "", &new);
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "POST /api/admin/chat lists collections and includes _users", || {
             let body = r#"{"model":"ministral-3:8b","messages":[{"role":"user","content":"List collections using the list_collections tool."}],"stream":false}"#;
@@ -157,6 +259,17 @@ fn main() {
             Ok(())
         }, &mut passed, &mut failed);
 
+        let t = token.clone();
+        test(&host, "POST /api/admin/chat get_documents reads settings", || {
+            let body = r#"{"model":"ministral-3:8b","messages":[{"role":"user","content":"Show the documents in the _settings collection using the get_documents tool with limit 1."}],"stream":false}"#;
+            let res = http_post_auth_timeout(&host, "/api/admin/chat", body, &t, 30)?;
+            assert_status(&res, 200)?;
+            let content = extract_message_content(&res).ok_or("Missing message content")?;
+            assert_contains(&content, "\\\"documents\\\"")?;
+            assert_contains(&content, "page_title")?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "POST /api/admin/chat lists projects", || {
             let body = r#"{"model":"ministral-3:8b","messages":[{"role":"user","content":"List projects using the list_projects tool."}],"stream":false}"#;
@@ -174,7 +287,7 @@ fn main() {
 
             let body = format!(r#"{{"name":"{}"}}"#, name);
             let res = http_post_auth(&host, "/api/projects", &body, &t)?;
-            if let Err(_) = assert_status(&res, 201) {
+            if assert_status(&res, 201).is_err() {
                 return Err(format!("Create failed: {}", extract_body(&res)));
             }
 
@@ -190,6 +303,7 @@ fn main() {
             assert_contains(&ports, name)?;
             assert_contains(&ports, "\"dev_port\"")?;
             assert_contains(&ports, "\"prod_port\"")?;
+            assert_contains(&http_get_auth(&host, "/api/projects", &t)?, &format!("\"{}\"", name))?;
 
             let collections = http_get_auth(&host, "/api/collections", &t)?;
             assert_status(&collections, 200)?;
@@ -205,12 +319,116 @@ fn main() {
             if ports_after.contains(name) {
                 return Err("Project ports still present after delete".into());
             }
+            if get_body(&http_get_auth(&host, "/api/projects", &t)?).contains(name) {
+                return Err("Project still registered after delete".into());
+            }
 
             let page_after = http_get(&host, &format!("/projects/{}/", name))?;
             assert_status(&page_after, 404)?;
             Ok(())
         }, &mut passed, &mut failed);
 
+        let t = token.clone();
+        test(&host, "POST /api/projects/:name/rebuild renders old placeholders", || {
+            let name = "hc-rebuild-project";
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            assert_status(&http_post_auth(&host, "/api/projects", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let stale = format!(
+                r#"{{"path":"projects/{}/index.html","content":"{{% include \"components/nav.html\" %}}<main>stale</main>"}}"#,
+                name
+            );
+            let written = http_put_auth(&host, "/api/admin/files", &stale, &t)?;
+            let rebuilt = http_post_auth(&host, &format!("/api/projects/{}/rebuild", name), "", &t)?;
+            let page = http_get(&host, &format!("/projects/{}/", name))?;
+            let missing = http_post_auth(&host, "/api/projects/hc-no-such-project/rebuild", "", &t)?;
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            assert_status(&written, 200)?;
+            assert_status(&rebuilt, 200)?;
+            assert_contains(&rebuilt, "index.html")?;
+            assert_contains(&page, &format!("Project: {}", name))?;
+            if get_body(&page).contains("{% include") {
+                return Err("Include tag left in rebuilt page".into());
+            }
+            assert_status(&missing, 404)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Project export and import round-trip", || {
+            let name = "hc-archive-project";
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            assert_status(&http_post_auth(&host, "/api/projects", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let export = http_get_auth(&host, &format!("/api/projects/{}/export", name), &t)?;
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            assert_status(&export, 200)?;
+            assert_contains(&export, "rpw-project/1")?;
+
+            let imported = http_post_auth(&host, "/api/projects/import", get_body(&export), &t)?;
+            let page = http_get(&host, &format!("/projects/{}/", name))?;
+            let again = http_post_auth(&host, "/api/projects/import", get_body(&export), &t)?;
+            let collections = http_get_auth(&host, "/api/collections?prefix=dev-hc-archive", &t)?;
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            assert_status(&imported, 201)?;
+            assert_contains(&imported, "\"dev_port\"")?;
+            assert_status(&page, 200)?;
+            assert_contains(&page, &format!("Project: {}", name))?;
+            assert_status(&again, 400)?;
+            assert_contains(&collections, &format!("dev-{}_users", name))
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "POST /api/admin/chat create_project assigns ports and collections", || {
+            let name = "hc-tool-project";
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+
+            let body = format!(
+                r#"{{"model":"ministral-3:8b","messages":[{{"role":"user","content":"Create a project named {} using the create_project tool."}}],"stream":false}}"#,
+                name
+            );
+            let res = http_post_auth_timeout(&host, "/api/admin/chat", &body, &t, 30)?;
+            assert_status(&res, 200)?;
+            let content = extract_message_content(&res).ok_or("Missing message content")?;
+            assert_contains(&content, "\\\"dev_port\\\"")?;
+            assert_contains(&content, "\\\"prod_port\\\"")?;
+
+            let ports = http_get_auth(&host, "/api/collections/_ports", &t)?;
+            let collections = http_get_auth(&host, "/api/collections", &t)?;
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+
+            assert_status(&ports, 200)?;
+            assert_contains(&ports, name)?;
+            assert_status(&collections, 200)?;
+            assert_contains(&collections, &format!("dev-{}_users", name))?;
+            assert_contains(&collections, &format!("dev-{}_sessions", name))?;
+            assert_contains(&collections, &format!("dev-{}_settings", name))?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "POST /api/admin/chat delete_project removes project", || {
+            let name = "hc-tool-delete";
+            let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+            let res = http_post_auth(&host, "/api/projects", &format!(r#"{{"name":"{}"}}"#, name), &t)?;
+            assert_status(&res, 201)?;
+
+            let body = format!(
+                r#"{{"model":"ministral-3:8b","messages":[{{"role":"user","content":"Delete the project named {} using the delete_project tool."}}],"stream":false}}"#,
+                name
+            );
+            let res = http_post_auth_timeout(&host, "/api/admin/chat", &body, &t, 30)?;
+            assert_status(&res, 200)?;
+            let content = extract_message_content(&res).ok_or("Missing message content")?;
+            assert_contains(&content, "\\\"deleted\\\"")?;
+
+            let ports = http_get_auth(&host, "/api/collections/_ports", &t)?;
+            if ports.contains(name) {
+                let _ = http_delete_auth(&host, &format!("/api/projects/{}", name), &t);
+                return Err("Project ports still present after tool delete".into());
+            }
+            let page = http_get(&host, &format!("/projects/{}/", name))?;
+            assert_status(&page, 404)?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "GET /api/admin/stats with admin returns 200", || {
             let res = http_get_auth(&host, "/api/admin/stats", &t)?;
@@ -220,6 +438,423 @@ fn main() {
             Ok(())
         }, &mut passed, &mut failed);
 
+        let t = token.clone();
+        test(&host, "POST /api/collections/{name}/rename keeps documents", || {
+            let (from, to) = ("hc-rename-from", "hc-rename-to");
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", from), &t);
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", to), &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, from), &t)?, 201)?;
+            let created = http_post_auth(&host, &format!("/api/collections/{}", from), r#"{"note":"kept"}"#, &t)?;
+            let id = extract_json_value(&created, "id").ok_or("missing id")?;
+            let body = format!(r#"{{"new_name":"{}"}}"#, to);
+            let renamed = http_post_auth(&host, &format!("/api/collections/{}/rename", from), &body, &t)?;
+            assert_status(&renamed, 200)?;
+            let doc = http_get_auth(&host, &format!("/api/collections/{}/{}", to, id), &t)?;
+            assert_status(&doc, 200)?;
+            assert_contains(&doc, "kept")?;
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, from), &t)?, 201)?;
+            let clash = http_post_auth(&host, &format!("/api/collections/{}/rename", from), &body, &t)?;
+            assert_status(&clash, 409)?;
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", from), &t);
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", to), &t);
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Collection quota refuses inserts past max_docs", || {
+            let name = "hc-quota";
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            let schema = format!(r#"{{"name":"{}","max_docs":1}}"#, name);
+            assert_status(&http_post_auth(&host, "/api/collections", &schema, &t)?, 201)?;
+            let path = format!("/api/collections/{}", name);
+            let first = http_post_auth(&host, &path, r#"{"n":1}"#, &t)?;
+            assert_status(&first, 201)?;
+            let full = http_post_auth(&host, &path, r#"{"n":2}"#, &t)?;
+            assert_status(&full, 429)?;
+            assert_contains(&full, "quota_exceeded")?;
+            let id = extract_json_value(&first, "id").ok_or("missing id")?;
+            assert_status(&http_delete_auth(&host, &format!("{}/{}", path, id), &t)?, 200)?;
+            assert_status(&http_post_auth(&host, &path, r#"{"n":3}"#, &t)?, 201)?;
+            let raised = http_post_auth(&host, &format!("{}/quota", path), r#"{"max_docs":2}"#, &t)?;
+            assert_status(&raised, 200)?;
+            assert_status(&http_post_auth(&host, &path, r#"{"n":4}"#, &t)?, 201)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Array and object fields round-trip and are type-checked", || {
+            let name = "hc-typed-fields";
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            let schema = format!(
+                r#"{{"name":"{}","fields":[{{"name":"tags","type":"array"}},{{"name":"meta","type":"object"}}]}}"#,
+                name
+            );
+            assert_status(&http_post_auth(&host, "/api/collections", &schema, &t)?, 201)?;
+            let path = format!("/api/collections/{}", name);
+            let created = http_post_auth(&host, &path, r#"{"tags":["a","b c",["d"]],"meta":{"views":3}}"#, &t)?;
+            assert_status(&created, 201)?;
+            let id = extract_json_value(&created, "id").ok_or("missing id")?;
+            let doc = http_get_auth(&host, &format!("{}/{}", path, id), &t)?;
+            assert_contains(&doc, r#""tags": ["a", "b c", ["d"]]"#)?;
+            assert_contains(&doc, r#""meta": {"views": 3}"#)?;
+            assert_status(&http_post_auth(&host, &path, r#"{"tags":"a,b"}"#, &t)?, 400)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Schema defaults fill fields a new document omits", || {
+            let name = "hc-defaults";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            let schema = format!(r#"{{"name":"{}","fields":[{{"name":"status","type":"string","default":"pending"}}]}}"#, name);
+            assert_status(&http_post_auth(&host, "/api/collections", &schema, &t)?, 201)?;
+            let bad = format!(r#"{{"name":"{}-bad","fields":[{{"name":"n","type":"int","default":"x"}}]}}"#, name);
+            assert_status(&http_post_auth(&host, "/api/collections", &bad, &t)?, 400)?;
+            let created = http_post_auth(&host, &path, "{}", &t)?;
+            let id = extract_json_value(&created, "id").ok_or("missing id")?;
+            let doc = http_get_auth(&host, &format!("{}/{}", path, id), &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_contains(&doc, r#""status": "pending""#)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Unique fields reject duplicate values", || {
+            let name = "hc-unique";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            let schema = format!(r#"{{"name":"{}","fields":[{{"name":"sku","type":"string","unique":true}}]}}"#, name);
+            assert_status(&http_post_auth(&host, "/api/collections", &schema, &t)?, 201)?;
+            let first = http_post_auth(&host, &path, r#"{"sku":"A-1"}"#, &t)?;
+            let id = extract_json_value(&first, "id").ok_or("missing id")?;
+            let dup = http_post_auth(&host, &path, r#"{"sku":"A-1"}"#, &t)?;
+            let same = http_put_auth(&host, &format!("{}/{}", path, id), r#"{"sku":"A-1"}"#, &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&dup, 400)?;
+            assert_status(&same, 200)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "POST ?ttl= stamps _expires_at", || {
+            let name = "hc-ttl";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let created = http_post_auth(&host, &format!("{}?ttl=3600", path), "{}", &t)?;
+            let bad = http_post_auth(&host, &format!("{}?ttl=soon", path), "{}", &t)?;
+            let id = extract_json_value(&created, "id").ok_or("missing id")?;
+            let doc = http_get_auth(&host, &format!("{}/{}", path, id), &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&bad, 400)?;
+            assert_contains(&doc, r#""_expires_at": "#)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Deeply nested JSON bodies are refused", || {
+            let bomb = format!(r#"{{"a":{}}}"#, "[".repeat(10_000));
+            let res = http_post_auth(&host, "/api/collections", &bomb, &t)?;
+            assert_status(&res, 400)?;
+            assert_contains(&res, "nested too deeply")
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "GET /distinct lists a field's values once, sorted", || {
+            let name = "hc-distinct";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            for body in [r#"{"category":"tools"}"#, r#"{"category":"books"}"#, r#"{"other":1}"#, r#"{"category":"tools"}"#] {
+                assert_status(&http_post_auth(&host, &path, body, &t)?, 201)?;
+            }
+            let res = http_get_auth(&host, &format!("{}/distinct?field=category", path), &t)?;
+            let missing = http_get_auth(&host, &format!("{}/distinct", path), &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&missing, 400)?;
+            assert_contains(&res, r#"{"values":["books","tools"]}"#)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "POST /api/batch applies all operations or none", || {
+            let name = "hc-batch";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let ok = format!(
+                r#"[{{"op":"insert","collection":"{0}","doc":{{"n":1}}}},{{"op":"insert","collection":"{0}","doc":{{"n":2}}}}]"#,
+                name
+            );
+            let res = http_post_auth(&host, "/api/batch", &ok, &t)?;
+            let failing = format!(
+                r#"[{{"op":"insert","collection":"{0}","doc":{{"n":3}}}},{{"op":"delete","collection":"{0}","id":"missing"}}]"#,
+                name
+            );
+            let rolled_back = http_post_auth(&host, "/api/batch", &failing, &t)?;
+            let docs = http_get_auth(&host, &path, &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&res, 200)?;
+            assert_contains(&res, r#""op":"insert""#)?;
+            assert_status(&rolled_back, 400)?;
+            assert_contains(&rolled_back, "op 1: Document not found")?;
+            if get_body(&docs).matches(r#""n": "#).count() != 2 {
+                return Err(format!("expected 2 documents after rollback, got {}", get_body(&docs)));
+            }
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "GET /api/collections?prefix= filters and pages names", || {
+            let names = ["hc-prefix-a", "hc-prefix-b", "hc-prefix-c"];
+            for name in names {
+                let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+                assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            }
+            let all = http_get_auth(&host, "/api/collections?prefix=hc-prefix-", &t)?;
+            let first = http_get_auth(&host, "/api/collections?prefix=hc-prefix-&limit=2", &t)?;
+            let rest = http_get_auth(&host, "/api/collections?prefix=hc-prefix-&limit=2&after=hc-prefix-b", &t)?;
+            for name in names {
+                let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            }
+            assert_contains(&all, r#"{"collections":["hc-prefix-a","hc-prefix-b","hc-prefix-c"]}"#)?;
+            assert_contains(&first, r#"{"collections":["hc-prefix-a","hc-prefix-b"],"next_cursor":"hc-prefix-b"}"#)?;
+            assert_contains(&rest, r#"{"collections":["hc-prefix-c"],"next_cursor":null}"#)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "PATCH merges nested fields and removes nulls", || {
+            let name = "hc-merge-patch";
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let path = format!("/api/collections/{}", name);
+            let created = http_post_auth(&host, &path, r#"{"meta":{"views":3,"tags":["a","b"]},"draft":true}"#, &t)?;
+            let id = extract_json_value(&created, "id").ok_or("missing id")?;
+            let doc_path = format!("{}/{}", path, id);
+            let patched = http_request(&host, "PATCH", &doc_path, Some(r#"{"meta":{"views":4,"tags":["c"]},"draft":null}"#), Some(&t))?;
+            let doc = http_get_auth(&host, &doc_path, &t)?;
+            let missing = http_request(&host, "PATCH", &format!("{}/nope", path), Some("{}"), Some(&t))?;
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            assert_status(&patched, 200)?;
+            assert_contains(&doc, r#""views": 4"#)?;
+            assert_contains(&doc, r#""tags": ["c"]"#)?;
+            if get_body(&doc).contains("draft") {
+                return Err(format!("draft was not removed: {}", get_body(&doc)));
+            }
+            assert_status(&missing, 404)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Oversized documents are refused with 413", || {
+            let name = "hc-doc-size";
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let path = format!("/api/collections/{}", name);
+            let big = http_post_auth(&host, &path, &format!(r#"{{"text": "{}"}}"#, "x".repeat(300 * 1024)), &t)?;
+            let fits = http_post_auth(&host, &path, &format!(r#"{{"text": "{}"}}"#, "x".repeat(250 * 1024)), &t)?;
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", name), &t);
+            assert_status(&big, 413)?;
+            assert_contains(&big, r#""code":"document_too_large""#)?;
+            assert_status(&fits, 201)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "POST /api/admin/flush writes the database file", || {
+            assert_status(&http_post(&host, "/api/admin/flush", "")?, 401)?;
+            let res = http_post_auth(&host, "/api/admin/flush", "", &t)?;
+            assert_status(&res, 200)?;
+            assert_contains(&res, r#""bytes":"#)?;
+            assert_contains(&res, r#""synced_at":"#)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "GET /api/admin/logs tails and follows the log", || {
+            assert_status(&http_get(&host, "/api/admin/logs")?, 401)?;
+            let res = http_get_auth(&host, "/api/admin/logs?lines=3", &t)?;
+            assert_status(&res, 200)?;
+            assert_contains(&res, r#""lines":["#)?;
+            let offset_of = |res: &str| get_body(res).rsplit(r#""offset":"#).next().unwrap_or("").trim_end_matches('}').to_string();
+            let mut offset = offset_of(&res);
+            // Another request while the follow waits proves the server is not blocked
+            let h = host.clone();
+            let poke = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(300));
+                // Rejected before routing, which logs a warning
+                http_get(&h, "/hc-%zz")
+            });
+            let mut seen = String::new();
+            for _ in 0..5 {
+                let followed = http_get_auth(&host, &format!("/api/admin/logs?follow&level=warn&offset={}", offset), &t)?;
+                assert_status(&followed, 200)?;
+                offset = offset_of(&followed);
+                seen.push_str(get_body(&followed));
+                if seen.contains("GET /hc-%zz -> 400") {
+                    break;
+                }
+            }
+            poke.join().map_err(|_| "poke thread panicked")??;
+            if seen.contains("GET /hc-%zz -> 400") { Ok(()) } else { Err(format!("follow missed the request: {}", seen)) }
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "?after=&limit= pages through a collection without gaps", || {
+            let name = "hc-paged";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            for n in 0..7 {
+                assert_status(&http_post_auth(&host, &path, &format!(r#"{{"n":"item-{}"}}"#, n), &t)?, 201)?;
+            }
+            let mut seen = Vec::new();
+            let mut cursor = String::new();
+            for _ in 0..5 {
+                let page = http_get_auth(&host, &format!("{}?limit=3&after={}", path, cursor), &t)?;
+                assert_status(&page, 200)?;
+                seen.extend(get_body(&page).match_indices("item-").map(|(i, _)| get_body(&page)[i..i + 6].to_string()));
+                match extract_json_value(&page, "next_cursor") {
+                    Some(next) => cursor = next,
+                    None => break,
+                }
+            }
+            let unknown = http_get_auth(&host, &format!("{}?after=nope", path), &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            let expected: Vec<String> = (0..7).map(|n| format!("item-{}", n)).collect();
+            if seen != expected {
+                return Err(format!("Pages returned {:?}", seen));
+            }
+            assert_status(&unknown, 400)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "PUT creates a document at the client's id, then updates it", || {
+            let name = "hc-upsert";
+            let path = format!("/api/collections/{}", name);
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, name), &t)?, 201)?;
+            let doc_path = format!("{}/sku-001", path);
+            let created = http_put_auth(&host, &doc_path, r#"{"title":"first"}"#, &t)?;
+            assert_status(&created, 201)?;
+            assert_contains(&created, r#""created":true"#)?;
+            assert_status(&http_put_auth(&host, &doc_path, r#"{"title":"second"}"#, &t)?, 200)?;
+            let doc = http_get_auth(&host, &doc_path, &t)?;
+            let bad = http_put_auth(&host, &format!("{}/bad.id", path), "{}", &t)?;
+            let _ = http_delete_auth(&host, &path, &t);
+            assert_contains(&doc, r#""title": "second""#)?;
+            assert_contains(&doc, r#""id": "sku-001""#)?;
+            assert_status(&bad, 400)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "?mine=true lists only the caller's documents; admins see all", || {
+            let stamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+            let collection = "hc-owned";
+            let _ = http_delete_auth(&host, &format!("/api/collections/{}", collection), &t);
+            assert_status(&http_post_auth(&host, "/api/collections", &format!(r#"{{"name":"{}"}}"#, collection), &t)?, 201)?;
+            let path = format!("/api/collections/{}", collection);
+
+            let mut users = Vec::new();
+            for who in ["a", "b"] {
+                let email = format!("hc-owner-{}-{}@example.com", who, stamp);
+                let body = format!(r#"{{"email":"{}","password":"password123","role":"user"}}"#, email);
+                let created = http_post_auth(&host, "/api/admin/users", &body, &t)?;
+                let user_id = extract_json_value(&created, "id").ok_or("user not created")?;
+                let login = http_post(&host, "/api/auth/login", &format!(r#"{{"email":"{}","password":"password123"}}"#, email))?;
+                let user_token = extract_json_value(&login, "token").ok_or("login failed")?;
+                let doc = http_post_auth(&host, &path, &format!(r#"{{"note":"from-{}"}}"#, who), &user_token)?;
+                assert_status(&doc, 201)?;
+                users.push((user_id, user_token));
+            }
+
+            let result = (|| {
+                let mine_a = http_get_auth(&host, &format!("{}?mine=true", path), &users[0].1)?;
+                assert_contains(&mine_a, "from-a")?;
+                if mine_a.contains("from-b") {
+                    return Err("user a sees user b's document".into());
+                }
+                let mine_b = http_get_auth(&host, &format!("{}?mine=true", path), &users[1].1)?;
+                assert_contains(&mine_b, "from-b")?;
+                if mine_b.contains("from-a") {
+                    return Err("user b sees user a's document".into());
+                }
+                let admin = http_get_auth(&host, &format!("{}?mine=true", path), &t)?;
+                assert_contains(&admin, "from-a")?;
+                assert_contains(&admin, "from-b")
+            })();
+            let _ = http_delete_auth(&host, &path, &t);
+            for (user_id, _) in &users {
+                let _ = http_delete_auth(&host, &format!("/api/admin/users/{}", user_id), &t);
+            }
+            result
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "PUT /api/projects/:name/proxy for unknown project returns 404", || {
+            let res = http_put_auth(&host, "/api/projects/hc-no-such-project/proxy", r#"{"rewrite_host":true}"#, &t)?;
+            assert_status(&res, 404)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "GET /api/admin/files lists and previews, rejecting traversal", || {
+            let res = http_get_auth(&host, "/api/admin/files?dir=projects/_template", &t)?;
+            assert_status(&res, 200)?;
+            assert_contains(&res, r#""name":"index.html","type":"file""#)?;
+            let preview = http_get_auth(&host, "/api/admin/files/content?path=projects/_template/index.html", &t)?;
+            assert_status(&preview, 200)?;
+            assert_json_has(&preview, "content")?;
+            assert_status(&http_get_auth(&host, "/api/admin/files?dir=projects/..%2F..", &t)?, 400)?;
+            assert_status(&http_get_auth(&host, "/api/admin/files/content?path=.env.local", &t)?, 400)?;
+            assert_status(&http_get(&host, "/api/admin/files?dir=projects")?, 401)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Admin file API writes, creates and deletes inside projects/", || {
+            let dir = format!("projects/hc-files-{}", std::process::id());
+            assert_status(&http_post_auth(&host, "/api/admin/files/dir", &format!(r#"{{"path":"{}/css"}}"#, dir), &t)?, 201)?;
+            let body = format!(r#"{{"path":"{}/css/site.css","content":"body {{ color: red; }}"}}"#, dir);
+            assert_status(&http_put_auth(&host, "/api/admin/files", &body, &t)?, 201)?;
+            assert_status(&http_put_auth(&host, "/api/admin/files", &body, &t)?, 200)?;
+            let preview = http_get_auth(&host, &format!("/api/admin/files/content?path={}/css/site.css", dir), &t)?;
+            assert_contains(&preview, "color: red")?;
+            let outside = http_put_auth(&host, "/api/admin/files", r#"{"path":"projects/../.env.local","content":"x"}"#, &t)?;
+            assert_status(&outside, 400)?;
+            assert_status(&http_delete_auth(&host, &format!("/api/admin/files?path={}/css/site.css", dir), &t)?, 200)?;
+            assert_status(&http_get_auth(&host, &format!("/api/admin/files/content?path={}/css/site.css", dir), &t)?, 404)?;
+            assert_status(&http_delete_auth(&host, &format!("/api/admin/files?path={}", dir), &t)?, 400)?;
+            assert_status(&http_delete_auth(&host, &format!("/api/admin/files?path={}/css", dir), &t)?, 200)?;
+            assert_status(&http_delete_auth(&host, &format!("/api/admin/files?path={}", dir), &t)?, 200)?;
+            assert_status(&http_delete_auth(&host, "/api/admin/files?path=projects", &t)?, 400)
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "GET /api/admin/stats/detailed breaks down collections", || {
+            let res = http_get_auth(&host, "/api/admin/stats/detailed", &t)?;
+            assert_status(&res, 200)?;
+            assert_contains(&res, r#""name":"_settings","documents":1,"#)?;
+            assert_json_has(&res, "file_bytes")?;
+            assert_json_has(&res, "last_sync")?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
+        let t = token.clone();
+        test(&host, "Maintenance mode returns 503 to visitors but not admins", || {
+            let on = http_post_auth(&host, "/api/admin/maintenance", r#"{"enabled":true}"#, &t)?;
+            assert_status(&on, 200)?;
+            assert_contains(&on, r#""maintenance":true"#)?;
+            let checks = (|| {
+                let page = http_get(&host, "/")?;
+                assert_status(&page, 503)?;
+                assert_contains(&page, "Retry-After: ")?;
+                assert_contains(&page, "Be right back")?;
+                assert_status(&http_get(&host, "/api/collections")?, 503)?;
+                assert_status(&http_get(&host, "/_admin")?, 200)?;
+                assert_status(&http_get_auth(&host, "/", &t)?, 200)?;
+                assert_status(&http_get_auth(&host, "/api/admin/stats", &t)?, 200)
+            })();
+            let off = http_post_auth(&host, "/api/admin/maintenance", r#"{"enabled":false}"#, &t)?;
+            checks?;
+            assert_contains(&off, r#""maintenance":false"#)?;
+            assert_status(&http_get(&host, "/")?, 200)?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "PUT /api/admin/settings persists infra + port fields", || {
             let settings = http_get_auth(&host, "/api/admin/settings", &t)?;
@@ -248,6 +883,30 @@ fn main() {
             Ok(())
         }, &mut passed, &mut failed);
 
+        let t = token.clone();
+        test(&host, "PUT /api/admin/settings honors If-Match", || {
+            let settings = http_get_auth(&host, "/api/admin/settings", &t)?;
+            assert_status(&settings, 200)?;
+            let etag = settings
+                .lines()
+                .find_map(|l| l.strip_prefix("ETag: "))
+                .ok_or("Missing ETag header")?
+                .trim()
+                .to_string();
+            let id = extract_json_value(&settings, "id").ok_or("Missing settings id")?;
+            let body = format!(r#"{{"id":"{}","page_title":"Rust Pure Web (etag check)"}}"#, id);
+            let res = http_put_auth_with_headers(&host, "/api/admin/settings", &body, &t, &[("If-Match", &etag)])?;
+            assert_status(&res, 200)?;
+
+            // The tag we just used is now stale
+            let restore = format!(r#"{{"id":"{}","page_title":"Rust Pure Web"}}"#, id);
+            let res = http_put_auth_with_headers(&host, "/api/admin/settings", &restore, &t, &[("If-Match", &etag)])?;
+            assert_status(&res, 409)?;
+            let res = http_put_auth(&host, "/api/admin/settings", &restore, &t)?;
+            assert_status(&res, 200)?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "GET /api/collections excludes system collections", || {
             let res = http_get_auth(&host, "/api/collections", &t)?;
@@ -267,6 +926,15 @@ fn main() {
             Ok(())
         }, &mut passed, &mut failed);
 
+        let t = token.clone();
+        test(&host, "POST /api/collections/_users rejects unknown role via write hook", || {
+            let body = r#"{"email":"hook@example.com","password":"x","role":"superuser"}"#;
+            let res = http_post_auth(&host, "/api/collections/_users", body, &t)?;
+            assert_status(&res, 400)?;
+            assert_contains(&res, "Invalid role")?;
+            Ok(())
+        }, &mut passed, &mut failed);
+
         let t = token.clone();
         test(&host, "POST /api/admin/chat find_free_ports returns dev/prod", || {
             let body = r#"{"model":"ministral-3:8b","messages":[{"role":"user","content":"Find free ports using the find_free_ports tool."}],"stream":false}"#;
@@ -286,11 +954,11 @@ fn main() {
             let _ = http_delete_auth(&host, &format!("/api/projects/{}", name_b), &t);
 
             let res_a = http_post_auth(&host, "/api/projects", &format!(r#"{{"name":"{}"}}"#, name_a), &t)?;
-            if let Err(_) = assert_status(&res_a, 201) {
+            if assert_status(&res_a, 201).is_err() {
                 return Err(format!("Create A failed: {}", extract_body(&res_a)));
             }
             let res_b = http_post_auth(&host, "/api/projects", &format!(r#"{{"name":"{}"}}"#, name_b), &t)?;
-            if let Err(_) = assert_status(&res_b, 201) {
+            if assert_status(&res_b, 201).is_err() {
                 return Err(format!("Create B failed: {}", extract_body(&res_b)));
             }
 
@@ -301,10 +969,10 @@ fn main() {
             if a_dev == b_dev || a_prod == b_prod {
                 return Err("Port assignments should be unique".into());
             }
-            if a_prod as i64 - a_dev as i64 != 100 {
+            if a_prod - a_dev != 100 {
                 return Err("Prod port should be dev+100".into());
             }
-            if b_prod as i64 - b_dev as i64 != 100 {
+            if b_prod - b_dev != 100 {
                 return Err("Prod port should be dev+100".into());
             }
 
@@ -411,6 +1079,14 @@ fn http_request(host: &str, method: &str, path: &str, body: Option<&str>, token:
     http_request_timeout(host, method, path, body, token, 5)
 }
 
+fn http_put_auth_with_headers(host: &str, path: &str, body: &str, token: &str, headers: &[(&str, &str)]) -> Result<String, String> {
+    http_request_full(host, "PUT", path, Some(body), Some(token), headers, 5)
+}
+
+fn http_get_with_headers(host: &str, path: &str, headers: &[(&str, &str)]) -> Result<String, String> {
+    http_request_full(host, "GET", path, None, None, headers, 5)
+}
+
 fn http_request_timeout(
     host: &str,
     method: &str,
@@ -418,6 +1094,18 @@ fn http_request_timeout(
     body: Option<&str>,
     token: Option<&str>,
     timeout_secs: u64,
+) -> Result<String, String> {
+    http_request_full(host, method, path, body, token, &[], timeout_secs)
+}
+
+fn http_request_full(
+    host: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    token: Option<&str>,
+    headers: &[(&str, &str)],
+    timeout_secs: u64,
 ) -> Result<String, String> {
     let (host_name, port) = split_host_port(host);
     let mut stream = connect_stream(&host_name, port, timeout_secs)?;
@@ -431,6 +1119,9 @@ fn http_request_timeout(
     if let Some(t) = token {
         request.push_str(&format!("Authorization: Bearer {}\r\n", t));
     }
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
 
     request.push_str("\r\n");
     request.push_str(body_bytes);
@@ -647,8 +1338,8 @@ fn extract_json_value(response: &str, key: &str) -> Option<String> {
 fn load_env() -> HashMap<String, String> {
     let mut env = HashMap::new();
 
-    // Try .env.local first, then .env
-    for path in &[".env.local", ".env"] {
+    // .env, overridden by .env.local, like the server
+    for path in &[".env", ".env.local"] {
         if let Ok(content) = fs::read_to_string(path) {
             for line in content.lines() {
                 let line = line.trim();
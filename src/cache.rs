@@ -0,0 +1,141 @@
+//! Bounded in-memory cache for reads that repeat on every request
+//!
+//! Entries expire `ttl` after they were stored, and when the cache is full
+//! the least recently used one makes room. Callers invalidate explicitly
+//! when the underlying data changes; the TTL only bounds how stale an entry
+//! can get if an invalidation is missed.
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::clock;
+
+pub struct Cache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Inner<K, V>>,
+}
+
+struct Inner<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Bumped on every access; an entry's `used` is the tick it was last read
+    tick: u64,
+    /// Bumped by `clear`, so a value computed before a clear is not stored after it
+    generation: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    stored: Instant,
+    used: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self { capacity: capacity.max(1), ttl, inner: Mutex::new(Inner { entries: HashMap::new(), tick: 0, generation: 0 }) }
+    }
+
+    /// The value for `key` if it is present and not expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(key)?;
+        if clock::instant().duration_since(entry.stored) >= self.ttl {
+            inner.entries.remove(key);
+            return None;
+        }
+        entry.used = tick;
+        Some(entry.value.clone())
+    }
+
+    /// Store `value`, evicting the least recently used entry if full.
+    #[cfg(test)]
+    pub fn insert(&self, key: K, value: V) {
+        let mut inner = self.inner.lock().unwrap();
+        self.store(&mut inner, key, value);
+    }
+
+    fn store(&self, inner: &mut Inner<K, V>, key: K, value: V) {
+        inner.tick += 1;
+        let tick = inner.tick;
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= self.capacity {
+            let oldest = inner.entries.iter().min_by_key(|(_, entry)| entry.used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, Entry { value, stored: clock::instant(), used: tick });
+    }
+
+    /// The cached value for `key`, computing and storing it on a miss.
+    /// `compute` runs without the lock; if the cache is cleared meanwhile,
+    /// the value may already be stale and is returned but not stored.
+    pub fn get_or_insert_with(&self, key: K, compute: impl FnOnce() -> V) -> V {
+        // Read before the lookup, so a clear between the two also counts
+        let generation = self.inner.lock().unwrap().generation;
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = compute();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            self.store(&mut inner, key, value.clone());
+        }
+        value
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = Cache::new(2, Duration::from_secs(60));
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // Reading `a` makes `b` the least recently used
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+        // Replacing a present key evicts nothing
+        cache.insert("c", 4);
+        assert_eq!((cache.get(&"a"), cache.get(&"c")), (Some(1), Some(4)));
+        cache.clear();
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let clock = MockClock::install(1_700_000_000);
+        let cache = Cache::new(8, Duration::from_secs(30));
+        let mut computed = 0;
+        assert_eq!(cache.get_or_insert_with("k", || { computed += 1; "v1" }), "v1");
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(cache.get_or_insert_with("k", || { computed += 1; "v2" }), "v1");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"k"), None);
+        assert_eq!(cache.get_or_insert_with("k", || { computed += 1; "v3" }), "v3");
+        assert_eq!(computed, 2);
+    }
+
+    #[test]
+    fn test_value_computed_across_a_clear_is_not_stored() {
+        let cache = Cache::new(8, Duration::from_secs(60));
+        // A write lands (and clears the cache) while the old value is being read
+        assert_eq!(cache.get_or_insert_with("k", || { cache.clear(); "stale" }), "stale");
+        assert_eq!(cache.get(&"k"), None);
+        assert_eq!(cache.get_or_insert_with("k", || "fresh"), "fresh");
+        assert_eq!(cache.get(&"k"), Some("fresh"));
+    }
+}
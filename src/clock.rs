@@ -0,0 +1,118 @@
+//! Time source behind a trait, so expiry and rate limiting can be tested at
+//! exact instants instead of against the system clock
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    /// Wall-clock time in Unix seconds
+    fn now(&self) -> i64;
+    /// Monotonic time, for measuring spans
+    fn instant(&self) -> Instant;
+}
+
+/// The real clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Unix seconds from the current clock
+pub fn now() -> i64 {
+    with_clock(|clock| clock.now())
+}
+
+/// Monotonic instant from the current clock
+pub fn instant() -> Instant {
+    with_clock(|clock| clock.instant())
+}
+
+#[cfg(not(test))]
+fn with_clock<T>(f: impl FnOnce(&dyn Clock) -> T) -> T {
+    f(&SystemClock)
+}
+
+/// Tests run one per thread, so an installed [`MockClock`] only affects the
+/// test that installed it.
+#[cfg(test)]
+fn with_clock<T>(f: impl FnOnce(&dyn Clock) -> T) -> T {
+    mock::CURRENT.with(|current| match &*current.borrow() {
+        Some(clock) => f(clock.as_ref()),
+        None => f(&SystemClock),
+    })
+}
+
+#[cfg(test)]
+pub use mock::MockClock;
+
+#[cfg(test)]
+mod mock {
+    use super::Clock;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, Instant};
+
+    thread_local! {
+        pub(super) static CURRENT: RefCell<Option<Arc<MockClock>>> = const { RefCell::new(None) };
+    }
+
+    /// A clock that stands still until advanced
+    pub struct MockClock {
+        epoch: i64,
+        start: Instant,
+        elapsed_ms: AtomicU64,
+    }
+
+    impl MockClock {
+        /// Install a clock reading `epoch` (Unix seconds) for the calling
+        /// thread; [`crate::clock::now`] and `instant` follow it from then on.
+        pub fn install(epoch: i64) -> Arc<Self> {
+            let clock = Arc::new(Self { epoch, start: Instant::now(), elapsed_ms: AtomicU64::new(0) });
+            CURRENT.with(|current| *current.borrow_mut() = Some(Arc::clone(&clock)));
+            clock
+        }
+
+        pub fn advance(&self, by: Duration) {
+            self.elapsed_ms.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+        }
+
+        fn elapsed(&self) -> Duration {
+            Duration::from_millis(self.elapsed_ms.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> i64 {
+            self.epoch + self.elapsed().as_secs() as i64
+        }
+
+        fn instant(&self) -> Instant {
+            self.start + self.elapsed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock_stands_still_until_advanced() {
+        let clock = MockClock::install(1_000);
+        let start = instant();
+        assert_eq!(now(), 1_000);
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(now(), 1_001);
+        assert_eq!(instant() - start, Duration::from_millis(1_500));
+        // Other threads keep the system clock
+        let other = std::thread::spawn(now).join().unwrap();
+        assert!(other > 1_000_000_000);
+    }
+}
@@ -0,0 +1,557 @@
+//! DEFLATE (RFC 1951) decoding and encoding, with gzip (RFC 1952) and zlib (RFC 1950) framing
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum InflateError {
+    /// Output would exceed the caller's limit
+    TooLarge,
+    /// Malformed or truncated stream
+    Invalid(&'static str),
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::TooLarge => write!(f, "decompressed body too large"),
+            InflateError::Invalid(reason) => write!(f, "invalid compressed data: {}", reason),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, InflateError>;
+
+/// Decode a body sent with `Content-Encoding: gzip` or `deflate`, producing at
+/// most `limit` bytes. Returns `None` for encodings we do not understand.
+pub fn decode(encoding: &str, data: &[u8], limit: usize) -> Option<Result<Vec<u8>>> {
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" => Some(gunzip(data, limit)),
+        // "deflate" is zlib-wrapped per RFC 9110, but some clients send raw DEFLATE
+        "deflate" if has_zlib_header(data) => Some(zlib_decompress(data, limit)),
+        "deflate" => Some(inflate(data, limit).map(|(out, _)| out)),
+        "identity" | "" => Some(Ok(data.to_vec())),
+        _ => None,
+    }
+}
+
+// ── Framing ──────────────────────────────────────────────────────────────────
+
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b {
+        return Err(InflateError::Invalid("not a gzip stream"));
+    }
+    if data[2] != 8 {
+        return Err(InflateError::Invalid("unsupported gzip method"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([byte_at(data, pos)?, byte_at(data, pos + 1)?]) as usize;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0));
+            pos += end.ok_or(InflateError::Invalid("truncated gzip header"))? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let (out, used) = inflate(data.get(pos..).ok_or(InflateError::Invalid("truncated gzip header"))?, limit)?;
+    let trailer = data.get(pos + used..pos + used + 8).ok_or(InflateError::Invalid("missing gzip trailer"))?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crc != crc32(&out) || size != out.len() as u32 {
+        return Err(InflateError::Invalid("gzip checksum mismatch"));
+    }
+    Ok(out)
+}
+
+/// Compress `data` as a gzip member (used for `Content-Encoding: gzip` responses).
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, CM=8 (deflate), no flags, no mtime, XFL=0, OS=255 (unknown)
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if !has_zlib_header(data) {
+        return Err(InflateError::Invalid("not a zlib stream"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(InflateError::Invalid("preset dictionaries are not supported"));
+    }
+    let (out, used) = inflate(&data[2..], limit)?;
+    let trailer = data.get(2 + used..2 + used + 4).ok_or(InflateError::Invalid("missing zlib trailer"))?;
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&out) {
+        return Err(InflateError::Invalid("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+fn has_zlib_header(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] & 0x0f == 8 && data[0] >> 4 <= 7 && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
+}
+
+fn byte_at(data: &[u8], pos: usize) -> Result<u8> {
+    data.get(pos).copied().ok_or(InflateError::Invalid("truncated gzip header"))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// ── DEFLATE ──────────────────────────────────────────────────────────────────
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Inflate a raw DEFLATE stream. Returns the output and the number of input
+/// bytes consumed, so callers can find the trailer that follows.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize)> {
+    let mut bits = BitReader { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => stored_block(&mut bits, &mut out, limit)?,
+            1 => {
+                let (lit, dist) = fixed_tables();
+                huffman_block(&mut bits, &mut out, limit, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut bits)?;
+                huffman_block(&mut bits, &mut out, limit, &lit, &dist)?;
+            }
+            _ => return Err(InflateError::Invalid("reserved block type")),
+        }
+        if last {
+            break;
+        }
+    }
+    Ok((out, bits.consumed()))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl BitReader<'_> {
+    /// Read `count` bits, least significant first.
+    fn read(&mut self, count: u8) -> Result<u32> {
+        let mut value = 0u32;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or(InflateError::Invalid("unexpected end of stream"))?;
+            value |= u32::from((byte >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn consumed(&self) -> usize {
+        self.pos + usize::from(self.bit != 0)
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time (as in zlib's puff.c).
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(InflateError::Invalid("over-subscribed Huffman code"));
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Invalid("invalid Huffman code"))
+    }
+}
+
+fn stored_block(bits: &mut BitReader, out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    bits.align();
+    let header = bits.data.get(bits.pos..bits.pos + 4).ok_or(InflateError::Invalid("truncated stored block"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(InflateError::Invalid("stored block length mismatch"));
+    }
+    let start = bits.pos + 4;
+    let block = bits.data.get(start..start + len as usize).ok_or(InflateError::Invalid("truncated stored block"))?;
+    if out.len() + block.len() > limit {
+        return Err(InflateError::TooLarge);
+    }
+    out.extend_from_slice(block);
+    bits.pos = start + len as usize;
+    Ok(())
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let lit = Huffman::new(&lengths).expect("fixed literal code is complete");
+    let dist = Huffman::new(&[5u8; 30]).expect("fixed distance code is complete");
+    (lit, dist)
+}
+
+fn dynamic_tables(bits: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let hlit = bits.read(5)? as usize + 257;
+    let hdist = bits.read(5)? as usize + 1;
+    let hclen = bits.read(4)? as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(InflateError::Invalid("too many length or distance codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..hclen] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let lengths_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; hlit + hdist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = lengths_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths[..i].last().ok_or(InflateError::Invalid("repeat with no previous length"))?;
+                (prev, 3 + bits.read(2)? as usize)
+            }
+            17 => (0, 3 + bits.read(3)? as usize),
+            _ => (0, 11 + bits.read(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(InflateError::Invalid("code lengths overflow"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(InflateError::Invalid("missing end-of-block code"));
+    }
+    Ok((Huffman::new(&lengths[..hlit])?, Huffman::new(&lengths[hlit..])?))
+}
+
+fn huffman_block(bits: &mut BitReader, out: &mut Vec<u8>, limit: usize, lit: &Huffman, dist: &Huffman) -> Result<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() >= limit {
+                    return Err(InflateError::TooLarge);
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(InflateError::Invalid("invalid length code"));
+                }
+                let len = LENGTH_BASE[index] as usize + bits.read(LENGTH_EXTRA[index])? as usize;
+                let index = dist.decode(bits)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(InflateError::Invalid("invalid distance code"));
+                }
+                let distance = DIST_BASE[index] as usize + bits.read(DIST_EXTRA[index])? as usize;
+                if distance > out.len() {
+                    return Err(InflateError::Invalid("distance too far back"));
+                }
+                if out.len() + len > limit {
+                    return Err(InflateError::TooLarge);
+                }
+                let start = out.len() - distance;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+// ── DEFLATE encoding ─────────────────────────────────────────────────────────
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates examined per position; trades ratio for speed.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// Raw DEFLATE stream: greedy LZ77 over a 32 KiB window, emitted as a single
+/// block with the fixed Huffman code.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.write(1, 1); // BFINAL
+    bits.write(1, 2); // BTYPE = 01, fixed Huffman
+
+    // Hash chains: `head` holds the latest position per 3-byte hash, `prev`
+    // links each position to the previous one with the same hash.
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let hash = |i: usize| {
+        let v = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(i)];
+            let max = MAX_MATCH.min(data.len() - i);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW {
+                    break;
+                }
+                let len = data[candidate..].iter().zip(&data[i..i + max]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, i - candidate);
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+            }
+        }
+        if best_len >= MIN_MATCH {
+            write_length(&mut bits, best_len);
+            write_distance(&mut bits, best_dist);
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            write_fixed_literal(&mut bits, u16::from(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    write_fixed_literal(&mut bits, 256); // end of block
+    bits.finish()
+}
+
+/// Fixed-code symbol (RFC 1951 section 3.2.6), sent most significant bit first.
+fn write_fixed_literal(bits: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    bits.write_reversed(u32::from(code), len);
+}
+
+fn write_length(bits: &mut BitWriter, len: usize) {
+    let index = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= len).unwrap_or(0);
+    write_fixed_literal(bits, 257 + index as u16);
+    bits.write((len - usize::from(LENGTH_BASE[index])) as u32, LENGTH_EXTRA[index]);
+}
+
+fn write_distance(bits: &mut BitWriter, dist: usize) {
+    let index = DIST_BASE.iter().rposition(|&base| usize::from(base) <= dist).unwrap_or(0);
+    bits.write_reversed(index as u32, 5);
+    bits.write((dist - usize::from(DIST_BASE[index])) as u32, DIST_EXTRA[index]);
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    /// Write the low `count` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, count: u8) {
+        for i in 0..count {
+            self.acc |= ((value >> i) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.acc as u8);
+                self.acc = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Write a Huffman code, most significant bit first.
+    fn write_reversed(&mut self, code: u32, len: u8) {
+        for i in (0..len).rev() {
+            self.write((code >> i) & 1, 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hex_decode;
+
+    const GZIP_JSON: &str = "1f8b0800000000000203ab56ca4bcc4d55b252724c4954d2512a494c2f56b28a56aa00b22b95626b019cfea5851f000000";
+    // zlib level 9 of `items()` below; long enough to use a dynamic Huffman block
+    const ZLIB_ITEMS: &str = "78da75d4bb6a03311040d15f09aa5d685e7af857428a405c6cb1a9dc19ffbb0b8384646e7d59347390f6fb918ebf74cd97f4ff7bded2351df7dbf995d3f3f20eb2863a82ae4146b035b4117c0d3a42aca18f50d66023d4edf0396f5b8b8fd0b74fe6c0b22d1fb36cdbcb9c59b6fdcb2c86964e9812a4298538a592a73402954ea29a9154854c5511558d50d511558350b510aa56bca18d50b513aa654235215453423543547342b540542b846a1551ad11aa7542f54ca82e84ea8a0fdf08d59d503d08d50ba27a25546f88ea9d5023236a08a186126a18a186136a04a146c1df6925d468841afd13f5e70574c2bd87";
+
+    fn items() -> String {
+        let items: Vec<String> = (0..60).map(|i| format!(r#"{{"id":{},"name":"item {}"}}"#, i, i * 7 % 13)).collect();
+        format!("[{}]", items.join(","))
+    }
+
+    #[test]
+    fn test_gunzip_json_body() {
+        let data = hex_decode(GZIP_JSON).unwrap();
+        let out = decode("gzip", &data, 1024).unwrap().unwrap();
+        assert_eq!(out, br#"{"name":"Ada","tags":["x","y"]}"#);
+    }
+
+    #[test]
+    fn test_deflate_dynamic_block_zlib_and_raw() {
+        let data = hex_decode(ZLIB_ITEMS).unwrap();
+        assert_eq!(decode("deflate", &data, 1 << 20).unwrap().unwrap(), items().into_bytes());
+        let raw = &data[2..data.len() - 4];
+        assert_eq!(decode("deflate", raw, 1 << 20).unwrap().unwrap(), items().into_bytes());
+    }
+
+    #[test]
+    fn test_stored_block() {
+        let data = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        assert_eq!(inflate(&data, 16).unwrap(), (b"hello".to_vec(), data.len()));
+    }
+
+    #[test]
+    fn test_limit_stops_expansion() {
+        let data = hex_decode(ZLIB_ITEMS).unwrap();
+        assert_eq!(zlib_decompress(&data, 100), Err(InflateError::TooLarge));
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let repetitive = "abcabcabc-".repeat(5000);
+        let noisy: Vec<u8> = (0..70_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let long_run = vec![b'z'; 100_000];
+        for data in [Vec::new(), b"a".to_vec(), items().into_bytes(), repetitive.into_bytes(), noisy, long_run] {
+            let packed = gzip(&data);
+            assert_eq!(gunzip(&packed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_deflate_shrinks_repetitive_text() {
+        let text = items().into_bytes();
+        let packed = deflate(&text);
+        assert!(packed.len() < text.len() / 3, "{} -> {}", text.len(), packed.len());
+        assert_eq!(inflate(&packed, text.len()).unwrap(), (text, packed.len()));
+    }
+
+    #[test]
+    fn test_corrupt_input_rejected() {
+        let mut data = hex_decode(GZIP_JSON).unwrap();
+        let last = data.len() - 5;
+        data[last] ^= 0xff;
+        assert!(matches!(gunzip(&data, 1024), Err(InflateError::Invalid(_))));
+        assert!(matches!(gunzip(b"{\"plain\":true}", 1024), Err(InflateError::Invalid(_))));
+        assert!(decode("br", b"", 10).is_none());
+    }
+}
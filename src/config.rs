@@ -1,8 +1,13 @@
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::Duration;
+use crate::urlpath;
 
 static ROOT_DIR: OnceLock<PathBuf> = OnceLock::new();
+static ENV_FILES: OnceLock<HashMap<String, String>> = OnceLock::new();
 
 pub fn root_dir() -> PathBuf {
     ROOT_DIR
@@ -22,26 +27,127 @@ pub fn templates_dir() -> PathBuf {
     public_dir().join("templates")
 }
 
+/// Directory for db.bin and backups: RPW_DATA_DIR (environment or
+/// .env.local; relative paths are under the root), default `root/data`.
 pub fn data_dir() -> PathBuf {
-    root_dir().join("data")
+    data_paths(&root_dir(), env_or_file).0
 }
 
-pub fn env_path() -> PathBuf {
-    root_dir().join(".env.local")
+/// The database file: RPW_DB_FILE inside [`data_dir`], default `db.bin`.
+/// Separate values let two instances share one checkout.
+pub fn db_path() -> PathBuf {
+    data_paths(&root_dir(), env_or_file).1
 }
 
+/// Whether the database stays in memory (RPW_DB_MEMORY=1): nothing is
+/// loaded from or written to [`db_path`].
+pub fn db_in_memory() -> bool {
+    env_or_file("RPW_DB_MEMORY").is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+/// Largest collection document, as stored in `db.bin`: MAX_DOCUMENT_BYTES,
+/// default 256 KB. Separate from the 16 MB request body cap.
+pub fn max_document_bytes() -> usize {
+    env_or_file("MAX_DOCUMENT_BYTES").and_then(|v| v.trim().parse().ok()).unwrap_or(256 * 1024)
+}
+
+/// How long a database operation may take before it is logged as slow:
+/// DB_SLOW_MS, default 100 ms. 0 turns the reporting off.
+pub fn db_slow_threshold() -> Duration {
+    Duration::from_millis(env_or_file("DB_SLOW_MS").and_then(|v| v.trim().parse().ok()).unwrap_or(100))
+}
+
+/// Whether a failed startup self-check stops the server (STARTUP_STRICT=1);
+/// otherwise problems are only reported.
+pub fn startup_strict() -> bool {
+    env_or_file("STARTUP_STRICT").is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+/// Where the admin panel page is served: ADMIN_PATH (default `/_admin`), or
+/// `None` with ADMIN_PANEL=off, which 404s the page while `/api/admin/*`
+/// stays available.
+pub fn admin_panel_path() -> Option<String> {
+    admin_panel(env_or_file("ADMIN_PATH").as_deref(), env_or_file("ADMIN_PANEL").as_deref())
+}
+
+/// A path that is unusable (`/`, under `/api`, traversal) keeps the default.
+fn admin_panel(path: Option<&str>, panel: Option<&str>) -> Option<String> {
+    if panel.is_some_and(|v| ["off", "false", "0"].iter().any(|off| v.eq_ignore_ascii_case(off))) {
+        return None;
+    }
+    let path = path
+        .and_then(|p| urlpath::normalize(&format!("/{}", p.trim().trim_start_matches('/'))))
+        .map(|p| urlpath::trim_trailing_slash(&p).to_string())
+        .filter(|p| p != "/" && !urlpath::under(p, "/api"));
+    Some(path.unwrap_or_else(|| "/_admin".to_string()))
+}
+
+/// Seconds a session may go unused before it ends, on top of its absolute
+/// expiry: SESSION_IDLE_TIMEOUT. Unset or 0 means no idle limit.
+pub fn session_idle_timeout() -> Option<i64> {
+    env_or_file("SESSION_IDLE_TIMEOUT").and_then(|v| v.trim().parse().ok()).filter(|secs| *secs > 0)
+}
+
+/// Whether changing your password logs out your other sessions. On
+/// unless PASSWORD_CHANGE_ENDS_SESSIONS=0.
+pub fn password_change_ends_sessions() -> bool {
+    !env_or_file("PASSWORD_CHANGE_ENDS_SESSIONS").is_some_and(|v| v.eq_ignore_ascii_case("false") || v == "0")
+}
+
+/// Whether startup creates the default admin and seeds `_settings`, and
+/// project creation adds the dev login. On unless RPW_DISABLE_SEED=1.
+pub fn seeding_enabled() -> bool {
+    should_seed(env_or_file("RPW_DISABLE_SEED").as_deref())
+}
+
+fn should_seed(disable: Option<&str>) -> bool {
+    !disable.is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+fn env_or_file(key: &str) -> Option<String> {
+    load_env(key).filter(|v| !v.is_empty())
+}
+
+/// `(data_dir, db_path)` under `root`, with settings read through `lookup`.
+fn data_paths(root: &Path, lookup: impl Fn(&str) -> Option<String>) -> (PathBuf, PathBuf) {
+    let data_dir = root.join(lookup("RPW_DATA_DIR").unwrap_or_else(|| "data".to_string()));
+    let db_path = data_dir.join(lookup("RPW_DB_FILE").unwrap_or_else(|| "db.bin".to_string()));
+    (data_dir, db_path)
+}
+
+/// A setting by precedence: the process environment, then `.env.local`,
+/// then `.env`. The files are read once, on first use.
 pub fn load_env(key: &str) -> Option<String> {
-    let content = std::fs::read_to_string(env_path()).ok()?;
-    for line in content.lines() {
-        let line = line.trim();
+    lookup(key, |k| env::var(k).ok(), ENV_FILES.get_or_init(|| read_env_files(&root_dir())))
+}
+
+fn lookup(key: &str, process: impl Fn(&str) -> Option<String>, files: &HashMap<String, String>) -> Option<String> {
+    process(key).or_else(|| files.get(key).cloned())
+}
+
+/// `.env` overlaid with `.env.local` from `root`; either may be missing.
+fn read_env_files(root: &Path) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for name in [".env", ".env.local"] {
+        if let Ok(content) = fs::read_to_string(root.join(name)) {
+            vars.extend(parse_env(&content));
+        }
+    }
+    vars
+}
+
+/// `KEY=value` lines; blank lines and `#` comments are skipped, quotes
+/// around a value dropped. The first assignment of a key in a file wins.
+fn parse_env(content: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for line in content.lines().map(str::trim) {
         if line.starts_with('#') || line.is_empty() { continue; }
         if let Some((k, v)) = line.split_once('=') {
-            if k.trim() == key {
-                return Some(v.trim().trim_matches('"').to_string());
-            }
+            vars.entry(k.trim().to_string())
+                .or_insert_with(|| v.trim().trim_matches('"').trim_matches('\'').to_string());
         }
     }
-    None
+    vars
 }
 
 /// Returns the configured CORS origin.
@@ -52,15 +158,321 @@ pub fn cors_origin() -> String {
     load_env("CORS_ORIGIN").unwrap_or_else(|| "*".to_string())
 }
 
+/// Methods allowed cross-origin unless a [`CorsRule`] narrows them.
+pub const CORS_METHODS: &str = "GET,POST,PUT,PATCH,DELETE,OPTIONS";
+
+/// CORS policy for requests at or below `prefix`.
+#[derive(Debug, PartialEq)]
+pub struct CorsRule {
+    pub prefix: String,
+    pub origin: String,
+    pub methods: String,
+}
+
+/// Origin and allowed methods for a request to `path`: the longest
+/// CORS_ROUTES prefix that matches, else [`cors_origin`] with every method.
+/// CORS_ROUTES is `;`-separated `prefix=origin` entries, each optionally
+/// followed by `|METHOD,METHOD`, e.g.
+/// `/api/collections/products=https://shop.example.com|GET;/api/contact=https://example.com`.
+pub fn cors_for(path: &str) -> (String, String) {
+    let rules = load_env("CORS_ROUTES").map(|v| parse_cors_routes(&v)).unwrap_or_default();
+    cors_policy(&rules, path, cors_origin())
+}
+
+fn parse_cors_routes(spec: &str) -> Vec<CorsRule> {
+    spec.split(';')
+        .filter_map(|entry| {
+            let (prefix, rest) = entry.trim().split_once('=')?;
+            let (origin, methods) = rest.split_once('|').unwrap_or((rest, CORS_METHODS));
+            let prefix = urlpath::trim_trailing_slash(prefix.trim());
+            let origin = origin.trim();
+            (prefix.starts_with('/') && !origin.is_empty()).then(|| CorsRule {
+                prefix: prefix.to_string(),
+                origin: origin.to_string(),
+                methods: methods.split(',').map(|m| m.trim().to_ascii_uppercase()).filter(|m| !m.is_empty()).collect::<Vec<_>>().join(","),
+            })
+        })
+        .collect()
+}
+
+fn cors_policy(rules: &[CorsRule], path: &str, global: String) -> (String, String) {
+    rules
+        .iter()
+        .filter(|rule| urlpath::under(path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.len())
+        .map(|rule| (rule.origin.clone(), rule.methods.clone()))
+        .unwrap_or((global, CORS_METHODS.to_string()))
+}
+
+/// Returns the directories (relative to the repo root) the AI tools may access.
+/// Reads TOOLS_ALLOWED_DIRS (comma-separated) from .env.local.
+/// Defaults to the docs templates and the projects folder.
+pub fn tool_allowed_dirs() -> Vec<String> {
+    load_env("TOOLS_ALLOWED_DIRS")
+        .map(|v| v.split(',').map(|d| d.trim().trim_matches('/').to_string()).filter(|d| !d.is_empty()).collect())
+        .unwrap_or_else(|| vec!["public/templates/docs".to_string(), "projects".to_string()])
+}
+
+/// Default Content-Security-Policy. Inline scripts/styles are allowed because
+/// the admin panel and templates use them.
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; font-src 'self' data:; connect-src 'self' ws: wss:; base-uri 'self'; form-action 'self'";
+
+/// Security headers sent with every response.
+/// Each value reads from .env.local; "off" (or an empty value) omits the header.
+pub struct SecurityHeaders {
+    /// FRAME_OPTIONS, default "DENY"
+    pub frame_options: Option<String>,
+    /// PROJECT_FRAME_OPTIONS for /projects/ pages, default "SAMEORIGIN"
+    pub project_frame_options: Option<String>,
+    /// CONTENT_SECURITY_POLICY, default [`DEFAULT_CSP`]
+    pub content_security_policy: Option<String>,
+    /// REFERRER_POLICY, default "same-origin"
+    pub referrer_policy: Option<String>,
+}
+
+impl SecurityHeaders {
+    pub fn from_env() -> Self {
+        Self {
+            frame_options: optional_header("FRAME_OPTIONS", "DENY"),
+            project_frame_options: optional_header("PROJECT_FRAME_OPTIONS", "SAMEORIGIN"),
+            content_security_policy: optional_header("CONTENT_SECURITY_POLICY", DEFAULT_CSP),
+            referrer_policy: optional_header("REFERRER_POLICY", "same-origin"),
+        }
+    }
+}
+
+fn optional_header(key: &str, default: &str) -> Option<String> {
+    let value = load_env(key).unwrap_or_else(|| default.to_string());
+    if value.is_empty() || value.eq_ignore_ascii_case("off") {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Returns the Strict-Transport-Security value the HTTPS proxy adds.
+/// Reads HSTS_MAX_AGE (seconds, default one year; 0 disables) and
+/// HSTS_INCLUDE_SUBDOMAINS (default true) from .env.local.
+pub fn hsts() -> Option<String> {
+    let max_age = load_env("HSTS_MAX_AGE")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(31_536_000);
+    let subdomains = load_env("HSTS_INCLUDE_SUBDOMAINS")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(true);
+    hsts_value(max_age, subdomains)
+}
+
+/// How the HTTPS proxy reaches an upstream: how long it waits before
+/// answering 504, and how often it tries to connect before answering 502.
+pub struct UpstreamPolicy {
+    pub connect_timeout: Duration,
+    /// Bounds each read and write once connected
+    pub read_timeout: Duration,
+    /// Connection attempts for an upstream that refuses, at least one
+    pub connect_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+/// PROXY_CONNECT_TIMEOUT (default 5) and PROXY_READ_TIMEOUT (default 60) in
+/// seconds, PROXY_CONNECT_ATTEMPTS (default 3) and PROXY_RETRY_DELAY_MS
+/// (default 200), from .env.local. A zero or invalid value keeps the default.
+pub fn upstream_policy() -> UpstreamPolicy {
+    let number = |key: &str, default: u64| load_env(key).and_then(|v| v.trim().parse().ok()).filter(|n| *n > 0).unwrap_or(default);
+    UpstreamPolicy {
+        connect_timeout: Duration::from_secs(number("PROXY_CONNECT_TIMEOUT", 5)),
+        read_timeout: Duration::from_secs(number("PROXY_READ_TIMEOUT", 60)),
+        connect_attempts: number("PROXY_CONNECT_ATTEMPTS", 3).min(u32::MAX as u64) as u32,
+        retry_delay: Duration::from_millis(number("PROXY_RETRY_DELAY_MS", 200)),
+    }
+}
+
+pub fn hsts_value(max_age: u64, include_subdomains: bool) -> Option<String> {
+    match (max_age, include_subdomains) {
+        (0, _) => None,
+        (age, true) => Some(format!("max-age={}; includeSubDomains", age)),
+        (age, false) => Some(format!("max-age={}", age)),
+    }
+}
+
+/// A character class a password policy can require.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Letter,
+    Lower,
+    Upper,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "letter" => Some(CharClass::Letter),
+            "lower" => Some(CharClass::Lower),
+            "upper" => Some(CharClass::Upper),
+            "digit" => Some(CharClass::Digit),
+            "symbol" => Some(CharClass::Symbol),
+            _ => None,
+        }
+    }
+
+    pub fn matches(self, c: char) -> bool {
+        match self {
+            CharClass::Letter => c.is_alphabetic(),
+            CharClass::Lower => c.is_lowercase(),
+            CharClass::Upper => c.is_uppercase(),
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+
+    /// "a digit", for "Password must contain a digit".
+    pub fn describe(self) -> &'static str {
+        match self {
+            CharClass::Letter => "a letter",
+            CharClass::Lower => "a lowercase letter",
+            CharClass::Upper => "an uppercase letter",
+            CharClass::Digit => "a digit",
+            CharClass::Symbol => "a symbol",
+        }
+    }
+}
+
+/// Scheme new password hashes use: PASSWORD_HASH=argon2id, or `pbkdf2`
+/// (the default). Existing hashes verify under either.
+pub fn password_hasher() -> crate::crypto::PasswordHasher {
+    match env_or_file("PASSWORD_HASH") {
+        Some(v) if v.eq_ignore_ascii_case("argon2id") => crate::crypto::PasswordHasher::ARGON2ID,
+        _ => crate::crypto::PasswordHasher::PBKDF2,
+    }
+}
+
+/// Key signed URLs are made with: URL_SIGNING_SECRET, else random bytes
+/// drawn once per process, so outstanding URLs stop working on restart.
+pub fn url_signing_key() -> &'static [u8] {
+    static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+    KEY.get_or_init(|| match env_or_file("URL_SIGNING_SECRET") {
+        Some(secret) => secret.into_bytes(),
+        None => crate::crypto::random_bytes(32),
+    })
+}
+
+/// How floats are written in JSON responses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FloatFormat {
+    /// The shortest decimal that reads back as the same `f64`
+    Shortest,
+    /// Rounded to this many decimals, trailing zeros dropped
+    Fixed(usize),
+}
+
+/// How numbers are written in JSON responses, read once from the
+/// environment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonNumbers {
+    /// JSON_FLOAT_FORMAT=fixed rounds to JSON_FLOAT_DECIMALS (default 6);
+    /// `shortest` is the default
+    pub floats: FloatFormat,
+    /// JSON_BIG_INTS=string writes integers beyond ±(2^53 − 1), which a
+    /// JavaScript number cannot hold exactly, as strings
+    pub big_ints_as_strings: bool,
+}
+
+impl JsonNumbers {
+    fn from_env() -> Self {
+        let floats = match env_or_file("JSON_FLOAT_FORMAT") {
+            Some(v) if v.eq_ignore_ascii_case("fixed") => FloatFormat::Fixed(
+                env_or_file("JSON_FLOAT_DECIMALS").and_then(|v| v.trim().parse().ok()).unwrap_or(6),
+            ),
+            _ => FloatFormat::Shortest,
+        };
+        let big_ints_as_strings = env_or_file("JSON_BIG_INTS").is_some_and(|v| v.eq_ignore_ascii_case("string"));
+        Self { floats, big_ints_as_strings }
+    }
+}
+
+pub fn json_numbers() -> JsonNumbers {
+    static NUMBERS: OnceLock<JsonNumbers> = OnceLock::new();
+    *NUMBERS.get_or_init(JsonNumbers::from_env)
+}
+
+/// Rules for new and changed passwords.
+pub struct PasswordPolicy {
+    /// PASSWORD_MIN_LENGTH in characters, default 8
+    pub min_length: usize,
+    /// PASSWORD_REQUIRE, a comma list of `letter`, `lower`, `upper`, `digit`
+    /// and `symbol`; default `letter,digit`, `none` for length only
+    pub require: Vec<CharClass>,
+    /// PASSWORD_BLOCKLIST=1 also refuses the bundled common passwords
+    pub blocklist: bool,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            blocklist: env_or_file("PASSWORD_BLOCKLIST").is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1"),
+            ..Self::parse(env_or_file("PASSWORD_MIN_LENGTH").as_deref(), env_or_file("PASSWORD_REQUIRE").as_deref())
+        }
+    }
+
+    fn parse(min_length: Option<&str>, require: Option<&str>) -> Self {
+        let require = match require {
+            Some(list) if list.trim().eq_ignore_ascii_case("none") => Vec::new(),
+            Some(list) => list.split(',').filter_map(CharClass::parse).collect(),
+            None => vec![CharClass::Letter, CharClass::Digit],
+        };
+        Self {
+            min_length: min_length.and_then(|v| v.trim().parse().ok()).unwrap_or(8),
+            require,
+            blocklist: false,
+        }
+    }
+}
+
 pub fn hot_reload() -> bool {
-    // Check system environment variable first, then .env.local file
-    env::var("HOT_RELOAD")
-        .ok()
-        .or_else(|| load_env("HOT_RELOAD"))
+    load_env("HOT_RELOAD")
         .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
         .unwrap_or(false)
 }
 
+/// Whether the `/ws/echo` debug endpoint is served.
+/// On with hot reload, or with WS_ECHO=1 (environment or .env.local).
+pub fn ws_echo() -> bool {
+    hot_reload()
+        || load_env("WS_ECHO")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false)
+}
+
+/// Subprotocols `/realtime` agrees to when a client offers them in
+/// `Sec-WebSocket-Protocol`. Reads REALTIME_PROTOCOLS (comma-separated);
+/// defaults to `rpw.realtime.v1`.
+pub fn realtime_protocols() -> Vec<String> {
+    load_env("REALTIME_PROTOCOLS")
+        .map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+        .unwrap_or_else(|| vec!["rpw.realtime.v1".to_string()])
+}
+
+/// Default documents tried, in order, when a project path is a directory.
+/// Reads INDEX_FILES (comma-separated) from .env.local; defaults to
+/// `index.html,index.htm`.
+pub fn index_files() -> Vec<String> {
+    load_env("INDEX_FILES")
+        .map(|v| v.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty() && !f.contains('/')).collect())
+        .unwrap_or_else(|| vec!["index.html".to_string(), "index.htm".to_string()])
+}
+
+/// Whether project directories without a default document get an HTML
+/// listing instead of a 404. On with hot reload, or with DIR_LISTING=1
+/// (environment or .env.local).
+pub fn dir_listing() -> bool {
+    hot_reload()
+        || load_env("DIR_LISTING")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false)
+}
+
 fn resolve_root_dir() -> PathBuf {
     if let Ok(exe) = env::current_exe() {
         if let Some(release_dir) = exe.parent() {
@@ -73,3 +485,96 @@ fn resolve_root_dir() -> PathBuf {
     }
     env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cors_prefix_overrides_global_origin() {
+        let rules = parse_cors_routes(
+            "/api/collections/products/=https://shop.example.com|get, post; /api/collections=https://admin.example.com; bogus; x=y",
+        );
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].prefix, "/api/collections/products");
+        let global = || "*".to_string();
+        assert_eq!(
+            cors_policy(&rules, "/api/collections/products/abc", global()),
+            ("https://shop.example.com".to_string(), "GET,POST".to_string())
+        );
+        assert_eq!(
+            cors_policy(&rules, "/api/collections/orders", global()),
+            ("https://admin.example.com".to_string(), CORS_METHODS.to_string())
+        );
+        assert_eq!(cors_policy(&rules, "/api/collections-x", global()), ("*".to_string(), CORS_METHODS.to_string()));
+        assert_eq!(cors_policy(&[], "/api/auth/login", global()).0, "*");
+    }
+
+    #[test]
+    fn test_env_precedence() {
+        let root = env::temp_dir().join(format!("rpw-env-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(".env"), "A=env\nB=env\nC=env\n").unwrap();
+        fs::write(root.join(".env.local"), "# local overrides\nB=\"local\"\nC=local\nB=ignored\n").unwrap();
+        let files = read_env_files(&root);
+        let process = |k: &str| (k == "C").then(|| "process".to_string());
+        assert_eq!(lookup("A", process, &files).as_deref(), Some("env"));
+        assert_eq!(lookup("B", process, &files).as_deref(), Some("local"));
+        assert_eq!(lookup("C", process, &files).as_deref(), Some("process"));
+        assert_eq!(lookup("D", process, &files), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_admin_panel_path() {
+        assert_eq!(admin_panel(None, None).as_deref(), Some("/_admin"));
+        assert_eq!(admin_panel(Some("ops-7f3a/"), None).as_deref(), Some("/ops-7f3a"));
+        assert_eq!(admin_panel(Some("/internal/panel"), Some("on")).as_deref(), Some("/internal/panel"));
+        for unusable in ["/", "/api/admin", "/../x", ""] {
+            assert_eq!(admin_panel(Some(unusable), None).as_deref(), Some("/_admin"), "{}", unusable);
+        }
+        assert_eq!(admin_panel(Some("/ops"), Some("off")), None);
+        assert_eq!(admin_panel(None, Some("0")), None);
+    }
+
+    #[test]
+    fn test_password_policy_parsing() {
+        let default = PasswordPolicy::parse(None, None);
+        assert_eq!(default.min_length, 8);
+        assert_eq!(default.require, vec![CharClass::Letter, CharClass::Digit]);
+
+        let strict = PasswordPolicy::parse(Some("12"), Some("upper, lower,digit,symbol,bogus"));
+        assert_eq!(strict.min_length, 12);
+        assert_eq!(strict.require, vec![CharClass::Upper, CharClass::Lower, CharClass::Digit, CharClass::Symbol]);
+
+        let relaxed = PasswordPolicy::parse(Some("six"), Some("none"));
+        assert_eq!(relaxed.min_length, 8);
+        assert!(relaxed.require.is_empty());
+    }
+
+    #[test]
+    fn test_data_paths_follow_env() {
+        let root = Path::new("/srv/site");
+        let unset = |_: &str| None;
+        assert_eq!(data_paths(root, unset), (root.join("data"), root.join("data/db.bin")));
+
+        let staging = |key: &str| match key {
+            "RPW_DATA_DIR" => Some("data-staging".to_string()),
+            "RPW_DB_FILE" => Some("staging.bin".to_string()),
+            _ => None,
+        };
+        assert_eq!(data_paths(root, staging), (root.join("data-staging"), root.join("data-staging/staging.bin")));
+
+        let absolute = |key: &str| (key == "RPW_DATA_DIR").then(|| "/var/lib/rpw".to_string());
+        assert_eq!(data_paths(root, absolute).1, PathBuf::from("/var/lib/rpw/db.bin"));
+    }
+
+    #[test]
+    fn test_seeding_is_on_unless_disabled() {
+        assert!(should_seed(None));
+        assert!(should_seed(Some("0")));
+        assert!(should_seed(Some("false")));
+        assert!(!should_seed(Some("1")));
+        assert!(!should_seed(Some("TRUE")));
+    }
+}
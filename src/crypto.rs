@@ -1,6 +1,8 @@
-//! Pure Rust cryptography - SHA-256, HMAC, PBKDF2, ChaCha20
-//! Implements FIPS 180-4 (SHA-256) and RFC 8439 (ChaCha20)
+//! Pure Rust cryptography - SHA-256, HMAC, PBKDF2, BLAKE2b, Argon2id, ChaCha20
+//! Implements FIPS 180-4 (SHA-256), RFC 7693 (BLAKE2b), RFC 9106 (Argon2id)
+//! and RFC 8439 (ChaCha20)
 
+use crate::config;
 use std::fs::File;
 use std::io::Read;
 
@@ -115,6 +117,278 @@ pub fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
     result
 }
 
+// ── BLAKE2b (RFC 7693) ───────────────────────────────────────────────────────
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// The eight mixing steps of a BLAKE2b round over `v`, with `g` mixing one
+/// column or diagonal. Argon2 reuses the round with its own `g`.
+fn blake2b_round(v: &mut [u64; 16], mut g: impl FnMut(&mut [u64; 16], usize, usize, usize, usize, usize)) {
+    g(v, 0, 4, 8, 12, 0);
+    g(v, 1, 5, 9, 13, 1);
+    g(v, 2, 6, 10, 14, 2);
+    g(v, 3, 7, 11, 15, 3);
+    g(v, 0, 5, 10, 15, 4);
+    g(v, 1, 6, 11, 12, 5);
+    g(v, 2, 7, 8, 13, 6);
+    g(v, 3, 4, 9, 14, 7);
+}
+
+fn blake2b_compress(h: &mut [u64; 8], block: &[u8; 128], bytes: u128, last: bool) {
+    let mut m = [0u64; 16];
+    for (word, chunk) in m.iter_mut().zip(block.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= bytes as u64;
+    v[13] ^= (bytes >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+    for round in 0..12 {
+        let s = &BLAKE2B_SIGMA[round % 10];
+        blake2b_round(&mut v, |v, a, b, c, d, step| {
+            let (x, y) = (m[s[2 * step]], m[s[2 * step + 1]]);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(32);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(24);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(63);
+        });
+    }
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+/// Unkeyed BLAKE2b with an `out_len`-byte digest (1 to 64)
+pub fn blake2b(out_len: usize, data: &[u8]) -> Vec<u8> {
+    assert!((1..=64).contains(&out_len));
+    let mut h = BLAKE2B_IV;
+    h[0] ^= 0x0101_0000 ^ out_len as u64;
+    let blocks = data.len().div_ceil(128).max(1);
+    for i in 0..blocks {
+        let chunk = &data[i * 128..data.len().min((i + 1) * 128)];
+        let mut block = [0u8; 128];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let last = i == blocks - 1;
+        blake2b_compress(&mut h, &block, (i * 128 + chunk.len()) as u128, last);
+    }
+    h.iter().flat_map(|word| word.to_le_bytes()).take(out_len).collect()
+}
+
+// ── Argon2id (RFC 9106) ──────────────────────────────────────────────────────
+
+const ARGON2_VERSION: u32 = 0x13;
+const ARGON2ID_TYPE: u32 = 2;
+const SYNC_POINTS: u32 = 4;
+
+/// One 1 KiB memory block
+#[derive(Clone, Copy)]
+struct Block([u64; 128]);
+
+impl Block {
+    const ZERO: Block = Block([0; 128]);
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut block = Self::ZERO;
+        for (word, chunk) in block.0.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        block
+    }
+
+    fn xor(&self, other: &Block) -> Block {
+        let mut out = *self;
+        for (a, b) in out.0.iter_mut().zip(other.0.iter()) {
+            *a ^= b;
+        }
+        out
+    }
+}
+
+/// The compression function G: the BLAKE2b round with multiplications,
+/// applied to the rows and then the columns of `x ^ y`.
+fn argon2_compress(x: &Block, y: &Block) -> Block {
+    let r = x.xor(y);
+    let mut q = r;
+    let mix = |v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, _: usize| {
+        let mul = |x: u64, y: u64| 2u64.wrapping_mul(x & 0xFFFF_FFFF).wrapping_mul(y & 0xFFFF_FFFF);
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(mul(v[a], v[b]));
+        v[d] = (v[d] ^ v[a]).rotate_right(32);
+        v[c] = v[c].wrapping_add(v[d]).wrapping_add(mul(v[c], v[d]));
+        v[b] = (v[b] ^ v[c]).rotate_right(24);
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(mul(v[a], v[b]));
+        v[d] = (v[d] ^ v[a]).rotate_right(16);
+        v[c] = v[c].wrapping_add(v[d]).wrapping_add(mul(v[c], v[d]));
+        v[b] = (v[b] ^ v[c]).rotate_right(63);
+    };
+    let mut v = [0u64; 16];
+    for row in 0..8 {
+        v.copy_from_slice(&q.0[16 * row..16 * row + 16]);
+        blake2b_round(&mut v, mix);
+        q.0[16 * row..16 * row + 16].copy_from_slice(&v);
+    }
+    for col in 0..8 {
+        for k in 0..8 {
+            v[2 * k] = q.0[2 * col + 16 * k];
+            v[2 * k + 1] = q.0[2 * col + 16 * k + 1];
+        }
+        blake2b_round(&mut v, mix);
+        for k in 0..8 {
+            q.0[2 * col + 16 * k] = v[2 * k];
+            q.0[2 * col + 16 * k + 1] = v[2 * k + 1];
+        }
+    }
+    q.xor(&r)
+}
+
+/// H': BLAKE2b stretched to any output length
+fn blake2b_long(out_len: usize, input: &[u8]) -> Vec<u8> {
+    let mut data = (out_len as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(input);
+    if out_len <= 64 {
+        return blake2b(out_len, &data);
+    }
+    let mut out = Vec::with_capacity(out_len);
+    let mut v = blake2b(64, &data);
+    while out_len - out.len() > 64 {
+        out.extend_from_slice(&v[..32]);
+        let remaining = out_len - out.len();
+        v = blake2b(if remaining > 64 { 64 } else { remaining }, &v);
+    }
+    out.extend_from_slice(&v);
+    out
+}
+
+/// Argon2id with memory `memory_kib`, `passes` and `lanes`, plus the
+/// optional secret and associated data of RFC 9106.
+fn argon2id_full(
+    password: &[u8],
+    salt: &[u8],
+    secret: &[u8],
+    data: &[u8],
+    (memory_kib, passes, lanes): (u32, u32, u32),
+    tag_len: usize,
+) -> Vec<u8> {
+    let mut h0_input = Vec::new();
+    for n in [lanes, tag_len as u32, memory_kib, passes, ARGON2_VERSION, ARGON2ID_TYPE] {
+        h0_input.extend_from_slice(&n.to_le_bytes());
+    }
+    for field in [password, salt, secret, data] {
+        h0_input.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        h0_input.extend_from_slice(field);
+    }
+    let h0 = blake2b(64, &h0_input);
+
+    let segment_len = (memory_kib / (SYNC_POINTS * lanes)).max(2) as usize;
+    let lane_len = segment_len * SYNC_POINTS as usize;
+    let block_count = lane_len * lanes as usize;
+    let mut memory = vec![Block::ZERO; block_count];
+    for lane in 0..lanes as usize {
+        for i in 0..2u32 {
+            let mut seed = h0.clone();
+            seed.extend_from_slice(&i.to_le_bytes());
+            seed.extend_from_slice(&(lane as u32).to_le_bytes());
+            memory[lane * lane_len + i as usize] = Block::from_bytes(&blake2b_long(1024, &seed));
+        }
+    }
+
+    for pass in 0..passes as usize {
+        for slice in 0..SYNC_POINTS as usize {
+            for lane in 0..lanes as usize {
+                // The first half of the first pass picks reference blocks
+                // independently of the password (the Argon2i half of Argon2id)
+                let independent = pass == 0 && slice < SYNC_POINTS as usize / 2;
+                let mut input = Block::ZERO;
+                let mut addresses = Block::ZERO;
+                if independent {
+                    input.0[..6].copy_from_slice(&[
+                        pass as u64,
+                        lane as u64,
+                        slice as u64,
+                        block_count as u64,
+                        passes as u64,
+                        ARGON2ID_TYPE as u64,
+                    ]);
+                }
+                let next_addresses = |input: &mut Block, addresses: &mut Block| {
+                    input.0[6] += 1;
+                    *addresses = argon2_compress(&Block::ZERO, &argon2_compress(&Block::ZERO, input));
+                };
+                let start = if pass == 0 && slice == 0 { 2 } else { 0 };
+                if independent && start == 2 {
+                    next_addresses(&mut input, &mut addresses);
+                }
+                for index in start..segment_len {
+                    let offset = lane * lane_len + slice * segment_len + index;
+                    let prev = if offset.is_multiple_of(lane_len) { offset + lane_len - 1 } else { offset - 1 };
+                    let pseudo_rand = if independent {
+                        if index.is_multiple_of(128) {
+                            next_addresses(&mut input, &mut addresses);
+                        }
+                        addresses.0[index % 128]
+                    } else {
+                        memory[prev].0[0]
+                    };
+                    let ref_lane = if pass == 0 && slice == 0 { lane } else { (pseudo_rand >> 32) as usize % lanes as usize };
+                    let same_lane = ref_lane == lane;
+
+                    // Blocks this one may reference: finished segments, plus
+                    // the current one so far when in the same lane
+                    let finished = if pass == 0 { slice * segment_len } else { lane_len - segment_len };
+                    let area = if same_lane {
+                        finished + index - 1
+                    } else {
+                        finished - usize::from(index == 0)
+                    };
+                    let j1 = pseudo_rand & 0xFFFF_FFFF;
+                    let relative = area - 1 - ((area as u64 * ((j1 * j1) >> 32)) >> 32) as usize;
+                    let start_position = if pass == 0 || slice == 3 { 0 } else { (slice + 1) * segment_len };
+                    let ref_index = (start_position + relative) % lane_len;
+
+                    let block = argon2_compress(&memory[prev], &memory[ref_lane * lane_len + ref_index]);
+                    memory[offset] = if pass == 0 { block } else { memory[offset].xor(&block) };
+                }
+            }
+        }
+    }
+
+    let mut last = memory[lane_len - 1];
+    for lane in 1..lanes as usize {
+        last = last.xor(&memory[lane * lane_len + lane_len - 1]);
+    }
+    let bytes: Vec<u8> = last.0.iter().flat_map(|word| word.to_le_bytes()).collect();
+    blake2b_long(tag_len, &bytes)
+}
+
+/// Argon2id (version 19) of `password`: a 32-byte tag using `memory_kib`
+/// KiB of memory, `passes` over it and `lanes` lanes
+pub fn argon2id(password: &[u8], salt: &[u8], memory_kib: u32, passes: u32, lanes: u32) -> Vec<u8> {
+    argon2id_full(password, salt, &[], &[], (memory_kib, passes, lanes), 32)
+}
+
 /// ChaCha20 quarter round
 fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
     state[a] = state[a].wrapping_add(state[b]); state[d] ^= state[a]; state[d] = state[d].rotate_left(16);
@@ -187,21 +461,105 @@ pub fn random_hex(len: usize) -> String {
     random_bytes(len).iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-/// Hash password with PBKDF2 (returns salt:hash as hex)
+/// A password hashing scheme with its cost parameters. Stored hashes are
+/// PHC strings (`$argon2id$v=19$m=19456,t=2,p=1$salt$hash`) naming both,
+/// so every stored hash verifies whichever scheme new ones use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PasswordHasher {
+    Pbkdf2 { iterations: u32 },
+    Argon2id { memory_kib: u32, passes: u32, lanes: u32 },
+}
+
+impl PasswordHasher {
+    pub const PBKDF2: Self = Self::Pbkdf2 { iterations: 100_000 };
+    /// The OWASP baseline: 19 MiB, two passes, one lane
+    pub const ARGON2ID: Self = Self::Argon2id { memory_kib: 19_456, passes: 2, lanes: 1 };
+
+    /// Hash `password` under a fresh random salt.
+    pub fn hash(&self, password: &str) -> String {
+        let salt = random_bytes(16);
+        let hash = self.derive(password.as_bytes(), &salt);
+        let (salt, hash) = (phc_base64(&salt), phc_base64(&hash));
+        match *self {
+            Self::Pbkdf2 { iterations } => format!("$pbkdf2-sha256$i={}${}${}", iterations, salt, hash),
+            Self::Argon2id { memory_kib, passes, lanes } => {
+                format!("$argon2id$v=19$m={},t={},p={}${}${}", memory_kib, passes, lanes, salt, hash)
+            }
+        }
+    }
+
+    fn derive(&self, password: &[u8], salt: &[u8]) -> Vec<u8> {
+        match *self {
+            Self::Pbkdf2 { iterations } => pbkdf2(password, salt, iterations).to_vec(),
+            Self::Argon2id { memory_kib, passes, lanes } => argon2id(password, salt, memory_kib, passes, lanes),
+        }
+    }
+
+    /// Scheme, salt and hash of a stored PHC string. Hashes from before the
+    /// format had a prefix (`salt:hash` in hex) are PBKDF2 but return `None`
+    /// here; [`verify_password`] handles them.
+    fn parse(stored: &str) -> Option<(Self, Vec<u8>, Vec<u8>)> {
+        let parts: Vec<&str> = stored.strip_prefix('$')?.split('$').collect();
+        let (hasher, salt, hash) = match parts.as_slice() {
+            ["pbkdf2-sha256", params, salt, hash] => {
+                let iterations = params.strip_prefix("i=")?.parse().ok().filter(|&i| i > 0)?;
+                (Self::Pbkdf2 { iterations }, salt, hash)
+            }
+            ["argon2id", "v=19", params, salt, hash] => {
+                let mut values = [0u32; 3];
+                for (value, (param, key)) in values.iter_mut().zip(params.split(',').zip(["m=", "t=", "p="])) {
+                    *value = param.strip_prefix(key)?.parse().ok()?;
+                }
+                let [memory_kib, passes, lanes] = values;
+                if passes == 0 || lanes == 0 || lanes > 255 || memory_kib < 8 * lanes {
+                    return None;
+                }
+                (Self::Argon2id { memory_kib, passes, lanes }, salt, hash)
+            }
+            _ => return None,
+        };
+        Some((hasher, phc_base64_decode(salt)?, phc_base64_decode(hash)?))
+    }
+}
+
+/// Hash a password with the configured scheme (PASSWORD_HASH)
 pub fn hash_password(password: &str) -> String {
-    let salt = random_bytes(16);
-    let hash = pbkdf2(password.as_bytes(), &salt, 100_000);
-    format!("{}:{}", hex_encode(&salt), hex_encode(&hash))
+    config::password_hasher().hash(password)
 }
 
-/// Verify password against stored hash
+/// Verify password against stored hash, whatever scheme it was made with
 pub fn verify_password(password: &str, stored: &str) -> bool {
-    let parts: Vec<&str> = stored.split(':').collect();
-    if parts.len() != 2 { return false; }
-    let salt = match hex_decode(parts[0]) { Some(s) => s, None => return false };
-    let stored_hash = match hex_decode(parts[1]) { Some(h) => h, None => return false };
-    let hash = pbkdf2(password.as_bytes(), &salt, 100_000);
-    hash[..] == stored_hash[..]
+    if let Some((hasher, salt, hash)) = PasswordHasher::parse(stored) {
+        return constant_time_eq(&hasher.derive(password.as_bytes(), &salt), &hash);
+    }
+    // Legacy `salt:hash` in hex, PBKDF2 with 100,000 iterations
+    let Some((salt, hash)) = stored.split_once(':') else { return false };
+    match (hex_decode(salt), hex_decode(hash)) {
+        (Some(salt), Some(hash)) => constant_time_eq(&pbkdf2(password.as_bytes(), &salt, 100_000), &hash),
+        _ => false,
+    }
+}
+
+/// Whether a stored hash was made with anything but the configured scheme
+/// and parameters, so it should be replaced after the next good login.
+pub fn needs_rehash(stored: &str) -> bool {
+    PasswordHasher::parse(stored).map(|(hasher, _, _)| hasher) != Some(config::password_hasher())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Unpadded base64, as PHC strings use
+fn phc_base64(data: &[u8]) -> String {
+    base64_encode(data).trim_end_matches('=').to_string()
+}
+
+fn phc_base64_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 4 == 1 {
+        return None;
+    }
+    base64_decode(&format!("{}{}", s, "=".repeat((4 - s.len() % 4) % 4)))
 }
 
 /// Hex encode
@@ -214,6 +572,48 @@ pub fn hex_decode(s: &str) -> Option<Vec<u8>> {
     (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).ok()).collect()
 }
 
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 (RFC 4648) with `=` padding
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode padded base64; `None` for anything malformed
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (index, chunk) in bytes.chunks(4).enumerate() {
+        let last = index == bytes.len() / 4 - 1;
+        let pad = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if pad > 2 || (pad > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - pad] {
+            n = n << 6 | BASE64.iter().position(|&t| t == c)? as u32;
+        }
+        n <<= 6 * pad as u32;
+        out.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8][..3 - pad]);
+    }
+    Some(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -224,4 +624,61 @@ mod tests {
         let hash = sha256(b"abc");
         assert_eq!(hex_encode(&hash), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
     }
+
+    #[test]
+    fn test_blake2b() {
+        assert_eq!(
+            hex_encode(&blake2b(64, b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(
+            hex_encode(&blake2b(64, b"")),
+            "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419\
+             d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+        );
+    }
+
+    #[test]
+    fn test_argon2id_rfc_9106_vector() {
+        let tag = argon2id_full(&[0x01; 32], &[0x02; 16], &[0x03; 8], &[0x04; 12], (32, 3, 4), 32);
+        assert_eq!(hex_encode(&tag), "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659");
+    }
+
+    #[test]
+    fn test_password_hashes_name_their_scheme() {
+        let light = PasswordHasher::Argon2id { memory_kib: 64, passes: 1, lanes: 2 };
+        let stored = light.hash("correct horse");
+        assert!(stored.starts_with("$argon2id$v=19$m=64,t=1,p=2$"), "{}", stored);
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("correct horsE", &stored));
+
+        let cheap = PasswordHasher::Pbkdf2 { iterations: 10 }.hash("correct horse");
+        assert!(cheap.starts_with("$pbkdf2-sha256$i=10$"));
+        assert!(verify_password("correct horse", &cheap));
+        assert!(needs_rehash(&cheap) && needs_rehash(&stored));
+
+        // Hashes from before the prefix still verify
+        let salt = [7u8; 16];
+        let legacy = format!("{}:{}", hex_encode(&salt), hex_encode(&pbkdf2(b"old secret", &salt, 100_000)));
+        assert!(verify_password("old secret", &legacy));
+        assert!(needs_rehash(&legacy));
+
+        for bad in ["", "$argon2id$v=19$m=1,t=1,p=1$c2FsdA$aGFzaA", "$argon2id$v=16$m=64,t=1,p=1$c2FsdA$aGFzaA", "$md5$x$y"] {
+            assert!(!verify_password("correct horse", bad), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        for (plain, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).as_deref(), Some(plain.as_bytes()));
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&bytes)), Some(bytes));
+        for bad in ["Zg=", "Z===", "Zg==Zm8=", "Zm9v!", "Zm 9"] {
+            assert_eq!(base64_decode(bad), None, "{}", bad);
+        }
+    }
 }
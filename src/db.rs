@@ -1,12 +1,48 @@
 //! In-memory document database with encrypted file sync
+use crate::api::json::JsonSerializer as Json;
 use crate::crypto::{chacha20, random_bytes, random_hex, sha256};
-use crate::{config, realtime};
+use crate::cache::Cache;
+use crate::config::{FloatFormat, JsonNumbers};
+use crate::{clock, config, logging, realtime};
+use std::cmp;
 use std::collections::HashMap;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::{RwLock, OnceLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-const DB_VERSION: u8 = 1;
+const DB_VERSION: u8 = 4;
+
+/// Id of the user who created a document through the API
+pub const OWNER_FIELD: &str = "_owner";
+
+/// Unix time at or after which the sweeper deletes a document
+pub const EXPIRES_FIELD: &str = "_expires_at";
+
+/// How often [`start_sweeper`] looks for expired documents
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fields an update can never change
+const IMMUTABLE_FIELDS: [&str; 3] = ["id", "created", OWNER_FIELD];
+
+/// Longest collection name accepted by [`valid_collection_name`].
+pub const MAX_COLLECTION_NAME: usize = 64;
+
+/// Suffixes of the `dev-{project}_*` collections created with a project.
+pub const PROJECT_COLLECTIONS: [&str; 3] = ["users", "sessions", "settings"];
+
+/// What a collection belongs to, derived from its name alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollectionKind {
+    /// `_`-prefixed collections owned by the server (`_users`, `_sessions`)
+    System,
+    /// `dev-{project}_users` and friends, created and removed with a project
+    Project,
+    /// Everything else: collections created through the API
+    User,
+}
 
 /// JSON-like value type
 #[derive(Clone, Debug)]
@@ -27,28 +63,374 @@ impl Value {
     pub fn as_object(&self) -> Option<&HashMap<String, Value>> {
         match self { Value::Object(o) => Some(o), _ => None }
     }
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self { Value::Array(a) => Some(a), _ => None }
+    }
 }
 
 pub type Document = HashMap<String, Value>;
-pub type Collection = HashMap<String, Document>;
+
+/// Documents keyed by id, iterated in insertion order. db.bin stores them in
+/// that order, so listings stay stable across requests and restarts.
+#[derive(Clone, Default)]
+pub struct Collection {
+    docs: HashMap<String, Document>,
+    order: Vec<String>,
+    /// Per unique field: value (as JSON) → id of the document holding it
+    unique: HashMap<String, HashMap<String, String>>,
+}
+
+impl Collection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert or replace a document; a replaced id keeps its position.
+    /// Unique indexes follow, but are not checked: see [`Collection::insert_checked`].
+    pub fn insert(&mut self, id: String, doc: Document) {
+        self.unindex(&id);
+        for (field, index) in self.unique.iter_mut() {
+            if let Some(key) = unique_key(&doc, field) {
+                index.insert(key, id.clone());
+            }
+        }
+        if self.docs.insert(id.clone(), doc).is_none() {
+            self.order.push(id);
+        }
+    }
+
+    /// [`Collection::insert`], refused when a unique field's value belongs to
+    /// another document.
+    pub fn insert_checked(&mut self, id: String, doc: Document) -> Result<(), WriteError> {
+        if let Some(field) = self.unique_conflict(&id, &doc) {
+            return Err(WriteError::Unique(field.to_string()));
+        }
+        self.insert(id, doc);
+        Ok(())
+    }
+
+    /// The first unique field whose value in `doc` is held by a document
+    /// other than `id`. One hash lookup per unique field.
+    pub fn unique_conflict(&self, id: &str, doc: &Document) -> Option<&str> {
+        self.unique.iter().find_map(|(field, index)| {
+            let holder = index.get(&unique_key(doc, field)?)?;
+            (holder != id).then_some(field.as_str())
+        })
+    }
+
+    /// Index `field` as unique, including the documents already stored. If
+    /// stored data has duplicates, the oldest document keeps the value.
+    pub fn add_unique(&mut self, field: &str) {
+        let mut index = HashMap::new();
+        for (id, doc) in self.iter() {
+            if let Some(key) = unique_key(doc, field) {
+                index.entry(key).or_insert_with(|| id.clone());
+            }
+        }
+        self.unique.insert(field.to_string(), index);
+    }
+
+    fn unindex(&mut self, id: &str) {
+        let Some(old) = self.docs.get(id) else { return };
+        for (field, index) in self.unique.iter_mut() {
+            if let Some(key) = unique_key(old, field) {
+                if index.get(&key).is_some_and(|holder| holder == id) {
+                    index.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Document> {
+        self.docs.get(id)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Document> {
+        self.unindex(id);
+        let doc = self.docs.remove(id)?;
+        self.order.retain(|other| other != id);
+        Some(doc)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// `(id, document)` pairs, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Document)> {
+        self.order.iter().filter_map(|id| self.docs.get_key_value(id))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Document> {
+        self.iter().map(|(_, doc)| doc)
+    }
+
+    /// In-place edits bypass the unique indexes; callers must not change
+    /// unique fields through this.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Document> {
+        self.docs.values_mut()
+    }
+
+    /// Up to `limit` documents following `after` (from the start when `None`),
+    /// oldest first. Only ids are scanned to find the cursor, no document is
+    /// touched before it. `None` when `after` is not in the collection.
+    pub fn page_after(&self, after: Option<&str>, limit: usize) -> Option<impl Iterator<Item = &Document>> {
+        let start = match after {
+            Some(after) => self.order.iter().position(|id| id == after)? + 1,
+            None => 0,
+        };
+        Some(self.order[start..].iter().take(limit).filter_map(|id| self.docs.get(id)))
+    }
+}
+
+/// One write in a [`Database::transaction`].
+pub enum Op {
+    Insert { collection: String, doc: Document },
+    /// Merge into an existing document, which must exist
+    Update { collection: String, id: String, doc: Document },
+    Delete { collection: String, id: String },
+}
+
+/// What an applied [`Op`] did, with the document's id.
+#[derive(Debug, PartialEq)]
+pub enum OpResult {
+    Inserted(String),
+    Updated(String),
+    Deleted(String),
+}
+
+/// One page of [`Database::find_after`]: the documents and, when more
+/// follow, the id to pass as the next cursor.
+#[derive(Default)]
+pub struct Page {
+    pub docs: Vec<Document>,
+    pub next_cursor: Option<String>,
+}
+
+/// Field types a schema may declare
+pub const FIELD_TYPES: [&str; 6] = ["string", "int", "float", "bool", "array", "object"];
+
+/// A declared field: (name, type, default applied on insert when absent)
+pub type SchemaField = (String, String, Option<Value>);
+
+/// What a schema does with fields a document has but it does not declare
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExtraFields {
+    /// Store them without comment
+    #[default]
+    Off,
+    /// Store them, but name them in the response and log a warning
+    Lenient,
+    /// Refuse the write
+    Strict,
+}
+
+impl ExtraFields {
+    pub const ALL: [ExtraFields; 3] = [ExtraFields::Off, ExtraFields::Lenient, ExtraFields::Strict];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExtraFields::Off => "off",
+            ExtraFields::Lenient => "lenient",
+            ExtraFields::Strict => "strict",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.as_str() == s)
+    }
+}
 
 /// Database schema definition
 #[derive(Clone)]
 pub struct Schema {
-    pub fields: Vec<(String, String)>, // (name, type)
+    pub fields: Vec<SchemaField>,
+    /// Fields whose non-null values may appear in only one document
+    pub unique: Vec<String>,
+    /// Most documents the collection may hold; 0 is unlimited
+    pub max_docs: usize,
+    pub extra_fields: ExtraFields,
+}
+
+impl Schema {
+    /// Schema from `(name, type)` pairs, without defaults or unique fields.
+    pub fn from_types(fields: Vec<(String, String)>) -> Self {
+        Self {
+            fields: fields.into_iter().map(|(name, ty)| (name, ty, None)).collect(),
+            unique: Vec::new(),
+            max_docs: 0,
+            extra_fields: ExtraFields::Off,
+        }
+    }
+
+    /// Fields of `doc` the schema does not declare, sorted. The fields the
+    /// database stamps itself never count.
+    pub fn unknown_fields(&self, doc: &Document) -> Vec<String> {
+        let mut unknown: Vec<String> = doc
+            .keys()
+            .filter(|key| !matches!(key.as_str(), "id" | "created" | "updated" | OWNER_FIELD | EXPIRES_FIELD))
+            .filter(|key| !self.fields.iter().any(|(name, _, _)| name == *key))
+            .cloned()
+            .collect();
+        unknown.sort();
+        unknown
+    }
+
+    /// Fill each declared field `doc` lacks with its default, if it has one.
+    pub fn apply_defaults(&self, doc: &mut Document) {
+        for (name, _, default) in &self.fields {
+            if let Some(default) = default {
+                doc.entry(name.clone()).or_insert_with(|| default.clone());
+            }
+        }
+    }
+
+    /// Check that each declared field present in `doc` holds a value of its
+    /// type. Null is accepted for any field; undeclared fields are refused
+    /// only by a strict schema.
+    pub fn check(&self, doc: &Document) -> Result<(), String> {
+        if self.extra_fields == ExtraFields::Strict {
+            let unknown = self.unknown_fields(doc);
+            if !unknown.is_empty() {
+                return Err(format!("Unknown field(s) for this collection: {}", unknown.join(", ")));
+            }
+        }
+        for (name, ty, _) in &self.fields {
+            match doc.get(name) {
+                Some(value) if !value_matches_type(value, ty) => {
+                    return Err(format!("Field '{}' must be of type {}", name, ty));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn value_matches_type(value: &Value, ty: &str) -> bool {
+    matches!(
+        (ty, value),
+        (_, Value::Null)
+            | ("string", Value::String(_))
+            | ("int", Value::Int(_))
+            | ("float", Value::Float(_) | Value::Int(_))
+            | ("bool", Value::Bool(_))
+            | ("array", Value::Array(_))
+            | ("object", Value::Object(_))
+    ) || !FIELD_TYPES.contains(&ty)
+}
+
+/// Size figures for one collection, as reported by the admin stats endpoint
+pub struct CollectionStat {
+    pub name: String,
+    pub documents: usize,
+    /// Bytes the collection takes in the (unencrypted) sync format
+    pub bytes: usize,
+    pub fields: usize,
+}
+
+/// Why a collection rename was refused
+#[derive(Debug, PartialEq)]
+pub enum RenameError {
+    NotFound,
+    Exists,
+    Invalid(&'static str),
+}
+
+/// Why a document write was refused
+#[derive(Debug, PartialEq)]
+pub enum WriteError {
+    /// The collection already holds its schema's `max_docs` documents
+    Quota(usize),
+    /// The value of this unique field belongs to another document
+    Unique(String),
+    /// A write hook's or the schema's message, or a missing collection
+    Other(String),
 }
 
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Quota(max_docs) => write!(f, "Collection is full ({} documents max)", max_docs),
+            WriteError::Unique(field) => write!(f, "Duplicate value for unique field '{}'", field),
+            WriteError::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<String> for WriteError {
+    fn from(msg: String) -> Self { WriteError::Other(msg) }
+}
+
+impl From<&str> for WriteError {
+    fn from(msg: &str) -> Self { WriteError::Other(msg.to_string()) }
+}
+
+/// Outcome of [`Database::update_if`]
+#[derive(Debug, PartialEq)]
+pub enum ConditionalUpdate {
+    Updated,
+    NotFound,
+    /// The stored document failed the precondition and was left alone
+    Refused,
+}
+
+/// Write hook: may mutate the document or reject the write with a message.
+/// Hooks run under the collection write lock and must not call back into the database.
+pub type WriteHook = Box<dyn Fn(&mut Document) -> Result<(), String> + Send + Sync>;
+
+type HookMap = RwLock<HashMap<String, Vec<WriteHook>>>;
+
+/// See [`on_change`]
+type ChangeListeners = Vec<(String, fn())>;
+
 /// The database
 pub struct Database {
     collections: RwLock<HashMap<String, Collection>>,
     schemas: RwLock<HashMap<String, Schema>>,
+    before_insert: HookMap,
+    before_update: HookMap,
     encryption_key: [u8; 32],
+    /// Unix time of the last successful write of db.bin (0 = never)
+    last_sync: AtomicI64,
+    /// False for in-memory databases: load, sync and backup do nothing
+    persist: bool,
+    /// Operations taking at least this many milliseconds are logged (0 = never)
+    slow_ms: AtomicU64,
+    /// Operations logged as slow since startup
+    slow_ops: AtomicU64,
+}
+
+/// Start of one timed database operation, see [`Database::finish_span`].
+struct Span {
+    op: &'static str,
+    start: Instant,
+}
+
+impl Span {
+    fn start(op: &'static str) -> Self {
+        Self { op, start: clock::instant() }
+    }
 }
 
 static DB: OnceLock<Database> = OnceLock::new();
 
 impl Database {
     fn new(key: &[u8]) -> Self {
+        Self::with_persistence(key, true)
+    }
+
+    /// A standalone database that never touches disk, for tests and
+    /// throwaway instances. It starts with the system collections.
+    pub fn in_memory(key: &[u8]) -> Self {
+        Self::with_persistence(key, false)
+    }
+
+    fn with_persistence(key: &[u8], persist: bool) -> Self {
         let mut encryption_key = [0u8; 32];
         let hash = sha256(key);
         encryption_key.copy_from_slice(&hash);
@@ -56,22 +438,31 @@ impl Database {
         let db = Database {
             collections: RwLock::new(HashMap::new()),
             schemas: RwLock::new(HashMap::new()),
+            before_insert: RwLock::new(HashMap::new()),
+            before_update: RwLock::new(HashMap::new()),
             encryption_key,
+            last_sync: AtomicI64::new(0),
+            persist,
+            slow_ms: AtomicU64::new(config::db_slow_threshold().as_millis() as u64),
+            slow_ops: AtomicU64::new(0),
         };
 
-        // Create default users collection
-        db.create_collection_internal("_users", vec![
+        // Create default users collection; emails are unique
+        let mut users = Schema::from_types(vec![
             ("email".into(), "string".into()),
             ("password".into(), "string".into()),
             ("role".into(), "string".into()),
             ("created".into(), "int".into()),
         ]);
+        users.unique.push("email".into());
+        db.insert_collection("_users", users);
 
         // Create sessions collection
         db.create_collection_internal("_sessions", vec![
             ("user_id".into(), "string".into()),
             ("token".into(), "string".into()),
             ("expires".into(), "int".into()),
+            ("last_used".into(), "int".into()),
         ]);
 
         // Create settings collection
@@ -84,6 +475,7 @@ impl Database {
             ("og_image".into(), "string".into()),
             ("twitter_card".into(), "string".into()),
             ("canonical_url".into(), "string".into()),
+            ("favicon".into(), "string".into()),
             ("nginx_hostname".into(), "string".into()),
             ("nginx_internal_ip".into(), "string".into()),
             ("dev_network_name".into(), "string".into()),
@@ -100,58 +492,290 @@ impl Database {
         ]);
 
         // Create internal ports collection for project allocations
-        db.create_collection_internal("_ports", vec![
-            ("project".into(), "string".into()),
-            ("dev_port".into(), "int".into()),
-            ("prod_port".into(), "int".into()),
-            ("created".into(), "int".into()),
-        ]);
+        db.create_collection_internal("_ports", ports_fields());
+
+        // Registry of projects, the source of truth for which ones exist
+        db.insert_collection("_projects", projects_schema());
 
         db
     }
 
     pub fn create_collection(&self, name: &str, fields: Vec<(String, String)>) {
-        self.create_collection_internal(name, fields);
+        self.create_collection_with_schema(name, Schema::from_types(fields));
+    }
+
+    /// Create a collection whose fields may declare defaults.
+    pub fn create_collection_with_schema(&self, name: &str, schema: Schema) {
+        self.insert_collection(name, schema);
         self.sync();
         broadcast_event("collection.created", name, None, None);
     }
 
+    /// Create `name` holding `docs` exactly as given (ids, timestamps and
+    /// owners kept), replacing any existing collection. For restoring data
+    /// exported elsewhere, so write hooks do not run; unique fields must hold.
+    pub fn restore_collection(&self, name: &str, schema: Schema, docs: Vec<Document>) -> Result<(), String> {
+        let mut col = Collection::new();
+        for field in &schema.unique {
+            col.add_unique(field);
+        }
+        for doc in docs {
+            let id = doc.get("id").and_then(Value::as_str).filter(|id| valid_doc_id(id)).ok_or("Invalid document id")?;
+            col.insert_checked(id.to_string(), doc).map_err(|e| e.to_string())?;
+        }
+        self.collections.write().unwrap().insert(name.to_string(), col);
+        self.schemas.write().unwrap().insert(name.to_string(), schema);
+        self.sync();
+        broadcast_event("collection.created", name, None, None);
+        Ok(())
+    }
+
     fn create_collection_internal(&self, name: &str, fields: Vec<(String, String)>) {
+        self.insert_collection(name, Schema::from_types(fields));
+    }
+
+    fn insert_collection(&self, name: &str, schema: Schema) {
         let mut cols = self.collections.write().unwrap();
         let mut schemas = self.schemas.write().unwrap();
-        cols.insert(name.to_string(), HashMap::new());
-        schemas.insert(name.to_string(), Schema { fields });
+        let mut col = Collection::new();
+        for field in &schema.unique {
+            col.add_unique(field);
+        }
+        cols.insert(name.to_string(), col);
+        schemas.insert(name.to_string(), schema);
+    }
+
+    pub fn schema(&self, collection: &str) -> Option<Schema> {
+        self.schemas.read().unwrap().get(collection).cloned()
+    }
+
+    /// Classify a collection by name. System collections are hidden from
+    /// listings and admin-only; project collections follow their project.
+    pub fn collection_kind(name: &str) -> CollectionKind {
+        if name.starts_with('_') {
+            return CollectionKind::System;
+        }
+        let project = name.strip_prefix("dev-").is_some_and(|rest| {
+            PROJECT_COLLECTIONS
+                .iter()
+                .any(|suffix| rest.strip_suffix(suffix).and_then(|p| p.strip_suffix('_')).is_some_and(|p| !p.is_empty()))
+        });
+        if project { CollectionKind::Project } else { CollectionKind::User }
     }
 
+    /// Non-system collection names, sorted.
     pub fn list_collections(&self) -> Vec<String> {
-        self.schemas
+        self.collections_with_prefix("", false)
+    }
+
+    /// Every collection name, system (`_`) ones included, sorted.
+    pub fn list_all_collections(&self) -> Vec<String> {
+        self.collections_with_prefix("", true)
+    }
+
+    /// Collection names starting with `prefix`, sorted; system collections
+    /// only with `include_system`.
+    pub fn collections_with_prefix(&self, prefix: &str, include_system: bool) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .schemas
             .read()
             .unwrap()
             .keys()
-            .filter(|k| !k.starts_with('_'))
+            .filter(|k| k.starts_with(prefix) && (include_system || Self::collection_kind(k) != CollectionKind::System))
             .cloned()
-            .collect()
+            .collect();
+        names.sort();
+        names
     }
 
-    pub fn list_all_collections(&self) -> Vec<String> {
-        self.schemas.read().unwrap().keys().cloned().collect()
+    /// Document count, serialized size and schema width of every collection, by name.
+    pub fn collection_stats(&self) -> Vec<CollectionStat> {
+        let cols = self.collections.read().unwrap();
+        let schemas = self.schemas.read().unwrap();
+        let mut stats: Vec<CollectionStat> = schemas
+            .iter()
+            .map(|(name, schema)| {
+                let col = cols.get(name);
+                let mut data = Vec::new();
+                for (id, doc) in col.into_iter().flat_map(Collection::iter) {
+                    write_string(&mut data, id);
+                    write_doc(&mut data, doc);
+                }
+                CollectionStat {
+                    name: name.clone(),
+                    documents: col.map_or(0, |c| c.len()),
+                    bytes: data.len(),
+                    fields: schema.fields.len(),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Size of db.bin on disk, if it exists.
+    pub fn file_size(&self) -> Option<u64> {
+        if !self.persist { return None; }
+        fs::metadata(config::db_path()).ok().map(|m| m.len())
+    }
+
+    /// Unix time db.bin was last written by this process (or its mtime at load).
+    pub fn last_sync(&self) -> Option<i64> {
+        Some(self.last_sync.load(Ordering::Relaxed)).filter(|&ts| ts > 0)
+    }
+
+    /// How many operations took longer than DB_SLOW_MS since startup.
+    pub fn slow_operations(&self) -> u64 {
+        self.slow_ops.load(Ordering::Relaxed)
+    }
+
+    /// End `span`, logging it when it took the slow threshold or longer.
+    /// `docs` is how many documents the operation had to deal with, so a
+    /// slow query can be told apart from a big collection.
+    fn finish_span(&self, span: Span, collection: &str, docs: usize) {
+        let elapsed = clock::instant().duration_since(span.start);
+        let threshold = self.slow_ms.load(Ordering::Relaxed);
+        if threshold == 0 || elapsed < Duration::from_millis(threshold) {
+            return;
+        }
+        self.slow_ops.fetch_add(1, Ordering::Relaxed);
+        logging::warn("db", &format!(
+            "slow {} on {}: {} ms ({} documents)", span.op, collection, elapsed.as_millis(), docs
+        ));
+    }
+
+    /// Register a hook run on every new document in `collection` before it is stored.
+    pub fn on_before_insert(&self, collection: &str, hook: WriteHook) {
+        self.before_insert.write().unwrap().entry(collection.to_string()).or_default().push(hook);
+    }
+
+    /// Register a hook run on the merged document before an update is stored.
+    pub fn on_before_update(&self, collection: &str, hook: WriteHook) {
+        self.before_update.write().unwrap().entry(collection.to_string()).or_default().push(hook);
+    }
+
+    fn run_hooks(hooks: &HookMap, collection: &str, doc: &mut Document) -> Result<(), String> {
+        if let Some(hooks) = hooks.read().unwrap().get(collection) {
+            for hook in hooks {
+                hook(doc)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn insert(&self, collection: &str, doc: Document) -> Option<String> {
+        self.try_insert(collection, doc).ok()
+    }
+
+    /// Insert a document, returning the hook's message if a write hook rejects it.
+    pub fn try_insert(&self, collection: &str, doc: Document) -> Result<String, WriteError> {
+        self.insert_doc(collection, doc, None)
+    }
+
+    /// Insert on behalf of a user: `_owner` is set to `owner`, replacing any
+    /// value supplied in the document.
+    pub fn try_insert_owned(&self, collection: &str, doc: Document, owner: &str) -> Result<String, WriteError> {
+        self.insert_doc(collection, doc, Some(owner))
+    }
+
+    /// Insert a document that expires `ttl_secs` from now: `_expires_at` is
+    /// set and the sweeper deletes it once that time has passed.
+    pub fn insert_with_ttl(&self, collection: &str, mut doc: Document, owner: Option<&str>, ttl_secs: i64) -> Result<String, WriteError> {
+        doc.insert(EXPIRES_FIELD.into(), Value::Int(now().saturating_add(ttl_secs)));
+        self.insert_doc(collection, doc, owner)
+    }
+
+    fn insert_doc(&self, collection: &str, doc: Document, owner: Option<&str>) -> Result<String, WriteError> {
+        let span = Span::start("insert");
         let mut cols = self.collections.write().unwrap();
-        let col = cols.get_mut(collection)?;
+        let col = cols.get_mut(collection).ok_or("Collection not found")?;
+        self.check_quota(collection, col)?;
         let id = random_hex(12);
-        let mut doc = doc;
-        doc.insert("id".into(), Value::String(id.clone()));
-        doc.insert("created".into(), Value::Int(now()));
-        doc.insert("updated".into(), Value::Int(now()));
-        col.insert(id.clone(), doc);
+        let doc = self.prepare_insert(collection, doc, owner, &id)?;
+        col.insert_checked(id.clone(), doc)?;
+        let docs = col.len();
         drop(cols);
+        // The sync that follows is timed on its own
+        self.finish_span(span, collection, docs);
         self.sync();
         if let Some(doc) = self.find_one(collection, &id) {
             broadcast_event("doc.created", collection, Some(&doc), Some(&id));
         }
-        Some(id)
+        Ok(id)
+    }
+
+    /// Refuse an insert into a collection already holding its schema's
+    /// `max_docs`; updates and deletes are never limited.
+    fn check_quota(&self, collection: &str, col: &Collection) -> Result<(), WriteError> {
+        match self.schemas.read().unwrap().get(collection) {
+            Some(schema) if schema.max_docs > 0 && col.len() >= schema.max_docs => Err(WriteError::Quota(schema.max_docs)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Set what a collection does with undeclared fields; documents already
+    /// stored keep theirs. `false` for an unknown collection.
+    pub fn set_extra_fields(&self, collection: &str, mode: ExtraFields) -> bool {
+        let mut schemas = self.schemas.write().unwrap();
+        let Some(schema) = schemas.get_mut(collection) else { return false };
+        schema.extra_fields = mode;
+        drop(schemas);
+        self.sync();
+        true
+    }
+
+    /// Set or clear (0) a collection's document quota. Documents already
+    /// over a lowered quota stay; only new inserts are refused. `false` for
+    /// an unknown collection.
+    pub fn set_max_docs(&self, collection: &str, max_docs: usize) -> bool {
+        let mut schemas = self.schemas.write().unwrap();
+        let Some(schema) = schemas.get_mut(collection) else { return false };
+        schema.max_docs = max_docs;
+        drop(schemas);
+        self.sync();
+        true
+    }
+
+    /// Fill schema defaults, run the insert hooks and stamp `id`, `created`,
+    /// `updated` and the owner.
+    fn prepare_insert(&self, collection: &str, mut doc: Document, owner: Option<&str>, id: &str) -> Result<Document, WriteError> {
+        if let Some(schema) = self.schemas.read().unwrap().get(collection) {
+            schema.apply_defaults(&mut doc);
+        }
+        Self::run_hooks(&self.before_insert, collection, &mut doc)?;
+        if let Some(owner) = owner {
+            doc.insert(OWNER_FIELD.into(), Value::String(owner.to_string()));
+        }
+        doc.insert("id".into(), Value::String(id.to_string()));
+        doc.insert("created".into(), Value::Int(now()));
+        doc.insert("updated".into(), Value::Int(now()));
+        Ok(doc)
+    }
+
+    /// Update the document `id`, or insert `doc` under that exact id when it
+    /// does not exist, owned by `owner` if given. `Ok(true)` means it was
+    /// created. The id must pass [`valid_doc_id`].
+    pub fn upsert(&self, collection: &str, id: &str, doc: Document, owner: Option<&str>) -> Result<bool, WriteError> {
+        if !valid_doc_id(id) {
+            return Err("Invalid document id".into());
+        }
+        // Existence check and write share one lock, so two creates cannot race
+        let mut cols = self.collections.write().unwrap();
+        let col = cols.get_mut(collection).ok_or("Collection not found")?;
+        let (doc, created) = match col.get(id) {
+            Some(existing) => (Self::merge_update(&self.before_update, collection, existing, id, doc)?, false),
+            None => {
+                self.check_quota(collection, col)?;
+                (self.prepare_insert(collection, doc, owner, id)?, true)
+            }
+        };
+        col.insert_checked(id.to_string(), doc)?;
+        drop(cols);
+        self.sync();
+        if let Some(doc) = self.find_one(collection, id) {
+            broadcast_event(if created { "doc.created" } else { "doc.updated" }, collection, Some(&doc), Some(id));
+        }
+        Ok(created)
     }
 
     pub fn find_one(&self, collection: &str, id: &str) -> Option<Document> {
@@ -159,38 +783,203 @@ impl Database {
     }
 
     pub fn find_by(&self, collection: &str, field: &str, value: &str) -> Option<Document> {
+        let span = Span::start("find_by");
         let cols = self.collections.read().unwrap();
         let col = cols.get(collection)?;
-        col.values().find(|doc| {
+        let found = col.values().find(|doc| {
             doc.get(field).and_then(|v| v.as_str()) == Some(value)
-        }).cloned()
+        }).cloned();
+        self.finish_span(span, collection, col.len());
+        found
     }
 
+    /// Every document in the collection, oldest first.
     pub fn find_all(&self, collection: &str) -> Vec<Document> {
-        self.collections.read().unwrap().get(collection)
+        let span = Span::start("find_all");
+        let docs: Vec<Document> = self.collections.read().unwrap().get(collection)
             .map(|c| c.values().cloned().collect())
-            .unwrap_or_default()
+            .unwrap_or_default();
+        self.finish_span(span, collection, docs.len());
+        docs
+    }
+
+    /// Cursor pagination in insertion order: up to `limit` documents after
+    /// the document `after` (from the start when `None`). `None` when the
+    /// collection or the cursor does not exist.
+    pub fn find_after(&self, collection: &str, after: Option<&str>, limit: usize) -> Option<Page> {
+        let cols = self.collections.read().unwrap();
+        let col = cols.get(collection)?;
+        // One extra document tells whether another page follows
+        let mut docs: Vec<Document> = col.page_after(after, limit + 1)?.cloned().collect();
+        let next_cursor = (docs.len() > limit).then(|| {
+            docs.truncate(limit);
+            docs.last().and_then(|d| d.get("id")).and_then(Value::as_str).unwrap_or_default().to_string()
+        });
+        Some(Page { docs, next_cursor })
+    }
+
+    /// The distinct values of `field` across a collection, sorted (see
+    /// [`compare_values`]). Documents without the field, or with `null`, are
+    /// skipped; an unknown collection has none.
+    pub fn distinct(&self, collection: &str, field: &str) -> Vec<Value> {
+        let cols = self.collections.read().unwrap();
+        let Some(col) = cols.get(collection) else { return Vec::new() };
+        let mut seen = HashMap::new();
+        for value in col.values().filter_map(|doc| doc.get(field)) {
+            if !matches!(value, Value::Null) {
+                seen.entry(value_to_json(value)).or_insert_with(|| value.clone());
+            }
+        }
+        let mut values: Vec<Value> = seen.into_values().collect();
+        values.sort_by(compare_values);
+        values
     }
 
     pub fn update(&self, collection: &str, id: &str, updates: Document) -> bool {
+        self.try_update(collection, id, updates).unwrap_or(false)
+    }
+
+    /// Update a document. `Ok(false)` means it does not exist; `Err` carries a hook rejection.
+    pub fn try_update(&self, collection: &str, id: &str, updates: Document) -> Result<bool, WriteError> {
+        Ok(self.update_if(collection, id, updates, |_| true)? == ConditionalUpdate::Updated)
+    }
+
+    /// Update a document only if `precondition` holds for the stored one.
+    /// Check and write share the collection lock, so a concurrent writer
+    /// cannot slip in between. `Err` carries a hook rejection.
+    pub fn update_if(
+        &self,
+        collection: &str,
+        id: &str,
+        updates: Document,
+        precondition: impl FnOnce(&Document) -> bool,
+    ) -> Result<ConditionalUpdate, WriteError> {
+        let span = Span::start("update");
         let mut cols = self.collections.write().unwrap();
-        if let Some(col) = cols.get_mut(collection) {
-            if let Some(doc) = col.get_mut(id) {
-                for (k, v) in updates {
-                    if k != "id" && k != "created" {
-                        doc.insert(k, v);
-                    }
+        let Some(col) = cols.get_mut(collection) else { return Ok(ConditionalUpdate::NotFound) };
+        let Some(doc) = col.get(id) else { return Ok(ConditionalUpdate::NotFound) };
+        if !precondition(doc) {
+            return Ok(ConditionalUpdate::Refused);
+        }
+        let merged = Self::merge_update(&self.before_update, collection, doc, id, updates)?;
+        col.insert_checked(id.to_string(), merged)?;
+        let docs = col.len();
+        drop(cols);
+        self.finish_span(span, collection, docs);
+        self.sync();
+        if let Some(doc) = self.find_one(collection, id) {
+            broadcast_event("doc.updated", collection, Some(&doc), Some(id));
+        }
+        Ok(ConditionalUpdate::Updated)
+    }
+
+    /// Apply a JSON Merge Patch to a document (see [`patched`]). `Ok(false)`
+    /// means it does not exist; `Err` carries a hook rejection.
+    pub fn patch(&self, collection: &str, id: &str, patch: Document) -> Result<bool, WriteError> {
+        let mut cols = self.collections.write().unwrap();
+        let Some(col) = cols.get_mut(collection) else { return Ok(false) };
+        let Some(doc) = col.get(id) else { return Ok(false) };
+        let merged = Self::merge_update(&self.before_update, collection, &patched(doc, patch), id, Document::new())?;
+        col.insert_checked(id.to_string(), merged)?;
+        drop(cols);
+        self.sync();
+        if let Some(doc) = self.find_one(collection, id) {
+            broadcast_event("doc.updated", collection, Some(&doc), Some(id));
+        }
+        Ok(true)
+    }
+
+    /// Apply `ops` in order, all or nothing, under one write lock. The ops
+    /// run against copies of the collections they touch, which replace the
+    /// originals only when every op succeeded; the first failure is returned
+    /// as `op N: reason` (from 0) and nothing changes. Inserts are owned by
+    /// `owner` if given. Syncs once.
+    pub fn transaction(&self, ops: Vec<Op>, owner: Option<&str>) -> Result<Vec<OpResult>, String> {
+        let mut cols = self.collections.write().unwrap();
+        let mut staged: HashMap<String, Collection> = HashMap::new();
+        let mut results = Vec::with_capacity(ops.len());
+        for (n, op) in ops.into_iter().enumerate() {
+            let name = match &op {
+                Op::Insert { collection, .. } | Op::Update { collection, .. } | Op::Delete { collection, .. } => collection.clone(),
+            };
+            if !staged.contains_key(&name) {
+                let Some(col) = cols.get(&name) else { return Err(format!("op {}: Collection not found", n)) };
+                staged.insert(name.clone(), col.clone());
+            }
+            let col = staged.get_mut(&name).expect("staged above");
+            let applied = match op {
+                Op::Insert { doc, .. } => {
+                    let id = random_hex(12);
+                    self.check_quota(&name, col)
+                        .and_then(|()| self.prepare_insert(&name, doc, owner, &id))
+                        .and_then(|doc| col.insert_checked(id.clone(), doc))
+                        .map(|()| OpResult::Inserted(id))
                 }
-                doc.insert("updated".into(), Value::Int(now()));
-                drop(cols);
-                self.sync();
-                if let Some(doc) = self.find_one(collection, id) {
-                    broadcast_event("doc.updated", collection, Some(&doc), Some(id));
+                Op::Update { id, doc, .. } => match col.get(&id) {
+                    Some(existing) => Self::merge_update(&self.before_update, &name, existing, &id, doc)
+                        .and_then(|merged| col.insert_checked(id.clone(), merged))
+                        .map(|()| OpResult::Updated(id)),
+                    None => Err("Document not found".into()),
+                },
+                Op::Delete { id, .. } => match col.remove(&id) {
+                    Some(_) => Ok(OpResult::Deleted(id)),
+                    None => Err("Document not found".into()),
+                },
+            };
+            results.push((name, applied.map_err(|e| format!("op {}: {}", n, e))?));
+        }
+        cols.extend(staged);
+        drop(cols);
+        self.sync();
+        for (collection, result) in &results {
+            match result {
+                OpResult::Inserted(id) | OpResult::Updated(id) => {
+                    let kind = if matches!(result, OpResult::Inserted(_)) { "doc.created" } else { "doc.updated" };
+                    if let Some(doc) = self.find_one(collection, id) {
+                        broadcast_event(kind, collection, Some(&doc), Some(id));
+                    }
                 }
-                return true;
+                OpResult::Deleted(id) => broadcast_event("doc.deleted", collection, None, Some(id)),
             }
         }
-        false
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    /// `doc` with `updates` merged in, once the update hooks accept it; `id`,
+    /// `created` and the owner are kept, `updated` is bumped.
+    fn merge_update(hooks: &HookMap, collection: &str, doc: &Document, id: &str, updates: Document) -> Result<Document, WriteError> {
+        let mut merged = doc.clone();
+        for (k, v) in updates {
+            if !IMMUTABLE_FIELDS.contains(&k.as_str()) {
+                merged.insert(k, v);
+            }
+        }
+        Self::run_hooks(hooks, collection, &mut merged)?;
+        merged.insert("id".into(), Value::String(id.to_string()));
+        for field in ["created", OWNER_FIELD] {
+            match doc.get(field) {
+                Some(value) => merged.insert(field.into(), value.clone()),
+                None => merged.remove(field),
+            };
+        }
+        merged.insert("updated".into(), Value::Int(now()));
+        Ok(merged)
+    }
+
+    /// Remove and return the first document whose `field` equals `value`.
+    /// Lookup and removal share one write lock, so concurrent callers cannot
+    /// both take the same document.
+    pub fn take_by(&self, collection: &str, field: &str, value: &str) -> Option<Document> {
+        let mut cols = self.collections.write().unwrap();
+        let col = cols.get_mut(collection)?;
+        let id = col.iter()
+            .find(|(_, doc)| doc.get(field).and_then(|v| v.as_str()) == Some(value))
+            .map(|(id, _)| id.clone())?;
+        let doc = col.remove(&id)?;
+        drop(cols);
+        self.sync();
+        broadcast_event("doc.deleted", collection, None, Some(&id));
+        Some(doc)
     }
 
     pub fn delete(&self, collection: &str, id: &str) -> bool {
@@ -206,8 +995,34 @@ impl Database {
         false
     }
 
+    /// Delete every document whose `_expires_at` is at or before `at`, in all
+    /// collections. Returns how many were removed.
+    pub fn sweep_expired(&self, at: i64) -> usize {
+        let mut cols = self.collections.write().unwrap();
+        let mut removed = Vec::new();
+        for (name, col) in cols.iter_mut() {
+            let expired: Vec<String> = col
+                .iter()
+                .filter(|(_, doc)| matches!(doc.get(EXPIRES_FIELD), Some(Value::Int(t)) if *t <= at))
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in expired {
+                col.remove(&id);
+                removed.push((name.clone(), id));
+            }
+        }
+        drop(cols);
+        if !removed.is_empty() {
+            self.sync();
+        }
+        for (collection, id) in &removed {
+            broadcast_event("doc.deleted", collection, None, Some(id));
+        }
+        removed.len()
+    }
+
     pub fn delete_collection(&self, name: &str) -> bool {
-        if name.starts_with('_') { return false; }
+        if Self::collection_kind(name) == CollectionKind::System { return false; }
         let mut cols = self.collections.write().unwrap();
         let mut schemas = self.schemas.write().unwrap();
         cols.remove(name);
@@ -219,6 +1034,46 @@ impl Database {
         true
     }
 
+    /// Move a collection's documents, schema and write hooks to a new name.
+    /// System (`_`-prefixed) collections can be neither source nor target.
+    pub fn rename_collection(&self, old: &str, new: &str) -> Result<(), RenameError> {
+        if [old, new].iter().any(|n| Self::collection_kind(n) == CollectionKind::System) {
+            return Err(RenameError::Invalid("System collections cannot be renamed"));
+        }
+        if !valid_collection_name(new) {
+            return Err(RenameError::Invalid("Invalid collection name"));
+        }
+        {
+            let mut cols = self.collections.write().unwrap();
+            let mut schemas = self.schemas.write().unwrap();
+            if !schemas.contains_key(old) {
+                return Err(RenameError::NotFound);
+            }
+            if schemas.contains_key(new) {
+                return Err(RenameError::Exists);
+            }
+            if let Some(schema) = schemas.remove(old) {
+                schemas.insert(new.to_string(), schema);
+            }
+            let docs = cols.remove(old).unwrap_or_default();
+            cols.insert(new.to_string(), docs);
+            for hooks in [&self.before_insert, &self.before_update] {
+                let mut hooks = hooks.write().unwrap();
+                if let Some(list) = hooks.remove(old) {
+                    hooks.insert(new.to_string(), list);
+                }
+            }
+        }
+        self.sync();
+        notify_change(old);
+        notify_change(new);
+        realtime::broadcast(&format!(
+            r#"{{"type":"collection.renamed","collection":"{}","from":"{}"}}"#,
+            new, old
+        ));
+        Ok(())
+    }
+
     /// Serialize database to binary
     fn serialize(&self) -> Vec<u8> {
         let cols = self.collections.read().unwrap();
@@ -230,10 +1085,17 @@ impl Database {
         for (name, schema) in schemas.iter() {
             write_string(&mut data, name);
             data.extend(&(schema.fields.len() as u32).to_le_bytes());
-            for (fname, ftype) in &schema.fields {
+            for (fname, ftype, default) in &schema.fields {
                 write_string(&mut data, fname);
                 write_string(&mut data, ftype);
+                let unique = schema.unique.contains(fname);
+                data.push(u8::from(default.is_some()) | u8::from(unique) << 1);
+                if let Some(value) = default {
+                    write_value(&mut data, value);
+                }
             }
+            data.extend(&(schema.max_docs as u32).to_le_bytes());
+            data.push(ExtraFields::ALL.iter().position(|mode| *mode == schema.extra_fields).unwrap_or(0) as u8);
         }
 
         // Write collections
@@ -249,8 +1111,10 @@ impl Database {
         data
     }
 
-    /// Deserialize database from binary
-    fn deserialize(&self, data: &[u8]) {
+    /// Deserialize database from binary written as `version`; version 1 had
+    /// no field defaults, version 2 no document quota, version 3 no
+    /// extra-field mode.
+    fn deserialize(&self, data: &[u8], version: u8) {
         let mut pos = 0;
         let mut schemas = self.schemas.write().unwrap();
         let mut cols = self.collections.write().unwrap();
@@ -261,30 +1125,68 @@ impl Database {
             let name = read_string(data, &mut pos);
             let field_count = read_u32(data, &mut pos);
             let mut fields = Vec::new();
+            let mut unique = Vec::new();
             for _ in 0..field_count {
                 let fname = read_string(data, &mut pos);
                 let ftype = read_string(data, &mut pos);
-                fields.push((fname, ftype));
+                // Flags: bit 0 a default follows, bit 1 unique
+                let flags = if version >= 2 { read_u8(data, &mut pos) } else { 0 };
+                let default = (flags & 1 != 0).then(|| read_value(data, &mut pos));
+                if flags & 2 != 0 {
+                    unique.push(fname.clone());
+                }
+                fields.push((fname, ftype, default));
             }
-            schemas.insert(name.clone(), Schema { fields });
-            cols.insert(name, HashMap::new());
+            let max_docs = if version >= 3 { read_u32(data, &mut pos) as usize } else { 0 };
+            let extra_fields = if version >= 4 { ExtraFields::ALL.get(read_u8(data, &mut pos) as usize).copied() } else { None };
+            schemas.insert(name.clone(), Schema { fields, unique, max_docs, extra_fields: extra_fields.unwrap_or_default() });
+            cols.insert(name, Collection::new());
         }
 
         // Read collections
         while pos < data.len() {
             let name = read_string(data, &mut pos);
             let doc_count = read_u32(data, &mut pos);
-            let col = cols.entry(name).or_insert_with(HashMap::new);
+            let col = cols.entry(name).or_default();
             for _ in 0..doc_count {
                 let id = read_string(data, &mut pos);
                 let doc = read_doc(data, &mut pos);
                 col.insert(id, doc);
             }
         }
+
+        for (name, schema) in schemas.iter() {
+            if let Some(col) = cols.get_mut(name) {
+                for field in &schema.unique {
+                    col.add_unique(field);
+                }
+            }
+        }
     }
 
     /// Sync to encrypted file
     fn sync(&self) {
+        if !self.persist { return; }
+        let _ = fs::create_dir_all(config::data_dir());
+        let _ = self.write_to(&config::db_path());
+    }
+
+    /// Write db.bin now and return its size and the sync time; `None` for
+    /// an in-memory database or when the write fails. Every write already
+    /// syncs, so this is for operators who want a confirmed file on disk,
+    /// e.g. right before a filesystem snapshot.
+    pub fn flush(&self) -> Option<(u64, i64)> {
+        if !self.persist { return None; }
+        let _ = fs::create_dir_all(config::data_dir());
+        let size = self.write_to(&config::db_path()).ok()?;
+        Some((size, self.last_sync()?))
+    }
+
+    /// Encrypt the current state into `path`, returning the bytes written.
+    /// Timed as `sync`, since it grows with the whole database.
+    fn write_to(&self, path: &Path) -> std::io::Result<u64> {
+        let span = Span::start("sync");
+        let docs = self.collections.read().unwrap().values().map(Collection::len).sum();
         let data = self.serialize();
         let nonce: [u8; 12] = random_bytes(12).try_into().unwrap_or([0; 12]);
         let encrypted = chacha20(&self.encryption_key, &nonce, &data);
@@ -293,28 +1195,52 @@ impl Database {
         file_data.extend_from_slice(&nonce);
         file_data.extend(encrypted);
 
-        let data_dir = config::data_dir();
-        let _ = fs::create_dir_all(&data_dir);
-        let _ = fs::write(db_path(), &file_data);
+        fs::write(path, &file_data)?;
+        self.last_sync.store(now(), Ordering::Relaxed);
+        self.finish_span(span, "all collections", docs);
+        Ok(file_data.len() as u64)
     }
 
     /// Load from encrypted file
     fn load(&self) {
-        if let Ok(file_data) = fs::read(db_path()) {
-            if file_data.len() < 14 || file_data[0] != DB_VERSION { return; }
+        if !self.persist { return; }
+        self.load_from(&config::db_path());
+    }
+
+    fn load_from(&self, path: &Path) {
+        if let Ok(file_data) = fs::read(path) {
+            let version = file_data.first().copied().unwrap_or(0);
+            if file_data.len() < 14 || !(1..=DB_VERSION).contains(&version) { return; }
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            if let Some(secs) = modified.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+                self.last_sync.store(secs.as_secs() as i64, Ordering::Relaxed);
+            }
             let nonce: [u8; 12] = file_data[1..13].try_into().unwrap();
             let decrypted = chacha20(&self.encryption_key, &nonce, &file_data[13..]);
-            self.deserialize(&decrypted);
+            self.deserialize(&decrypted, version);
             self.migrate_system_defaults();
         }
     }
 
-    /// Create backup
-    pub fn backup(&self) -> String {
+    /// Copy db.bin to a timestamped backup next to it; `None` for an
+    /// in-memory database, which has no file.
+    pub fn backup(&self) -> Option<String> {
+        if !self.persist { return None; }
         let timestamp = now();
         let backup_path = config::data_dir().join(format!("backup_{}.bin", timestamp));
-        let _ = fs::copy(db_path(), &backup_path);
-        backup_path.to_string_lossy().to_string()
+        let _ = fs::copy(config::db_path(), &backup_path);
+        Some(backup_path.to_string_lossy().to_string())
+    }
+
+    /// Path of the backup called `name` (as [`Database::backup`] names
+    /// them) if it exists; anything else in the data directory is refused.
+    pub fn backup_file(name: &str) -> Option<PathBuf> {
+        let stamp = name.strip_prefix("backup_")?.strip_suffix(".bin")?;
+        if stamp.is_empty() || !stamp.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let path = config::data_dir().join(name);
+        path.is_file().then_some(path)
     }
 
     fn migrate_system_defaults(&self) {
@@ -326,21 +1252,46 @@ impl Database {
         let mut cols = self.collections.write().unwrap();
         let mut schemas = self.schemas.write().unwrap();
         if !schemas.contains_key("_ports") {
-            cols.insert("_ports".to_string(), HashMap::new());
-            schemas.insert("_ports".to_string(), Schema {
-                fields: vec![
-                    ("project".into(), "string".into()),
-                    ("dev_port".into(), "int".into()),
-                    ("prod_port".into(), "int".into()),
-                    ("created".into(), "int".into()),
-                ],
-            });
+            cols.insert("_ports".to_string(), Collection::new());
+        }
+        let ports = schemas.entry("_ports".to_string()).or_insert_with(|| Schema::from_types(Vec::new()));
+        for (name, ty) in ports_fields() {
+            if !ports.fields.iter().any(|(existing, _, _)| *existing == name) {
+                ports.fields.push((name, ty, None));
+            }
+        }
+        // Files written before the registry existed: every project had a `_ports` row
+        if !schemas.contains_key("_projects") {
+            let schema = projects_schema();
+            let mut registry = Collection::new();
+            registry.add_unique("name");
+            for doc in cols.get("_ports").into_iter().flat_map(Collection::values) {
+                let mut entry: Document = ["project", "dev_port", "prod_port", "created"]
+                    .iter()
+                    .filter_map(|key| Some((key.to_string(), doc.get(*key)?.clone())))
+                    .collect();
+                let Some(name) = entry.remove("project") else { continue };
+                entry.insert("name".into(), name);
+                entry.insert("status".into(), Value::String("active".into()));
+                let id = random_hex(12);
+                entry.insert("id".into(), Value::String(id.clone()));
+                let _ = registry.insert_checked(id, entry);
+            }
+            cols.insert("_projects".to_string(), registry);
+            schemas.insert("_projects".to_string(), schema);
+        }
+        // Files written before unique fields existed
+        if let (Some(users), Some(col)) = (schemas.get_mut("_users"), cols.get_mut("_users")) {
+            if !users.unique.iter().any(|field| field == "email") {
+                users.unique.push("email".into());
+                col.add_unique("email");
+            }
         }
     }
 
     fn ensure_settings_defaults(&self) {
         let mut cols = self.collections.write().unwrap();
-        let col = cols.entry("_settings".to_string()).or_insert_with(HashMap::new);
+        let col = cols.entry("_settings".to_string()).or_default();
         if col.is_empty() {
             let mut doc = Document::new();
             doc.insert("page_title".into(), Value::String("Rust Pure Web".into()));
@@ -351,6 +1302,7 @@ impl Database {
             doc.insert("og_image".into(), Value::String("".into()));
             doc.insert("twitter_card".into(), Value::String("summary_large_image".into()));
             doc.insert("canonical_url".into(), Value::String("".into()));
+            doc.insert("favicon".into(), Value::String("".into()));
             doc.insert("nginx_hostname".into(), Value::String("proxy.olibuijr.com".into()));
             doc.insert("nginx_internal_ip".into(), Value::String("192.168.8.4".into()));
             doc.insert("dev_network_name".into(), Value::String("dev".into()));
@@ -369,7 +1321,7 @@ impl Database {
             return;
         }
 
-        for (_id, doc) in col.iter_mut() {
+        for doc in col.values_mut() {
             set_default(doc, "page_title", Value::String("Rust Pure Web".into()));
             set_default(doc, "meta_description", Value::String("Zero-dependency Rust web framework.".into()));
             set_default(doc, "meta_keywords", Value::String("rust, web, zero-deps".into()));
@@ -378,6 +1330,7 @@ impl Database {
             set_default(doc, "og_image", Value::String("".into()));
             set_default(doc, "twitter_card", Value::String("summary_large_image".into()));
             set_default(doc, "canonical_url", Value::String("".into()));
+            set_default(doc, "favicon", Value::String("".into()));
             set_default(doc, "nginx_hostname", Value::String("proxy.olibuijr.com".into()));
             set_default(doc, "nginx_internal_ip", Value::String("192.168.8.4".into()));
             set_default(doc, "dev_network_name", Value::String("dev".into()));
@@ -396,11 +1349,36 @@ impl Database {
 
 }
 
-fn db_path() -> std::path::PathBuf {
-    config::data_dir().join("db.bin")
+/// Schema of `_ports`: a project's dev/prod ports plus its reverse proxy settings
+fn ports_fields() -> Vec<(String, String)> {
+    vec![
+        ("project".into(), "string".into()),
+        ("dev_port".into(), "int".into()),
+        ("prod_port".into(), "int".into()),
+        ("created".into(), "int".into()),
+        ("rewrite_host".into(), "bool".into()),
+        ("access".into(), "string".into()),
+        ("allow_roles".into(), "array".into()),
+        ("allow_users".into(), "array".into()),
+    ]
+}
+
+/// Schema of `_projects`: one entry per project, unique by name. `status`
+/// is `creating` while the project is being set up, then `active`.
+fn projects_schema() -> Schema {
+    let mut schema = Schema::from_types(vec![
+        ("name".into(), "string".into()),
+        ("created".into(), "int".into()),
+        ("dev_port".into(), "int".into()),
+        ("prod_port".into(), "int".into()),
+        ("status".into(), "string".into()),
+    ]);
+    schema.unique.push("name".into());
+    schema
 }
 
 fn broadcast_event(kind: &str, collection: &str, doc: Option<&Document>, id: Option<&str>) {
+    notify_change(collection);
     let mut payload = Vec::new();
     payload.push(format!(r#""type":"{}""#, kind));
     payload.push(format!(r#""collection":"{}""#, collection));
@@ -421,6 +1399,13 @@ fn write_string(data: &mut Vec<u8>, s: &str) {
     data.extend(s.as_bytes());
 }
 
+/// Bytes `doc` takes up in `db.bin`.
+pub fn serialized_size(doc: &Document) -> usize {
+    let mut data = Vec::new();
+    write_doc(&mut data, doc);
+    data.len()
+}
+
 fn write_doc(data: &mut Vec<u8>, doc: &Document) {
     data.extend(&(doc.len() as u32).to_le_bytes());
     for (k, v) in doc {
@@ -457,6 +1442,12 @@ fn read_u32(data: &[u8], pos: &mut usize) -> u32 {
     val
 }
 
+fn read_u8(data: &[u8], pos: &mut usize) -> u8 {
+    let val = data[*pos];
+    *pos += 1;
+    val
+}
+
 fn read_string(data: &[u8], pos: &mut usize) -> String {
     let len = read_u32(data, pos) as usize;
     let s = String::from_utf8_lossy(&data[*pos..*pos+len]).to_string();
@@ -495,39 +1486,964 @@ fn read_value(data: &[u8], pos: &mut usize) -> Value {
     }
 }
 
+/// Collection names are 1-64 ASCII letters, digits, `-` or `_`, so they are
+/// safe as JSON keys and in derived names like `dev-{project}_users`.
+pub fn valid_collection_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_COLLECTION_NAME
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Client-chosen document ids follow the same rules as collection names:
+/// 1-64 ASCII letters, digits, `-` or `_`, safe in URLs and JSON.
+pub fn valid_doc_id(id: &str) -> bool {
+    valid_collection_name(id)
+}
+
+/// Total order for sorting values: booleans, then numbers (ints and floats
+/// together), strings, arrays and objects; within a kind by value, arrays
+/// and objects by their JSON text.
+fn compare_values(a: &Value, b: &Value) -> cmp::Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Int(x), Value::Int(y)) => x.cmp(y),
+        (Value::Int(x), Value::Float(y)) => (*x as f64).total_cmp(y),
+        (Value::Float(x), Value::Int(y)) => x.total_cmp(&(*y as f64)),
+        (Value::Float(x), Value::Float(y)) => x.total_cmp(y),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)).then_with(|| value_to_json(a).cmp(&value_to_json(b))),
+    }
+}
+
+/// Index key of a unique field; null and missing values are not indexed.
+fn unique_key(doc: &Document, field: &str) -> Option<String> {
+    match doc.get(field)? {
+        Value::Null => None,
+        value => Some(value_to_json(value)),
+    }
+}
+
+/// Unix seconds from [`clock::now`], mockable in tests
 pub fn now() -> i64 {
-    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+    clock::now()
+}
+
+/// Start the background thread that deletes expired documents (sessions
+/// included) every [`SWEEP_INTERVAL`].
+pub fn start_sweeper() {
+    thread::spawn(|| loop {
+        thread::sleep(SWEEP_INTERVAL);
+        let removed = get().sweep_expired(now());
+        if removed > 0 {
+            logging::info("db", &format!("swept {} expired documents", removed));
+        }
+    });
 }
 
-/// Initialize database with encryption key
+/// Initialize database with encryption key. With RPW_DB_MEMORY=1 nothing is
+/// loaded from or written to disk.
 pub fn init(key: &str) {
+    if config::db_in_memory() {
+        return init_in_memory(key);
+    }
     let db = DB.get_or_init(|| Database::new(key.as_bytes()));
     db.load();
 }
 
+/// Initialize the global database in memory only; its data is gone on exit.
+pub fn init_in_memory(key: &str) {
+    DB.get_or_init(|| Database::in_memory(key.as_bytes()));
+}
+
 /// Get database reference
 pub fn get() -> &'static Database {
     DB.get().expect("Database not initialized")
 }
 
+/// Functions run after any write to a collection, keyed by collection name.
+static CHANGE_LISTENERS: RwLock<ChangeListeners> = RwLock::new(Vec::new());
+
+/// Run `listener` after every change to `collection` (documents written or
+/// deleted, the collection created, renamed or dropped). Meant for dropping
+/// cached reads.
+pub fn on_change(collection: &str, listener: fn()) {
+    CHANGE_LISTENERS.write().unwrap().push((collection.to_string(), listener));
+}
+
+fn notify_change(collection: &str) {
+    let listeners: Vec<fn()> = CHANGE_LISTENERS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(name, _)| name == collection)
+        .map(|(_, listener)| *listener)
+        .collect();
+    for listener in listeners {
+        listener();
+    }
+}
+
+/// The site settings document. Pages and the proxy read it on every
+/// request, so it is kept until `_settings` changes.
+pub fn settings() -> Option<Document> {
+    settings_cache().get_or_insert_with((), || get().find_all("_settings").into_iter().next())
+}
+
+fn settings_cache() -> &'static Cache<(), Option<Document>> {
+    static SETTINGS: OnceLock<Cache<(), Option<Document>>> = OnceLock::new();
+    SETTINGS.get_or_init(|| {
+        on_change("_settings", || settings_cache().clear());
+        Cache::new(1, Duration::from_secs(300))
+    })
+}
+
 pub fn doc_to_json_for_collection(collection: &str, doc: &Document) -> String {
     let pairs: Vec<String> = doc.iter().filter_map(|(k, v)| {
         if collection == "_users" && k == "password" {
             return None;
         }
-        Some(format!(r#""{}": {}"#, k, value_to_json(v)))
+        Some(format!("{}: {}", Json::wrap_string(k), value_to_json(v)))
     }).collect();
     format!("{{{}}}", pairs.join(", "))
 }
 
+/// RFC 7386 JSON Merge Patch: objects in `patch` merge into `target`
+/// recursively, a `null` removes the field, and anything else (arrays
+/// included) replaces the target value.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !matches!(target, Value::Object(_)) {
+        *target = Value::Object(Document::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            match value {
+                Value::Null => { target.remove(key); }
+                _ => merge_patch(target.entry(key.clone()).or_insert(Value::Null), value),
+            }
+        }
+    }
+}
+
+/// `doc` with `patch` applied by [`merge_patch`]. `id`, `created` and the
+/// owner cannot be patched.
+pub fn patched(doc: &Document, mut patch: Document) -> Document {
+    patch.retain(|key, _| !IMMUTABLE_FIELDS.contains(&key.as_str()));
+    let mut target = Value::Object(doc.clone());
+    merge_patch(&mut target, &Value::Object(patch));
+    match target {
+        Value::Object(doc) => doc,
+        _ => Document::new(),
+    }
+}
+
 pub fn value_to_json(v: &Value) -> String {
     match v {
         Value::Null => "null".into(),
         Value::Bool(b) => if *b { "true" } else { "false" }.into(),
-        Value::Int(i) => i.to_string(),
-        Value::Float(f) => f.to_string(),
-        Value::String(s) => format!(r#""{}""#, s.replace('\\', "\\\\").replace('"', "\\\"")),
+        Value::Int(i) => int_to_json(*i, config::json_numbers()),
+        Value::Float(f) => float_to_json(*f, config::json_numbers().floats),
+        Value::String(s) => Json::wrap_string(s),
         Value::Array(arr) => format!("[{}]", arr.iter().map(value_to_json).collect::<Vec<_>>().join(", ")),
         Value::Object(obj) => doc_to_json_for_collection("", obj),
     }
 }
+
+/// Largest integer a JavaScript number holds exactly (`Number.MAX_SAFE_INTEGER`)
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+fn int_to_json(i: i64, numbers: JsonNumbers) -> String {
+    if numbers.big_ints_as_strings && !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&i) {
+        return format!("\"{}\"", i);
+    }
+    i.to_string()
+}
+
+/// JSON has no NaN or infinity, so those are written as `null`.
+fn float_to_json(f: f64, format: FloatFormat) -> String {
+    if !f.is_finite() {
+        return "null".into();
+    }
+    match format {
+        FloatFormat::Shortest => f.to_string(),
+        FloatFormat::Fixed(decimals) => {
+            let fixed = format!("{:.*}", decimals, f);
+            let trimmed = if fixed.contains('.') { fixed.trim_end_matches('0').trim_end_matches('.') } else { &fixed };
+            if trimmed == "-0" { "0".into() } else { trimmed.to_string() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_collection_name() {
+        for name in ["posts", "dev-shop_users", "_settings", "Orders2024", "a"] {
+            assert!(valid_collection_name(name), "{} should be accepted", name);
+        }
+        for name in ["", "my posts", "a/b", "../x", "quo\"te", "emoji✓", "dot.name", "tab\t"] {
+            assert!(!valid_collection_name(name), "{:?} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn test_collection_kind() {
+        for name in ["_users", "_sessions", "_settings", "_", "_dev-shop_users"] {
+            assert_eq!(Database::collection_kind(name), CollectionKind::System, "{}", name);
+        }
+        for name in ["dev-shop_users", "dev-my-site_settings", "dev-a_sessions", "dev-dev-x_users"] {
+            assert_eq!(Database::collection_kind(name), CollectionKind::Project, "{}", name);
+        }
+        for name in ["posts", "dev-_users", "shop_users", "dev-shop_orders", "dev-shopusers", "users"] {
+            assert_eq!(Database::collection_kind(name), CollectionKind::User, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_project_registry_backfilled_from_ports() {
+        let db = Database::in_memory(b"registry");
+        db.collections.write().unwrap().remove("_projects");
+        db.schemas.write().unwrap().remove("_projects");
+        let mut row = Document::new();
+        row.insert("project".into(), Value::String("old-shop".into()));
+        row.insert("dev_port".into(), Value::Int(3501));
+        row.insert("prod_port".into(), Value::Int(3601));
+        db.insert("_ports", row);
+
+        db.ensure_internal_collections();
+        let entry = db.find_by("_projects", "name", "old-shop").unwrap();
+        assert!(matches!(entry.get("dev_port"), Some(Value::Int(3501))));
+        assert_eq!(entry.get("status").and_then(Value::as_str), Some("active"));
+        assert!(!entry.contains_key("project"));
+        db.ensure_internal_collections();
+        assert_eq!(db.find_all("_projects").len(), 1);
+    }
+
+    #[test]
+    fn test_schema_checks_array_and_object_fields() {
+        let schema = Schema::from_types(vec![("tags".into(), "array".into()), ("meta".into(), "object".into()), ("n".into(), "int".into())]);
+        let doc = crate::api::json::parse_json(r#"{"tags":["a",["b"],{"c":1}],"meta":{"k":[1,2]},"n":3}"#);
+        assert_eq!(schema.check(&doc), Ok(()));
+
+        let wrong_array = crate::api::json::parse_json(r#"{"tags":"a,b"}"#);
+        assert_eq!(schema.check(&wrong_array), Err("Field 'tags' must be of type array".into()));
+        let wrong_object = crate::api::json::parse_json(r#"{"meta":[1]}"#);
+        assert!(schema.check(&wrong_object).is_err());
+        assert_eq!(schema.check(&crate::api::json::parse_json(r#"{"tags":null,"other":1}"#)), Ok(()));
+    }
+
+    #[test]
+    fn test_nested_values_round_trip_through_json() {
+        let input = r#"{"tags":["x","y\n\"z\""],"meta":{"nested":{"list":[1,true,null]}}}"#;
+        let value = crate::api::json::parse_value(input).unwrap();
+        let reparsed = crate::api::json::parse_value(&value_to_json(&value)).expect("valid JSON");
+        let (doc, again) = (value.as_object().unwrap(), reparsed.as_object().unwrap());
+        assert_eq!(value_to_json(&doc["tags"]), r#"["x", "y\n\"z\""]"#);
+        for key in ["tags", "meta"] {
+            assert_eq!(value_to_json(&again[key]), value_to_json(&doc[key]));
+        }
+    }
+
+    #[test]
+    fn test_collection_name_length_bound() {
+        assert!(valid_collection_name(&"x".repeat(MAX_COLLECTION_NAME)));
+        assert!(!valid_collection_name(&"x".repeat(MAX_COLLECTION_NAME + 1)));
+    }
+
+    fn test_db(collection: &str) -> Database {
+        let db = Database::in_memory(b"hook-test");
+        db.create_collection_internal(collection, vec![("total".into(), "int".into())]);
+        db
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let obj = |pairs: &[(&str, Value)]| Value::Object(pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect());
+        let mut target = obj(&[
+            ("title", Value::String("Lamp".into())),
+            ("meta", obj(&[("views", Value::Int(3)), ("tags", Value::Array(vec![Value::Int(1), Value::Int(2)]))])),
+            ("draft", Value::Bool(true)),
+        ]);
+        merge_patch(&mut target, &obj(&[
+            ("meta", obj(&[("views", Value::Int(4)), ("tags", Value::Array(vec![Value::Int(9)])), ("new", obj(&[("gone", Value::Null)]))])),
+            ("draft", Value::Null),
+        ]));
+        let doc = target.as_object().unwrap();
+        assert_eq!(doc.get("title").and_then(Value::as_str), Some("Lamp"));
+        assert!(!doc.contains_key("draft"));
+        let meta = doc.get("meta").and_then(Value::as_object).unwrap();
+        // Nested fields merge, arrays are replaced whole, nulls never get stored
+        assert!(matches!(meta.get("views"), Some(Value::Int(4))));
+        assert!(matches!(meta.get("tags"), Some(Value::Array(items)) if items.len() == 1));
+        assert!(meta.get("new").and_then(Value::as_object).is_some_and(|o| o.is_empty()));
+
+        // A non-object patch replaces the target
+        merge_patch(&mut target, &Value::Int(1));
+        assert!(matches!(target, Value::Int(1)));
+    }
+
+    #[test]
+    fn test_patch_keeps_immutable_fields() {
+        let db = test_db("things");
+        let mut doc = Document::new();
+        doc.insert("total".into(), Value::Int(1));
+        doc.insert("meta".into(), Value::Object(Document::from([("a".to_string(), Value::Int(1))])));
+        let id = db.try_insert("things", doc).unwrap();
+        let mut patch = Document::new();
+        patch.insert("id".into(), Value::String("other".into()));
+        patch.insert("created".into(), Value::Null);
+        patch.insert("meta".into(), Value::Object(Document::from([("b".to_string(), Value::Int(2))])));
+        assert_eq!(db.patch("things", &id, patch), Ok(true));
+        let stored = db.find_one("things", &id).unwrap();
+        assert_eq!(stored.get("id").and_then(Value::as_str), Some(id.as_str()));
+        assert!(stored.contains_key("created"));
+        assert_eq!(stored.get("meta").and_then(Value::as_object).map(|m| m.len()), Some(2));
+        assert_eq!(db.patch("things", "missing", Document::new()), Ok(false));
+    }
+
+    #[test]
+    fn test_written_file_loads_back_to_current_state() {
+        let path = std::env::temp_dir().join(format!("rpw-flush-{}.bin", std::process::id()));
+        let db = Database::in_memory(b"flush-test");
+        db.create_collection("notes", vec![("title".into(), "string".into())]);
+        let mut doc = Document::new();
+        doc.insert("title".into(), Value::String("snapshot me".into()));
+        let id = db.try_insert("notes", doc).unwrap();
+
+        let size = db.write_to(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        assert!(db.last_sync().is_some());
+
+        let copy = Database::in_memory(b"flush-test");
+        copy.load_from(&path);
+        let found = copy.find_one("notes", &id).unwrap();
+        assert_eq!(found.get("title").and_then(Value::as_str), Some("snapshot me"));
+        assert_eq!(copy.find_all("notes").len(), 1);
+        // In-memory databases have no file of their own to flush
+        assert_eq!(db.flush(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_update_if_checks_precondition_under_the_lock() {
+        let db = test_db("orders");
+        let mut doc = Document::new();
+        doc.insert("total".into(), Value::Int(1));
+        let id = db.try_insert("orders", doc).unwrap();
+        let total = |doc: &Document| match doc.get("total") { Some(Value::Int(n)) => Some(*n), _ => None };
+        let mut update = Document::new();
+        update.insert("total".into(), Value::Int(2));
+
+        let refused = db.update_if("orders", &id, update.clone(), |doc| total(doc) == Some(5));
+        assert_eq!(refused, Ok(ConditionalUpdate::Refused));
+        assert_eq!(total(&db.find_one("orders", &id).unwrap()), Some(1));
+        assert_eq!(db.update_if("orders", &id, update.clone(), |doc| total(doc) == Some(1)), Ok(ConditionalUpdate::Updated));
+        assert_eq!(total(&db.find_one("orders", &id).unwrap()), Some(2));
+        assert_eq!(db.update_if("orders", "missing", update, |_| true), Ok(ConditionalUpdate::NotFound));
+    }
+
+    #[test]
+    fn test_slow_operations_are_counted_past_the_threshold() {
+        use crate::clock::MockClock;
+        let clock = MockClock::install(1_700_000_000);
+        let db = Database::in_memory(b"slow-test");
+        db.slow_ms.store(100, Ordering::Relaxed);
+        let span = Span::start("find_all");
+        clock.advance(Duration::from_millis(99));
+        db.finish_span(span, "notes", 3);
+        assert_eq!(db.slow_operations(), 0);
+        let span = Span::start("find_all");
+        clock.advance(Duration::from_millis(100));
+        db.finish_span(span, "notes", 3);
+        assert_eq!(db.slow_operations(), 1);
+        // 0 turns the reporting off
+        db.slow_ms.store(0, Ordering::Relaxed);
+        let span = Span::start("find_all");
+        clock.advance(Duration::from_secs(5));
+        db.finish_span(span, "notes", 3);
+        assert_eq!(db.slow_operations(), 1);
+    }
+
+    #[test]
+    fn test_large_sync_is_reported_as_slow() {
+        let path = std::env::temp_dir().join(format!("rpw-slow-{}.bin", std::process::id()));
+        let db = Database::in_memory(b"slow-sync-test");
+        db.create_collection("bulk", vec![("body".into(), "string".into())]);
+        let mut cols = db.collections.write().unwrap();
+        let bulk = cols.get_mut("bulk").unwrap();
+        for n in 0..20_000 {
+            let mut doc = Document::new();
+            doc.insert("body".into(), Value::String("x".repeat(64)));
+            bulk.insert(format!("doc-{}", n), doc);
+        }
+        drop(cols);
+        // Serializing and encrypting ~2 MB takes well over a millisecond
+        db.slow_ms.store(1, Ordering::Relaxed);
+        db.write_to(&path).unwrap();
+        assert_eq!(db.slow_operations(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_in_memory_database_is_isolated() {
+        let db = Database::in_memory(b"memory-test");
+        let other = Database::in_memory(b"memory-test");
+        db.create_collection("notes", vec![("title".into(), "string".into())]);
+        let mut doc = Document::new();
+        doc.insert("title".into(), Value::String("hello".into()));
+        let id = db.try_insert("notes", doc).unwrap();
+
+        let found = db.find_one("notes", &id).unwrap();
+        assert_eq!(found.get("title").and_then(Value::as_str), Some("hello"));
+        let by_title = db.find_by("notes", "title", "hello").unwrap();
+        assert_eq!(by_title.get("id").and_then(Value::as_str), Some(id.as_str()));
+        assert!(other.find_all("notes").is_empty());
+        assert!(db.list_all_collections().contains(&"_users".to_string()));
+        assert_eq!(db.last_sync(), None);
+        assert_eq!(db.file_size(), None);
+        assert_eq!(db.backup(), None);
+    }
+
+    #[test]
+    fn test_insertion_order_survives_reload() {
+        let db = test_db("orders");
+        let mut ids: Vec<String> = (0..20)
+            .map(|n| {
+                let mut doc = Document::new();
+                doc.insert("total".into(), Value::Int(n));
+                db.try_insert("orders", doc).unwrap()
+            })
+            .collect();
+        assert!(db.delete("orders", &ids.remove(7)));
+        let listed = |db: &Database| -> Vec<String> {
+            db.find_all("orders").iter()
+                .filter_map(|doc| doc.get("id").and_then(Value::as_str).map(String::from))
+                .collect()
+        };
+        assert_eq!(listed(&db), ids);
+
+        let reloaded = Database::in_memory(b"hook-test");
+        reloaded.deserialize(&db.serialize(), DB_VERSION);
+        assert_eq!(listed(&reloaded), ids);
+    }
+
+    #[test]
+    fn test_find_after_pages_without_gaps_or_duplicates() {
+        let db = test_db("events");
+        for n in 0..23 {
+            let mut doc = Document::new();
+            doc.insert("total".into(), Value::Int(n));
+            db.try_insert("events", doc).unwrap();
+        }
+        let mut seen = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let page = db.find_after("events", cursor.as_deref(), 5).unwrap();
+            assert!(page.docs.len() <= 5);
+            seen.extend(page.docs.iter().map(|d| d.get("id").and_then(Value::as_str).unwrap().to_string()));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        let all: Vec<String> = db.find_all("events").iter()
+            .map(|d| d.get("id").and_then(Value::as_str).unwrap().to_string())
+            .collect();
+        assert_eq!(seen, all);
+        assert_eq!(seen.len(), 23);
+
+        let last = db.find_after("events", Some(&all[22]), 5).unwrap();
+        assert!(last.docs.is_empty() && last.next_cursor.is_none());
+        let exact = db.find_after("events", Some(&all[17]), 5).unwrap();
+        assert_eq!(exact.docs.len(), 5);
+        assert!(exact.next_cursor.is_none());
+        assert!(db.find_after("events", Some("no-such-id"), 5).is_none());
+        assert!(db.find_after("missing", None, 5).is_none());
+    }
+
+    #[test]
+    fn test_upsert_creates_then_updates_at_same_id() {
+        let db = test_db("orders");
+        let mut doc = Document::new();
+        doc.insert("total".into(), Value::Int(5));
+        assert_eq!(db.upsert("orders", "order-42", doc, Some("user-1")), Ok(true));
+        let created = db.find_one("orders", "order-42").unwrap();
+        assert_eq!(created.get("id").and_then(Value::as_str), Some("order-42"));
+        assert_eq!(created.get(OWNER_FIELD).and_then(Value::as_str), Some("user-1"));
+
+        let mut update = Document::new();
+        update.insert("total".into(), Value::Int(9));
+        update.insert("id".into(), Value::String("other".into()));
+        assert_eq!(db.upsert("orders", "order-42", update, Some("user-2")), Ok(false));
+        let updated = db.find_one("orders", "order-42").unwrap();
+        assert!(matches!(updated.get("total"), Some(Value::Int(9))));
+        assert_eq!(updated.get(OWNER_FIELD).and_then(Value::as_str), Some("user-1"));
+        assert!(matches!((created.get("created"), updated.get("created")), (Some(Value::Int(a)), Some(Value::Int(b))) if a == b));
+        assert_eq!(db.find_all("orders").len(), 1);
+
+        assert!(db.upsert("orders", "../x", Document::new(), None).is_err());
+        assert!(db.upsert("orders", "", Document::new(), None).is_err());
+        assert!(db.upsert("missing", "a", Document::new(), None).is_err());
+    }
+
+    fn defaults_db() -> Database {
+        let db = Database::in_memory(b"defaults-test");
+        db.create_collection_with_schema("orders", Schema {
+            fields: vec![
+                ("status".into(), "string".into(), Some(Value::String("pending".into()))),
+                ("total".into(), "int".into(), None),
+            ],
+            unique: Vec::new(),
+            max_docs: 0,
+            extra_fields: ExtraFields::Off,
+        });
+        db
+    }
+
+    #[test]
+    fn test_schema_defaults_fill_missing_fields() {
+        let db = defaults_db();
+        let id = db.try_insert("orders", Document::new()).unwrap();
+        assert_eq!(db.find_one("orders", &id).unwrap().get("status").and_then(Value::as_str), Some("pending"));
+        assert!(!db.find_one("orders", &id).unwrap().contains_key("total"));
+
+        let mut explicit = Document::new();
+        explicit.insert("status".into(), Value::String("paid".into()));
+        let id = db.try_insert("orders", explicit).unwrap();
+        assert_eq!(db.find_one("orders", &id).unwrap().get("status").and_then(Value::as_str), Some("paid"));
+
+        assert_eq!(db.upsert("orders", "by-put", Document::new(), None), Ok(true));
+        assert_eq!(db.find_one("orders", "by-put").unwrap().get("status").and_then(Value::as_str), Some("pending"));
+    }
+
+    #[test]
+    fn test_defaults_survive_reload_and_old_format_loads() {
+        let db = defaults_db();
+        let reloaded = Database::in_memory(b"defaults-test");
+        reloaded.deserialize(&db.serialize(), DB_VERSION);
+        let id = reloaded.try_insert("orders", Document::new()).unwrap();
+        assert_eq!(reloaded.find_one("orders", &id).unwrap().get("status").and_then(Value::as_str), Some("pending"));
+
+        // Version 1: schema fields were (name, type) only
+        let mut v1 = Vec::new();
+        v1.extend(&1u32.to_le_bytes());
+        write_string(&mut v1, "legacy");
+        v1.extend(&1u32.to_le_bytes());
+        write_string(&mut v1, "title");
+        write_string(&mut v1, "string");
+        write_string(&mut v1, "legacy");
+        v1.extend(&1u32.to_le_bytes());
+        write_string(&mut v1, "doc-1");
+        let mut doc = Document::new();
+        doc.insert("title".into(), Value::String("old".into()));
+        write_doc(&mut v1, &doc);
+
+        let old = Database::in_memory(b"defaults-test");
+        old.deserialize(&v1, 1);
+        let fields = old.schema("legacy").unwrap().fields;
+        assert!(matches!(fields.as_slice(), [(name, ty, None)] if name == "title" && ty == "string"));
+        assert_eq!(old.find_one("legacy", "doc-1").unwrap().get("title").and_then(Value::as_str), Some("old"));
+    }
+
+    #[test]
+    fn test_change_listeners_follow_writes() {
+        use std::sync::atomic::AtomicUsize;
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let db = test_db("watched");
+        on_change("watched", || { CALLS.fetch_add(1, Ordering::Relaxed); });
+        let id = db.insert("watched", Document::new()).unwrap();
+        db.update("watched", &id, Document::new());
+        db.delete("watched", &id);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+        db.rename_collection("watched", "watched-2").unwrap();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_settings_are_cached_until_changed() {
+        init_in_memory("settings-cache-test");
+        let before = settings();
+        let id = match &before {
+            Some(doc) => doc.get("id").and_then(Value::as_str).unwrap().to_string(),
+            None => get().insert("_settings", Document::new()).unwrap(),
+        };
+        let mut update = Document::new();
+        update.insert("cache_probe".into(), Value::Int(7));
+        assert!(get().update("_settings", &id, update));
+        assert!(matches!(settings().and_then(|s| s.get("cache_probe").cloned()), Some(Value::Int(7))));
+    }
+
+    #[test]
+    fn test_quota_refuses_inserts_past_the_cap() {
+        let db = test_db("inbox");
+        assert!(db.set_max_docs("inbox", 2));
+        let first = db.try_insert("inbox", Document::new()).unwrap();
+        assert_eq!(db.upsert("inbox", "second", Document::new(), None), Ok(true));
+        assert_eq!(db.try_insert("inbox", Document::new()), Err(WriteError::Quota(2)));
+        assert_eq!(db.upsert("inbox", "third", Document::new(), None), Err(WriteError::Quota(2)));
+        let insert = || Op::Insert { collection: "inbox".into(), doc: Document::new() };
+        assert!(db.transaction(vec![insert()], None).is_err());
+        // Updates still work at the cap, and a delete frees a slot
+        assert_eq!(db.upsert("inbox", "second", Document::new(), None), Ok(false));
+        assert!(db.delete("inbox", &first));
+        assert!(db.transaction(vec![insert()], None).is_ok());
+        assert_eq!(db.try_insert("inbox", Document::new()), Err(WriteError::Quota(2)));
+        assert_eq!(WriteError::Quota(2).to_string(), "Collection is full (2 documents max)");
+
+        let reloaded = Database::in_memory(b"quota-test");
+        reloaded.deserialize(&db.serialize(), DB_VERSION);
+        assert_eq!(reloaded.schema("inbox").unwrap().max_docs, 2);
+        assert!(db.set_max_docs("inbox", 0));
+        assert!(db.try_insert("inbox", Document::new()).is_ok());
+        assert!(!db.set_max_docs("missing", 1));
+    }
+
+    #[test]
+    fn test_extra_field_modes() {
+        let db = test_db("orders");
+        let mut doc = Document::new();
+        doc.insert("total".into(), Value::Int(3));
+        doc.insert("totl".into(), Value::Int(3));
+        doc.insert(OWNER_FIELD.into(), Value::String("u1".into()));
+        let schema = db.schema("orders").unwrap();
+        assert_eq!(schema.extra_fields, ExtraFields::Off);
+        assert_eq!(schema.unknown_fields(&doc), ["totl"]);
+        assert!(schema.check(&doc).is_ok());
+
+        assert!(db.set_extra_fields("orders", ExtraFields::Strict));
+        let schema = db.schema("orders").unwrap();
+        assert_eq!(schema.check(&doc), Err("Unknown field(s) for this collection: totl".into()));
+        doc.remove("totl");
+        assert!(schema.check(&doc).is_ok());
+        assert!(!db.set_extra_fields("missing", ExtraFields::Lenient));
+
+        let reloaded = Database::in_memory(b"extra-fields-test");
+        reloaded.deserialize(&db.serialize(), DB_VERSION);
+        assert_eq!(reloaded.schema("orders").unwrap().extra_fields, ExtraFields::Strict);
+        assert_eq!(ExtraFields::parse("lenient"), Some(ExtraFields::Lenient));
+        assert_eq!(ExtraFields::parse("loose"), None);
+    }
+
+    #[test]
+    fn test_sweep_removes_only_expired_documents() {
+        let db = test_db("links");
+        let expired = db.insert_with_ttl("links", Document::new(), None, 30).unwrap();
+        let live = db.insert_with_ttl("links", Document::new(), Some("user-1"), 3600).unwrap();
+        let plain = db.try_insert("links", Document::new()).unwrap();
+        assert_eq!(db.find_one("links", &live).unwrap().get(OWNER_FIELD).and_then(Value::as_str), Some("user-1"));
+
+        assert_eq!(db.sweep_expired(now()), 0);
+        assert_eq!(db.sweep_expired(now() + 60), 1);
+        assert!(db.find_one("links", &expired).is_none());
+        assert!(db.find_one("links", &live).is_some());
+        assert!(db.find_one("links", &plain).is_some());
+    }
+
+    #[test]
+    fn test_collection_names_filter_by_prefix() {
+        let db = Database::in_memory(b"prefix-test");
+        for name in ["dev-shop_users", "orders", "dev-blog_posts", "_audit", "dev-blog_users"] {
+            db.create_collection(name, Vec::new());
+        }
+        assert_eq!(db.collections_with_prefix("dev-", false), ["dev-blog_posts", "dev-blog_users", "dev-shop_users"]);
+        assert_eq!(db.collections_with_prefix("dev-blog", false), ["dev-blog_posts", "dev-blog_users"]);
+        assert!(!db.list_collections().iter().any(|name| name.starts_with('_')));
+        assert!(db.collections_with_prefix("_", true).contains(&"_audit".to_string()));
+        assert!(db.collections_with_prefix("_", false).is_empty());
+    }
+
+    #[test]
+    fn test_distinct_skips_missing_and_sorts() {
+        let db = test_db("products");
+        for category in [Some("tools"), Some("garden"), None, Some("tools"), Some("books")] {
+            let mut doc = Document::new();
+            if let Some(category) = category {
+                doc.insert("category".into(), Value::String(category.into()));
+            }
+            db.try_insert("products", doc).unwrap();
+        }
+        let mut null = Document::new();
+        null.insert("category".into(), Value::Null);
+        db.try_insert("products", null).unwrap();
+
+        let names: Vec<_> = db.distinct("products", "category").iter().filter_map(|v| v.as_str().map(String::from)).collect();
+        assert_eq!(names, ["books", "garden", "tools"]);
+        assert!(db.distinct("products", "missing").is_empty());
+        assert!(db.distinct("no-such-collection", "category").is_empty());
+
+        for total in [Value::Int(3), Value::Float(1.5), Value::Int(3), Value::Int(-2)] {
+            let mut doc = Document::new();
+            doc.insert("total".into(), total);
+            db.try_insert("products", doc).unwrap();
+        }
+        let totals = db.distinct("products", "total");
+        assert!(matches!(totals.as_slice(), [Value::Int(-2), Value::Float(f), Value::Int(3)] if *f == 1.5));
+    }
+
+    #[test]
+    fn test_transaction_applies_all_ops() {
+        let db = test_db("orders");
+        db.create_collection_internal("stock", vec![("total".into(), "int".into())]);
+        let mut item = Document::new();
+        item.insert("total".into(), Value::Int(5));
+        assert_eq!(db.upsert("stock", "widget", item, None), Ok(true));
+        let gone = db.try_insert("orders", Document::new()).unwrap();
+
+        let mut order = Document::new();
+        order.insert("total".into(), Value::Int(1));
+        let mut decrement = Document::new();
+        decrement.insert("total".into(), Value::Int(4));
+        let results = db.transaction(vec![
+            Op::Insert { collection: "orders".into(), doc: order },
+            Op::Update { collection: "stock".into(), id: "widget".into(), doc: decrement },
+            Op::Delete { collection: "orders".into(), id: gone.clone() },
+        ], Some("user-1")).unwrap();
+
+        let OpResult::Inserted(order_id) = &results[0] else { panic!("expected an insert, got {:?}", results[0]) };
+        assert_eq!(results[1..], [OpResult::Updated("widget".into()), OpResult::Deleted(gone.clone())]);
+        let order = db.find_one("orders", order_id).unwrap();
+        assert_eq!(order.get(OWNER_FIELD).and_then(Value::as_str), Some("user-1"));
+        assert!(matches!(db.find_one("stock", "widget").unwrap().get("total"), Some(Value::Int(4))));
+        assert!(db.find_one("orders", &gone).is_none());
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_on_failure() {
+        let db = unique_db();
+        let kept = db.try_insert("products", sku("A-1")).unwrap();
+        let err = db.transaction(vec![
+            Op::Insert { collection: "products".into(), doc: sku("B-1") },
+            Op::Delete { collection: "products".into(), id: kept.clone() },
+            Op::Update { collection: "products".into(), id: "missing".into(), doc: sku("C-1") },
+            Op::Insert { collection: "products".into(), doc: sku("D-1") },
+        ], None).unwrap_err();
+        assert_eq!(err, "op 2: Document not found");
+        assert_eq!(db.find_all("products").len(), 1);
+        assert!(db.find_one("products", &kept).is_some());
+
+        // Staged ops see each other, so a batch cannot duplicate a unique value
+        let err = db.transaction(vec![
+            Op::Insert { collection: "products".into(), doc: sku("B-1") },
+            Op::Insert { collection: "products".into(), doc: sku("B-1") },
+        ], None).unwrap_err();
+        assert_eq!(err, format!("op 1: {}", WriteError::Unique("sku".into())));
+        assert_eq!(db.find_all("products").len(), 1);
+        assert!(db.transaction(vec![Op::Delete { collection: "nope".into(), id: "x".into() }], None).is_err());
+    }
+
+    fn unique_db() -> Database {
+        let db = Database::in_memory(b"unique-test");
+        let mut schema = Schema::from_types(vec![("sku".into(), "string".into())]);
+        schema.unique.push("sku".into());
+        db.create_collection_with_schema("products", schema);
+        db
+    }
+
+    fn sku(value: &str) -> Document {
+        let mut doc = Document::new();
+        doc.insert("sku".into(), Value::String(value.into()));
+        doc
+    }
+
+    #[test]
+    fn test_unique_field_rejects_duplicate_insert() {
+        let db = unique_db();
+        db.try_insert("products", sku("A-1")).unwrap();
+        assert_eq!(db.try_insert("products", sku("A-1")), Err(WriteError::Unique("sku".into())));
+        assert_eq!(db.upsert("products", "p2", sku("A-1"), None), Err(WriteError::Unique("sku".into())));
+        assert_eq!(db.find_all("products").len(), 1);
+
+        // Missing and null values are not unique
+        db.try_insert("products", Document::new()).unwrap();
+        db.try_insert("products", Document::new()).unwrap();
+        let mut null = Document::new();
+        null.insert("sku".into(), Value::Null);
+        db.try_insert("products", null).unwrap();
+        assert_eq!(db.find_all("products").len(), 4);
+    }
+
+    #[test]
+    fn test_unique_field_on_update() {
+        let db = unique_db();
+        let a = db.try_insert("products", sku("A-1")).unwrap();
+        let b = db.try_insert("products", sku("B-1")).unwrap();
+        assert_eq!(db.try_update("products", &b, sku("A-1")), Err(WriteError::Unique("sku".into())));
+        assert_eq!(db.find_one("products", &b).unwrap().get("sku").and_then(Value::as_str), Some("B-1"));
+
+        // A document may keep its own value
+        assert_eq!(db.try_update("products", &a, sku("A-1")), Ok(true));
+        assert_eq!(db.upsert("products", &a, sku("A-1"), None), Ok(false));
+
+        // A freed value can be taken, by update or after a delete
+        assert_eq!(db.try_update("products", &a, sku("A-2")), Ok(true));
+        assert_eq!(db.try_update("products", &b, sku("A-1")), Ok(true));
+        assert!(db.delete("products", &b));
+        db.try_insert("products", sku("A-1")).unwrap();
+    }
+
+    #[test]
+    fn test_unique_fields_survive_reload() {
+        let db = unique_db();
+        db.try_insert("products", sku("A-1")).unwrap();
+        let reloaded = Database::in_memory(b"unique-test");
+        reloaded.deserialize(&db.serialize(), DB_VERSION);
+        assert_eq!(reloaded.schema("products").unwrap().unique, vec!["sku".to_string()]);
+        assert_eq!(reloaded.try_insert("products", sku("A-1")), Err(WriteError::Unique("sku".into())));
+    }
+
+    #[test]
+    fn test_take_by_removes_once() {
+        let db = test_db("tokens");
+        let mut doc = Document::new();
+        doc.insert("token".into(), Value::String("abc".into()));
+        db.try_insert("tokens", doc).unwrap();
+        let taken = db.take_by("tokens", "token", "abc").unwrap();
+        assert_eq!(taken.get("token").and_then(Value::as_str), Some("abc"));
+        assert!(db.take_by("tokens", "token", "abc").is_none());
+        assert!(db.find_all("tokens").is_empty());
+    }
+
+    #[test]
+    fn test_before_insert_hook_stamps_field() {
+        let db = test_db("orders");
+        db.on_before_insert("orders", Box::new(|doc| {
+            doc.insert("updated_by".into(), Value::String("hook".into()));
+            Ok(())
+        }));
+        let id = db.try_insert("orders", Document::new()).unwrap();
+        let doc = db.find_one("orders", &id).unwrap();
+        assert_eq!(doc.get("updated_by").and_then(Value::as_str), Some("hook"));
+    }
+
+    #[test]
+    fn test_hooks_reject_documents() {
+        let db = test_db("orders");
+        let check_total: fn(&mut Document) -> Result<(), String> = |doc| match doc.get("total") {
+            Some(Value::Int(n)) if *n >= 0 => Ok(()),
+            _ => Err("total must be non-negative".into()),
+        };
+        db.on_before_insert("orders", Box::new(check_total));
+        db.on_before_update("orders", Box::new(check_total));
+
+        let mut bad = Document::new();
+        bad.insert("total".into(), Value::Int(-5));
+        assert_eq!(db.try_insert("orders", bad.clone()), Err("total must be non-negative".into()));
+        assert!(db.find_all("orders").is_empty());
+
+        let mut good = Document::new();
+        good.insert("total".into(), Value::Int(10));
+        let id = db.try_insert("orders", good).unwrap();
+        assert!(db.try_update("orders", &id, bad).is_err());
+        assert!(matches!(db.find_one("orders", &id).unwrap().get("total"), Some(Value::Int(10))));
+        assert_eq!(db.try_update("orders", "missing", Document::new()), Ok(false));
+    }
+
+    #[test]
+    fn test_owner_is_stamped_and_immutable() {
+        let db = test_db("notes");
+        let mut doc = Document::new();
+        doc.insert(OWNER_FIELD.into(), Value::String("spoofed".into()));
+        let id = db.try_insert_owned("notes", doc, "user-a").unwrap();
+        let owner = |db: &Database| db.find_one("notes", &id).unwrap().get(OWNER_FIELD).and_then(Value::as_str).map(String::from);
+        assert_eq!(owner(&db).as_deref(), Some("user-a"));
+
+        let mut updates = Document::new();
+        updates.insert(OWNER_FIELD.into(), Value::String("user-b".into()));
+        updates.insert("total".into(), Value::Int(1));
+        assert_eq!(db.try_update("notes", &id, updates), Ok(true));
+        assert_eq!(owner(&db).as_deref(), Some("user-a"));
+
+        // Unowned documents cannot gain an owner through an update either
+        let plain = db.try_insert("notes", Document::new()).unwrap();
+        let mut claim = Document::new();
+        claim.insert(OWNER_FIELD.into(), Value::String("user-b".into()));
+        db.try_update("notes", &plain, claim).unwrap();
+        assert!(!db.find_one("notes", &plain).unwrap().contains_key(OWNER_FIELD));
+    }
+
+    #[test]
+    fn test_rename_collection_moves_documents_and_schema() {
+        let db = test_db("orders");
+        let mut doc = Document::new();
+        doc.insert("total".into(), Value::Int(7));
+        let id = db.try_insert("orders", doc).unwrap();
+
+        assert_eq!(db.rename_collection("orders", "purchases"), Ok(()));
+        assert!(db.find_one("orders", &id).is_none());
+        assert!(matches!(db.find_one("purchases", &id).unwrap().get("total"), Some(Value::Int(7))));
+        let names = db.list_all_collections();
+        assert!(names.contains(&"purchases".to_string()) && !names.contains(&"orders".to_string()));
+        assert_eq!(db.schemas.read().unwrap()["purchases"].fields.len(), 1);
+    }
+
+    #[test]
+    fn test_rename_collection_rejections() {
+        let db = test_db("orders");
+        db.create_collection_internal("invoices", Vec::new());
+        assert_eq!(db.rename_collection("orders", "invoices"), Err(RenameError::Exists));
+        assert_eq!(db.rename_collection("missing", "other"), Err(RenameError::NotFound));
+        assert!(matches!(db.rename_collection("orders", "bad name"), Err(RenameError::Invalid(_))));
+        assert!(matches!(db.rename_collection("_users", "people"), Err(RenameError::Invalid(_))));
+        assert!(matches!(db.rename_collection("orders", "_settings"), Err(RenameError::Invalid(_))));
+        // Nothing moved
+        assert!(db.list_all_collections().contains(&"orders".to_string()));
+    }
+
+    #[test]
+    fn test_collection_stats_counts_seeded_documents() {
+        let db = test_db("orders");
+        for total in [1, 2, 3] {
+            let mut doc = Document::new();
+            doc.insert("total".into(), Value::Int(total));
+            db.try_insert("orders", doc).unwrap();
+        }
+        let stats = db.collection_stats();
+        let orders = stats.iter().find(|s| s.name == "orders").expect("orders listed");
+        assert_eq!((orders.documents, orders.fields), (3, 1));
+        assert!(orders.bytes > 0);
+        let users = stats.iter().find(|s| s.name == "_users").expect("system collections listed");
+        assert_eq!((users.documents, users.bytes), (0, 0));
+        assert!(stats.windows(2).all(|w| w[0].name <= w[1].name));
+    }
+
+    #[test]
+    fn test_float_formats() {
+        let sum = 0.1 + 0.2;
+        assert_eq!(float_to_json(sum, FloatFormat::Shortest), "0.30000000000000004");
+        assert_eq!(float_to_json(sum, FloatFormat::Fixed(6)), "0.3");
+        assert_eq!(float_to_json(-273.16, FloatFormat::Fixed(1)), "-273.2");
+        assert_eq!(float_to_json(2.0, FloatFormat::Fixed(2)), "2");
+        assert_eq!(float_to_json(-0.0000001, FloatFormat::Fixed(3)), "0");
+        assert_eq!(float_to_json(1e-7, FloatFormat::Shortest), "0.0000001");
+        for f in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(float_to_json(f, FloatFormat::Shortest), "null");
+        }
+    }
+
+    #[test]
+    fn test_big_ints_can_be_strings() {
+        let strings = JsonNumbers { floats: FloatFormat::Shortest, big_ints_as_strings: true };
+        let numbers = JsonNumbers { big_ints_as_strings: false, ..strings };
+        assert_eq!(int_to_json(9_007_199_254_740_993, strings), "\"9007199254740993\"");
+        assert_eq!(int_to_json(-9_007_199_254_740_992, strings), "\"-9007199254740992\"");
+        assert_eq!(int_to_json(i64::MAX, strings), "\"9223372036854775807\"");
+        assert_eq!(int_to_json(MAX_SAFE_INTEGER, strings), "9007199254740991");
+        assert_eq!(int_to_json(-MAX_SAFE_INTEGER, strings), "-9007199254740991");
+        assert_eq!(int_to_json(9_007_199_254_740_993, numbers), "9007199254740993");
+    }
+}
@@ -3,118 +3,664 @@ use std::fs;
 use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
-use std::time::UNIX_EPOCH;
-use crate::{api, auth, config, logging, pages, realtime, template, ws};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use crate::api::json::{parse_json, JsonSerializer as Json};
+use crate::crypto::{hex_encode, random_hex, sha256};
+use crate::negotiate::Format;
+use crate::{api, auth, compress, config, db, ipfilter, logging, maintenance, negotiate, pages, realtime, template, urlpath, ws};
 
 const RELOAD_SCRIPT: &str = r#"<script>
 (function(){let m=0;setInterval(async()=>{const r=await fetch('/__dev/mtime');const t=await r.text();if(m&&t!==m)location.reload();m=t;},500);})();
 </script>"#;
 
+/// Upper bounds for what a single request may make us buffer.
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+pub const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long a client may go quiet while sending its request. Requests are
+/// read on the accept thread, so a stalled one would hold up everyone.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Also what a request line without a version (HTTP/0.9 style) is taken as.
+const HTTP_1_0: &str = "HTTP/1.0";
+
 pub fn handle(mut stream: TcpStream) {
-    let mut buffer = [0; 8192];
-    let n = stream.read(&mut buffer).unwrap_or(0);
-    let request = String::from_utf8_lossy(&buffer[..n]);
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let read = read_request(&mut stream);
+    // Upgraded and streaming connections do their own reading from here
+    let _ = stream.set_read_timeout(None);
+    let (request, refused) = match read {
+        Ok(request) => (request, None),
+        Err(Incomplete::TooLarge(head)) => (head, Some("413 Payload Too Large")),
+        // Nothing sent at all (an idle preconnect): just hang up
+        Err(Incomplete::TimedOut(partial)) if partial.is_empty() => return,
+        Err(Incomplete::TimedOut(partial)) => (partial, Some("408 Request Timeout")),
+    };
+
+    let (method, path, query, version, headers, raw_body) = parse_request(&request);
+    let request_id = request_id(&headers);
+    let client_ip = client_ip(&stream, &headers);
+    let _scope = logging::request_scope(&request_id);
 
-    let (method, path, query, headers, body) = parse_request(&request);
+    if let Some(status) = refused {
+        reject(&mut stream, &method, &path, status, &request_id);
+        return;
+    }
+
+    // HTTP/1.1 made Host mandatory (RFC 9112 §3.2); 1.0 clients may omit it.
+    if version != HTTP_1_0 && !headers.contains_key("host") {
+        reject(&mut stream, &method, &path, "400 Bad Request", &request_id);
+        return;
+    }
+    let Some(path) = urlpath::normalize(&path) else {
+        reject(&mut stream, &method, &path, "400 Bad Request", &request_id);
+        return;
+    };
+    let body = match decode_body(&headers, &raw_body) {
+        Ok(body) => body,
+        Err(status) => {
+            reject(&mut stream, &method, &path, status, &request_id);
+            return;
+        }
+    };
 
-    if is_websocket(&headers) && path == "/realtime" {
-        if !authorize_realtime(&headers, &query) {
+    if path == "/realtime" {
+        let format = negotiate::preferred(headers.get("accept").map(String::as_str), &path);
+        let refused = if method == "OPTIONS" {
+            Some(("200 OK", Vec::new(), "text/plain", true, vec![("Allow".to_string(), "OPTIONS, GET".to_string())]))
+        } else if !is_websocket(&headers) {
+            Some(upgrade_error("426 Upgrade Required", "/realtime only speaks WebSocket; send Upgrade: websocket.", format))
+        } else {
+            ws::upgrade_problem(&method, &headers).map(|problem| upgrade_error("400 Bad Request", problem, format))
+        };
+        if let Some(routed) = refused {
+            respond(&mut stream, &method, &path, &request_id, routed);
+            return;
+        }
+        let Some(token) = authorize_realtime(&headers, &query) else {
             let _ = stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
             return;
+        };
+        if let Ok(protocol) = ws::handshake(&mut stream, &headers, &config::realtime_protocols()) {
+            realtime::register(stream, token, protocol);
+        }
+        return;
+    }
+
+    if method == "GET" && path == "/api/events/stream" {
+        let Some(token) = authorize_event_stream(&headers, &query) else {
+            reject(&mut stream, &method, &path, "401 Unauthorized", &request_id);
+            return;
+        };
+        let last_event_id = headers.get("last-event-id").and_then(|id| id.trim().parse().ok());
+        realtime::register_event_stream(stream, &event_stream_head(&path, &request_id), token, last_event_id);
+        return;
+    }
+
+    if is_websocket(&headers) && path == "/ws/echo" {
+        if !config::ws_echo() {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+            return;
+        }
+        if let Some(problem) = ws::upgrade_problem(&method, &headers) {
+            let format = negotiate::preferred(headers.get("accept").map(String::as_str), &path);
+            respond(&mut stream, &method, &path, &request_id, upgrade_error("400 Bad Request", problem, format));
+            return;
         }
-        if ws::handshake(&mut stream, &headers).is_ok() {
-            realtime::register(stream);
+        if ws::handshake(&mut stream, &headers, &[]).is_ok() {
+            thread::spawn(move || ws::echo(stream));
         }
         return;
     }
 
-    let (status, content, content_type, cors) = route(&method, &path, &headers, &body);
+    // A long poll or an AI chat holds its connection open; it gets its own
+    // thread so the accept loop keeps serving everyone else.
+    if runs_long(&method, &path, &query) {
+        thread::spawn(move || {
+            let _scope = logging::request_scope(&request_id);
+            let routed = route(&method, &path, &query, &headers, &body, &request_id, &client_ip);
+            respond(&mut stream, &method, &path, &request_id, routed);
+        });
+        return;
+    }
+
+    let routed = route(&method, &path, &query, &headers, &body, &request_id, &client_ip);
+    respond(&mut stream, &method, &path, &request_id, routed);
+}
+
+/// `GET /api/admin/logs?follow` waits for new log lines; `POST
+/// /api/admin/chat` waits on Ollama, retries included.
+fn runs_long(method: &str, path: &str, query: &str) -> bool {
+    (path == "/api/admin/logs" && api::utils::query_param(query, "follow").is_some())
+        || (method == "POST" && path == "/api/admin/chat")
+}
+
+fn respond<S: Write>(stream: &mut S, method: &str, path: &str, request_id: &str, routed: Routed) {
+    let (status, content, content_type, cors, extra_headers) = routed;
     logging::info("http", &format!("{} {} -> {}", method, path, status));
 
     let mut response = format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nX-Content-Type-Options: nosniff\r\nX-Frame-Options: DENY\r\nReferrer-Policy: same-origin\r\n",
-        status, content_type, content.len()
+        "HTTP/1.1 {}\r\nX-Request-Id: {}\r\n", status, request_id
     );
+    // A 304 carries no body, so it must not describe one either.
+    if status != NOT_MODIFIED {
+        response.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n", content_type, content.len()));
+    }
+    for (name, value) in security_headers(&config::SecurityHeaders::from_env(), path) {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
     if cors {
-        // CORS origin is configurable via CORS_ORIGIN env var (defaults to "*" for development)
-        // Production deployments should set a specific origin (e.g., "https://example.com")
-        let origin = config::cors_origin();
+        // CORS_ORIGIN sets the origin (default "*" for development); CORS_ROUTES
+        // can give path prefixes their own origin and methods
+        let (origin, methods) = config::cors_for(path);
         response.push_str(&format!(
-            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Headers: Content-Type, Authorization, X-Requested-With\r\nAccess-Control-Allow-Methods: GET,POST,PUT,DELETE,OPTIONS\r\n",
-            origin
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Headers: Content-Type, Authorization, X-Requested-With\r\nAccess-Control-Allow-Methods: {}\r\n",
+            origin, methods
         ));
     }
+    // Every connection carries one request, which is also what an HTTP/1.0
+    // client assumes unless it asked for keep-alive; say so for 1.1 clients.
+    if !extra_headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("connection")) {
+        response.push_str("Connection: close\r\n");
+    }
+    for (name, value) in extra_headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
     response.push_str("\r\n");
 
     let _ = stream.write_all(response.as_bytes());
     let _ = stream.write_all(&content);
 }
 
-fn parse_request(req: &str) -> (String, String, String, HashMap<String, String>, String) {
-    let mut lines = req.lines();
+/// Answer a request that never reaches routing with an empty error response.
+fn reject(stream: &mut TcpStream, method: &str, path: &str, status: &str, request_id: &str) {
+    logging::warn("http", &format!("{} {} -> {}", method, path, status));
+    let _ = stream.write_all(format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n", status, request_id
+    ).as_bytes());
+}
+
+/// Why [`read_request`] gave up before the whole request arrived.
+#[derive(Debug)]
+enum Incomplete {
+    /// Content-Length over [`MAX_BODY_BYTES`]; just the head, body unread.
+    TooLarge(Vec<u8>),
+    /// The client went quiet past the read timeout; whatever it had sent.
+    TimedOut(Vec<u8>),
+}
+
+fn timed_out(err: &std::io::Error) -> bool {
+    matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+/// Read the request head, answer `Expect: 100-continue` with an interim
+/// response (never to an HTTP/1.0 client, which does not know them), then
+/// read the body up to its Content-Length.
+fn read_request<S: Read + Write>(stream: &mut S) -> Result<Vec<u8>, Incomplete> {
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 8192];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            break buf.len();
+        }
+        match stream.read(&mut tmp) {
+            Err(err) if timed_out(&err) => return Err(Incomplete::TimedOut(buf)),
+            Ok(0) | Err(_) => break buf.len(),
+            Ok(n) => buf.extend_from_slice(&tmp[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_lowercase();
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            (k.trim() == name).then(|| v.trim().to_string())
+        })
+    };
+    let declared = header("content-length").unwrap_or_default();
+    let length = match declared.parse::<usize>() {
+        Ok(length) => length,
+        // Too many digits for a usize is over the cap as well
+        Err(_) if !declared.is_empty() && declared.bytes().all(|b| b.is_ascii_digit()) => usize::MAX,
+        Err(_) => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        buf.truncate(head_end);
+        return Err(Incomplete::TooLarge(buf));
+    }
+
+    if buf.len() < head_end + length {
+        let http_1_0 = head.lines().next().is_none_or(|line| !line.trim_end().ends_with("http/1.1"));
+        if header("expect").as_deref() == Some("100-continue") && !http_1_0 {
+            let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+        while buf.len() < head_end + length {
+            match stream.read(&mut tmp) {
+                Err(err) if timed_out(&err) => return Err(Incomplete::TimedOut(buf)),
+                Ok(0) | Err(_) => break,
+                Ok(n) => buf.extend_from_slice(&tmp[..n]),
+            }
+        }
+    }
+    buf.truncate(head_end + length);
+    Ok(buf)
+}
+
+fn parse_request(req: &[u8]) -> (String, String, String, String, HashMap<String, String>, Vec<u8>) {
+    let (head, body) = match req.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => (&req[..pos], &req[pos + 4..]),
+        None => (req, &[][..]),
+    };
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
     let first = lines.next().unwrap_or("");
     let mut parts = first.split_whitespace();
-    let method = parts.next().unwrap_or("GET").to_string();
+    let method = parts.next().unwrap_or("GET").to_ascii_uppercase();
     let raw_path = parts.next().unwrap_or("/");
     let (path, query) = raw_path.split_once('?').unwrap_or((raw_path, ""));
     let path = path.to_string();
     let query = query.to_string();
+    let version = parts.next().map_or(HTTP_1_0.to_string(), str::to_ascii_uppercase);
 
     let mut headers = HashMap::new();
-    let mut body_start = false;
-    let mut body = String::new();
-
     for line in lines {
-        if line.is_empty() {
-            body_start = true;
-            continue;
-        }
-        if body_start {
-            body.push_str(line);
-        } else if let Some((k, v)) = line.split_once(':') {
+        if let Some((k, v)) = line.split_once(':') {
             headers.insert(k.trim().to_lowercase(), v.trim().to_string());
         }
     }
 
-    (method, path, query, headers, body)
+    (method, path, query, version, headers, body.to_vec())
+}
+
+/// Request body as text, inflated first when sent with a gzip/deflate
+/// `Content-Encoding`. The inflated size is capped like a plain body.
+fn decode_body(headers: &HashMap<String, String>, body: &[u8]) -> Result<String, &'static str> {
+    let Some(encoding) = headers.get("content-encoding") else {
+        return Ok(String::from_utf8_lossy(body).into_owned());
+    };
+    match compress::decode(encoding, body, MAX_BODY_BYTES) {
+        Some(Ok(bytes)) => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        Some(Err(compress::InflateError::TooLarge)) => Err("413 Payload Too Large"),
+        Some(Err(_)) => Err("400 Bad Request"),
+        None => Err("415 Unsupported Media Type"),
+    }
+}
+
+type Routed = (&'static str, Vec<u8>, &'static str, bool, Vec<(String, String)>);
+
+/// Hardening headers for a response; project pages get their own framing policy
+/// so they can be embedded.
+fn security_headers(cfg: &config::SecurityHeaders, path: &str) -> Vec<(&'static str, String)> {
+    let mut headers = vec![("X-Content-Type-Options", "nosniff".to_string())];
+    let frame = if path.starts_with("/projects/") { &cfg.project_frame_options } else { &cfg.frame_options };
+    if let Some(value) = frame {
+        headers.push(("X-Frame-Options", value.clone()));
+    }
+    if let Some(value) = &cfg.referrer_policy {
+        headers.push(("Referrer-Policy", value.clone()));
+    }
+    if let Some(value) = &cfg.content_security_policy {
+        headers.push(("Content-Security-Policy", value.clone()));
+    }
+    headers
+}
+
+/// Reuse the caller's `X-Request-Id` when it is a sane token, otherwise mint one.
+pub fn request_id(headers: &HashMap<String, String>) -> String {
+    match headers.get("x-request-id") {
+        Some(id) if valid_request_id(id) => id.clone(),
+        _ => random_hex(8),
+    }
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
 }
 
-fn route(method: &str, path: &str, headers: &HashMap<String, String>, body: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
+/// Peer address, or the proxy's `X-Forwarded-For` when the peer is the local proxy.
+fn client_ip(stream: &TcpStream, headers: &HashMap<String, String>) -> String {
+    let peer = match stream.peer_addr() {
+        Ok(addr) => addr.ip(),
+        Err(_) => return String::new(),
+    };
+    if peer.is_loopback() {
+        if let Some(forwarded) = headers.get("x-forwarded-for") {
+            if let Some(first) = forwarded.split(',').next().map(str::trim).filter(|ip| !ip.is_empty()) {
+                return first.to_string();
+            }
+        }
+    }
+    peer.to_string()
+}
+
+fn route(method: &str, path: &str, query: &str, headers: &HashMap<String, String>, body: &str, request_id: &str, client_ip: &str) -> Routed {
+    // `/docs/` and `/docs` are one route; only project directories keep the
+    // slash, so relative links in their pages resolve.
+    let dir_path = path;
+    let path = urlpath::trim_trailing_slash(path);
+
     // Handle OPTIONS for CORS
     if method == "OPTIONS" {
-        return ("200 OK", Vec::new(), "text/plain", true);
+        return ("200 OK", Vec::new(), "text/plain", true, Vec::new());
+    }
+
+    let req = api::Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers: headers.clone(),
+        body: body.to_string(),
+        request_id: request_id.to_string(),
+        client_ip: client_ip.to_string(),
+        signed: auth::verify_signed_url(method, path, query),
+    };
+
+    let format = negotiate::preferred(headers.get("accept").map(String::as_str), path);
+    let panel = config::admin_panel_path();
+
+    if ipfilter::is_admin_path(path, panel.as_deref()) && !ipfilter::IpFilter::from_env().permits(client_ip) {
+        logging::warn("ipfilter", &format!("blocked {} from {}", path, client_ip));
+        return error_response("403 Forbidden", "You do not have access to this page.", format);
+    }
+
+    if maintenance::blocks(path, panel.as_deref(), auth::is_admin(&api::utils::get_token(&req))) {
+        return maintenance_response(format);
+    }
+
+    // Backups are binary, so they are sent from here rather than as JSON
+    if let Some(name) = path.strip_prefix("/api/admin/backups/") {
+        return backup_download(&req, name, format);
     }
 
     // API routes
     if path.starts_with("/api/") {
-        let req = api::Request {
-            method: method.to_string(),
-            path: path.to_string(),
-            headers: headers.clone(),
-            body: body.to_string(),
-        };
-        let res = api::handle(&req);
-        let status = match res.status {
-            200 => "200 OK",
-            201 => "201 Created",
-            400 => "400 Bad Request",
-            401 => "401 Unauthorized",
-            404 => "404 Not Found",
+        return api_routed(api::handle(&req), format);
+    }
+
+    let (status, content, content_type, cors, extra_headers) = page(path, dir_path, headers, panel.as_deref());
+    if status == NOT_FOUND {
+        return error_response(NOT_FOUND, "The page you are looking for does not exist.", format);
+    }
+    (status, content, content_type, cors, extra_headers)
+}
+
+/// `GET /api/admin/backups/{name}`: a backup file, for an admin session or
+/// the signed URL `POST /api/admin/backup` hands out.
+fn backup_download(req: &api::Request, name: &str, format: Format) -> Routed {
+    if req.method != "GET" {
+        return api_routed(api::negotiated(req, api::Response::not_found()), format);
+    }
+    if !api::utils::require_admin(req) {
+        return api_routed(api::negotiated(req, api::Response::unauthorized()), format);
+    }
+    let Some(data) = db::Database::backup_file(name).and_then(|path| fs::read(path).ok()) else {
+        return api_routed(api::negotiated(req, api::Response::not_found()), format);
+    };
+    let disposition = format!("attachment; filename=\"{}\"", name);
+    (status_line(200), data, "application/octet-stream", true, vec![("Content-Disposition".to_string(), disposition)])
+}
+
+/// An API response with its status line; errors become plain text when
+/// the client asked for it.
+fn api_routed(res: api::Response, format: Format) -> Routed {
+    let status = status_line(res.status);
+    if res.status >= 400 && format == Format::Text {
+        let message = parse_json(&res.body).get("error").and_then(|v| v.as_str()).unwrap_or(status_reason(res.status)).to_string();
+        return (status, message.into_bytes(), Format::Text.content_type(), true, res.headers);
+    }
+    (status, res.body.into_bytes(), "application/json", true, res.headers)
+}
+
+/// Status line for a code, with the reason phrase from RFC 9110 (plus 429
+/// from RFC 6585). An unregistered code is sent as the `x00` of its class,
+/// which is how a client has to read it anyway.
+pub fn status_line(code: u16) -> &'static str {
+    match code {
+        100 => "100 Continue",
+        101 => "101 Switching Protocols",
+        200 => "200 OK",
+        201 => "201 Created",
+        202 => "202 Accepted",
+        203 => "203 Non-Authoritative Information",
+        204 => "204 No Content",
+        205 => "205 Reset Content",
+        206 => "206 Partial Content",
+        300 => "300 Multiple Choices",
+        301 => "301 Moved Permanently",
+        302 => "302 Found",
+        303 => "303 See Other",
+        304 => "304 Not Modified",
+        307 => "307 Temporary Redirect",
+        308 => "308 Permanent Redirect",
+        400 => "400 Bad Request",
+        401 => "401 Unauthorized",
+        402 => "402 Payment Required",
+        403 => "403 Forbidden",
+        404 => "404 Not Found",
+        405 => "405 Method Not Allowed",
+        406 => "406 Not Acceptable",
+        407 => "407 Proxy Authentication Required",
+        408 => "408 Request Timeout",
+        409 => "409 Conflict",
+        410 => "410 Gone",
+        411 => "411 Length Required",
+        412 => "412 Precondition Failed",
+        413 => "413 Payload Too Large",
+        414 => "414 URI Too Long",
+        415 => "415 Unsupported Media Type",
+        416 => "416 Range Not Satisfiable",
+        417 => "417 Expectation Failed",
+        421 => "421 Misdirected Request",
+        422 => "422 Unprocessable Content",
+        426 => "426 Upgrade Required",
+        428 => "428 Precondition Required",
+        429 => "429 Too Many Requests",
+        431 => "431 Request Header Fields Too Large",
+        451 => "451 Unavailable For Legal Reasons",
+        500 => "500 Internal Server Error",
+        501 => "501 Not Implemented",
+        502 => "502 Bad Gateway",
+        503 => "503 Service Unavailable",
+        504 => "504 Gateway Timeout",
+        505 => "505 HTTP Version Not Supported",
+        _ => match code / 100 {
+            1 => "100 Continue",
+            2 => "200 OK",
+            3 => "300 Multiple Choices",
+            4 => "400 Bad Request",
             _ => "500 Internal Server Error",
-        };
-        return (status, res.body.into_bytes(), "application/json", true);
+        },
     }
+}
+
+/// Reason phrase alone, e.g. `"Conflict"` for 409.
+pub fn status_reason(code: u16) -> &'static str {
+    let line = status_line(code);
+    line.split_once(' ').map_or(line, |(_, reason)| reason)
+}
 
-    // Page routes
+/// Page routes. The admin panel answers only at `panel`; without one the
+/// path falls through to static files like any other.
+fn page(path: &str, dir_path: &str, headers: &HashMap<String, String>, panel: Option<&str>) -> Routed {
     match path {
-        "/__dev/mtime" if config::hot_reload() => get_mtime(),
-        "/__dev/mtime" => ("404 Not Found", b"Not Found".to_vec(), "text/plain", false),
-        "/" | "/index.html" => render_page(pages::index().render()),
-        "/_admin" => render_admin(),
-        p if p.starts_with("/docs") => render_page(render_docs(p)),
-        p if p.starts_with("/projects/") => serve_project(p),
-        _ => serve_file(path),
+        "/__dev/mtime" if config::hot_reload() => with_no_headers(get_mtime()),
+        "/__dev/mtime" => with_no_headers(not_found()),
+        "/" | "/index.html" => with_no_headers(render_page(pages::index().render())),
+        p if panel == Some(p) => with_no_headers(render_admin()),
+        p if urlpath::under(p, "/docs") => with_no_headers(render_docs(p)),
+        p if urlpath::under(p, "/projects") => static_response(serve_project(dir_path), path, headers),
+        "/favicon.ico" => static_response(serve_favicon(), path, headers),
+        "/robots.txt" => static_response(serve_robots(panel, &site_url(headers)), path, headers),
+        "/sitemap.xml" => static_response(serve_sitemap(&site_url(headers)), path, headers),
+        _ => static_response(serve_file(path), path, headers),
+    }
+}
+
+const NOT_FOUND: &str = "404 Not Found";
+const NOT_MODIFIED: &str = "304 Not Modified";
+
+/// Smallest body worth gzipping; below this the header overhead wins.
+const GZIP_MIN_BYTES: usize = 256;
+
+fn with_no_headers((status, content, content_type, cors): (&'static str, Vec<u8>, &'static str, bool)) -> Routed {
+    (status, content, content_type, cors, Vec::new())
+}
+
+/// Caching and compression for files served from disk, shared by `public/`
+/// and project sites: a strong ETag over the body, 304 when `If-None-Match`
+/// matches it, gzip for compressible types the client accepts, and a
+/// year-long cache for files that never change at their URL.
+fn static_response(
+    (status, content, content_type, cors): (&'static str, Vec<u8>, &'static str, bool),
+    path: &str,
+    headers: &HashMap<String, String>,
+) -> Routed {
+    if status != "200 OK" {
+        return (status, content, content_type, cors, Vec::new());
+    }
+    let compressible = is_compressible(content_type);
+    let gzip = compressible
+        && content.len() >= GZIP_MIN_BYTES
+        && accepts_gzip(headers.get("accept-encoding").map(String::as_str).unwrap_or(""));
+    // The encoded and identity bodies differ, so they get distinct tags.
+    let etag = format!("\"{}{}\"", &hex_encode(&sha256(&content))[..16], if gzip { "-gz" } else { "" });
+
+    let mut extra = vec![
+        ("ETag".to_string(), etag.clone()),
+        ("Cache-Control".to_string(), cache_control(path, content_type).to_string()),
+    ];
+    if compressible {
+        extra.push(("Vary".to_string(), "Accept-Encoding".to_string()));
     }
+    if headers.get("if-none-match").is_some_and(|v| etag_matches(v, &etag)) {
+        return (NOT_MODIFIED, Vec::new(), content_type, cors, extra);
+    }
+    if gzip {
+        extra.push(("Content-Encoding".to_string(), "gzip".to_string()));
+        return (status, compress::gzip(&content), content_type, cors, extra);
+    }
+    (status, content, content_type, cors, extra)
+}
+
+/// Whether an `If-None-Match` list names `etag` (weak comparison, `*` matches).
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether `Accept-Encoding` allows gzip, i.e. lists it without `q=0`.
+fn accepts_gzip(header: &str) -> bool {
+    header.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or("");
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        (coding.eq_ignore_ascii_case("gzip") || coding == "*") && q > 0.0
+    })
+}
+
+/// woff and woff2 are compressed already; ttf and otf are not.
+fn is_compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || matches!(
+            content_type,
+            "application/javascript" | "application/json" | "image/svg+xml" | "application/xml" | "font/ttf" | "font/otf"
+        )
+}
+
+/// Fonts and fingerprinted assets (`app.3f9a2c1b.js`) are cached for a year
+/// without revalidation: a new version ships under a new name. Everything
+/// else is revalidated against its ETag on each use.
+fn cache_control(path: &str, content_type: &str) -> &'static str {
+    if content_type.starts_with("font/") || is_fingerprinted(path) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    }
+}
+
+/// Whether the file name carries a content hash: a `.` or `-` separated
+/// part of at least 8 hex digits, some of them numeric, before the extension.
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or("");
+    let Some((stem, _)) = name.rsplit_once('.') else { return false };
+    stem.split(['.', '-']).skip(1).any(|part| {
+        part.len() >= 8 && part.bytes().all(|b| b.is_ascii_hexdigit()) && part.bytes().any(|b| b.is_ascii_digit())
+    })
+}
+
+/// Content type for a file extension; unknown ones are served as bytes.
+fn mime_type(ext: &str) -> &'static str {
+    match ext.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "application/javascript",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Page-route miss; `route` turns it into a negotiated error response.
+fn not_found() -> (&'static str, Vec<u8>, &'static str, bool) {
+    (NOT_FOUND, Vec::new(), "text/plain", false)
+}
+
+/// Error response in the negotiated format: `{"error":...}`, the styled
+/// error page, or the bare message.
+fn error_response(status: &'static str, detail: &str, format: Format) -> Routed {
+    let (code, reason) = status.split_once(' ').unwrap_or((status, ""));
+    let (body, cors) = match format {
+        Format::Json => (format!("{{{}}}", Json::key_string("error", reason)).into_bytes(), true),
+        Format::Text => (reason.as_bytes().to_vec(), true),
+        Format::Html => {
+            let mut ctx = pages::settings_context(reason);
+            ctx.set("status_code", code);
+            ctx.set("message", reason);
+            ctx.set("detail", detail);
+            (render_page(template::render(&template::load("error.html"), &ctx)).1, false)
+        }
+    };
+    (status, body, format.content_type(), cors, Vec::new())
+}
+
+/// 503 for visitors while maintenance mode is on: the "be right back" page,
+/// or a plain error for API and non-HTML clients.
+fn maintenance_response(format: Format) -> Routed {
+    const STATUS: &str = "503 Service Unavailable";
+    let (status, body, content_type, cors, mut headers) = match format {
+        Format::Html => {
+            let ctx = pages::settings_context("Maintenance");
+            let (_, html, content_type, cors) = render_page(template::render(&template::load("maintenance.html"), &ctx));
+            (STATUS, html, content_type, cors, Vec::new())
+        }
+        _ => error_response(STATUS, "Down for maintenance", format),
+    };
+    headers.push(("Retry-After".to_string(), maintenance::RETRY_AFTER_SECS.to_string()));
+    (status, body, content_type, cors, headers)
 }
 
 fn render_page(mut html: String) -> (&'static str, Vec<u8>, &'static str, bool) {
@@ -125,9 +671,12 @@ fn render_page(mut html: String) -> (&'static str, Vec<u8>, &'static str, bool)
     ("200 OK", html.into_bytes(), "text/html", false)
 }
 
-fn render_docs(path: &str) -> String {
+fn render_docs(path: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
     let slug = path.trim_start_matches("/docs").trim_start_matches('/');
-    pages::docs(slug).render()
+    match pages::docs(slug) {
+        Some(page) => render_page(page.render()),
+        None => not_found(),
+    }
 }
 
 fn render_admin() -> (&'static str, Vec<u8>, &'static str, bool) {
@@ -141,18 +690,29 @@ fn render_admin() -> (&'static str, Vec<u8>, &'static str, bool) {
 }
 
 fn serve_project(path: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
-    let rel_path = path.trim_start_matches("/projects/").trim_start_matches('/');
-    let mut file_path = config::root_dir().join("projects").join(rel_path);
+    serve_project_from(&config::root_dir().join("projects"), path.trim_start_matches("/projects"))
+}
 
-    // If directory, look for index.html
+fn serve_project_from(root: &Path, rel_path: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
+    let Some(mut file_path) = urlpath::resolve_normalized(root, rel_path) else {
+        return not_found();
+    };
+
+    // A directory serves its default document, a listing when enabled, or 404
     if file_path.is_dir() {
-        file_path = file_path.join("index.html");
+        match directory_index(&file_path, &config::index_files()) {
+            Some(index) => file_path = index,
+            None if config::dir_listing() => {
+                return render_page(render_listing(&file_path, &format!("/projects{}", rel_path)));
+            }
+            None => return not_found(),
+        }
     }
 
     if let Ok(content) = fs::read(&file_path) {
         let ext = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
-        match ext {
-            "html" => {
+        match mime_type(ext) {
+            "text/html" => {
                 let html_str = String::from_utf8_lossy(&content).to_string();
                 let mut ctx = pages::settings_context("Project");
                 // Add defaults for footer/nav
@@ -163,16 +723,69 @@ fn serve_project(path: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
                 let (status, bytes, ct, cors) = render_page(rendered);
                 (status, bytes, ct, cors)
             }
-            "css" => ("200 OK", content, "text/css", false),
-            "js" => ("200 OK", content, "application/javascript", false),
-            "png" => ("200 OK", content, "image/png", false),
-            "jpg" | "jpeg" => ("200 OK", content, "image/jpeg", false),
-            "svg" => ("200 OK", content, "image/svg+xml", false),
-            _ => ("200 OK", content, "text/plain", false),
+            ct => ("200 OK", content, ct, false),
         }
     } else {
-        ("404 Not Found", b"Not Found".to_vec(), "text/plain", false)
+        not_found()
+    }
+}
+
+/// First of `names` that exists as a file inside `dir`.
+fn directory_index(dir: &Path, names: &[String]) -> Option<PathBuf> {
+    names.iter().map(|name| dir.join(name)).find(|p| p.is_file())
+}
+
+/// Plain HTML index of `dir`: directories first, then files, each with its
+/// size and modification time (UTC). Links are absolute under `url_path`.
+fn render_listing(dir: &Path, url_path: &str) -> String {
+    let base = url_path.trim_end_matches('/');
+    let mut entries: Vec<(bool, String, u64, u64)> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .filter_map(|entry| {
+                    let meta = entry.metadata().ok()?;
+                    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                    Some((meta.is_dir(), entry.file_name().to_string_lossy().into_owned(), meta.len(), modified))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let title = template::escape_html(&format!("Index of {}/", base));
+    let mut html = format!("<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h1>{0}</h1><table>", title);
+    html.push_str("<tr><th>Name</th><th>Size</th><th>Modified</th></tr>");
+    if base != "/projects" {
+        html.push_str(&format!("<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>", template::escape_html(&base[..base.rfind('/').unwrap_or(0)])));
     }
+    for (is_dir, name, size, modified) in entries {
+        let name = template::escape_html(&name);
+        let slash = if is_dir { "/" } else { "" };
+        let size = if is_dir { "-".to_string() } else { size.to_string() };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}/{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            template::escape_html(base), name, slash, name, slash, size, utc_datetime(modified)
+        ));
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+/// `YYYY-MM-DD HH:MM` for a Unix timestamp (proleptic Gregorian, UTC).
+fn utc_datetime(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let (hour, minute) = (secs % 86_400 / 3600, secs % 3600 / 60);
+    // Civil-from-days, counting eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute)
 }
 
 fn get_mtime() -> (&'static str, Vec<u8>, &'static str, bool) {
@@ -199,26 +812,95 @@ fn get_mtime() -> (&'static str, Vec<u8>, &'static str, bool) {
 fn serve_file(path: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
     let file_path = match safe_public_path(path) {
         Some(p) => p,
-        None => return ("404 Not Found", b"Not Found".to_vec(), "text/plain", false),
+        None => return not_found(),
     };
 
     if let Ok(mut content) = fs::read(&file_path) {
-        let ct = match Path::new(path).extension().and_then(|e| e.to_str()) {
-            Some("html") => { content = inject_reload(content); "text/html" }
-            Some("css") => "text/css",
-            Some("js") => "application/javascript",
-            Some("png") => "image/png",
-            Some("jpg") | Some("jpeg") => "image/jpeg",
-            Some("svg") => "image/svg+xml",
-            Some("json") => "application/json",
-            Some("woff2") => "font/woff2",
-            Some("ico") => "image/x-icon",
-            _ => "text/plain",
-        };
+        let ct = mime_type(Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or(""));
+        if ct == "text/html" {
+            content = inject_reload(content);
+        }
         ("200 OK", content, ct, false)
     } else {
-        ("404 Not Found", b"Not Found".to_vec(), "text/plain", false)
+        not_found()
+    }
+}
+
+/// `/favicon.ico`, which browsers ask for on every page whether or not a
+/// page links one. Nothing to show is a 204, not a 404 logged each time.
+fn serve_favicon() -> (&'static str, Vec<u8>, &'static str, bool) {
+    let configured = db::settings().and_then(|s| s.get("favicon").and_then(db::Value::as_str).map(str::to_string));
+    match favicon_from(&config::public_dir(), configured.as_deref()) {
+        Some((content, content_type)) => ("200 OK", content, content_type, false),
+        None => ("204 No Content", Vec::new(), "image/x-icon", false),
+    }
+}
+
+/// The icon under `public`: the file the `favicon` setting names, else
+/// `favicon.ico`, else the bundled `favicon.svg`.
+fn favicon_from(public: &Path, configured: Option<&str>) -> Option<(Vec<u8>, &'static str)> {
+    let configured = configured.map(str::trim).filter(|p| !p.is_empty()).map(|p| format!("/{}", p.trim_start_matches('/')));
+    configured.iter().map(String::as_str).chain(["/favicon.ico", "/favicon.svg"]).find_map(|path| {
+        let file = urlpath::resolve_normalized(public, path)?;
+        let content = fs::read(&file).ok()?;
+        Some((content, mime_type(file.extension().and_then(|e| e.to_str()).unwrap_or(""))))
+    })
+}
+
+/// `/robots.txt`: `public/robots.txt` when there is one, else [`robots_txt`].
+fn serve_robots(panel: Option<&str>, site: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
+    match serve_file("/robots.txt") {
+        found @ ("200 OK", ..) => found,
+        _ => ("200 OK", robots_txt(panel, site).into_bytes(), "text/plain", false),
+    }
+}
+
+/// Crawlers may read the pages, docs and project sites, but not the API,
+/// the dev endpoints or the admin panel.
+fn robots_txt(panel: Option<&str>, site: &str) -> String {
+    let mut out = String::from("User-agent: *\nAllow: /\nDisallow: /api/\nDisallow: /__dev/\n");
+    if let Some(panel) = panel {
+        out.push_str(&format!("Disallow: {}\n", panel));
+    }
+    out.push_str(&format!("Sitemap: {}/sitemap.xml\n", site));
+    out
+}
+
+/// `/sitemap.xml`: `public/sitemap.xml` when there is one, else [`sitemap_xml`]
+/// over the docs and the current projects.
+fn serve_sitemap(site: &str) -> (&'static str, Vec<u8>, &'static str, bool) {
+    match serve_file("/sitemap.xml") {
+        found @ ("200 OK", ..) => found,
+        _ => ("200 OK", sitemap_xml(site, &api::projects::project_names()).into_bytes(), "application/xml", false),
+    }
+}
+
+fn sitemap_xml(site: &str, projects: &[String]) -> String {
+    let paths = ["/".to_string(), "/docs".to_string()]
+        .into_iter()
+        .chain(pages::DOC_SLUGS.iter().map(|slug| format!("/docs/{}", slug)))
+        .chain(projects.iter().map(|name| format!("/projects/{}/", name)));
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for path in paths {
+        out.push_str(&format!("  <url><loc>{}</loc></url>\n", template::escape_html(&format!("{}{}", site, path))));
     }
+    out.push_str("</urlset>\n");
+    out
+}
+
+/// The site's origin for absolute URLs, without a trailing slash: the
+/// `canonical_url` setting if it is a valid URL, else `https://` and the `nginx_hostname` setting,
+/// else the request's own Host.
+fn site_url(headers: &HashMap<String, String>) -> String {
+    let settings = db::settings();
+    let setting = |key: &str| {
+        settings.as_ref().and_then(|s| s.get(key)).and_then(db::Value::as_str).map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+    };
+    let site = setting("canonical_url")
+        .filter(|url| pages::is_http_url(url))
+        .or_else(|| setting("nginx_hostname").map(|host| format!("https://{}", host)))
+        .unwrap_or_else(|| format!("http://{}", headers.get("host").map_or("localhost", String::as_str)));
+    site.trim_end_matches('/').to_string()
 }
 
 fn inject_reload(content: Vec<u8>) -> Vec<u8> {
@@ -245,56 +927,447 @@ fn ensure_doctype(html: String) -> String {
 }
 
 fn safe_public_path(path: &str) -> Option<PathBuf> {
-    let clean = if path.starts_with('/') { path } else { return None };
-    let rel = Path::new(clean.trim_start_matches('/'));
-    for component in rel.components() {
-        if matches!(component, std::path::Component::ParentDir | std::path::Component::Prefix(_)) {
-            return None;
-        }
+    urlpath::resolve_normalized(&config::public_dir(), path)
+}
+
+/// A WebSocket endpoint's answer to a request it cannot upgrade: the error
+/// page for a browser, `detail` as the message for anything else. A 426
+/// names the protocol to upgrade to, as RFC 9110 requires.
+fn upgrade_error(status: &'static str, detail: &str, format: Format) -> Routed {
+    let (status, body, content_type, cors, mut headers) = match format {
+        Format::Html => error_response(status, detail, format),
+        Format::Json => (status, format!("{{{}}}", Json::key_string("error", detail)).into_bytes(), format.content_type(), true, Vec::new()),
+        Format::Text => (status, detail.as_bytes().to_vec(), format.content_type(), true, Vec::new()),
+    };
+    if status.starts_with("426") {
+        headers.push(("Upgrade".to_string(), "websocket".to_string()));
+        headers.push(("Connection".to_string(), "Upgrade, close".to_string()));
     }
-    Some(config::public_dir().join(rel))
+    (status, body, content_type, cors, headers)
 }
 
 fn is_websocket(headers: &HashMap<String, String>) -> bool {
     headers.get("upgrade").map(|v| v.eq_ignore_ascii_case("websocket")).unwrap_or(false)
 }
 
-fn authorize_realtime(headers: &HashMap<String, String>, query: &str) -> bool {
-    let token = query_param(query, "token")
-        .or_else(|| headers.get("authorization").map(|h| h.trim_start_matches("Bearer ").to_string()))
+/// The admin token a `/realtime` upgrade presents, if it is valid. The hub
+/// keeps re-checking it while the socket is open.
+fn authorize_realtime(headers: &HashMap<String, String>, query: &str) -> Option<String> {
+    let token = api::utils::query_param(query, "token")
+        .or_else(|| headers.get("authorization").and_then(|h| auth::bearer(h)).map(str::to_string))
         .unwrap_or_default();
-    auth::is_admin(&token)
+    auth::is_admin(&token).then_some(token)
 }
 
-fn query_param(query: &str, key: &str) -> Option<String> {
-    query.split('&').filter_map(|pair| {
-        let mut parts = pair.splitn(2, '=');
-        let k = parts.next()?;
-        let v = parts.next().unwrap_or("");
-        if k == key {
-            Some(url_decode(v))
-        } else {
-            None
+/// Admin token for `/api/events/stream`. `EventSource` cannot set headers,
+/// so besides `?token=` and a Bearer header the session cookie counts.
+fn authorize_event_stream(headers: &HashMap<String, String>, query: &str) -> Option<String> {
+    let token = api::utils::query_param(query, "token").unwrap_or_else(|| auth::request_token(headers));
+    auth::is_admin(&token).then_some(token)
+}
+
+/// Head of a `text/event-stream` response. It has no length: the body is
+/// the events, for as long as the client stays connected.
+fn event_stream_head(path: &str, request_id: &str) -> String {
+    let (origin, methods) = config::cors_for(path);
+    format!(
+        "HTTP/1.1 200 OK\r\nX-Request-Id: {}\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: {}\r\n\r\n",
+        request_id, origin, methods
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> config::SecurityHeaders {
+        config::SecurityHeaders {
+            frame_options: Some("DENY".into()),
+            project_frame_options: Some("SAMEORIGIN".into()),
+            content_security_policy: Some(config::DEFAULT_CSP.into()),
+            referrer_policy: Some("same-origin".into()),
+        }
+    }
+
+    /// In-memory stream: serves `input` in small chunks and records writes.
+    struct MockStream {
+        input: Vec<u8>,
+        pos: usize,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = (self.input.len() - self.pos).min(buf.len()).min(16);
+            buf[..n].copy_from_slice(&self.input[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
         }
-    }).next()
-}
-
-fn url_decode(value: &str) -> String {
-    let mut out = String::new();
-    let mut chars = value.chars();
-    while let Some(c) = chars.next() {
-        if c == '%' {
-            let h1 = chars.next().unwrap_or('0');
-            let h2 = chars.next().unwrap_or('0');
-            let hex = format!("{}{}", h1, h2);
-            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
-                out.push(byte as char);
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    fn mock(input: &str) -> MockStream {
+        MockStream { input: input.as_bytes().to_vec(), pos: 0, output: Vec::new() }
+    }
+
+    #[test]
+    fn test_expect_continue_gets_interim_response() {
+        let body = format!(r#"{{"note":"{}"}}"#, "x".repeat(100));
+        let raw = format!(
+            "POST /api/contact HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(), body
+        );
+        let mut stream = mock(&raw);
+        let request = read_request(&mut stream).unwrap();
+        assert_eq!(stream.output, b"HTTP/1.1 100 Continue\r\n\r\n");
+        let (method, path, _, version, headers, parsed_body) = parse_request(&request);
+        assert_eq!(version, "HTTP/1.1");
+        assert_eq!((method.as_str(), path.as_str()), ("POST", "/api/contact"));
+        assert_eq!(headers.get("expect").map(String::as_str), Some("100-continue"));
+        assert_eq!(parsed_body, body.as_bytes());
+    }
+
+    #[test]
+    fn test_http_1_0_gets_no_interim_response() {
+        let raw = "POST /api/contact HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n{}";
+        let mut stream = mock(raw);
+        let (_, _, _, version, headers, body) = parse_request(&read_request(&mut stream).unwrap());
+        assert!(stream.output.is_empty());
+        assert_eq!((version.as_str(), body.as_slice()), ("HTTP/1.0", &b"{}"[..]));
+        assert!(!headers.contains_key("host"));
+        // A request line without a version is the oldest HTTP there is
+        let (_, path, _, version, _, _) = parse_request(b"GET /\r\n\r\n");
+        assert_eq!((path.as_str(), version.as_str()), ("/", "HTTP/1.0"));
+    }
+
+    #[test]
+    fn test_body_read_to_content_length_without_expect() {
+        let raw = "PUT /x HTTP/1.1\r\nContent-Length: 10\r\n\r\n{\"a\":\n\"b\"}trailing";
+        let mut stream = mock(raw);
+        let (_, _, _, _, _, body) = parse_request(&read_request(&mut stream).unwrap());
+        assert!(stream.output.is_empty());
+        assert_eq!(body, b"{\"a\":\n\"b\"}");
+    }
+
+    #[test]
+    fn test_oversized_content_length_is_refused_unread() {
+        for length in [(MAX_BODY_BYTES + 1).to_string(), "99999999999999999999999".to_string()] {
+            let raw = format!("POST /api/contact HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n{{}}", length);
+            let mut stream = mock(&raw);
+            let Err(Incomplete::TooLarge(head)) = read_request(&mut stream) else { panic!("read the body") };
+            // No 100 Continue: the client should not start sending
+            assert!(stream.output.is_empty());
+            let (method, path, _, _, _, body) = parse_request(&head);
+            assert_eq!((method.as_str(), path.as_str(), body.len()), ("POST", "/api/contact", 0));
+        }
+        let at_cap = format!("PUT /x HTTP/1.1\r\nContent-Length: {}\r\n\r\n{{}}", MAX_BODY_BYTES);
+        assert!(read_request(&mut mock(&at_cap)).is_ok());
+    }
+
+    /// A client that sends `input` and then goes quiet until the read times out.
+    struct Stalled(MockStream);
+
+    impl Read for Stalled {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(std::io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
             }
-        } else if c == '+' {
-            out.push(' ');
-        } else {
-            out.push(c);
         }
     }
-    out
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.write(buf) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_stalled_client_times_out() {
+        let Err(Incomplete::TimedOut(partial)) = read_request(&mut Stalled(mock(""))) else { panic!("no timeout") };
+        assert!(partial.is_empty());
+        let Err(Incomplete::TimedOut(partial)) = read_request(&mut Stalled(mock("GET / HT"))) else { panic!("no timeout") };
+        assert_eq!(partial, b"GET / HT");
+        let raw = "POST /api/contact HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        let Err(Incomplete::TimedOut(partial)) = read_request(&mut Stalled(mock(raw))) else { panic!("no timeout") };
+        assert_eq!(parse_request(&partial).1, "/api/contact");
+    }
+
+    #[test]
+    fn test_gzip_body_is_inflated() {
+        let gzip = crate::crypto::hex_decode(
+            "1f8b0800000000000203ab56ca4bcc4d55b252724c4954d2512a494c2f56b28a56aa00b22b95626b019cfea5851f000000",
+        ).unwrap();
+        let mut raw = format!(
+            "POST /api/collections/people HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            gzip.len()
+        ).into_bytes();
+        raw.extend_from_slice(&gzip);
+        let mut stream = MockStream { input: raw, pos: 0, output: Vec::new() };
+        let (_, _, _, _, headers, body) = parse_request(&read_request(&mut stream).unwrap());
+        assert_eq!(decode_body(&headers, &body).unwrap(), r#"{"name":"Ada","tags":["x","y"]}"#);
+    }
+
+    #[test]
+    fn test_bad_content_encoding_rejected() {
+        let mut headers = HashMap::new();
+        headers.insert("content-encoding".to_string(), "gzip".to_string());
+        assert_eq!(decode_body(&headers, b"{}"), Err("400 Bad Request"));
+        headers.insert("content-encoding".to_string(), "br".to_string());
+        assert_eq!(decode_body(&headers, b"{}"), Err("415 Unsupported Media Type"));
+    }
+
+    #[test]
+    fn test_directory_index_fallback() {
+        let dir = std::env::temp_dir().join(format!("rpw-dir-index-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = vec!["index.html".to_string(), "index.htm".to_string()];
+        assert_eq!(directory_index(&dir, &names), None);
+        fs::write(dir.join("index.htm"), "<p>htm</p>").unwrap();
+        assert!(directory_index(&dir, &names).is_some_and(|p| p.ends_with("index.htm")));
+        fs::write(dir.join("index.html"), "<p>html</p>").unwrap();
+        assert!(directory_index(&dir, &names).is_some_and(|p| p.ends_with("index.html")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_percent_in_file_names_is_served() {
+        let root = std::env::temp_dir().join(format!("rpw-percent-{}", std::process::id()));
+        fs::create_dir_all(root.join("shop")).unwrap();
+        fs::write(root.join("shop/100%.txt"), "all of it").unwrap();
+        // What routing hands over for `/projects/shop/100%25.txt`
+        let path = urlpath::normalize("/shop/100%25.txt").unwrap();
+        let (status, body, _, _) = serve_project_from(&root, &path);
+        assert_eq!((status, body.as_slice()), ("200 OK", &b"all of it"[..]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_directory_listing() {
+        let dir = std::env::temp_dir().join(format!("rpw-dir-listing-{}", std::process::id()));
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::write(dir.join("notes <draft>.txt"), "12345").unwrap();
+
+        let html = render_listing(&dir, "/projects/shop/");
+        assert!(html.contains("<h1>Index of /projects/shop/</h1>"));
+        assert!(html.contains("<a href=\"/projects/\">../</a>"));
+        assert!(html.contains("<a href=\"/projects/shop/assets/\">assets/</a></td><td>-</td>"));
+        assert!(html.contains("notes &lt;draft&gt;.txt</a></td><td>5</td>"));
+        assert!(html.find("assets/").unwrap() < html.find("notes").unwrap());
+        assert!(!render_listing(&dir, "/projects").contains("../"));
+        assert_eq!(utc_datetime(0), "1970-01-01 00:00");
+        assert_eq!(utc_datetime(1_709_210_096), "2024-02-29 12:34");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_project_css_gets_etag_and_304() {
+        let root = std::env::temp_dir().join(format!("rpw-project-etag-{}", std::process::id()));
+        fs::create_dir_all(root.join("shop")).unwrap();
+        fs::write(root.join("shop/site.css"), "body { color: #333; }\n".repeat(40)).unwrap();
+
+        let page = serve_project_from(&root, "/shop/site.css");
+        assert_eq!(page.2, "text/css");
+        let (status, body, _, _, headers) = static_response(page, "/projects/shop/site.css", &HashMap::new());
+        assert_eq!(status, "200 OK");
+        let etag = headers.iter().find(|(k, _)| k == "ETag").map(|(_, v)| v.clone()).unwrap();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        let conditional = HashMap::from([("if-none-match".to_string(), format!("W/{}, \"other\"", etag))]);
+        let (status, empty, _, _, _) = static_response(serve_project_from(&root, "/shop/site.css"), "/projects/shop/site.css", &conditional);
+        assert_eq!(status, NOT_MODIFIED);
+        assert!(empty.is_empty());
+
+        let gzip = HashMap::from([("accept-encoding".to_string(), "br, gzip;q=0.8".to_string())]);
+        let (_, packed, _, _, headers) = static_response(serve_project_from(&root, "/shop/site.css"), "/projects/shop/site.css", &gzip);
+        assert!(headers.iter().any(|(k, v)| k == "Content-Encoding" && v == "gzip"));
+        assert_eq!(compress::gunzip(&packed, usize::MAX).unwrap(), body);
+        assert!(!accepts_gzip("gzip;q=0"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fonts_are_cached_for_a_year_and_not_gzipped() {
+        let root = std::env::temp_dir().join(format!("rpw-project-font-{}", std::process::id()));
+        fs::create_dir_all(root.join("shop/fonts")).unwrap();
+        fs::write(root.join("shop/fonts/inter.woff2"), vec![0x77; 4096]).unwrap();
+        let path = "/projects/shop/fonts/inter.woff2";
+
+        let gzip = HashMap::from([("accept-encoding".to_string(), "gzip".to_string())]);
+        let (status, body, content_type, _, headers) =
+            static_response(serve_project_from(&root, "/shop/fonts/inter.woff2"), path, &gzip);
+        assert_eq!((status, content_type, body.len()), ("200 OK", "font/woff2", 4096));
+        let cache = headers.iter().find(|(k, _)| k == "Cache-Control").map(|(_, v)| v.as_str());
+        assert_eq!(cache, Some("public, max-age=31536000, immutable"));
+        assert!(!headers.iter().any(|(k, _)| k == "Content-Encoding" || k == "Vary"));
+
+        let etag = headers.iter().find(|(k, _)| k == "ETag").map(|(_, v)| v.clone()).unwrap();
+        let conditional = HashMap::from([("if-none-match".to_string(), etag)]);
+        let (status, _, _, _, headers) =
+            static_response(serve_project_from(&root, "/shop/fonts/inter.woff2"), path, &conditional);
+        assert_eq!(status, NOT_MODIFIED);
+        assert!(headers.iter().any(|(k, v)| k == "Cache-Control" && v.ends_with("immutable")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprinted_assets_are_immutable() {
+        assert_eq!(cache_control("/assets/app.3f9a2c1b.js", "application/javascript"), "public, max-age=31536000, immutable");
+        assert_eq!(cache_control("/assets/chunk-0a1b2c3d4e.css", "text/css"), "public, max-age=31536000, immutable");
+        assert_eq!(cache_control("/fonts/inter.ttf", "font/ttf"), "public, max-age=31536000, immutable");
+        for path in ["/app.js", "/jquery-3.7.1.min.js", "/bootstrap-datepicker.css", "/deadbeefcafe.js", "/0123456789"] {
+            assert_eq!(cache_control(path, "application/javascript"), "no-cache", "{}", path);
+        }
+        assert!(is_compressible("font/ttf") && !is_compressible("font/woff2"));
+    }
+
+    fn header<'a>(headers: &'a [(&str, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_status_lines() {
+        assert_eq!(status_line(200), "200 OK");
+        assert_eq!(status_line(405), "405 Method Not Allowed");
+        assert_eq!(status_line(422), "422 Unprocessable Content");
+        assert_eq!(status_line(503), "503 Service Unavailable");
+        // Unregistered codes fall back to their class
+        assert_eq!(status_line(299), "200 OK");
+        assert_eq!(status_line(499), "400 Bad Request");
+        assert_eq!(status_line(599), "500 Internal Server Error");
+        assert_eq!(status_line(0), "500 Internal Server Error");
+    }
+
+    #[test]
+    fn test_status_reasons() {
+        for (code, reason) in [
+            (304, "Not Modified"),
+            (403, "Forbidden"),
+            (409, "Conflict"),
+            (413, "Payload Too Large"),
+            (415, "Unsupported Media Type"),
+            (429, "Too Many Requests"),
+        ] {
+            assert_eq!(status_reason(code), reason);
+            // A handler's status reaches the client as is
+            let routed = api_routed(api::Response::json(code, ""), Format::Json);
+            assert_eq!(routed.0, format!("{} {}", code, reason));
+        }
+        let (_, body, _, _, _) =
+            api_routed(api::Response::json(415, ""), Format::Text);
+        assert_eq!(body, b"Unsupported Media Type");
+    }
+
+    #[test]
+    fn test_429_has_status_line_and_retry_after() {
+        let res = api::Response::too_many_requests(std::time::Duration::from_millis(1_500));
+        let mut stream = mock("");
+        respond(&mut stream, "POST", "/api/auth/login", "req-1", api_routed(res, Format::Json));
+        let out = String::from_utf8(stream.output).unwrap();
+        assert!(out.starts_with("HTTP/1.1 429 Too Many Requests\r\n"), "{}", out);
+        assert!(out.contains("\r\nRetry-After: 2\r\n"));
+        assert!(out.ends_with(r#""code":"rate_limited"}"#));
+
+        let (status, body, content_type, _, headers) =
+            api_routed(api::Response::too_many_requests(std::time::Duration::ZERO), Format::Text);
+        assert_eq!((status, content_type), ("429 Too Many Requests", "text/plain"));
+        assert_eq!(body, b"Too many requests");
+        assert_eq!(headers, vec![("Retry-After".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn test_admin_panel_follows_configured_path() {
+        // The panel reads site settings
+        crate::db::init_in_memory("handler-test");
+        let headers = HashMap::new();
+        let (status, _, content_type, _, _) = page("/ops-7f3a", "/ops-7f3a", &headers, Some("/ops-7f3a"));
+        assert_eq!((status, content_type), ("200 OK", "text/html"));
+        assert_eq!(page("/_admin", "/_admin", &headers, Some("/ops-7f3a")).0, NOT_FOUND);
+        assert_eq!(page("/_admin", "/_admin", &headers, None).0, NOT_FOUND);
+    }
+
+    #[test]
+    fn test_default_security_headers() {
+        let headers = security_headers(&defaults(), "/");
+        assert_eq!(header(&headers, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(header(&headers, "X-Frame-Options"), Some("DENY"));
+        assert_eq!(header(&headers, "Referrer-Policy"), Some("same-origin"));
+        assert!(header(&headers, "Content-Security-Policy").unwrap().starts_with("default-src 'self'"));
+    }
+
+    #[test]
+    fn test_project_pages_use_project_frame_options() {
+        let headers = security_headers(&defaults(), "/projects/shop/index.html");
+        assert_eq!(header(&headers, "X-Frame-Options"), Some("SAMEORIGIN"));
+    }
+
+    #[test]
+    fn test_security_headers_overridden_or_disabled() {
+        let cfg = config::SecurityHeaders {
+            frame_options: Some("SAMEORIGIN".into()),
+            content_security_policy: None,
+            referrer_policy: Some("no-referrer".into()),
+            ..defaults()
+        };
+        let headers = security_headers(&cfg, "/");
+        assert_eq!(header(&headers, "X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(header(&headers, "Referrer-Policy"), Some("no-referrer"));
+        assert_eq!(header(&headers, "Content-Security-Policy"), None);
+    }
+
+    #[test]
+    fn test_favicon_prefers_the_configured_file() {
+        let public = std::env::temp_dir().join(format!("rpw-favicon-{}", std::process::id()));
+        fs::create_dir_all(public.join("brand")).unwrap();
+        assert_eq!(favicon_from(&public, None), None);
+
+        fs::write(public.join("favicon.svg"), "<svg/>").unwrap();
+        assert_eq!(favicon_from(&public, Some("")), Some((b"<svg/>".to_vec(), "image/svg+xml")));
+        fs::write(public.join("brand/icon.ico"), [0u8, 0, 1, 0]).unwrap();
+        assert_eq!(favicon_from(&public, Some("brand/icon.ico")), Some((vec![0, 0, 1, 0], "image/x-icon")));
+        assert_eq!(favicon_from(&public, Some("/brand/icon.ico")).map(|(_, ct)| ct), Some("image/x-icon"));
+        // A setting naming a missing file, or one outside public/, falls back
+        assert_eq!(favicon_from(&public, Some("brand/gone.ico")).map(|(_, ct)| ct), Some("image/svg+xml"));
+        assert_eq!(favicon_from(&public, Some("../etc/passwd")).map(|(_, ct)| ct), Some("image/svg+xml"));
+        fs::remove_dir_all(&public).unwrap();
+    }
+
+    #[test]
+    fn test_generated_robots_txt() {
+        let robots = robots_txt(Some("/_admin"), "https://example.com");
+        let lines: Vec<&str> = robots.lines().collect();
+        assert_eq!(lines, [
+            "User-agent: *", "Allow: /", "Disallow: /api/", "Disallow: /__dev/", "Disallow: /_admin",
+            "Sitemap: https://example.com/sitemap.xml",
+        ]);
+        assert!(!robots_txt(None, "https://example.com").contains("_admin"));
+    }
+
+    #[test]
+    fn test_sitemap_lists_pages_docs_and_projects() {
+        let xml = sitemap_xml("https://example.com", &["shop".to_string()]);
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns="));
+        assert!(xml.ends_with("</urlset>\n"));
+        for loc in ["https://example.com/", "https://example.com/docs", "https://example.com/docs/routing", "https://example.com/projects/shop/"] {
+            assert!(xml.contains(&format!("<url><loc>{}</loc></url>", loc)), "{}", loc);
+        }
+        assert_eq!(xml.matches("<url>").count(), xml.matches("</url>").count());
+        assert_eq!(xml.matches("<url>").count(), 3 + pages::DOC_SLUGS.len());
+        assert!(sitemap_xml("https://example.com/?a=1&b=2", &[]).contains("https://example.com/?a=1&amp;b=2/</loc>"));
+        // Every listed docs slug has a page, and nothing else does
+        for slug in pages::DOC_SLUGS {
+            assert!(pages::docs(slug).is_some(), "{}", slug);
+        }
+        assert!(pages::docs("").is_some() && pages::docs("nope").is_none());
+    }
 }
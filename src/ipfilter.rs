@@ -0,0 +1,169 @@
+//! CIDR allow/deny lists gating the admin panel and admin API
+use crate::{config, logging};
+use std::net::IpAddr;
+
+/// An IPv4 or IPv6 network such as `10.0.0.0/8` or `fd00::/8`.
+/// A bare address is a single-host network.
+#[derive(Debug, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+            None => (s.trim(), None),
+        };
+        let network = addr.parse::<IpAddr>().ok()?.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+        Some(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net) as u128, u32::from(ip) as u128, 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    net >> shift == ip >> shift
+}
+
+/// Allow/deny decision for a client IP. With no allowlist every address is
+/// allowed unless it is on the denylist.
+pub struct IpFilter {
+    allow: Option<Vec<Cidr>>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Reads ADMIN_IP_ALLOWLIST and ADMIN_IP_DENYLIST from .env.local.
+    pub fn from_env() -> Self {
+        Self::new(
+            &config::load_env("ADMIN_IP_ALLOWLIST").unwrap_or_default(),
+            &config::load_env("ADMIN_IP_DENYLIST").unwrap_or_default(),
+        )
+    }
+
+    /// Both lists are comma-separated CIDRs. Invalid entries are skipped with a
+    /// warning; an allowlist made only of invalid entries allows nobody.
+    pub fn new(allow: &str, deny: &str) -> Self {
+        let allow = if allow.trim().is_empty() { None } else { Some(parse_list(allow)) };
+        Self { allow, deny: parse_list(deny) }
+    }
+
+    pub fn permits(&self, client_ip: &str) -> bool {
+        let Ok(ip) = client_ip.parse::<IpAddr>() else {
+            return self.allow.is_none() && self.deny.is_empty();
+        };
+        if self.deny.iter().any(|c| c.contains(&ip)) {
+            return false;
+        }
+        match &self.allow {
+            Some(allow) => allow.iter().any(|c| c.contains(&ip)),
+            None => true,
+        }
+    }
+}
+
+fn parse_list(list: &str) -> Vec<Cidr> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let cidr = Cidr::parse(entry);
+            if cidr.is_none() {
+                logging::warn("ipfilter", &format!("ignoring invalid CIDR {:?}", entry));
+            }
+            cidr
+        })
+        .collect()
+}
+
+/// Paths the filter applies to: the admin page at `panel` (see
+/// [`crate::config::admin_panel_path`]) and the admin API.
+pub fn is_admin_path(path: &str, panel: Option<&str>) -> bool {
+    panel == Some(path) || path == "/api/admin" || path.starts_with("/api/admin/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_matching() {
+        let net = Cidr::parse("192.168.1.0/24").unwrap();
+        assert!(net.contains(&"192.168.1.77".parse().unwrap()));
+        assert!(!net.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!net.contains(&"::1".parse().unwrap()));
+        // IPv4-mapped IPv6 peers match IPv4 networks
+        assert!(net.contains(&"::ffff:192.168.1.5".parse().unwrap()));
+
+        let v6 = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db9::1".parse().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&"8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.1").unwrap().contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+        assert_eq!(Cidr::parse("not-an-ip/8"), None);
+    }
+
+    #[test]
+    fn test_allowlist_admits_listed_ip() {
+        let filter = IpFilter::new("10.0.0.0/8, 127.0.0.1", "");
+        assert!(filter.permits("10.1.2.3"));
+        assert!(filter.permits("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_allowlist_rejects_other_ip() {
+        let filter = IpFilter::new("10.0.0.0/8", "");
+        assert!(!filter.permits("203.0.113.9"));
+        assert!(!filter.permits(""));
+        assert!(!IpFilter::new("bogus", "").permits("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_denylist_overrides_allowlist() {
+        let filter = IpFilter::new("10.0.0.0/8", "10.0.0.13");
+        assert!(!filter.permits("10.0.0.13"));
+        assert!(filter.permits("10.0.0.14"));
+    }
+
+    #[test]
+    fn test_disabled_allows_everyone() {
+        let filter = IpFilter::new("", "");
+        assert!(filter.permits("203.0.113.9"));
+        assert!(filter.permits("::1"));
+        assert!(filter.permits(""));
+    }
+
+    #[test]
+    fn test_admin_paths() {
+        assert!(is_admin_path("/_admin", Some("/_admin")));
+        assert!(is_admin_path("/api/admin/stats", Some("/_admin")));
+        assert!(!is_admin_path("/api/administrators", Some("/_admin")));
+        assert!(!is_admin_path("/api/collections", Some("/_admin")));
+        assert!(is_admin_path("/ops", Some("/ops")));
+        assert!(!is_admin_path("/_admin", Some("/ops")));
+        assert!(is_admin_path("/api/admin/stats", None));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::config;
-use std::env;
+use std::cell::RefCell;
 use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::sync::{Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -9,17 +10,39 @@ static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
 static LOG_ENABLED: OnceLock<bool> = OnceLock::new();
 static LOG_PATH: OnceLock<String> = OnceLock::new();
 
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Tags every log line written on this thread with `req=<id>` until the guard drops.
+pub fn request_scope(id: &str) -> RequestScope {
+    REQUEST_ID.with(|cell| *cell.borrow_mut() = Some(id.to_string()));
+    RequestScope
+}
+
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|cell| cell.borrow().clone())
+}
+
+pub struct RequestScope;
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
 pub fn init() {
-    let enabled = env::var("LOG_ENABLED").map(|v| v != "0" && v.to_lowercase() != "false").unwrap_or(true);
+    let enabled = config::load_env("LOG_ENABLED").map(|v| v != "0" && v.to_lowercase() != "false").unwrap_or(true);
     let _ = LOG_ENABLED.set(enabled);
 
     if !enabled {
         return;
     }
 
-    let path = env::var("LOG_PATH")
+    let path = config::load_env("LOG_PATH")
         .map(|p| config::root_dir().join(p))
-        .unwrap_or_else(|_| config::root_dir().join("logs.log"));
+        .unwrap_or_else(|| config::root_dir().join("logs.log"));
     let _ = LOG_PATH.set(path.to_string_lossy().to_string());
     let file = OpenOptions::new()
         .create(true)
@@ -51,6 +74,10 @@ fn write(level: &str, scope: &str, message: &str) {
         return;
     }
     let ts = timestamp();
+    let scope = match request_id() {
+        Some(id) => format!("{} req={}", scope, id),
+        None => scope.to_string(),
+    };
     if let Some(lock) = LOG_FILE.get() {
         if let Ok(mut file) = lock.lock() {
             let _ = writeln!(file, "{} [{}] {} - {}", ts, level, scope, message);
@@ -70,3 +97,106 @@ fn timestamp() -> String {
 pub fn log_path() -> Option<String> {
     LOG_PATH.get().cloned()
 }
+
+/// How much of the file [`tail`] reads per step, walking back from the end.
+const TAIL_BLOCK: u64 = 8192;
+
+/// The last `n` lines of the log at `path`, oldest first, counting only
+/// lines at `level` (`INFO`, `WARN`, `ERROR`) when given. Reads backwards
+/// from the end, so a long log costs no more than the lines returned.
+pub fn tail(path: &Path, n: usize, level: Option<&str>) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let mut pos = file.metadata()?.len();
+    // Start of the line that straddles the previous block boundary
+    let mut carry = Vec::new();
+    let mut newest_first = Vec::new();
+    while pos > 0 && newest_first.len() < n {
+        let len = TAIL_BLOCK.min(pos);
+        pos -= len;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut block = vec![0; len as usize];
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&carry);
+        let mut lines: Vec<&[u8]> = block.split(|&b| b == b'\n').collect();
+        let first = if pos > 0 { lines.remove(0).to_vec() } else { Vec::new() };
+        for line in lines.into_iter().rev() {
+            let line = String::from_utf8_lossy(line);
+            if !line.is_empty() && level.is_none_or(|level| at_level(&line, level)) {
+                newest_first.push(line.into_owned());
+            }
+        }
+        carry = first;
+    }
+    newest_first.truncate(n);
+    newest_first.reverse();
+    Ok(newest_first)
+}
+
+/// Complete lines written after byte `offset`, and the offset to continue
+/// from. A file shorter than `offset` was truncated by a restart and is read
+/// from the start.
+pub fn read_from(path: &Path, offset: u64, level: Option<&str>) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let start = if offset > file.metadata()?.len() { 0 } else { offset };
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    // A line still being written is left for the next call
+    let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let lines = String::from_utf8_lossy(&data[..complete])
+        .lines()
+        .filter(|line| !line.is_empty() && level.is_none_or(|level| at_level(line, level)))
+        .map(str::to_string)
+        .collect();
+    Ok((lines, start + complete as u64))
+}
+
+/// Lines look like `<timestamp> [LEVEL] scope - message`.
+fn at_level(line: &str, level: &str) -> bool {
+    line.split_once(' ').is_some_and(|(_, rest)| {
+        rest.strip_prefix('[').and_then(|r| r.split_once(']')).is_some_and(|(l, _)| l.eq_ignore_ascii_case(level))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str, lines: &[String]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rpw-log-{}-{}.log", name, std::process::id()));
+        std::fs::write(&path, lines.iter().map(|l| format!("{}\n", l)).collect::<String>()).unwrap();
+        path
+    }
+
+    fn line(n: usize) -> String {
+        let level = if n.is_multiple_of(10) { "ERROR" } else { "INFO" };
+        format!("1700000000 [{}] test - line {}", level, n)
+    }
+
+    #[test]
+    fn test_tail_returns_last_lines() {
+        let lines: Vec<String> = (1..=3000).map(line).collect();
+        let path = fixture("tail", &lines);
+        assert_eq!(tail(&path, 3, None).unwrap(), lines[2997..]);
+        assert_eq!(tail(&path, 2, Some("error")).unwrap(), [line(2990), line(3000)]);
+        // More than fits in one block, and more than the file has
+        assert_eq!(tail(&path, 500, None).unwrap(), lines[2500..]);
+        assert_eq!(tail(&path, 5000, None).unwrap().len(), 3000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_from_offset() {
+        let lines: Vec<String> = (1..=3).map(line).collect();
+        let path = fixture("follow", &lines);
+        let (_, end) = read_from(&path, 0, None).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{}\npartial", line(10)).unwrap();
+        let (new, next) = read_from(&path, end, None).unwrap();
+        assert_eq!(new, [line(10)]);
+        assert_eq!(read_from(&path, next, None).unwrap().0, Vec::<String>::new());
+        // Truncated since: start over
+        assert_eq!(read_from(&path, 1 << 20, Some("ERROR")).unwrap().0, [line(10)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,29 @@
+#![allow(clippy::collapsible_if)]
+
 mod config;
 mod logging;
+mod maintenance;
+mod negotiate;
 mod api;
 mod auth;
+mod cache;
+mod clock;
+mod compress;
 mod crypto;
 mod db;
 mod handler;
+mod ipfilter;
 mod pages;
 mod ports;
 mod proxy;
+mod ratelimit;
 mod server;
+mod startup;
 mod template;
+#[cfg(test)]
+mod testserver;
 mod realtime;
+mod urlpath;
 mod ws;
 
 fn main() {
@@ -25,28 +38,40 @@ fn main() {
     let key = config::load_env("SECRET_KEY").unwrap_or_else(|| {
         eprintln!("WARNING: SECRET_KEY not found in .env.local, using default (insecure!)");
         logging::warn("config", "SECRET_KEY missing, using insecure default");
-        "default-insecure-key-change-me".to_string()
+        startup::DEFAULT_KEY.to_string()
     });
 
     // Initialize database
     db::init(&key);
+    if !startup::self_check(&key) {
+        eprintln!("Refusing to start: fix the errors above or unset STARTUP_STRICT");
+        std::process::exit(1);
+    }
+    db::start_sweeper();
+    realtime::start_token_recheck(auth::is_admin);
+    auth::register_hooks();
+    let normalized = auth::normalize_stored_emails();
+    if normalized > 0 {
+        logging::info("auth", &format!("lowercased {} stored emails", normalized));
+    }
 
-    // Create default admin if no users exist
-    if db::get().find_all("_users").is_empty() {
+    // Create default admin if no users exist (RPW_DISABLE_SEED=1 skips this and the settings seed)
+    let seed = config::seeding_enabled();
+    if seed && db::get().find_all("_users").is_empty() {
         if let (Some(email), Some(password)) = (config::load_env("ADMIN_EMAIL"), config::load_env("ADMIN_PASSWORD")) {
             let result = auth::register(&email, &password);
             if result.success {
                 println!("Created default admin: {}", email);
                 logging::info("auth", "default admin created");
             } else {
-                eprintln!("Failed to create admin: {:?}", result.error);
+                eprintln!("Failed to create admin: {}", result.message.unwrap_or_else(|| format!("{:?}", result.error)));
                 logging::error("auth", "failed to create default admin");
             }
         }
     }
 
     // Seed settings defaults once
-    if db::get().find_all("_settings").is_empty() {
+    if seed && db::get().find_all("_settings").is_empty() {
         let mut doc = db::Document::new();
         doc.insert("page_title".into(), db::Value::String("Rust Pure Web".into()));
         doc.insert("meta_description".into(), db::Value::String("Zero-dependency Rust web framework.".into()));
@@ -56,6 +81,7 @@ fn main() {
         doc.insert("og_image".into(), db::Value::String("".into()));
         doc.insert("twitter_card".into(), db::Value::String("summary_large_image".into()));
         doc.insert("canonical_url".into(), db::Value::String("".into()));
+        doc.insert("favicon".into(), db::Value::String("".into()));
         doc.insert("nginx_hostname".into(), db::Value::String("proxy.olibuijr.com".into()));
         doc.insert("nginx_internal_ip".into(), db::Value::String("192.168.8.4".into()));
         doc.insert("dev_network_name".into(), db::Value::String("dev".into()));
@@ -75,8 +101,11 @@ fn main() {
     api::contact::ensure_contact_collection();
 
     println!("Server listening on http://0.0.0.0:3460");
-    println!("Admin panel: http://0.0.0.0:3460/_admin");
+    match config::admin_panel_path() {
+        Some(panel) => println!("Admin panel: http://0.0.0.0:3460{}", panel),
+        None => println!("Admin panel: disabled (ADMIN_PANEL=off)"),
+    }
     logging::info("server", "listening on 0.0.0.0:3460");
-    std::thread::spawn(|| proxy::run_proxy());
+    std::thread::spawn(proxy::run_proxy);
     server::run("0.0.0.0:3460");
 }
@@ -0,0 +1,67 @@
+//! Maintenance mode: while enabled, everyone but admins gets a 503
+use crate::urlpath;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Seconds clients are told to wait before retrying.
+pub const RETRY_AFTER_SECS: u64 = 300;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Paths served even during maintenance so admins can still sign in and
+/// the login page can load its assets. `panel` is the admin page's path.
+pub fn exempt(path: &str, panel: Option<&str>) -> bool {
+    match path {
+        p if panel == Some(p) => true,
+        "/api/auth/login" | "/api/auth/me" | "/favicon.svg" => true,
+        p if p.starts_with("/__dev/") => true,
+        p if p.starts_with("/api/") || p.starts_with("/projects/") || urlpath::under(p, "/docs") => false,
+        // Static assets under public/, but not pages
+        p => p.rsplit('/').next().and_then(|name| name.rsplit_once('.')).is_some_and(|(_, ext)| ext != "html"),
+    }
+}
+
+/// Whether a request must be turned away with a 503.
+pub fn blocks(path: &str, panel: Option<&str>, is_admin: bool) -> bool {
+    enabled() && !is_admin && !exempt(path, panel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exempt_paths() {
+        assert!(exempt("/_admin", Some("/_admin")));
+        assert!(exempt("/api/auth/login", Some("/_admin")));
+        assert!(exempt("/__dev/mtime", Some("/_admin")));
+        assert!(exempt("/css/app.css", Some("/_admin")));
+        assert!(!exempt("/", Some("/_admin")));
+        assert!(!exempt("/index.html", Some("/_admin")));
+        assert!(!exempt("/docs/intro", Some("/_admin")));
+        assert!(!exempt("/api/collections", Some("/_admin")));
+        assert!(!exempt("/projects/demo/app.js", Some("/_admin")));
+        assert!(exempt("/ops", Some("/ops")));
+        assert!(!exempt("/_admin", Some("/ops")));
+        assert!(!exempt("/_admin", None));
+    }
+
+    #[test]
+    fn test_toggle_and_admin_bypass() {
+        set(true);
+        assert!(enabled());
+        assert!(blocks("/", Some("/_admin"), false));
+        assert!(!blocks("/", Some("/_admin"), true));
+        assert!(!blocks("/api/auth/login", Some("/_admin"), false));
+        set(false);
+        assert!(!enabled());
+        assert!(!blocks("/", Some("/_admin"), false));
+    }
+}
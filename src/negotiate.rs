@@ -0,0 +1,135 @@
+//! Content negotiation: pick JSON, HTML or plain text from `Accept` and the route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Html,
+    Text,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Html => "text/html",
+            Format::Text => "text/plain",
+        }
+    }
+}
+
+/// Formats a route can answer with, its default first. The API never
+/// answers with HTML.
+fn candidates(path: &str) -> &'static [Format] {
+    if path.starts_with("/api/") {
+        &[Format::Json, Format::Text]
+    } else {
+        &[Format::Html, Format::Json, Format::Text]
+    }
+}
+
+/// Best format for `path` given the request's `Accept` header. Falls back to
+/// the route's default when the header is missing or nothing matches.
+pub fn preferred(accept: Option<&str>, path: &str) -> Format {
+    let options = candidates(path);
+    let Some(accept) = accept.filter(|a| !a.trim().is_empty()) else {
+        return options[0];
+    };
+    let ranges = parse_accept(accept);
+    let mut best = (options[0], 0.0);
+    for &format in options {
+        let q = quality(&ranges, format.content_type());
+        if q > best.1 {
+            best = (format, q);
+        }
+    }
+    best.0
+}
+
+/// Whether `Accept` asks for API errors in the nested envelope with an
+/// `errors=nested` parameter, as in `application/json; errors=nested`.
+pub fn nested_errors(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| range.split(';').skip(1).any(|p| p.trim().eq_ignore_ascii_case("errors=nested")))
+    })
+}
+
+/// `(media range, q)` pairs from an Accept header.
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let range = params.next()?.trim().to_ascii_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((range, q))
+        })
+        .collect()
+}
+
+/// q of the most specific range matching `mime` (exact > `type/*` > `*/*`).
+fn quality(ranges: &[(String, f32)], mime: &str) -> f32 {
+    let major = mime.split('/').next().unwrap_or("");
+    let mut best: Option<(u8, f32)> = None;
+    for (range, q) in ranges {
+        let specificity = match range.split_once('/') {
+            _ if range == mime => 3,
+            Some((t, "*")) if t == major => 2,
+            Some(("*", "*")) => 1,
+            _ => continue,
+        };
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, *q));
+        }
+    }
+    best.map(|(_, q)| q).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROWSER: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+    #[test]
+    fn test_defaults_by_route_class() {
+        assert_eq!(preferred(None, "/missing"), Format::Html);
+        assert_eq!(preferred(None, "/api/missing"), Format::Json);
+        assert_eq!(preferred(Some("*/*"), "/missing"), Format::Html);
+        assert_eq!(preferred(Some("*/*"), "/api/missing"), Format::Json);
+    }
+
+    #[test]
+    fn test_accept_json_vs_html_on_same_path() {
+        assert_eq!(preferred(Some("application/json"), "/missing"), Format::Json);
+        assert_eq!(preferred(Some(BROWSER), "/missing"), Format::Html);
+        // The API stays JSON for browsers
+        assert_eq!(preferred(Some(BROWSER), "/api/missing"), Format::Json);
+        assert_eq!(preferred(Some("text/html"), "/api/missing"), Format::Json);
+    }
+
+    #[test]
+    fn test_quality_and_specificity() {
+        assert_eq!(preferred(Some("text/plain"), "/api/x"), Format::Text);
+        assert_eq!(preferred(Some("application/json;q=0.5, text/plain"), "/api/x"), Format::Text);
+        assert_eq!(preferred(Some("text/*;q=0.9, text/html;q=0.1"), "/page"), Format::Text);
+        assert_eq!(preferred(Some("text/html;q=0, application/json;q=0.2"), "/page"), Format::Json);
+        assert_eq!(preferred(Some("image/png"), "/page"), Format::Html);
+    }
+
+    #[test]
+    fn test_nested_errors_are_opt_in() {
+        assert!(nested_errors(Some("application/json; errors=nested")));
+        assert!(nested_errors(Some("text/plain;q=0.5, application/json;q=1;errors=nested")));
+        assert!(!nested_errors(Some("application/json")));
+        assert!(!nested_errors(Some("application/json; errors=flat")));
+        assert!(!nested_errors(None));
+        // Still plain JSON as far as the format goes
+        assert_eq!(preferred(Some("application/json; errors=nested"), "/api/x"), Format::Json);
+    }
+}
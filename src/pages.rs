@@ -83,9 +83,13 @@ impl DocsPage {
 }
 
 pub fn settings_context(page_title: &str) -> Context {
+    context_from_settings(db::settings().as_ref(), page_title)
+}
+
+/// Page context from a `_settings` document. Every value is escaped when
+/// rendered except `canonical_tag`, which is built from a checked URL.
+fn context_from_settings(data: Option<&db::Document>, page_title: &str) -> Context {
     let mut ctx = Context::new();
-    let settings = db::get().find_all("_settings");
-    let data = settings.first();
 
     let site_title = get_setting(data, "page_title").unwrap_or("Rust Pure Web".to_string());
     let meta_description = get_setting(data, "meta_description").unwrap_or_default();
@@ -98,11 +102,11 @@ pub fn settings_context(page_title: &str) -> Context {
         .unwrap_or_else(|| meta_description.clone());
     let og_image = get_setting(data, "og_image").unwrap_or_default();
     let twitter_card = get_setting(data, "twitter_card").unwrap_or_default();
-    let canonical_url = get_setting(data, "canonical_url").unwrap_or_default();
+    let canonical_url = get_setting(data, "canonical_url").filter(|url| is_http_url(url)).unwrap_or_default();
     let canonical_tag = if canonical_url.is_empty() {
         String::new()
     } else {
-        format!(r#"<link rel="canonical" href="{}">"#, canonical_url)
+        format!(r#"<link rel="canonical" href="{}">"#, template::escape_html(&canonical_url))
     };
 
     ctx.set("page_title", page_title);
@@ -115,11 +119,32 @@ pub fn settings_context(page_title: &str) -> Context {
     ctx.set("twitter_card", &twitter_card);
     ctx.set("canonical_url", &canonical_url);
     ctx.set_raw("canonical_tag", &canonical_tag);
+    // The same values for `{% for pair in settings %}`
+    ctx.set_map("settings", [
+        ("page_title", site_title.as_str()),
+        ("meta_description", &meta_description),
+        ("meta_keywords", &meta_keywords),
+        ("og_title", &og_title),
+        ("og_description", &og_description),
+        ("og_image", &og_image),
+        ("twitter_card", &twitter_card),
+        ("canonical_url", &canonical_url),
+    ]);
     ctx.set("body_class", "");
 
     ctx
 }
 
+/// An absolute `http`/`https` URL with a host and nothing a URL cannot
+/// contain unencoded (whitespace, controls, quotes, angle brackets).
+pub fn is_http_url(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")) else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    !host.is_empty() && !url.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '<' | '>' | '`'))
+}
+
 fn get_setting(doc: Option<&db::Document>, key: &str) -> Option<String> {
     doc.and_then(|d| d.get(key)).and_then(value_to_string)
 }
@@ -134,9 +159,20 @@ fn value_to_string(value: &db::Value) -> Option<String> {
     }
 }
 
-/// Docs page factory
-pub fn docs(slug: &str) -> DocsPage {
-    match slug {
+/// Every docs page below `/docs`, in sidebar order. `/docs` itself is the
+/// introduction.
+pub const DOC_SLUGS: [&str; 19] = [
+    "installation", "quick-start", "zero-dependency", "architecture", "server", "templates", "variables",
+    "loops", "conditionals", "includes", "components", "styling", "routing", "hot-reload", "deployment",
+    "authentication", "database", "api", "crypto",
+];
+
+/// Docs page factory; `None` for a slug with no page
+pub fn docs(slug: &str) -> Option<DocsPage> {
+    if !slug.is_empty() && slug != "index" && !DOC_SLUGS.contains(&slug) {
+        return None;
+    }
+    Some(match slug {
         "" | "index" => DocsPage { page_title: "Introduction", content_file: "intro.html", active_page: "intro" },
         "zero-dependency" => DocsPage { page_title: "Zero Dependencies", content_file: "zero-dependency.html", active_page: "zerodep" },
         "templates" => DocsPage { page_title: "Template Engine", content_file: "templates.html", active_page: "templates" },
@@ -157,8 +193,8 @@ pub fn docs(slug: &str) -> DocsPage {
         "database" => DocsPage { page_title: "Database", content_file: "database.html", active_page: "database" },
         "api" => DocsPage { page_title: "API Reference", content_file: "api.html", active_page: "api" },
         "crypto" => DocsPage { page_title: "Cryptography", content_file: "crypto.html", active_page: "crypto" },
-        _ => DocsPage { page_title: "Not Found", content_file: "intro.html", active_page: "intro" },
-    }
+        _ => return None,
+    })
 }
 
 /// Default index page data
@@ -187,3 +223,59 @@ pub fn index() -> IndexPage {
         ],
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAD: &str = r#"<meta property="og:image" content="{{ og_image }}"><meta name="description" content="{{ meta_description }}">{{ canonical_tag }}"#;
+
+    fn render_head(settings: &[(&str, &str)]) -> String {
+        let mut doc = db::Document::new();
+        for (key, value) in settings {
+            doc.insert(key.to_string(), db::Value::String(value.to_string()));
+        }
+        context_from_settings(Some(&doc), "Home").apply(HEAD)
+    }
+
+    #[test]
+    fn test_canonical_url_cannot_inject_markup() {
+        let html = render_head(&[("canonical_url", r#"https://example.com/"><script>alert(1)</script>"#)]);
+        assert!(!html.contains("<script") && !html.contains("<link"), "{}", html);
+
+        let html = render_head(&[("canonical_url", "javascript:alert(1)")]);
+        assert!(!html.contains("<link"), "{}", html);
+
+        let html = render_head(&[("canonical_url", "https://example.com/docs?a=1&b=2")]);
+        assert!(html.ends_with(r#"<link rel="canonical" href="https://example.com/docs?a=1&amp;b=2">"#), "{}", html);
+    }
+
+    #[test]
+    fn test_meta_values_are_escaped() {
+        let evil = r#""><script>alert(1)</script>"#;
+        let html = render_head(&[("og_image", evil), ("meta_description", evil)]);
+        assert!(!html.contains("<script"), "{}", html);
+        assert_eq!(html.matches("&quot;&gt;&lt;script&gt;").count(), 2);
+    }
+
+    #[test]
+    fn test_settings_can_be_iterated() {
+        let mut doc = db::Document::new();
+        doc.insert("page_title".into(), db::Value::String("Shop".into()));
+        doc.insert("meta_keywords".into(), db::Value::String("a, b".into()));
+        let ctx = context_from_settings(Some(&doc), "Home");
+        let html = ctx.apply("{% for pair in settings %}{{ pair.key }}={{ pair.value }};{% endfor %}");
+        assert!(html.starts_with("page_title=Shop;meta_description=;meta_keywords=a, b;og_title=Home | Shop;"), "{}", html);
+        assert!(html.ends_with("canonical_url=;"), "{}", html);
+    }
+
+    #[test]
+    fn test_is_http_url() {
+        for url in ["https://example.com", "http://example.com:8080/a/b?c=d#e", "https://[::1]/"] {
+            assert!(is_http_url(url), "{}", url);
+        }
+        for url in ["", "example.com", "https://", "https:///path", "ftp://example.com", "https://exa mple.com", "https://example.com/\"x"] {
+            assert!(!is_http_url(url), "{}", url);
+        }
+    }
+}
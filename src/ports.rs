@@ -17,7 +17,7 @@ pub fn find_free_port_pair(
     let used = assigned_ports();
 
     for dev in dev_start..=dev_end {
-        let prod = (dev as i32 + delta) as i32;
+        let prod = dev as i32 + delta;
         if prod < prod_start as i32 || prod > prod_end as i32 {
             continue;
         }
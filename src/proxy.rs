@@ -1,24 +1,52 @@
 //! HTTPS reverse proxy for external routes.
 use std::collections::HashMap;
-use std::env;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use rustls::ServerConfig;
-use rustls::pki_types::CertificateDer;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
 
-use crate::{auth, config, db, ports};
+use crate::api::json::JsonSerializer as Json;
+use crate::cache::Cache;
+use crate::{auth, config, db, handler, logging, ports};
 
 const BASE_HOSTS: [&str; 2] = ["olibuijr.com", "www.olibuijr.com"];
 
+/// Admin-only upstream report served on the base host
+const HEALTH_PATH: &str = "/__proxy/health";
+
+/// How long a health probe waits for an upstream to accept a connection
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Hosts whose route is remembered, and for how long at most.
+const ROUTE_CACHE_SIZE: usize = 256;
+const ROUTE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Run the HTTPS proxy and HTTP redirector. Without usable certificates, or
+/// when either port is taken, the proxy stays off and only the app listener
+/// on 3460 serves traffic.
 pub fn run_proxy() {
-    let tls_config = load_tls_config();
-    let tls_config = Arc::new(tls_config);
+    let tls_config = match load_tls_config() {
+        Ok(cfg) => Arc::new(cfg),
+        Err(e) => {
+            logging::warn("proxy", &format!("HTTPS proxy disabled: {}", e));
+            return;
+        }
+    };
 
     let http_port = env_port("RPW_HTTP_PORT", 80);
     let https_port = env_port("RPW_HTTPS_PORT", 443);
-    let http_listener = TcpListener::bind(("0.0.0.0", http_port)).unwrap();
-    let https_listener = TcpListener::bind(("0.0.0.0", https_port)).unwrap();
+    let (http_listener, https_listener) = match (listen(http_port), listen(https_port)) {
+        (Ok(http), Ok(https)) => (http, https),
+        (Err(e), _) | (_, Err(e)) => {
+            logging::warn("proxy", &format!("HTTPS proxy disabled: {}", e));
+            return;
+        }
+    };
 
     std::thread::spawn(move || {
         for stream in http_listener.incoming().flatten() {
@@ -39,13 +67,16 @@ fn handle_http(mut stream: TcpStream) {
         Some(v) => v,
         None => return,
     };
-    let host = extract_host(&_req.headers).unwrap_or_default();
+    let Some(host) = extract_host(&_req) else {
+        let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+        return;
+    };
     let target_host = if host.eq_ignore_ascii_case("olibuijr.com") {
         "www.olibuijr.com"
     } else {
         host.as_str()
     };
-    let location = format!("https://{}{}", target_host, _req.path);
+    let location = format!("https://{}{}", target_host, split_target(&_req.path).1);
     let response = format!(
         "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
         location
@@ -53,9 +84,12 @@ fn handle_http(mut stream: TcpStream) {
     let _ = stream.write_all(response.as_bytes());
 }
 
+fn listen(port: u16) -> Result<TcpListener, String> {
+    TcpListener::bind(("0.0.0.0", port)).map_err(|e| format!("cannot listen on port {}: {}", port, e))
+}
+
 fn env_port(key: &str, default_port: u16) -> u16 {
-    env::var(key)
-        .ok()
+    config::load_env(key)
         .and_then(|v| v.parse::<u16>().ok())
         .unwrap_or(default_port)
 }
@@ -71,27 +105,44 @@ fn handle_https(stream: TcpStream, tls_config: Arc<ServerConfig>) {
         Some(v) => v,
         None => return,
     };
+    let request_id = handler::request_id(&req.headers);
+    let _scope = logging::request_scope(&request_id);
+    let client_ip = tls_stream.sock.peer_addr().map(|a| a.ip().to_string()).unwrap_or_default();
+    let raw = set_headers(raw, &req, &[("X-Request-Id", &request_id), ("X-Forwarded-For", &client_ip)]);
 
-    let host = extract_host(&req.headers).unwrap_or_default();
+    let Some(host) = extract_host(&req) else {
+        let _ = respond_bad_request(&mut tls_stream);
+        return;
+    };
     if host.eq_ignore_ascii_case("olibuijr.com") {
-        let location = format!("https://www.olibuijr.com{}", req.path);
+        let location = format!("https://www.olibuijr.com{}", split_target(&req.path).1);
         let response = format!(
             "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
             location
         );
-        let _ = tls_stream.write_all(response.as_bytes());
+        let _ = tls_stream.write_all(&with_hsts(response.into_bytes(), config::hsts().as_deref()));
         return;
     }
     match route_target(&host) {
+        Route::Base if req.path.split('?').next() == Some(HEALTH_PATH) => {
+            if !authorize(&Access::Admin, &req.headers, &mut tls_stream) {
+                return;
+            }
+            let _ = respond_health(&mut tls_stream);
+        }
         Route::Base => {
-            let _ = proxy_to("127.0.0.1", 3460, &raw, &mut tls_stream);
+            let _ = proxy_to("base", "127.0.0.1", 3460, &raw, &mut tls_stream, &config::upstream_policy());
         }
-        Route::Project { host, port } => {
-            if !authorize(&req.headers) {
-                let _ = respond_unauthorized(&mut tls_stream);
+        Route::Project(upstream) => {
+            if !authorize(&upstream.access, &req.headers, &mut tls_stream) {
                 return;
             }
-            let _ = proxy_to(&host, port, &raw, &mut tls_stream);
+            let raw = if upstream.rewrite_host {
+                with_upstream_host(raw, &upstream.authority())
+            } else {
+                raw
+            };
+            let _ = proxy_to(&upstream.name, &upstream.host, upstream.port, &raw, &mut tls_stream, &config::upstream_policy());
         }
         Route::NotFound => {
             let _ = respond_not_found(&mut tls_stream);
@@ -99,28 +150,45 @@ fn handle_https(stream: TcpStream, tls_config: Arc<ServerConfig>) {
     }
 }
 
+/// Where requests for `host` go. Resolving reads `_ports` and `_settings`,
+/// so results are cached until either changes.
 fn route_target(host: &str) -> Route {
+    routes().get_or_insert_with(host.to_string(), || resolve_route(host))
+}
+
+fn routes() -> &'static Cache<String, Route> {
+    static ROUTES: OnceLock<Cache<String, Route>> = OnceLock::new();
+    ROUTES.get_or_init(|| {
+        for collection in ["_ports", "_settings"] {
+            db::on_change(collection, || routes().clear());
+        }
+        Cache::new(ROUTE_CACHE_SIZE, ROUTE_CACHE_TTL)
+    })
+}
+
+fn resolve_route(host: &str) -> Route {
     if BASE_HOSTS.iter().any(|h| h.eq_ignore_ascii_case(host)) {
         return Route::Base;
     }
 
     if let Some(project) = host.strip_suffix(".olibuijr.com") {
         if let Some(dev_project) = project.strip_prefix("dev-") {
-            if let Some((ip, port)) = project_target(dev_project, "dev_port", "dev_ip_base") {
-                return Route::Project { host: ip, port };
+            if let Some(upstream) = project_target(dev_project, project, "dev_port", "dev_ip_base") {
+                return Route::Project(upstream);
             }
-        } else if let Some((ip, port)) = project_target(project, "prod_port", "prod_ip_base") {
-            return Route::Project { host: ip, port };
+        } else if let Some(upstream) = project_target(project, project, "prod_port", "prod_ip_base") {
+            return Route::Project(upstream);
         }
     }
 
     Route::NotFound
 }
 
-fn project_target(project: &str, port_key: &str, base_key: &str) -> Option<(String, u16)> {
+/// `label` names the upstream in logs: the host's first label, such as `dev-shop`.
+fn project_target(project: &str, label: &str, port_key: &str, base_key: &str) -> Option<Upstream> {
     let docs = db::get().find_all("_ports");
-    let settings = db::get().find_all("_settings");
-    let base = settings.first()
+    let settings = db::settings();
+    let base = settings.as_ref()
         .and_then(|doc| doc.get(base_key))
         .and_then(|v| v.as_str())
         .unwrap_or("10.35.0.");
@@ -130,7 +198,9 @@ fn project_target(project: &str, port_key: &str, base_key: &str) -> Option<(Stri
             if let Some(db::Value::Int(port)) = doc.get(port_key) {
                 if *port > 0 && *port <= u16::MAX as i64 {
                     if let Some(ip) = ports::ip_from_port(base, *port as u16) {
-                        return Some((ip, 80));
+                        let rewrite_host = matches!(doc.get("rewrite_host"), Some(db::Value::Bool(true)));
+                        let access = Access::from_doc(&doc);
+                        return Some(Upstream { name: label.to_string(), host: ip, port: 80, rewrite_host, access });
                     }
                 }
             }
@@ -139,33 +209,309 @@ fn project_target(project: &str, port_key: &str, base_key: &str) -> Option<(Stri
     None
 }
 
-fn authorize(headers: &HashMap<String, String>) -> bool {
-    let token = headers.get("authorization")
-        .map(|h| h.trim_start_matches("Bearer ").to_string())
-        .or_else(|| headers.get("cookie")
-            .and_then(|c| c.split(';').find(|p| p.trim().starts_with("token=")))
-            .map(|p| p.trim().trim_start_matches("token=").to_string()))
-        .unwrap_or_default();
-    auth::is_admin(&token)
+/// Check `access` for the requesting user, answering 401 (anonymous) or 403
+/// (signed in but not allowed) when it fails.
+fn authorize(access: &Access, headers: &HashMap<String, String>, stream: &mut dyn Write) -> bool {
+    if *access == Access::Public {
+        return true;
+    }
+    let user = request_user(headers);
+    if access.permits(user.as_ref()) {
+        return true;
+    }
+    let _ = if user.is_some() { respond_forbidden(stream) } else { respond_unauthorized(stream) };
+    false
+}
+
+fn request_user(headers: &HashMap<String, String>) -> Option<db::Document> {
+    auth::get_user(&auth::request_token(headers))
+}
+
+fn respond_forbidden(stream: &mut dyn Write) -> std::io::Result<()> {
+    let response = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_vec();
+    stream.write_all(&with_hsts(response, config::hsts().as_deref()))
 }
 
 fn respond_unauthorized(stream: &mut dyn Write) -> std::io::Result<()> {
-    stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n")
+    let response = b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_vec();
+    stream.write_all(&with_hsts(response, config::hsts().as_deref()))
+}
+
+/// Every host the routing table can serve a project on, with its upstream.
+fn project_hosts() -> Vec<(String, String, u16)> {
+    let mut hosts = Vec::new();
+    for doc in db::get().find_all("_ports") {
+        let Some(project) = doc.get("project").and_then(|v| v.as_str()) else { continue };
+        for host in [format!("dev-{}.olibuijr.com", project), format!("{}.olibuijr.com", project)] {
+            if let Route::Project(upstream) = route_target(&host) {
+                hosts.push((host, upstream.host, upstream.port));
+            }
+        }
+    }
+    hosts
+}
+
+/// Whether something accepts TCP connections at `host:port` within `timeout`.
+fn reachable(host: &str, port: u16, timeout: Duration) -> bool {
+    let Ok(ip) = host.parse() else { return false };
+    TcpStream::connect_timeout(&SocketAddr::new(ip, port), timeout).is_ok()
+}
+
+/// Probe every project upstream in parallel and report up/down per host.
+fn respond_health(stream: &mut dyn Write) -> std::io::Result<()> {
+    let hosts = project_hosts();
+    let up: Vec<bool> = std::thread::scope(|scope| {
+        let probes: Vec<_> = hosts.iter()
+            .map(|(_, ip, port)| scope.spawn(move || reachable(ip, *port, PROBE_TIMEOUT)))
+            .collect();
+        probes.into_iter().map(|p| p.join().unwrap_or(false)).collect()
+    });
+    let entries: Vec<String> = hosts.iter().zip(&up)
+        .map(|((host, ip, port), up)| format!(
+            r#"{{"host":{},"upstream":{},"up":{}}}"#,
+            Json::wrap_string(host),
+            Json::wrap_string(&format!("{}:{}", ip, port)),
+            up
+        ))
+        .collect();
+    let body = format!(
+        r#"{{"up":{},"down":{},"projects":[{}]}}"#,
+        up.iter().filter(|u| **u).count(),
+        up.iter().filter(|u| !**u).count(),
+        entries.join(",")
+    );
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nCache-Control: no-store\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(&with_hsts(response.into_bytes(), config::hsts().as_deref()))
+}
+
+fn respond_bad_request(stream: &mut dyn Write) -> std::io::Result<()> {
+    let response = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n".to_vec();
+    stream.write_all(&with_hsts(response, config::hsts().as_deref()))
+}
+
+/// An empty response with `status`, for gateway errors.
+fn respond_status(stream: &mut dyn Write, status: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).into_bytes();
+    stream.write_all(&with_hsts(response, config::hsts().as_deref()))
 }
 
 fn respond_not_found(stream: &mut dyn Write) -> std::io::Result<()> {
-    stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+    let response = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec();
+    stream.write_all(&with_hsts(response, config::hsts().as_deref()))
 }
 
-fn proxy_to(host: &str, port: u16, raw: &[u8], client: &mut dyn Write) -> std::io::Result<()> {
-    let mut upstream = TcpStream::connect((host, port))?;
+/// Forward `raw` to the upstream `name` and relay the response. One that
+/// does not answer in time gets the client a 504, one that still cannot be
+/// reached after `policy.connect_attempts`, or that answers with a body it
+/// does not frame properly, a 502.
+fn proxy_to(
+    name: &str,
+    host: &str,
+    port: u16,
+    raw: &[u8],
+    client: &mut dyn Write,
+    policy: &config::UpstreamPolicy,
+) -> std::io::Result<()> {
+    match exchange(host, port, raw, policy) {
+        Ok(response) => client.write_all(&with_hsts(response, config::hsts().as_deref())),
+        Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+            logging::warn("proxy", &format!("{} ({}:{}) timed out: {}", name, host, port, e));
+            respond_status(client, "504 Gateway Timeout")
+        }
+        Err(e) if e.kind() == ErrorKind::InvalidData => {
+            logging::warn("proxy", &format!("{} ({}:{}) sent a malformed response: {}", name, host, port, e));
+            respond_status(client, "502 Bad Gateway")
+        }
+        Err(e) => {
+            logging::warn("proxy", &format!("{} ({}:{}) unreachable: {}", name, host, port, e));
+            respond_status(client, "502 Bad Gateway")
+        }
+    }
+}
+
+fn exchange(host: &str, port: u16, raw: &[u8], policy: &config::UpstreamPolicy) -> std::io::Result<Vec<u8>> {
+    let mut upstream = connect(host, port, policy)?;
+    upstream.set_read_timeout(Some(policy.read_timeout))?;
+    upstream.set_write_timeout(Some(policy.read_timeout))?;
     upstream.write_all(raw)?;
+    read_response(&mut upstream, raw.starts_with(b"HEAD "))
+}
+
+/// Connect to an upstream, trying again after `policy.retry_delay` while it
+/// refuses, so a project that is restarting is waited for. A timeout is not
+/// retried: the upstream is there but not answering. Nothing has been sent
+/// yet, so a retry is always safe.
+fn connect(host: &str, port: u16, policy: &config::UpstreamPolicy) -> std::io::Result<TcpStream> {
+    let mut attempt = 1;
+    loop {
+        let mut last_error = std::io::Error::new(ErrorKind::NotFound, "no address");
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, policy.connect_timeout) {
+                Ok(upstream) => return Ok(upstream),
+                Err(e) => last_error = e,
+            }
+        }
+        if attempt >= policy.connect_attempts || last_error.kind() == ErrorKind::TimedOut {
+            return Err(last_error);
+        }
+        attempt += 1;
+        std::thread::sleep(policy.retry_delay);
+    }
+}
+
+/// Read one response from an upstream, framed the way HTTP/1.1 frames it:
+/// no body for HEAD, 1xx, 204 and 304, else a chunked or Content-Length
+/// body. Only a response with neither is read to EOF, because a keep-alive
+/// upstream never closes. Interim 1xx responses are dropped: the client's
+/// whole request was read before it was forwarded.
+fn read_response(upstream: &mut dyn Read, head_only: bool) -> std::io::Result<Vec<u8>> {
     let mut buf = Vec::new();
-    upstream.read_to_end(&mut buf)?;
-    client.write_all(&buf)?;
-    Ok(())
+    loop {
+        let Some(head_end) = fill_until(upstream, &mut buf, |buf| Ok(find(buf, 0, b"\r\n\r\n").map(|i| i + 4)))? else {
+            return Ok(buf);
+        };
+        let head = String::from_utf8_lossy(&buf[..head_end]).to_ascii_lowercase();
+        let status: u16 = head.split(' ').nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+        let header = |name: &str| {
+            head.lines().skip(1).find_map(|line| {
+                let (k, v) = line.split_once(':')?;
+                (k.trim() == name).then(|| v.trim().to_string())
+            })
+        };
+        if status == 101 {
+            upstream.read_to_end(&mut buf)?;
+            return Ok(buf);
+        }
+        if (100..200).contains(&status) {
+            buf.drain(..head_end);
+            continue;
+        }
+        let end = if head_only || status == 204 || status == 304 {
+            Some(head_end)
+        } else if header("transfer-encoding").is_some_and(|te| te.ends_with("chunked")) {
+            fill_until(upstream, &mut buf, |buf| chunked_end(buf, head_end))?
+        } else if let Some(length) = header("content-length").and_then(|v| v.parse::<usize>().ok()) {
+            fill_until(upstream, &mut buf, |buf| Ok((buf.len() >= head_end + length).then_some(head_end + length)))?
+        } else {
+            upstream.read_to_end(&mut buf)?;
+            None
+        };
+        // A short body (upstream closed early) is passed on as it came
+        buf.truncate(end.unwrap_or(buf.len()));
+        return Ok(buf);
+    }
+}
+
+/// Read into `buf` until `done` finds where the wanted part ends; `None` if
+/// the stream ends first. An error from `done` stops the read.
+fn fill_until(
+    stream: &mut dyn Read,
+    buf: &mut Vec<u8>,
+    done: impl Fn(&[u8]) -> std::io::Result<Option<usize>>,
+) -> std::io::Result<Option<usize>> {
+    let mut tmp = [0u8; 8192];
+    loop {
+        if let Some(end) = done(buf)? {
+            return Ok(Some(end));
+        }
+        match stream.read(&mut tmp)? {
+            0 => return Ok(None),
+            n => buf.extend_from_slice(&tmp[..n]),
+        }
+    }
+}
+
+fn find(buf: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    buf.get(from..)?.windows(needle.len()).position(|w| w == needle).map(|i| from + i)
+}
+
+/// End of a chunked body starting at `from`, through the last chunk and
+/// any trailers; `None` while incomplete. A chunk size that is not hex, or
+/// that no buffer could hold, is `InvalidData`.
+fn chunked_end(buf: &[u8], from: usize) -> std::io::Result<Option<usize>> {
+    let malformed = || std::io::Error::new(ErrorKind::InvalidData, "malformed chunked body");
+    let mut pos = from;
+    loop {
+        let Some(line_end) = find(buf, pos, b"\r\n") else { return Ok(None) };
+        let size_line = String::from_utf8_lossy(&buf[pos..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16).map_err(|_| malformed())?;
+        pos = line_end + 2;
+        if size == 0 {
+            // Trailers, if any, end with an empty line
+            return Ok(if buf.get(pos..pos + 2) == Some(b"\r\n") { Some(pos + 2) } else { find(buf, pos, b"\r\n\r\n").map(|i| i + 4) });
+        }
+        pos = pos.checked_add(size).and_then(|p| p.checked_add(2)).ok_or_else(malformed)?;
+        if buf.len() < pos {
+            return Ok(None);
+        }
+    }
+}
+
+/// Add `Strict-Transport-Security` after the status line of an HTTPS response,
+/// unless disabled or the upstream already set one.
+fn with_hsts(response: Vec<u8>, hsts: Option<&str>) -> Vec<u8> {
+    let Some(value) = hsts else { return response; };
+    let Some(line_end) = response.windows(2).position(|w| w == b"\r\n") else { return response; };
+    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap_or(response.len());
+    let head = String::from_utf8_lossy(&response[..head_end]).to_ascii_lowercase();
+    if head.contains("\r\nstrict-transport-security:") {
+        return response;
+    }
+    let mut out = Vec::with_capacity(response.len() + value.len() + 32);
+    out.extend_from_slice(&response[..line_end + 2]);
+    out.extend_from_slice(format!("Strict-Transport-Security: {}\r\n", value).as_bytes());
+    out.extend_from_slice(&response[line_end + 2..]);
+    out
+}
+
+
+/// Replace (or add) request headers before forwarding upstream, so the app
+/// sees the proxy's request id and the real client address.
+fn set_headers(raw: Vec<u8>, req: &ParsedRequest, headers: &[(&str, &str)]) -> Vec<u8> {
+    let Some(line_end) = raw.windows(2).position(|w| w == b"\r\n") else { return raw; };
+    if req.body_offset < line_end + 4 {
+        return raw;
+    }
+    let mut out = Vec::with_capacity(raw.len() + 64);
+    out.extend_from_slice(&raw[..line_end + 2]);
+    for line in raw[line_end + 2..req.body_offset - 2].split_inclusive(|&b| b == b'\n') {
+        let lower = line.to_ascii_lowercase();
+        let replaced = headers.iter().any(|(name, _)| {
+            lower.starts_with(name.to_ascii_lowercase().as_bytes()) && lower.get(name.len()) == Some(&b':')
+        });
+        if !replaced {
+            out.extend_from_slice(line);
+        }
+    }
+    for (name, value) in headers {
+        out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&raw[req.body_offset..]);
+    out
 }
 
+/// Point a request at `authority`: an absolute-form target
+/// (`GET http://public.host/x`) becomes origin-form and `Host` is replaced.
+fn with_upstream_host(raw: Vec<u8>, authority: &str) -> Vec<u8> {
+    let Some(line_end) = raw.windows(2).position(|w| w == b"\r\n") else { return raw; };
+    let line = String::from_utf8_lossy(&raw[..line_end]).into_owned();
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return raw;
+    };
+    let target = split_target(target).1;
+    let mut out = format!("{} {} {}", method, target, version).into_bytes();
+    out.extend_from_slice(&raw[line_end..]);
+    match parse_request_bytes(&out) {
+        Some(req) => set_headers(out, &req, &[("Host", authority)]),
+        None => raw,
+    }
+}
 
 fn read_request(stream: &mut dyn Read) -> Option<(Vec<u8>, ParsedRequest)> {
     let mut buf = Vec::new();
@@ -203,6 +549,7 @@ fn parse_request_bytes(buf: &[u8]) -> Option<ParsedRequest> {
     let mut lines = text.lines();
     let first = lines.next()?;
     let mut parts = first.split_whitespace();
+    let _method = parts.next()?;
     let path = parts.next()?.to_string();
     let mut headers = HashMap::new();
     let mut offset = 0usize;
@@ -225,41 +572,543 @@ fn parse_request_bytes(buf: &[u8]) -> Option<ParsedRequest> {
     })
 }
 
-fn extract_host(headers: &HashMap<String, String>) -> Option<String> {
-    headers.get("host").map(|h| h.split(':').next().unwrap_or(h).to_string())
+/// The host a request is for, without its port: the Host header, or the
+/// authority of an absolute-form target when Host is missing, as HTTP/1.0
+/// clients may send. `None` when the request names no host at all.
+fn extract_host(req: &ParsedRequest) -> Option<String> {
+    let authority = req
+        .headers
+        .get("host")
+        .map(String::as_str)
+        .filter(|h| !h.trim().is_empty())
+        .or_else(|| split_target(&req.path).0)?;
+    let host = authority.trim().split(':').next().unwrap_or_default();
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// An absolute-form request target (`http://host/path`) split into its
+/// authority and origin-form path; any other target is all path.
+fn split_target(target: &str) -> (Option<&str>, &str) {
+    let Some(rest) = ["http://", "https://"].iter().find_map(|scheme| target.strip_prefix(scheme)) else {
+        return (None, target);
+    };
+    match rest.find('/') {
+        Some(i) => (Some(&rest[..i]), &rest[i..]),
+        None => (Some(rest), "/"),
+    }
+}
+
+/// TLS settings from `certs/`: `server.crt`/`server.key` is the default pair,
+/// and each `certs/<host>/` holding its own pair is served to clients asking
+/// for that SNI name (`certs/*.example.com/` matches any direct subdomain).
+fn load_tls_config() -> Result<ServerConfig, String> {
+    tls_config_from(&config::root_dir().join("certs"))
+}
+
+fn tls_config_from(dir: &Path) -> Result<ServerConfig, String> {
+    let builder = ServerConfig::builder().with_no_client_auth();
+    let default = read_pem(&dir.join("server.crt"), &dir.join("server.key"));
+    let hosts = host_cert_dirs(dir);
+    if hosts.is_empty() {
+        let (certs, key) = default?;
+        return builder
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("invalid cert/key: {}", e));
+    }
+
+    let provider = builder.crypto_provider().clone();
+    let certify = |(certs, key)| {
+        CertifiedKey::from_der(certs, key, &provider)
+            .map(Arc::new)
+            .map_err(|e| format!("invalid cert/key: {}", e))
+    };
+    let mut resolver = SniResolver {
+        by_host: HashMap::new(),
+        default: match default {
+            Ok(pair) => Some(certify(pair)?),
+            Err(e) => {
+                logging::warn("proxy", &format!("no default certificate: {}", e));
+                None
+            }
+        },
+    };
+    for (host, path) in hosts {
+        match read_pem(&path.join("server.crt"), &path.join("server.key")).and_then(certify) {
+            Ok(key) => {
+                resolver.by_host.insert(host.to_ascii_lowercase(), key);
+            }
+            Err(e) => logging::warn("proxy", &format!("skipping certificate for {}: {}", host, e)),
+        }
+    }
+    Ok(builder.with_cert_resolver(Arc::new(resolver)))
 }
 
-fn load_tls_config() -> ServerConfig {
-    let cert_path = config::root_dir().join("certs/server.crt");
-    let key_path = config::root_dir().join("certs/server.key");
+type PemPair = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
 
-    let cert_file = std::fs::File::open(cert_path).expect("missing certs/server.crt");
-    let key_file = std::fs::File::open(key_path).expect("missing certs/server.key");
-    let mut cert_reader = std::io::BufReader::new(cert_file);
-    let mut key_reader = std::io::BufReader::new(key_file);
+fn read_pem(cert_path: &Path, key_path: &Path) -> Result<PemPair, String> {
+    let open = |path: &Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+    };
+    let mut cert_reader = open(cert_path)?;
+    let mut key_reader = open(key_path)?;
 
     let certs: Vec<CertificateDer> = rustls_pemfile::certs(&mut cert_reader)
         .filter_map(Result::ok)
         .collect();
+    if certs.is_empty() {
+        return Err(format!("no certificates in {}", cert_path.display()));
+    }
     let key = rustls_pemfile::private_key(&mut key_reader)
         .ok()
         .flatten()
-        .expect("invalid private key");
+        .ok_or_else(|| format!("no private key in {}", key_path.display()))?;
+    Ok((certs, key))
+}
+
+/// Subdirectories of `dir` that contain a `server.crt`, keyed by directory name.
+fn host_cert_dirs(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut hosts: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join("server.crt").is_file())
+        .filter_map(|path| Some((path.file_name()?.to_str()?.to_string(), path)))
+        .collect();
+    hosts.sort();
+    hosts
+}
+
+/// Picks a certificate by SNI name: exact host, then a `*.` wildcard for its
+/// parent domain, then the default.
+#[derive(Debug)]
+struct SniResolver {
+    by_host: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
 
-    ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .expect("invalid cert/key")
+impl SniResolver {
+    fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let host = server_name.map(str::to_ascii_lowercase);
+        host.and_then(|host| {
+            self.by_host.get(&host).or_else(|| {
+                let (_, parent) = host.split_once('.')?;
+                self.by_host.get(&format!("*.{}", parent))
+            }).cloned()
+        })
+        .or_else(|| self.default.clone())
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.select(client_hello.server_name())
+    }
 }
 
+#[derive(Clone)]
 enum Route {
     Base,
-    Project { host: String, port: u16 },
+    Project(Upstream),
     NotFound,
 }
 
+/// Where a project host is forwarded, with its per-project settings from `_ports`
+#[derive(Clone)]
+struct Upstream {
+    name: String,
+    host: String,
+    port: u16,
+    /// Send the upstream's own address as `Host` instead of the public name
+    rewrite_host: bool,
+    access: Access,
+}
+
+/// Who may reach a project through the proxy, from the `access`,
+/// `allow_roles` and `allow_users` fields of its `_ports` entry.
+#[derive(Clone, Debug, PartialEq)]
+enum Access {
+    /// Anyone, signed in or not
+    Public,
+    /// Admins only (the default)
+    Admin,
+    /// Admins, plus users holding one of `roles` or listed in `users` by id or email
+    Restricted { roles: Vec<String>, users: Vec<String> },
+}
+
+impl Access {
+    fn from_doc(doc: &db::Document) -> Self {
+        let list = |key: &str| -> Vec<String> {
+            doc.get(key)
+                .and_then(db::Value::as_array)
+                .map(|items| items.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                .unwrap_or_default()
+        };
+        match doc.get("access").and_then(db::Value::as_str) {
+            Some("public") => Access::Public,
+            Some("restricted") => Access::Restricted { roles: list("allow_roles"), users: list("allow_users") },
+            _ => Access::Admin,
+        }
+    }
+
+    fn permits(&self, user: Option<&db::Document>) -> bool {
+        let field = |key: &str| user.and_then(|u| u.get(key)).and_then(db::Value::as_str);
+        if *self == Access::Public {
+            return true;
+        }
+        if field("role") == Some("admin") {
+            return true;
+        }
+        match self {
+            Access::Restricted { roles, users } => {
+                field("role").is_some_and(|role| roles.iter().any(|r| r == role))
+                    || [field("id"), field("email")]
+                        .into_iter()
+                        .flatten()
+                        .any(|who| users.iter().any(|u| u.eq_ignore_ascii_case(who)))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Upstream {
+    fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
 struct ParsedRequest {
     path: String,
     headers: HashMap<String, String>,
     body_offset: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(raw: &str, headers: &[(&str, &str)]) -> String {
+        let req = parse_request_bytes(raw.as_bytes()).unwrap();
+        String::from_utf8(set_headers(raw.as_bytes().to_vec(), &req, headers)).unwrap()
+    }
+
+    #[test]
+    fn test_hsts_added_to_https_responses() {
+        let out = with_hsts(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_vec(), Some("max-age=600"));
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nStrict-Transport-Security: max-age=600\r\nContent-Length: 2\r\n\r\nok");
+
+        let upstream = b"HTTP/1.1 200 OK\r\nstrict-transport-security: max-age=1\r\n\r\n".to_vec();
+        assert_eq!(with_hsts(upstream.clone(), Some("max-age=600")), upstream);
+        assert_eq!(with_hsts(upstream.clone(), None), upstream);
+    }
+
+    #[test]
+    fn test_request_line_path() {
+        let req = parse_request_bytes(b"GET /__proxy/health?x=1 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(req.path, "/__proxy/health?x=1");
+        assert_eq!(extract_host(&req).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_host_without_host_header() {
+        let absolute = parse_request_bytes(b"GET http://dev-foo.olibuijr.com:80/x HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(extract_host(&absolute).as_deref(), Some("dev-foo.olibuijr.com"));
+        assert_eq!(split_target(&absolute.path), (Some("dev-foo.olibuijr.com:80"), "/x"));
+        for raw in [&b"GET / HTTP/1.0\r\n\r\n"[..], b"GET / HTTP/1.1\r\nHost: \r\n\r\n", b"GET / HTTP/1.1\r\nHost: :443\r\n\r\n"] {
+            let req = parse_request_bytes(raw).unwrap();
+            assert_eq!(extract_host(&req), None, "{}", String::from_utf8_lossy(raw));
+        }
+    }
+
+    #[test]
+    fn test_taken_port_is_an_error_not_a_panic() {
+        let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = listen(port).unwrap_err();
+        assert!(err.starts_with(&format!("cannot listen on port {}: ", port)), "{}", err);
+    }
+
+    #[test]
+    fn test_reachable_up_and_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(reachable("127.0.0.1", port, PROBE_TIMEOUT));
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+        assert!(!reachable("127.0.0.1", closed_port, PROBE_TIMEOUT));
+        assert!(!reachable("not-an-ip", port, PROBE_TIMEOUT));
+    }
+
+    #[test]
+    fn test_missing_certs_are_an_error() {
+        let dir = std::env::temp_dir().join("rpw-no-certs");
+        let err = tls_config_from(&dir).unwrap_err();
+        assert!(err.contains("server.crt"), "{}", err);
+    }
+
+    /// Stand-in key: the resolver never signs, it only hands keys out.
+    #[derive(Debug)]
+    struct NoKey;
+
+    impl rustls::sign::SigningKey for NoKey {
+        fn choose_scheme(&self, _: &[rustls::SignatureScheme]) -> Option<Box<dyn rustls::sign::Signer>> {
+            None
+        }
+        fn algorithm(&self) -> rustls::SignatureAlgorithm {
+            rustls::SignatureAlgorithm::ECDSA
+        }
+    }
+
+    fn tagged(tag: u8) -> Arc<CertifiedKey> {
+        Arc::new(CertifiedKey::new(vec![CertificateDer::from(vec![tag])], Arc::new(NoKey)))
+    }
+
+    #[test]
+    fn test_sni_resolver_selects_by_host() {
+        let mut by_host = HashMap::new();
+        by_host.insert("dev-blog.olibuijr.com".to_string(), tagged(1));
+        by_host.insert("*.olibuijr.com".to_string(), tagged(2));
+        let resolver = SniResolver { by_host, default: Some(tagged(3)) };
+        let pick = |name| resolver.select(name).map(|key| key.cert[0].as_ref()[0]);
+
+        assert_eq!(pick(Some("dev-blog.olibuijr.com")), Some(1));
+        assert_eq!(pick(Some("DEV-BLOG.olibuijr.com")), Some(1));
+        assert_eq!(pick(Some("shop.olibuijr.com")), Some(2));
+        assert_eq!(pick(Some("a.b.olibuijr.com")), Some(3));
+        assert_eq!(pick(Some("example.org")), Some(3));
+        assert_eq!(pick(None), Some(3));
+
+        let no_default = SniResolver { by_host: HashMap::new(), default: None };
+        assert!(no_default.select(Some("example.org")).is_none());
+    }
+
+    #[test]
+    fn test_upstream_host_rewrite() {
+        let out = with_upstream_host(b"GET /x?y=1 HTTP/1.1\r\nHost: dev-foo.olibuijr.com\r\nAccept: */*\r\n\r\n".to_vec(), "10.35.0.7");
+        assert_eq!(out, b"GET /x?y=1 HTTP/1.1\r\nAccept: */*\r\nHost: 10.35.0.7\r\n\r\n");
+
+        let absolute = b"POST https://dev-foo.olibuijr.com/api?q HTTP/1.1\r\nHost: dev-foo.olibuijr.com\r\n\r\nbody".to_vec();
+        assert_eq!(with_upstream_host(absolute, "10.35.0.7"), b"POST /api?q HTTP/1.1\r\nHost: 10.35.0.7\r\n\r\nbody");
+
+        let bare = b"GET http://dev-foo.olibuijr.com HTTP/1.1\r\n\r\n".to_vec();
+        assert_eq!(with_upstream_host(bare, "10.35.0.7"), b"GET / HTTP/1.1\r\nHost: 10.35.0.7\r\n\r\n");
+    }
+
+    #[test]
+    fn test_rewritten_host_reaches_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = Upstream {
+            name: "dev-foo".into(),
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            rewrite_host: true,
+            access: Access::Admin,
+        };
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let (raw, _) = read_request(&mut conn).unwrap();
+            conn.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").unwrap();
+            String::from_utf8(raw).unwrap()
+        });
+
+        let raw = with_upstream_host(b"GET /page HTTP/1.1\r\nHost: dev-foo.olibuijr.com\r\n\r\n".to_vec(), &upstream.authority());
+        let mut client = Vec::new();
+        proxy_to(&upstream.name, &upstream.host, upstream.port, &raw, &mut client, &config::upstream_policy()).unwrap();
+
+        let received = server.join().unwrap();
+        assert!(received.contains(&format!("\r\nHost: 127.0.0.1:{}\r\n", upstream.port)), "{}", received);
+        assert!(!received.contains("olibuijr.com"), "{}", received);
+        assert!(String::from_utf8_lossy(&client).starts_with("HTTP/1.1 204"));
+    }
+
+    /// An upstream that answers one request with `response` and then keeps
+    /// the connection open, as a keep-alive server does.
+    fn keep_alive_upstream(response: &'static [u8]) -> (u16, std::sync::mpsc::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            read_request(&mut conn).unwrap();
+            conn.write_all(response).unwrap();
+            let _ = wait.recv();
+        });
+        (port, done)
+    }
+
+    #[test]
+    fn test_framed_responses_from_keep_alive_upstream() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"GET / HTTP/1.1\r\n\r\n", b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: keep-alive\r\n\r\nhello"),
+            (b"GET / HTTP/1.1\r\n\r\n", b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;x=y\r\n world\r\n0\r\n\r\n"),
+            (b"HEAD / HTTP/1.1\r\n\r\n", b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"),
+            (b"GET / HTTP/1.1\r\n\r\n", b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"),
+        ];
+        for (request, response) in cases {
+            let (port, done) = keep_alive_upstream(response);
+            let mut client = Vec::new();
+            // Returns once the response is complete, though the upstream stays open
+            proxy_to("test", "127.0.0.1", port, request, &mut client, &config::upstream_policy()).unwrap();
+            let relayed = response.strip_prefix(&b"HTTP/1.1 100 Continue\r\n\r\n"[..]).unwrap_or(response);
+            assert_eq!(client, with_hsts(relayed.to_vec(), config::hsts().as_deref()), "{}", String::from_utf8_lossy(&client));
+            done.send(()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_silent_upstream_gets_504() {
+        let (port, done) = keep_alive_upstream(b"");
+        let policy = config::UpstreamPolicy {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_millis(200),
+            connect_attempts: 1,
+            retry_delay: Duration::ZERO,
+        };
+        let started = std::time::Instant::now();
+        let mut client = Vec::new();
+        proxy_to("test", "127.0.0.1", port, b"GET / HTTP/1.1\r\n\r\n", &mut client, &policy).unwrap();
+        assert!(client.starts_with(b"HTTP/1.1 504 Gateway Timeout\r\n"), "{}", String::from_utf8_lossy(&client));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        done.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_upstream_gets_502() {
+        let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut client = Vec::new();
+        proxy_to("dev-down", "127.0.0.1", closed_port, b"GET / HTTP/1.1\r\n\r\n", &mut client, &config::upstream_policy()).unwrap();
+        assert!(client.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"), "{}", String::from_utf8_lossy(&client));
+    }
+
+    #[test]
+    fn test_overflowing_chunk_size_gets_502() {
+        let (port, done) = keep_alive_upstream(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffe\r\nab");
+        let mut client = Vec::new();
+        proxy_to("dev-broken", "127.0.0.1", port, b"GET / HTTP/1.1\r\n\r\n", &mut client, &config::upstream_policy()).unwrap();
+        assert!(client.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"), "{}", String::from_utf8_lossy(&client));
+        let _ = done.send(());
+    }
+
+    #[test]
+    fn test_connect_is_retried_until_the_upstream_is_back() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        // The upstream comes back between the first and the second attempt
+        let server = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (mut conn, _) = listener.accept().unwrap();
+            let (raw, _) = read_request(&mut conn).unwrap();
+            conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            raw
+        });
+        let policy = config::UpstreamPolicy {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(5),
+            connect_attempts: 3,
+            retry_delay: Duration::from_millis(400),
+        };
+        let request = b"POST /save HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        let mut client = Vec::new();
+        proxy_to("dev-restarting", "127.0.0.1", port, request, &mut client, &policy).unwrap();
+        assert!(client.starts_with(b"HTTP/1.1 200 OK\r\n") && client.ends_with(b"ok"), "{}", String::from_utf8_lossy(&client));
+        assert_eq!(server.join().unwrap(), request);
+
+        // With a single attempt the same blip is a 502
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut client = Vec::new();
+        let once = config::UpstreamPolicy { connect_attempts: 1, ..policy };
+        proxy_to("dev-restarting", "127.0.0.1", port, request, &mut client, &once).unwrap();
+        assert!(client.starts_with(b"HTTP/1.1 502 "));
+    }
+
+    #[test]
+    fn test_chunked_end() {
+        let body = b"HTTP/1.1 200 OK\r\n\r\n3\r\nabc\r\n0\r\nX-Sum: 1\r\n\r\n";
+        assert_eq!(chunked_end(body, 19).unwrap(), Some(body.len()));
+        assert_eq!(chunked_end(&body[..body.len() - 2], 19).unwrap(), None);
+        assert_eq!(chunked_end(b"3\r\nab", 0).unwrap(), None);
+        // Sizes that would overflow the position, or are not hex, are refused
+        let huge = format!("{:x}\r\nab", usize::MAX - 1);
+        assert_eq!(chunked_end(huge.as_bytes(), 0).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(chunked_end(b"zz\r\nab", 0).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    fn user(id: &str, email: &str, role: &str) -> db::Document {
+        let mut doc = db::Document::new();
+        for (key, value) in [("id", id), ("email", email), ("role", role)] {
+            doc.insert(key.into(), db::Value::String(value.into()));
+        }
+        doc
+    }
+
+    fn ports_doc(access: &str, roles: &[&str], users: &[&str]) -> db::Document {
+        let strings = |items: &[&str]| db::Value::Array(items.iter().map(|s| db::Value::String(s.to_string())).collect());
+        let mut doc = db::Document::new();
+        doc.insert("access".into(), db::Value::String(access.into()));
+        doc.insert("allow_roles".into(), strings(roles));
+        doc.insert("allow_users".into(), strings(users));
+        doc
+    }
+
+    #[test]
+    fn test_public_project_allows_anonymous() {
+        let access = Access::from_doc(&ports_doc("public", &[], &[]));
+        assert_eq!(access, Access::Public);
+        assert!(access.permits(None));
+        assert!(access.permits(Some(&user("u1", "a@b.c", "user"))));
+    }
+
+    #[test]
+    fn test_admin_only_project() {
+        let access = Access::from_doc(&db::Document::new());
+        assert_eq!(access, Access::Admin);
+        assert!(!access.permits(None));
+        assert!(!access.permits(Some(&user("u1", "a@b.c", "user"))));
+        assert!(access.permits(Some(&user("u2", "root@b.c", "admin"))));
+    }
+
+    #[test]
+    fn test_restricted_project_by_role_or_user() {
+        let by_role = Access::from_doc(&ports_doc("restricted", &["user"], &[]));
+        assert!(!by_role.permits(None));
+        assert!(by_role.permits(Some(&user("u1", "a@b.c", "user"))));
+        assert!(by_role.permits(Some(&user("u2", "root@b.c", "admin"))));
+        assert!(!by_role.permits(Some(&user("u3", "x@b.c", "guest"))));
+
+        let by_user = Access::from_doc(&ports_doc("restricted", &[], &["u1", "Friend@Example.com"]));
+        assert!(by_user.permits(Some(&user("u1", "a@b.c", "user"))));
+        assert!(by_user.permits(Some(&user("u9", "friend@example.com", "user"))));
+        assert!(!by_user.permits(Some(&user("u3", "x@b.c", "user"))));
+    }
+
+    #[test]
+    fn test_hsts_value() {
+        assert_eq!(config::hsts_value(31_536_000, true).as_deref(), Some("max-age=31536000; includeSubDomains"));
+        assert_eq!(config::hsts_value(600, false).as_deref(), Some("max-age=600"));
+        assert_eq!(config::hsts_value(0, true), None);
+    }
+
+    #[test]
+    fn test_headers_added_before_body() {
+        let out = forwarded("POST /x HTTP/1.1\r\nHost: a\r\n\r\nbody", &[("X-Request-Id", "abc123")]);
+        assert_eq!(out, "POST /x HTTP/1.1\r\nHost: a\r\nX-Request-Id: abc123\r\n\r\nbody");
+    }
+
+    #[test]
+    fn test_headers_replace_incoming_values() {
+        let raw = "GET / HTTP/1.1\r\nx-request-id: bad id!\r\nX-Forwarded-For: 6.6.6.6\r\nX-Forwarded-Forx: keep\r\nHost: a\r\n\r\n";
+        let out = forwarded(raw, &[("X-Request-Id", "fresh"), ("X-Forwarded-For", "203.0.113.9")]);
+        assert_eq!(
+            out,
+            "GET / HTTP/1.1\r\nX-Forwarded-Forx: keep\r\nHost: a\r\nX-Request-Id: fresh\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Sliding-window rate limiting shared by throttled endpoints (login, contact, ...)
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::clock;
+
+/// Outcome of a rate limit check
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    RetryAfter(Duration),
+}
+
+/// At most `max` hits per key within any `window`-long span.
+pub struct SlidingWindow<K> {
+    max: usize,
+    window: Duration,
+    hits: Mutex<HashMap<K, VecDeque<Instant>>>,
+}
+
+impl<K: Eq + Hash + Clone> SlidingWindow<K> {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self { max, window, hits: Mutex::new(HashMap::new()) }
+    }
+
+    /// Record a hit for `key` unless it is over the limit.
+    pub fn check(&self, key: K) -> Decision {
+        self.check_at(key, clock::instant())
+    }
+
+    fn check_at(&self, key: K, now: Instant) -> Decision {
+        let mut hits = self.hits.lock().unwrap();
+        let times = hits.entry(key).or_default();
+        while times.front().is_some_and(|t| now.duration_since(*t) >= self.window) {
+            times.pop_front();
+        }
+        if times.len() < self.max {
+            times.push_back(now);
+            return Decision::Allowed;
+        }
+        let oldest = times.front().copied().unwrap_or(now);
+        Decision::RetryAfter(self.window.saturating_sub(now.duration_since(oldest)))
+    }
+
+    /// Forget a key, e.g. after a successful login.
+    pub fn clear(&self, key: &K) {
+        self.hits.lock().unwrap().remove(key);
+    }
+
+    /// Drop keys whose hits have all aged out of the window.
+    pub fn prune(&self) {
+        self.prune_at(clock::instant());
+    }
+
+    fn prune_at(&self, now: Instant) {
+        self.hits.lock().unwrap().retain(|_, times| {
+            times.back().is_some_and(|t| now.duration_since(*t) < self.window)
+        });
+    }
+
+    #[cfg(test)]
+    fn tracked_keys(&self) -> usize {
+        self.hits.lock().unwrap().len()
+    }
+}
+
+/// Lazily create a process-wide limiter and start its background pruner.
+pub fn shared<K>(cell: &'static OnceLock<SlidingWindow<K>>, max: usize, window: Duration) -> &'static SlidingWindow<K>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+{
+    let mut created = false;
+    let limiter = cell.get_or_init(|| {
+        created = true;
+        SlidingWindow::new(max, window)
+    });
+    if created {
+        let every = window.max(Duration::from_secs(1));
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            limiter.prune();
+        });
+    }
+    limiter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_window_boundary() {
+        let limiter = SlidingWindow::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        assert_eq!(limiter.check_at("ip", start), Decision::Allowed);
+        assert_eq!(limiter.check_at("ip", start + Duration::from_secs(4)), Decision::Allowed);
+        assert_eq!(
+            limiter.check_at("ip", start + Duration::from_secs(6)),
+            Decision::RetryAfter(Duration::from_secs(4))
+        );
+        // Other keys are independent
+        assert_eq!(limiter.check_at("other", start + Duration::from_secs(6)), Decision::Allowed);
+        // The first hit leaves the window exactly `window` later
+        assert_eq!(limiter.check_at("ip", start + Duration::from_secs(10)), Decision::Allowed);
+        assert_eq!(
+            limiter.check_at("ip", start + Duration::from_secs(11)),
+            Decision::RetryAfter(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn test_check_follows_the_clock() {
+        let clock = MockClock::install(0);
+        let limiter = SlidingWindow::new(1, Duration::from_secs(60));
+        assert_eq!(limiter.check("ip"), Decision::Allowed);
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(limiter.check("ip"), Decision::RetryAfter(Duration::from_secs(1)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.check("ip"), Decision::Allowed);
+    }
+
+    #[test]
+    fn test_clear_resets_key() {
+        let limiter = SlidingWindow::new(1, Duration::from_secs(60));
+        assert_eq!(limiter.check("user"), Decision::Allowed);
+        assert!(matches!(limiter.check("user"), Decision::RetryAfter(_)));
+        limiter.clear(&"user");
+        assert_eq!(limiter.check("user"), Decision::Allowed);
+    }
+
+    #[test]
+    fn test_prune_drops_stale_keys() {
+        let limiter = SlidingWindow::new(5, Duration::from_secs(10));
+        let start = Instant::now();
+        limiter.check_at("old", start);
+        limiter.check_at("recent", start + Duration::from_secs(8));
+        limiter.prune_at(start + Duration::from_secs(12));
+        assert_eq!(limiter.tracked_keys(), 1);
+        limiter.prune_at(start + Duration::from_secs(30));
+        assert_eq!(limiter.tracked_keys(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_checks_respect_max() {
+        let limiter = Arc::new(SlidingWindow::new(50, Duration::from_secs(60)));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || {
+                    (0..20).filter(|_| limiter.check("shared") == Decision::Allowed).count()
+                })
+            })
+            .collect();
+        let allowed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(allowed, 50);
+    }
+}
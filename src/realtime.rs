@@ -1,53 +1,250 @@
-use crate::ws;
+use crate::api::json::parse_value;
+use crate::{logging, ws};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 use std::net::TcpStream;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Mutex, OnceLock};
 use std::thread;
+use std::time::Duration;
+
+/// Number of broadcast events retained for replay. A client whose cursor is
+/// older than the retained window gets `{"type":"resync"}` instead of a replay
+/// and must refetch its state.
+const HISTORY_LIMIT: usize = 1000;
+
+/// How long a new connection may take to send its `{"since":cursor}` frame
+/// before it joins the live stream.
+const REPLAY_WAIT: Duration = Duration::from_millis(300);
+
+/// How often live connections have their token checked again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How a client receives events.
+#[derive(Clone, Copy, PartialEq)]
+enum Transport {
+    /// An upgraded `/realtime` socket: one text frame per event
+    WebSocket,
+    /// A `text/event-stream` response from `/api/events/stream`: one `data:`
+    /// message per event, with its sequence number as the event id
+    EventStream,
+}
 
 struct Client {
     id: u64,
     stream: TcpStream,
+    /// Token the connection was authorized with
+    token: String,
+    transport: Transport,
+}
+
+/// Bounded, sequenced log of broadcast events.
+struct History {
+    seq: u64,
+    events: VecDeque<(u64, String)>,
+    limit: usize,
+}
+
+enum Replay {
+    Events(Vec<(u64, String)>),
+    Resync(u64),
+}
+
+impl History {
+    fn new(limit: usize) -> Self {
+        Self { seq: 0, events: VecDeque::new(), limit }
+    }
+
+    /// Assign the next sequence number and retain the stamped message.
+    fn push(&mut self, message: &str) -> String {
+        self.seq += 1;
+        let stamped = stamp(self.seq, message);
+        if self.events.len() == self.limit {
+            self.events.pop_front();
+        }
+        self.events.push_back((self.seq, stamped.clone()));
+        stamped
+    }
+
+    /// Events newer than `cursor`, or a resync when some were already dropped
+    /// (or the cursor comes from before a restart).
+    fn since(&self, cursor: u64) -> Replay {
+        let oldest = self.events.front().map(|(seq, _)| *seq).unwrap_or(self.seq + 1);
+        if cursor > self.seq || cursor + 1 < oldest {
+            return Replay::Resync(self.seq);
+        }
+        Replay::Events(self.events.iter().filter(|(seq, _)| *seq > cursor).cloned().collect())
+    }
+}
+
+fn stamp(seq: u64, message: &str) -> String {
+    match message.strip_prefix('{') {
+        Some(rest) if rest.trim_start().starts_with('}') => format!("{{\"seq\":{}}}", seq),
+        Some(rest) => format!("{{\"seq\":{},{}", seq, rest),
+        None => message.to_string(),
+    }
 }
 
 static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 static HUB: OnceLock<Mutex<Vec<Client>>> = OnceLock::new();
+static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
 
 fn hub() -> &'static Mutex<Vec<Client>> {
     HUB.get_or_init(|| Mutex::new(Vec::new()))
 }
 
-pub fn register(stream: TcpStream) {
+fn history() -> &'static Mutex<History> {
+    HISTORY.get_or_init(|| Mutex::new(History::new(HISTORY_LIMIT)))
+}
+
+/// Take over an upgraded `/realtime` socket authorized with `token`, which
+/// agreed on `protocol` in the handshake (if the client offered one we speak).
+pub fn register(stream: TcpStream, token: String, protocol: Option<String>) {
     let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
-    let writer = match stream.try_clone() {
+    let protocol = protocol.as_deref().unwrap_or("none");
+    logging::info("realtime", &format!("client {} connected (subprotocol {})", id, protocol));
+    let mut writer = match stream.try_clone() {
         Ok(s) => s,
         Err(_) => return,
     };
-
-    hub().lock().unwrap().push(Client { id, stream: writer });
+    let joined_at = history().lock().unwrap().seq;
 
     thread::spawn(move || {
         let mut reader = stream;
-        loop {
-            match ws::read_frame(&mut reader) {
-                Ok(frame) => match frame.opcode {
-                    0x8 => break,
-                    0x9 => {
-                        let _ = send_pong(id, &frame.payload);
-                    }
-                    _ => {}
-                },
-                Err(_) => break,
+
+        // A reconnecting client announces its last seen cursor first; anyone
+        // else is caught up from the moment the socket was accepted.
+        let _ = reader.set_read_timeout(Some(REPLAY_WAIT));
+        let first = ws::read_frame(&mut reader);
+        let _ = reader.set_read_timeout(None);
+        let cursor = match &first {
+            Ok(frame) if frame.opcode == 0x1 => parse_since(&frame.payload).unwrap_or(joined_at),
+            Ok(frame) if frame.opcode == 0x8 => {
+                let _ = ws::reply_close(&mut writer, &frame.payload);
+                return;
+            }
+            _ => joined_at,
+        };
+
+        if !join(Client { id, stream: writer, token, transport: Transport::WebSocket }, cursor) {
+            return;
+        }
+
+        if let Ok(frame) = &first {
+            if frame.opcode == 0x9 {
+                let _ = send_pong(id, &frame.payload);
+            }
+        }
+        while let Ok(frame) = ws::read_frame(&mut reader) {
+            match frame.opcode {
+                0x8 => {
+                    send_close(id, &frame.payload);
+                    break;
+                }
+                0x9 => {
+                    let _ = send_pong(id, &frame.payload);
+                }
+                _ => {}
             }
         }
         remove(id);
     });
 }
 
+/// Take over a `/api/events/stream` connection: write the response `head`,
+/// then events. `last_event_id` is the `Last-Event-ID` a browser sends when
+/// it reconnects; without one the stream starts from the moment before the
+/// head went out, so a client that acts on seeing it misses nothing.
+pub fn register_event_stream(mut stream: TcpStream, head: &str, token: String, last_event_id: Option<u64>) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    logging::info("realtime", &format!("client {} connected (event stream)", id));
+    let cursor = last_event_id.unwrap_or_else(|| history().lock().unwrap().seq);
+    if stream.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+    let Ok(mut reader) = stream.try_clone() else { return };
+    if !join(Client { id, stream, token, transport: Transport::EventStream }, cursor) {
+        return;
+    }
+    // The client sends nothing more; reading only notices when it hangs up.
+    thread::spawn(move || {
+        let _ = reader.set_read_timeout(None);
+        let mut buf = [0u8; 256];
+        while matches!(reader.read(&mut buf), Ok(n) if n > 0) {}
+        remove(id);
+    });
+}
+
+/// Catch `client` up from `cursor` and add it to the hub. Holding the hub
+/// lock keeps broadcasts out until the replay is written. False if the
+/// connection is already gone.
+fn join(mut client: Client, cursor: u64) -> bool {
+    let mut hub = hub().lock().unwrap();
+    let replay = history().lock().unwrap().since(cursor);
+    let sent = match replay {
+        Replay::Events(events) => events.iter().all(|(seq, e)| send(&mut client, *seq, e).is_ok()),
+        Replay::Resync(seq) => send(&mut client, seq, &format!(r#"{{"type":"resync","seq":{}}}"#, seq)).is_ok(),
+    };
+    if sent {
+        hub.push(client);
+    }
+    sent
+}
+
+/// Write one event in the client's framing.
+fn send(client: &mut Client, seq: u64, message: &str) -> io::Result<()> {
+    match client.transport {
+        Transport::WebSocket => ws::write_text(&mut client.stream, message),
+        Transport::EventStream => client.stream.write_all(event_stream_message(seq, message).as_bytes()),
+    }
+}
+
+/// An SSE message: the id a reconnecting browser sends back as
+/// `Last-Event-ID`, then the payload with each line as a `data:` field.
+fn event_stream_message(seq: u64, message: &str) -> String {
+    let mut out = format!("id: {}\n", seq);
+    for line in message.lines() {
+        out.push_str(&format!("data: {}\n", line));
+    }
+    out.push('\n');
+    out
+}
+
+fn parse_since(payload: &[u8]) -> Option<u64> {
+    let value = parse_value(std::str::from_utf8(payload).ok()?)?;
+    match value.as_object()?.get("since")? {
+        crate::db::Value::Int(n) if *n >= 0 => Some(*n as u64),
+        _ => None,
+    }
+}
+
 pub fn broadcast(message: &str) {
     let mut hub = hub().lock().unwrap();
+    let (seq, message) = {
+        let mut history = history().lock().unwrap();
+        let message = history.push(message);
+        (history.seq, message)
+    };
     let mut dead = Vec::new();
     for client in hub.iter_mut() {
-        if ws::write_text(&mut client.stream, message).is_err() {
+        if send(client, seq, &message).is_err() {
+            dead.push(client.id);
+        }
+    }
+    if !dead.is_empty() {
+        hub.retain(|c| !dead.contains(&c.id));
+    }
+}
+
+/// Send a binary frame to every WebSocket client. Binary payloads carry no
+/// sequence number, are not retained for replay, and cannot be sent on an
+/// event stream.
+#[allow(dead_code)] // API for realtime features with binary payloads; no caller yet
+pub fn broadcast_binary(data: &[u8]) {
+    let mut hub = hub().lock().unwrap();
+    let mut dead = Vec::new();
+    for client in hub.iter_mut().filter(|c| c.transport == Transport::WebSocket) {
+        if ws::write_binary(&mut client.stream, data).is_err() {
             dead.push(client.id);
         }
     }
@@ -66,7 +263,208 @@ fn send_pong(id: u64, payload: &[u8]) -> bool {
     false
 }
 
+/// Finish the close handshake a client started.
+fn send_close(id: u64, payload: &[u8]) {
+    let mut hub = hub().lock().unwrap();
+    if let Some(client) = hub.iter_mut().find(|c| c.id == id) {
+        let _ = ws::reply_close(&mut client.stream, payload);
+    }
+}
+
+/// Tell every client the server is restarting (close code 1012) and forget them.
+#[allow(dead_code)] // for a graceful shutdown path; the server has none yet
+pub fn shutdown() {
+    let mut hub = hub().lock().unwrap();
+    for client in hub.iter_mut() {
+        if client.transport == Transport::WebSocket {
+            let _ = ws::write_close(&mut client.stream, ws::CLOSE_RESTART, "Service restart");
+        }
+        let _ = client.stream.shutdown(std::net::Shutdown::Both);
+    }
+    hub.clear();
+}
+
+/// Re-check every connection's token with `is_valid` each
+/// [`RECHECK_INTERVAL`], so a session that expires or is revoked stops
+/// receiving events.
+pub fn start_token_recheck(is_valid: fn(&str) -> bool) {
+    thread::spawn(move || loop {
+        thread::sleep(RECHECK_INTERVAL);
+        close_revoked(is_valid);
+    });
+}
+
+/// Close (code 1008 on a WebSocket) and forget the connections whose token fails
+/// `is_valid`. Tokens are checked without holding the hub lock, so
+/// broadcasts are not held up by the lookups. Returns how many were closed.
+fn close_revoked(is_valid: impl Fn(&str) -> bool) -> usize {
+    let tokens: Vec<(u64, String)> = hub().lock().unwrap().iter().map(|c| (c.id, c.token.clone())).collect();
+    let revoked: Vec<u64> = tokens.into_iter().filter(|(_, token)| !is_valid(token)).map(|(id, _)| id).collect();
+    if revoked.is_empty() {
+        return 0;
+    }
+    let mut hub = hub().lock().unwrap();
+    for client in hub.iter_mut().filter(|c| revoked.contains(&c.id)) {
+        if client.transport == Transport::WebSocket {
+            let _ = ws::write_close(&mut client.stream, ws::CLOSE_POLICY, "Session expired");
+        }
+        let _ = client.stream.shutdown(std::net::Shutdown::Both);
+    }
+    hub.retain(|c| !revoked.contains(&c.id));
+    revoked.len()
+}
+
 fn remove(id: u64) {
     let mut hub = hub().lock().unwrap();
     hub.retain(|c| c.id != id);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn events(replay: Replay) -> Vec<String> {
+        match replay {
+            Replay::Events(events) => events.into_iter().map(|(_, event)| event).collect(),
+            Replay::Resync(_) => panic!("unexpected resync"),
+        }
+    }
+
+    #[test]
+    fn test_history_stamps_sequence() {
+        let mut history = History::new(10);
+        assert_eq!(history.push(r#"{"type":"a"}"#), r#"{"seq":1,"type":"a"}"#);
+        assert_eq!(history.push("{}"), r#"{"seq":2}"#);
+    }
+
+    #[test]
+    fn test_history_replays_events_after_cursor() {
+        let mut history = History::new(10);
+        for kind in ["a", "b", "c"] {
+            history.push(&format!(r#"{{"type":"{}"}}"#, kind));
+        }
+        assert_eq!(events(history.since(1)), vec![r#"{"seq":2,"type":"b"}"#, r#"{"seq":3,"type":"c"}"#]);
+        assert!(events(history.since(3)).is_empty());
+    }
+
+    #[test]
+    fn test_history_resyncs_beyond_retention() {
+        let mut history = History::new(2);
+        for _ in 0..5 {
+            history.push(r#"{"type":"x"}"#);
+        }
+        assert!(matches!(history.since(1), Replay::Resync(5)));
+        assert_eq!(events(history.since(3)).len(), 2);
+        // Cursor from a previous process lifetime
+        assert!(matches!(history.since(99), Replay::Resync(5)));
+    }
+
+    #[test]
+    fn test_broadcast_binary_reaches_clients() {
+        broadcast(r#"{"type":"binary.before"}"#);
+        let cursor = history().lock().unwrap().seq - 1;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        register(server, String::new(), None);
+
+        let since = format!(r#"{{"since":{}}}"#, cursor);
+        let mut frame = vec![0x81, 0x80 | since.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(since.as_bytes());
+        client.write_all(&frame).unwrap();
+
+        // Once the replay arrives the client is in the hub (or about to be:
+        // broadcasts wait on the hub lock the replay is written under).
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        loop {
+            let frame = ws::read_frame(&mut client).expect("replayed event");
+            if frame.payload.ends_with(b"\"binary.before\"}") {
+                break;
+            }
+        }
+        broadcast_binary(&[1, 2, 3, 250]);
+        loop {
+            let frame = ws::read_frame(&mut client).expect("binary frame");
+            if frame.opcode == 0x2 {
+                assert_eq!(frame.payload, [1, 2, 3, 250]);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_client_close_is_answered_with_close_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        register(server, String::new(), None);
+
+        // Masked close frame with status 1001 (going away)
+        client.write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xE9]).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let frame = ws::read_frame(&mut client).expect("close reply");
+        assert_eq!(frame.opcode, 0x8);
+        assert_eq!(frame.payload, 1001u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_revoked_token_closes_live_connection() {
+        broadcast(r#"{"type":"revoke.before"}"#);
+        let cursor = history().lock().unwrap().seq - 1;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        register(server, "revoked-token".into(), None);
+
+        let since = format!(r#"{{"since":{}}}"#, cursor);
+        let mut frame = vec![0x81, 0x80 | since.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(since.as_bytes());
+        client.write_all(&frame).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        loop {
+            let frame = ws::read_frame(&mut client).expect("replayed event");
+            if frame.payload.ends_with(b"\"revoke.before\"}") {
+                break;
+            }
+        }
+
+        // What the recheck thread does once the session is gone
+        assert_eq!(close_revoked(|token| token != "revoked-token"), 1);
+        let frame = ws::read_frame(&mut client).expect("close frame");
+        assert_eq!(frame.opcode, 0x8);
+        assert_eq!(frame.payload[..2], ws::CLOSE_POLICY.to_be_bytes());
+        assert!(!hub().lock().unwrap().iter().any(|c| c.token == "revoked-token"));
+    }
+
+    #[test]
+    fn test_reconnect_with_cursor_receives_missed_events() {
+        broadcast(r#"{"type":"replay.before"}"#);
+        let cursor = history().lock().unwrap().seq;
+        broadcast(r#"{"type":"replay.missed.1"}"#);
+        broadcast(r#"{"type":"replay.missed.2"}"#);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        register(server, String::new(), None);
+
+        // Client frames are masked; a zero mask keeps the payload readable.
+        let since = format!(r#"{{"since":{}}}"#, cursor);
+        let mut frame = vec![0x81, 0x80 | since.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(since.as_bytes());
+        client.write_all(&frame).unwrap();
+
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut received = Vec::new();
+        while !received.iter().any(|m: &String| m.contains("replay.missed.2")) {
+            let frame = ws::read_frame(&mut client).expect("replayed event");
+            received.push(String::from_utf8(frame.payload).unwrap());
+        }
+        assert!(received.iter().any(|m| m.contains("replay.missed.1")));
+        assert!(!received.iter().any(|m| m.contains("replay.before")));
+    }
+}
@@ -2,7 +2,11 @@ use std::net::TcpListener;
 use crate::handler;
 
 pub fn run(addr: &str) {
-    let listener = TcpListener::bind(addr).unwrap();
+    serve(TcpListener::bind(addr).unwrap());
+}
+
+/// Accept loop on a listener the caller has bound, e.g. to port 0 in tests.
+pub fn serve(listener: TcpListener) {
     for stream in listener.incoming().flatten() {
         handler::handle(stream);
     }
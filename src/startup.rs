@@ -0,0 +1,146 @@
+//! Boot-time self-check, so a broken install is reported at startup
+//! instead of surfacing later as 404s or silently failed syncs
+use crate::{config, logging};
+use std::fs;
+use std::path::Path;
+
+/// Key `main` falls back to when SECRET_KEY is missing.
+pub const DEFAULT_KEY: &str = "default-insecure-key-change-me";
+
+/// Placeholder keys from the README and `.env.local.example`.
+const PLACEHOLDER_KEYS: &[&str] = &[
+    DEFAULT_KEY,
+    "your-256-bit-secret-key",
+    "change-this-to-a-secure-random-key-at-least-32-chars",
+];
+
+/// Templates every install needs: the admin panel and the home page.
+const REQUIRED_TEMPLATES: &[&str] = &["admin.html", "index.html"];
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    fn error(message: String) -> Self {
+        Self { severity: Severity::Error, message }
+    }
+}
+
+/// Check the install after `db::init` and report each problem on stderr and
+/// in the log. `false` when STARTUP_STRICT=1 and an error was found, in
+/// which case the server should not start.
+pub fn self_check(key: &str) -> bool {
+    let data_dir = (!config::db_in_memory()).then(config::data_dir);
+    let issues = check(data_dir.as_deref(), &config::public_dir(), key);
+    for issue in &issues {
+        match issue.severity {
+            Severity::Warning => {
+                eprintln!("WARNING: {}", issue.message);
+                logging::warn("startup", &issue.message);
+            }
+            Severity::Error => {
+                eprintln!("ERROR: {}", issue.message);
+                logging::error("startup", &issue.message);
+            }
+        }
+    }
+    !(config::startup_strict() && issues.iter().any(|i| i.severity == Severity::Error))
+}
+
+/// Problems with the data directory (`None` for an in-memory database), the
+/// public and templates directories, and the encryption key.
+fn check(data_dir: Option<&Path>, public_dir: &Path, key: &str) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if let Some(dir) = data_dir {
+        if let Err(e) = probe_writable(dir) {
+            issues.push(Issue::error(format!("data dir {} is not writable: {}", dir.display(), e)));
+        }
+    }
+    if !public_dir.is_dir() {
+        issues.push(Issue::error(format!("public dir {} is missing", public_dir.display())));
+    } else {
+        let templates = public_dir.join("templates");
+        for name in REQUIRED_TEMPLATES {
+            if !templates.join(name).is_file() {
+                issues.push(Issue::error(format!("template {} is missing", templates.join(name).display())));
+            }
+        }
+    }
+    if PLACEHOLDER_KEYS.contains(&key) {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            message: "SECRET_KEY is unset or a placeholder; the database is not protected".into(),
+        });
+    }
+    issues
+}
+
+/// Create the directory if needed and write and remove a probe file, the
+/// same operations a sync and a backup perform.
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-check");
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        let base = std::env::temp_dir().join(format!("rpw-startup-{}-{}", name, std::process::id()));
+        fs::create_dir_all(base.join("public/templates")).unwrap();
+        for template in REQUIRED_TEMPLATES {
+            fs::write(base.join("public/templates").join(template), "<html></html>").unwrap();
+        }
+        base
+    }
+
+    #[test]
+    fn test_healthy_install_passes() {
+        let base = fixture("ok");
+        let issues = check(Some(&base.join("data")), &base.join("public"), "a-real-key");
+        assert!(issues.is_empty(), "{:?}", issues);
+        assert!(base.join("data").is_dir());
+        assert!(!base.join("data/.write-check").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_unwritable_data_dir_is_an_error() {
+        let base = fixture("data");
+        // A file where the directory should be cannot be written into
+        fs::write(base.join("data"), "").unwrap();
+        let issues = check(Some(&base.join("data")), &base.join("public"), "a-real-key");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert!(issues[0].message.contains("not writable"));
+        assert!(check(None, &base.join("public"), "a-real-key").is_empty());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_missing_template_and_placeholder_key() {
+        let base = fixture("templates");
+        fs::remove_file(base.join("public/templates/admin.html")).unwrap();
+        let issues = check(None, &base.join("public"), DEFAULT_KEY);
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].message.contains("admin.html"));
+        assert_eq!(issues[1].severity, Severity::Warning);
+
+        let issues = check(None, &base.join("missing"), "a-real-key");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("public dir"));
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
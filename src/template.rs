@@ -11,8 +11,12 @@ pub struct Context {
 enum CtxValue {
     Text(String),
     Raw(String),
+    List(Vec<Record>),
 }
 
+/// One entry of a list value: its fields in order, read as `{{ item.field }}`
+type Record = Vec<(String, String)>;
+
 impl Context {
     pub fn new() -> Self {
         Self { props: HashMap::new() }
@@ -28,17 +32,111 @@ impl Context {
         self
     }
 
-    /// Replace all {{ key }} with values
+    /// A list for `{% for item in key %}`; each entry's fields are escaped
+    /// like [`Context::set`] values.
+    pub fn set_list(&mut self, key: &str, items: Vec<Record>) -> &mut Self {
+        self.props.insert(key.to_string(), CtxValue::List(items));
+        self
+    }
+
+    /// A map for `{% for pair in key %}`, as entries with `key` and `value`
+    /// fields in the order given.
+    pub fn set_map<K: AsRef<str>, V: AsRef<str>>(&mut self, key: &str, pairs: impl IntoIterator<Item = (K, V)>) -> &mut Self {
+        let items = pairs
+            .into_iter()
+            .map(|(k, v)| vec![("key".to_string(), k.as_ref().to_string()), ("value".to_string(), v.as_ref().to_string())])
+            .collect();
+        self.set_list(key, items)
+    }
+
+    /// Expand `{% for item in key %}` over list values and replace all
+    /// {{ key }} with values. Unknown keys are left in place.
     pub fn apply(&self, template: &str) -> String {
-        let mut result = template.to_string();
-        for (key, value) in &self.props {
-            let rendered = match value {
-                CtxValue::Text(v) => escape_html(v),
-                CtxValue::Raw(v) => v.clone(),
-            };
-            result = result.replace(&format!("{{{{ {} }}}}", key), &rendered);
+        self.substitute(template, &[])
+    }
+
+    /// One pass over `template`, so a value is never itself scanned for tags.
+    /// `items` are the loop entries being rendered, innermost last.
+    fn substitute(&self, template: &str, items: &[(&str, &Record)]) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = [rest.find("{{ "), rest.find("{% for ")].into_iter().flatten().min() {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let (rendered, len) = if rest.starts_with("{{ ") { self.variable(rest, items) } else { self.for_loop(rest, items) };
+            match rendered {
+                Some(rendered) => out.push_str(&rendered),
+                None => out.push_str(&rest[..len]),
+            }
+            rest = &rest[len..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// The value for the `{{ key }}` at the start of `tag`, and the tag's length.
+    fn variable(&self, tag: &str, items: &[(&str, &Record)]) -> (Option<String>, usize) {
+        let Some(end) = tag.find(" }}") else { return (None, tag.len()) };
+        let key = &tag[3..end];
+        let value = match key.split_once('.') {
+            Some((item, field)) => items
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == item)
+                .and_then(|(_, record)| record.iter().find(|(k, _)| k == field))
+                .map(|(_, v)| escape_html(v)),
+            None => match self.props.get(key) {
+                Some(CtxValue::Text(v)) => Some(escape_html(v)),
+                Some(CtxValue::Raw(v)) => Some(v.clone()),
+                Some(CtxValue::List(_)) | None => None,
+            },
+        };
+        (value, end + 3)
+    }
+
+    /// The `{% for item in key %}...{% endfor %}` at the start of `tag`
+    /// rendered once per entry of the list `key`, and the block's length.
+    /// Only the opening tag is consumed when `key` is not a list.
+    fn for_loop(&self, tag: &str, items: &[(&str, &Record)]) -> (Option<String>, usize) {
+        let Some(open_end) = tag.find(" %}").map(|i| i + 3) else { return (None, tag.len()) };
+        let mut words = tag[7..open_end - 3].split(' ');
+        let (Some(item), Some("in"), Some(key), None) = (words.next(), words.next(), words.next(), words.next()) else {
+            return (None, open_end);
+        };
+        let Some(CtxValue::List(list)) = self.props.get(key) else { return (None, open_end) };
+        let Some(body_len) = loop_body_len(&tag[open_end..]) else { return (None, open_end) };
+        let body = &tag[open_end..open_end + body_len];
+        let mut rendered = String::new();
+        for record in list {
+            let mut scope = items.to_vec();
+            scope.push((item, record));
+            rendered.push_str(&self.substitute(body, &scope));
+        }
+        (Some(rendered), open_end + body_len + ENDFOR.len())
+    }
+}
+
+const ENDFOR: &str = "{% endfor %}";
+
+/// Length of a loop body up to its matching `{% endfor %}`, skipping over
+/// nested loops.
+fn loop_body_len(body: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut pos = 0;
+    loop {
+        let next_open = body[pos..].find("{% for ");
+        let next_close = body[pos..].find(ENDFOR)?;
+        match next_open {
+            Some(open) if open < next_close => {
+                depth += 1;
+                pos += open + 7;
+            }
+            _ if depth == 0 => return Some(pos + next_close),
+            _ => {
+                depth -= 1;
+                pos += next_close + ENDFOR.len();
+            }
         }
-        result
     }
 }
 
@@ -138,7 +236,7 @@ where
     html.to_string()
 }
 
-fn escape_html(value: &str) -> String {
+pub fn escape_html(value: &str) -> String {
     let mut out = String::with_capacity(value.len());
     for ch in value.chars() {
         match ch {
@@ -152,3 +250,43 @@ fn escape_html(value: &str) -> String {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_map_renders_in_a_loop() {
+        let mut ctx = Context::new();
+        ctx.set("site", "Rust Pure Web");
+        ctx.set_map("settings", [("page_title", "Home & Away"), ("og_image", "<b>")]);
+        let html = ctx.apply("<dl>{% for pair in settings %}<dt>{{ pair.key }}</dt><dd>{{ pair.value }} ({{ site }})</dd>{% endfor %}</dl>");
+        assert_eq!(
+            html,
+            "<dl><dt>page_title</dt><dd>Home &amp; Away (Rust Pure Web)</dd><dt>og_image</dt><dd>&lt;b&gt; (Rust Pure Web)</dd></dl>"
+        );
+        ctx.set_map("settings", Vec::<(&str, &str)>::new());
+        assert_eq!(ctx.apply("[{% for pair in settings %}{{ pair.key }}{% endfor %}]"), "[]");
+    }
+
+    #[test]
+    fn test_nested_lists_and_unknown_tags() {
+        let mut ctx = Context::new();
+        let row = |k: &str, v: &str| vec![(k.to_string(), v.to_string())];
+        ctx.set_list("groups", vec![row("name", "a"), row("name", "b")]);
+        ctx.set_list("tags", vec![row("label", "x"), row("label", "y")]);
+        let html = ctx.apply("{% for g in groups %}{{ g.name }}:{% for t in tags %}{{ g.name }}{{ t.label }} {% endfor %};{% endfor %}");
+        assert_eq!(html, "a:ax ay ;b:bx by ;");
+        // Tags the context cannot fill stay as written, for a later pass
+        assert_eq!(ctx.apply("{{ children }} {% for x in nothing %}{{ x.y }}{% endfor %}"), "{{ children }} {% for x in nothing %}{{ x.y }}{% endfor %}");
+    }
+
+    #[test]
+    fn test_values_are_not_rescanned() {
+        let mut ctx = Context::new();
+        ctx.set("title", "{{ secret }}");
+        ctx.set_raw("secret", "<script>");
+        ctx.set_map("settings", [("k", "{{ secret }}")]);
+        assert_eq!(ctx.apply("{{ title }}|{% for p in settings %}{{ p.value }}{% endfor %}"), "{{ secret }}|{{ secret }}");
+    }
+}
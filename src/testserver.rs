@@ -0,0 +1,357 @@
+//! In-process server for end-to-end tests: `cargo test` boots the real
+//! handler on an ephemeral port and talks raw HTTP/1.1 to it, the way the
+//! healthcheck talks to a deployed instance
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::api::json::parse_json;
+use crate::db::{self, Value};
+use crate::server;
+
+/// Address of the test server, started on first use. All tests in the
+/// binary share it and the in-memory database, so each test should use its
+/// own collection names and emails.
+pub fn start() -> &'static str {
+    static ADDR: OnceLock<String> = OnceLock::new();
+    ADDR.get_or_init(|| {
+        db::init_in_memory("testserver");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind test server");
+        let addr = listener.local_addr().expect("test server address").to_string();
+        std::thread::spawn(move || server::serve(listener));
+        addr
+    })
+}
+
+/// A response as the client saw it.
+pub struct Reply {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Reply {
+    /// Header value by lowercase name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// A top-level field of a JSON body.
+    pub fn json(&self, key: &str) -> Option<Value> {
+        parse_json(&self.body).remove(key)
+    }
+
+    pub fn string(&self, key: &str) -> String {
+        self.json(key).and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+    }
+}
+
+pub fn get(path: &str, token: Option<&str>) -> Reply {
+    request("GET", path, None, token)
+}
+
+pub fn post(path: &str, body: &str, token: Option<&str>) -> Reply {
+    request("POST", path, Some(body), token)
+}
+
+/// Send one request on a fresh connection and read until the server closes it.
+pub fn request(method: &str, path: &str, body: Option<&str>, token: Option<&str>) -> Reply {
+    let addr = start();
+    let mut stream = TcpStream::connect(addr).expect("connect to test server");
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+
+    let body = body.unwrap_or("");
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method, path, addr, body.len()
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).expect("write request");
+    stream.write_all(body.as_bytes()).expect("write body");
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).expect("read response");
+    parse_reply(&String::from_utf8_lossy(&raw))
+}
+
+/// Send `raw` exactly as given, for requests the helpers above cannot
+/// express, and read until the server closes the connection.
+pub fn send_raw(raw: &str) -> Reply {
+    let mut stream = TcpStream::connect(start()).expect("connect to test server");
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+    stream.write_all(raw.as_bytes()).expect("write request");
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).expect("read response");
+    parse_reply(&reply)
+}
+
+fn parse_reply(raw: &str) -> Reply {
+    let (head, body) = raw.split_once("\r\n\r\n").unwrap_or((raw, ""));
+    let mut lines = head.lines();
+    let status = lines.next().and_then(|line| line.split(' ').nth(1)).and_then(|code| code.parse().ok()).unwrap_or(0);
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Reply { status, headers, body: body.to_string() }
+}
+
+/// Make a registered user an admin. The shared database usually has users
+/// from other tests already, so the first-user rule cannot be relied on.
+pub fn promote(user_id: &str) {
+    let mut role = db::Document::new();
+    role.insert("role".into(), Value::String("admin".into()));
+    assert!(db::get().update("_users", user_id, role), "no user {}", user_id);
+}
+
+/// Register a fresh user with an email starting with `prefix`, [`promote`]
+/// it and log in: the session token of a new admin.
+pub fn admin_token(prefix: &str) -> String {
+    let email = format!("{}-{}@example.com", prefix, crate::crypto::random_hex(4));
+    let credentials = format!(r#"{{"email":"{}","password":"Harness-Passphrase-42"}}"#, email);
+    let registered = post("/api/auth/register", &credentials, None);
+    assert_eq!(registered.status, 201, "{}", registered.body);
+    promote(&registered.string("user_id"));
+    let login = post("/api/auth/login", &credentials, None);
+    assert_eq!(login.status, 200, "{}", login.body);
+    login.string("token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_login_and_read_back_a_document() {
+        let token = admin_token("e2e");
+        assert!(!token.is_empty());
+        assert_eq!(get("/api/auth/me", None).status, 401);
+        let me = get("/api/auth/me", Some(&token));
+        assert_eq!(me.status, 200, "{}", me.body);
+
+        let created = post("/api/collections", r#"{"name":"e2e-notes","fields":[{"name":"title","type":"string"}]}"#, Some(&token));
+        assert_eq!(created.status, 201, "{}", created.body);
+        let inserted = post("/api/collections/e2e-notes", r#"{"title":"From the harness"}"#, Some(&token));
+        assert_eq!(inserted.status, 201, "{}", inserted.body);
+        assert_eq!(inserted.header("content-type"), Some("application/json"));
+
+        let read = get(&format!("/api/collections/e2e-notes/{}", inserted.string("id")), Some(&token));
+        assert_eq!(read.status, 200, "{}", read.body);
+        assert_eq!(read.string("title"), "From the harness");
+        assert_eq!(read.string("_owner"), me.string("id"));
+    }
+
+    #[test]
+    fn test_signed_url_stands_in_for_a_session() {
+        assert_eq!(get("/api/admin/stats", None).status, 401);
+        let url = crate::auth::sign_url("GET", "/api/admin/stats", db::now() + 60);
+        let reply = get(&url, None);
+        assert_eq!(reply.status, 200, "{}", reply.body);
+        assert!(reply.json("collections").is_some());
+        assert_eq!(get(&url.replace("/stats", "/users"), None).status, 401);
+        let for_post = crate::auth::sign_url("POST", "/api/admin/stats", db::now() + 60);
+        assert_eq!(get(&for_post, None).status, 401);
+        let expired = crate::auth::sign_url("GET", "/api/admin/stats", db::now() - 1);
+        assert_eq!(get(&expired, None).status, 401);
+    }
+
+    /// Open `/api/events/stream` and read past its head.
+    fn open_event_stream(token: &str, last_event_id: Option<u64>) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(start()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let resume = last_event_id.map(|id| format!("Last-Event-ID: {}\r\n", id)).unwrap_or_default();
+        write!(stream, "GET /api/events/stream?token={} HTTP/1.1\r\nHost: test\r\n{}\r\n", token, resume).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("event stream head");
+            if line.trim().is_empty() {
+                break;
+            }
+            head.push(line.trim().to_string());
+        }
+        assert_eq!(head[0], "HTTP/1.1 200 OK");
+        assert!(head.contains(&"Content-Type: text/event-stream".to_string()), "{:?}", head);
+        reader
+    }
+
+    /// The next `count` events about `collection`, as `(id, data)`.
+    fn read_events(reader: &mut BufReader<TcpStream>, count: usize, collection: &str) -> Vec<(u64, String)> {
+        let mut events = Vec::new();
+        let (mut id, mut data) = (0, String::new());
+        while events.len() < count {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("event");
+            let line = line.trim_end();
+            if let Some(value) = line.strip_prefix("id: ") {
+                id = value.parse().unwrap();
+            } else if let Some(value) = line.strip_prefix("data: ") {
+                data.push_str(value);
+            } else if line.is_empty() {
+                if data.contains(&format!(r#""collection":"{}""#, collection)) {
+                    events.push((id, data.clone()));
+                }
+                data.clear();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_event_stream_delivers_and_replays_db_events() {
+        let token = admin_token("sse");
+        assert_eq!(request("GET", "/api/events/stream", None, None).status, 401);
+
+        let mut stream = open_event_stream(&token, None);
+        post("/api/collections", r#"{"name":"sse-notes","fields":[{"name":"title","type":"string"}]}"#, Some(&token));
+        let inserted = post("/api/collections/sse-notes", r#"{"title":"Streamed"}"#, Some(&token));
+        let events = read_events(&mut stream, 2, "sse-notes");
+        assert!(events[0].1.contains(r#""type":"collection.created""#), "{:?}", events);
+        assert!(events[1].1.contains(r#""type":"doc.created""#), "{:?}", events);
+        assert!(events[1].1.contains(&inserted.string("id")));
+        assert!(events[0].0 < events[1].0);
+
+        // A browser reconnecting with the last id it saw gets what came after
+        let mut resumed = open_event_stream(&token, Some(events[0].0));
+        assert_eq!(read_events(&mut resumed, 1, "sse-notes"), events[1..]);
+    }
+
+    #[test]
+    fn test_realtime_refuses_what_it_cannot_upgrade() {
+        let plain = get("/realtime", None);
+        assert_eq!(plain.status, 426);
+        assert_eq!(plain.header("upgrade"), Some("websocket"));
+
+        let malformed = send_raw("GET /realtime HTTP/1.1\r\nHost: test\r\nAccept: application/json\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+        assert_eq!(malformed.status, 400);
+        assert_eq!(malformed.string("error"), "Missing Sec-WebSocket-Key header");
+
+        let options = request("OPTIONS", "/realtime", None, None);
+        assert_eq!(options.status, 200);
+        assert_eq!(options.header("allow"), Some("OPTIONS, GET"));
+    }
+
+    #[test]
+    fn test_malformed_authorization_is_refused() {
+        let reply = send_raw("GET /api/auth/me HTTP/1.1\r\nHost: test\r\nAuthorization: not-a-bearer\r\n\r\n");
+        assert_eq!(reply.status, 401);
+        assert_eq!(reply.header("www-authenticate"), Some("Bearer"));
+        assert!(reply.string("error").starts_with("Malformed Authorization header"));
+    }
+
+    #[test]
+    fn test_basic_auth_from_a_proxy_does_not_lock_anyone_out() {
+        let basic = "Authorization: Basic dXNlcjpwYXNz\r\n";
+        let spec = send_raw(&format!("GET /api/openapi.json HTTP/1.1\r\nHost: test\r\n{}\r\n", basic));
+        assert_eq!(spec.status, 200);
+
+        let email = format!("e2e-{}@example.com", crate::crypto::random_hex(4));
+        let credentials = format!(r#"{{"email":"{}","password":"Harness-Passphrase-42"}}"#, email);
+        assert_eq!(post("/api/auth/register", &credentials, None).status, 201);
+        let login = send_raw(&format!(
+            "POST /api/auth/login HTTP/1.1\r\nHost: test\r\n{}Content-Length: {}\r\n\r\n{}",
+            basic, credentials.len(), credentials
+        ));
+        assert_eq!(login.status, 200, "{}", login.body);
+        // The session cookie still works next to the proxy's header
+        let me = send_raw(&format!(
+            "GET /api/auth/me HTTP/1.1\r\nHost: test\r\n{}Cookie: token={}\r\n\r\n", basic, login.string("token")
+        ));
+        assert_eq!(me.status, 200, "{}", me.body);
+        // A wrong password is still reported as such, not as the header
+        let wrong = credentials.replace("Harness-Passphrase-42", "Wrong-Passphrase-42");
+        let refused = send_raw(&format!(
+            "POST /api/auth/login HTTP/1.1\r\nHost: test\r\n{}Content-Length: {}\r\n\r\n{}",
+            basic, wrong.len(), wrong
+        ));
+        assert_eq!(refused.status, 400, "{}", refused.body);
+        assert_eq!(refused.string("code"), "invalid_credentials");
+    }
+
+    #[test]
+    fn test_nested_error_envelope_on_request() {
+        let flat = post("/api/auth/register", r#"{"email":"nope","password":"Harness-Passphrase-42"}"#, None);
+        assert_eq!(flat.string("code"), "invalid_email");
+        let ask = |body: &str| send_raw(&format!(
+            "POST /api/auth/register HTTP/1.1\r\nHost: test\r\nAccept: application/json; errors=nested\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(), body
+        ));
+        let nested = ask(r#"{"email":"nope","password":"Harness-Passphrase-42"}"#);
+        assert_eq!(nested.status, 400);
+        assert!(nested.body.contains(r#""error":{"code":"invalid_email","message":"#), "{}", nested.body);
+        assert!(nested.body.ends_with(r#","field":"email"}}"#), "{}", nested.body);
+        let malformed = ask("{oops");
+        assert!(malformed.body.starts_with(r#"{"error":{"code":"invalid_json","#), "{}", malformed.body);
+    }
+
+    #[test]
+    fn test_http_1_0_may_omit_host_but_1_1_may_not() {
+        let old = send_raw("GET /api/openapi.json HTTP/1.0\r\n\r\n");
+        assert_eq!(old.status, 200);
+        assert_eq!(old.header("connection"), Some("close"));
+        assert_eq!(send_raw("GET /api/openapi.json HTTP/1.1\r\n\r\n").status, 400);
+        assert_eq!(get("/api/openapi.json", None).header("connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_batch_refuses_oversized_documents() {
+        let token = admin_token("e2e");
+        assert_eq!(post("/api/collections", r#"{"name":"e2e-bulk"}"#, Some(&token)).status, 201);
+        let id = post("/api/collections/e2e-bulk", r#"{"body":"small"}"#, Some(&token)).string("id");
+
+        let big = "x".repeat(crate::config::max_document_bytes());
+        let insert = format!(r#"[{{"op":"insert","collection":"e2e-bulk","doc":{{"body":"{}"}}}}]"#, big);
+        let refused = post("/api/batch", &insert, Some(&token));
+        assert_eq!(refused.status, 413, "{}", &refused.body[..refused.body.len().min(200)]);
+        let update = format!(r#"[{{"op":"update","collection":"e2e-bulk","id":"{}","doc":{{"body":"{}"}}}}]"#, id, big);
+        assert_eq!(post("/api/batch", &update, Some(&token)).status, 413);
+        let read = get(&format!("/api/collections/e2e-bulk/{}", id), Some(&token));
+        assert_eq!(read.string("body"), "small");
+    }
+
+    #[test]
+    fn test_settings_if_match_needs_a_current_tag_and_an_id() {
+        let token = admin_token("e2e");
+        let id = match db::get().find_all("_settings").first() {
+            Some(doc) => doc.get("id").and_then(Value::as_str).unwrap().to_string(),
+            None => db::get().insert("_settings", db::Document::new()).unwrap(),
+        };
+        let put = |body: &str, etag: &str| send_raw(&format!(
+            "PUT /api/admin/settings HTTP/1.1\r\nHost: test\r\nAuthorization: Bearer {}\r\nIf-Match: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            token, etag, body.len(), body
+        ));
+        let stale = put(&format!(r#"{{"id":"{}","page_title":"Stale"}}"#, id), "\"1-000000000000\"");
+        assert_eq!(stale.status, 409, "{}", stale.body);
+        let copies = db::get().find_all("_settings").len();
+        let without_id = put(r#"{"page_title":"Second copy"}"#, "*");
+        assert_eq!(without_id.status, 400, "{}", without_id.body);
+        assert_eq!(db::get().find_all("_settings").len(), copies);
+    }
+
+    #[test]
+    fn test_lenient_collection_stores_undeclared_fields_with_a_hint() {
+        let token = admin_token("e2e");
+
+        let schema = r#"{"name":"e2e-drafts","fields":[{"name":"title","type":"string"}],"extra_fields":"lenient"}"#;
+        assert_eq!(post("/api/collections", schema, Some(&token)).status, 201);
+        let inserted = post("/api/collections/e2e-drafts", r#"{"title":"Hi","titel":"typo","tags":[]}"#, Some(&token));
+        assert_eq!(inserted.status, 201, "{}", inserted.body);
+        assert!(inserted.body.ends_with(r#","_unknown_fields":["tags","titel"]}"#), "{}", inserted.body);
+        let read = get(&format!("/api/collections/e2e-drafts/{}", inserted.string("id")), Some(&token));
+        assert_eq!(read.string("titel"), "typo");
+        let clean = post("/api/collections/e2e-drafts", r#"{"title":"Fine"}"#, Some(&token));
+        assert!(clean.json("_unknown_fields").is_none(), "{}", clean.body);
+
+        let strict = post("/api/collections/e2e-drafts/extra-fields", r#"{"mode":"strict"}"#, Some(&token));
+        assert_eq!(strict.status, 200, "{}", strict.body);
+        let refused = post("/api/collections/e2e-drafts", r#"{"title":"Hi","titel":"typo"}"#, Some(&token));
+        assert_eq!(refused.status, 400, "{}", refused.body);
+        let bad = post("/api/collections/e2e-drafts/extra-fields", r#"{"mode":"loose"}"#, Some(&token));
+        assert_eq!(bad.status, 400);
+    }
+}
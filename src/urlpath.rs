@@ -0,0 +1,168 @@
+//! Request path normalization, applied before routing
+
+use std::path::{Path, PathBuf};
+
+/// Canonical form of a request path: percent-decoded, duplicate slashes and
+/// `.` segments collapsed, a single trailing slash kept when present.
+/// `None` for anything that could escape a route: `..` segments, encoded
+/// `/`, `\` or NUL, malformed escapes, invalid UTF-8 or a relative path.
+pub fn normalize(raw: &str) -> Option<String> {
+    if !raw.starts_with('/') {
+        return None;
+    }
+    let decoded = percent_decode(raw)?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains('\\') => return None,
+            s => segments.push(s),
+        }
+    }
+    let mut path = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && (decoded.ends_with('/') || decoded.ends_with("/.")) {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// `path` without its trailing slash; the root stays `/`.
+pub fn trim_trailing_slash(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+/// Whether `path` is `prefix` itself or lies below it: `/docs/intro` is under
+/// `/docs`, `/docsfoo` is not.
+pub fn under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Resolve an already decoded path, such as one [`normalize`] returned, to
+/// an existing file or directory inside `root`. It is not decoded again,
+/// which would turn `/100%25.txt`, arriving as `/100%.txt`, into a miss.
+/// `..` and `\` are refused, and the result is canonicalized so symlinks
+/// pointing outside `root` are refused too.
+pub fn resolve_normalized(root: &Path, path: &str) -> Option<PathBuf> {
+    if path.split('/').any(|segment| segment == ".." || segment.contains(['\\', '\0'])) {
+        return None;
+    }
+    let root = root.canonicalize().ok()?;
+    let resolved = root.join(path.trim_start_matches('/')).canonicalize().ok()?;
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+fn percent_decode(raw: &str) -> Option<String> {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+        let byte = u8::from_str_radix(hex, 16).ok()?;
+        if matches!(byte, b'/' | b'\\' | 0) {
+            return None;
+        }
+        out.push(byte);
+        i += 3;
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_traversal_is_rejected() {
+        assert_eq!(normalize("/a/../b"), None);
+        assert_eq!(normalize("/%2e%2e/Cargo.toml"), None);
+        assert_eq!(normalize("/%2E%2E%2Fetc"), None);
+        assert_eq!(normalize("/docs/..%5c..%5csecret"), None);
+        assert_eq!(normalize("/a%2fb"), None);
+        assert_eq!(normalize("/a%00b"), None);
+        assert_eq!(normalize("relative/path"), None);
+    }
+
+    #[test]
+    fn test_duplicate_slashes_and_dot_segments_collapse() {
+        assert_eq!(normalize("//styles.css").as_deref(), Some("/styles.css"));
+        assert_eq!(normalize("/a//b///c").as_deref(), Some("/a/b/c"));
+        assert_eq!(normalize("/./docs/./intro").as_deref(), Some("/docs/intro"));
+        assert_eq!(normalize("/projects/shop//").as_deref(), Some("/projects/shop/"));
+        assert_eq!(normalize("/").as_deref(), Some("/"));
+        assert_eq!(normalize("//").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn test_percent_decoding() {
+        assert_eq!(normalize("/caf%C3%A9").as_deref(), Some("/café"));
+        assert_eq!(normalize("/a%20b").as_deref(), Some("/a b"));
+        assert_eq!(normalize("/%zz"), None);
+        assert_eq!(normalize("/%4"), None);
+        assert_eq!(normalize("/%C3"), None);
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        assert_eq!(trim_trailing_slash("/docs/"), "/docs");
+        assert_eq!(trim_trailing_slash("/docs"), "/docs");
+        assert_eq!(trim_trailing_slash("/"), "/");
+    }
+
+    #[test]
+    fn test_prefix_requires_boundary() {
+        assert!(under("/docs", "/docs"));
+        assert!(under("/docs/intro", "/docs"));
+        assert!(!under("/docsfoo", "/docs"));
+        assert!(under("/projects/shop/", "/projects"));
+        assert!(!under("/projectsx/shop", "/projects"));
+    }
+
+    #[test]
+    fn test_resolve_rejects_symlink_and_encoded_escapes() {
+        let base = std::env::temp_dir().join(format!("rpw-resolve-under-{}", std::process::id()));
+        let root = base.join("public");
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::write(root.join("css/site.css"), "body{}").unwrap();
+        fs::write(base.join("secret.txt"), "nope").unwrap();
+        let _ = fs::remove_file(root.join("leak.txt"));
+        std::os::unix::fs::symlink(base.join("secret.txt"), root.join("leak.txt")).unwrap();
+
+        let resolve = |raw: &str| normalize(raw).and_then(|path| resolve_normalized(&root, &path));
+        assert!(resolve("/css/site.css").is_some_and(|p| p.ends_with("css/site.css")));
+        assert!(resolve("/css%2Fsite.css").is_none());
+        assert!(resolve("/%2e%2e/secret.txt").is_none());
+        assert!(resolve("/css/%2E%2E/%2E%2E/secret.txt").is_none());
+        assert!(resolve("/leak.txt").is_none());
+        assert!(resolve("/missing.txt").is_none());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_normalized_paths_are_not_decoded_twice() {
+        let root = std::env::temp_dir().join(format!("rpw-resolve-normalized-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("100%.txt"), "all").unwrap();
+        fs::write(root.join("a b.html"), "<p>space</p>").unwrap();
+        fs::write(root.join("%41.txt"), "literal").unwrap();
+
+        for (raw, file) in [("/100%25.txt", "100%.txt"), ("/a%20b.html", "a b.html"), ("/%2541.txt", "%41.txt")] {
+            let clean = normalize(raw).unwrap();
+            assert!(resolve_normalized(&root, &clean).is_some_and(|p| p.ends_with(file)), "{}", raw);
+        }
+        assert!(resolve_normalized(&root, "/../secret.txt").is_none());
+        assert!(resolve_normalized(&root, "/..\\secret.txt").is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,30 +1,76 @@
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use crate::crypto::{base64_decode, base64_encode};
 
 const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
+/// Largest message `echo` will reassemble from fragments.
+const MAX_ECHO_MESSAGE: usize = 1024 * 1024;
+
+/// Close status codes (RFC 6455 section 7.4)
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_POLICY: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+pub const CLOSE_RESTART: u16 = 1012;
+
 pub struct Frame {
+    /// Final fragment of a message
+    pub fin: bool,
     pub opcode: u8,
     pub payload: Vec<u8>,
 }
 
-pub fn handshake(stream: &mut TcpStream, headers: &HashMap<String, String>) -> io::Result<()> {
-    let key = headers.get("sec-websocket-key").ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Missing key"))?;
+/// What is wrong with an upgrade request that cannot be answered with 101:
+/// RFC 6455 wants a GET whose `Sec-WebSocket-Key` is 16 bytes in base64.
+pub fn upgrade_problem(method: &str, headers: &HashMap<String, String>) -> Option<&'static str> {
+    if method != "GET" {
+        return Some("WebSocket upgrades must use GET");
+    }
+    match headers.get("sec-websocket-key").map(|key| base64_decode(key.trim())) {
+        None => Some("Missing Sec-WebSocket-Key header"),
+        Some(Some(nonce)) if nonce.len() == 16 => None,
+        Some(_) => Some("Sec-WebSocket-Key must be a base64-encoded 16-byte nonce"),
+    }
+}
+
+/// Answer the upgrade with 101, agreeing on the first protocol the client
+/// offers in `Sec-WebSocket-Protocol` that is in `supported`. Returns that
+/// protocol; without a match the header is left out and the client decides
+/// whether to carry on.
+pub fn handshake(stream: &mut impl Write, headers: &HashMap<String, String>, supported: &[String]) -> io::Result<Option<String>> {
+    let key = headers.get("sec-websocket-key").ok_or_else(|| io::Error::other("Missing key"))?;
     let accept = websocket_accept(key);
-    let response = format!(
+    let protocol = select_protocol(headers.get("sec-websocket-protocol").map(String::as_str), supported);
+    let mut response = format!(
         "HTTP/1.1 101 Switching Protocols\r\n\
 Upgrade: websocket\r\n\
 Connection: Upgrade\r\n\
-Sec-WebSocket-Accept: {}\r\n\r\n",
+Sec-WebSocket-Accept: {}\r\n",
         accept
     );
-    stream.write_all(response.as_bytes())
+    if let Some(protocol) = &protocol {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes())?;
+    Ok(protocol)
+}
+
+/// First of the comma-separated `offered` protocols that is `supported`.
+/// Protocol names are tokens and compare exactly.
+fn select_protocol(offered: Option<&str>, supported: &[String]) -> Option<String> {
+    offered?
+        .split(',')
+        .map(str::trim)
+        .find(|protocol| supported.iter().any(|s| s == protocol))
+        .map(str::to_string)
 }
 
-pub fn read_frame(stream: &mut TcpStream) -> io::Result<Frame> {
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Frame> {
     let mut header = [0u8; 2];
     stream.read_exact(&mut header)?;
+    let fin = header[0] & 0x80 != 0;
     let opcode = header[0] & 0x0F;
     let masked = header[1] & 0x80 != 0;
     let mut len = (header[1] & 0x7F) as u64;
@@ -55,18 +101,93 @@ pub fn read_frame(stream: &mut TcpStream) -> io::Result<Frame> {
         }
     }
 
-    Ok(Frame { opcode, payload })
+    Ok(Frame { fin, opcode, payload })
 }
 
 pub fn write_text(stream: &mut TcpStream, text: &str) -> io::Result<()> {
     write_frame(stream, 0x1, text.as_bytes())
 }
 
+pub fn write_binary(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    write_frame(stream, 0x2, data)
+}
+
 pub fn write_pong(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
     write_frame(stream, 0xA, payload)
 }
 
-fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+/// Send a close frame with a status code and a reason, cut to fit the
+/// 125-byte control frame limit on a character boundary.
+pub fn write_close(stream: &mut TcpStream, code: u16, reason: &str) -> io::Result<()> {
+    write_frame(stream, 0x8, &close_payload(code, reason))
+}
+
+/// Answer a peer's close frame by echoing its status code, or 1000 when it sent none.
+pub fn reply_close(stream: &mut TcpStream, peer_payload: &[u8]) -> io::Result<()> {
+    let code = match peer_payload {
+        [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]),
+        _ => CLOSE_NORMAL,
+    };
+    write_close(stream, code, "")
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    payload
+}
+
+/// Debug loop behind `/ws/echo`: reassembles fragmented text and binary
+/// messages and sends each one back with its own opcode, answers pings, and
+/// returns after the close handshake or on error.
+pub fn echo(mut stream: TcpStream) {
+    let mut message: Option<(u8, Vec<u8>)> = None;
+    while let Ok(frame) = read_frame(&mut stream) {
+        let complete = match (frame.opcode, message.as_mut()) {
+            (0x8, _) => {
+                let _ = reply_close(&mut stream, &frame.payload);
+                break;
+            }
+            (0x9, _) => {
+                if write_pong(&mut stream, &frame.payload).is_err() {
+                    break;
+                }
+                continue;
+            }
+            (0x1 | 0x2, None) => {
+                message = Some((frame.opcode, frame.payload));
+                frame.fin
+            }
+            (0x0, Some((_, buf))) => {
+                buf.extend_from_slice(&frame.payload);
+                frame.fin
+            }
+            // Pong, or a continuation with nothing to continue
+            _ => continue,
+        };
+        if message.as_ref().is_some_and(|(_, m)| m.len() > MAX_ECHO_MESSAGE) {
+            let _ = write_close(&mut stream, CLOSE_TOO_BIG, "Message too big");
+            break;
+        }
+        if !complete {
+            continue;
+        }
+        let sent = match message.take() {
+            Some((0x1, data)) => write_text(&mut stream, &String::from_utf8_lossy(&data)),
+            Some((_, data)) => write_binary(&mut stream, &data),
+            None => Ok(()),
+        };
+        if sent.is_err() {
+            break;
+        }
+    }
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
     let mut header = Vec::with_capacity(10);
     header.push(0x80 | (opcode & 0x0F));
 
@@ -92,38 +213,6 @@ fn websocket_accept(key: &str) -> String {
     base64_encode(&hash)
 }
 
-fn base64_encode(data: &[u8]) -> String {
-    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut out = String::new();
-    let mut i = 0;
-    while i < data.len() {
-        let b0 = data[i];
-        let b1 = if i + 1 < data.len() { data[i + 1] } else { 0 };
-        let b2 = if i + 2 < data.len() { data[i + 2] } else { 0 };
-
-        let idx0 = (b0 >> 2) & 0x3F;
-        let idx1 = ((b0 & 0x03) << 4) | ((b1 >> 4) & 0x0F);
-        let idx2 = ((b1 & 0x0F) << 2) | ((b2 >> 6) & 0x03);
-        let idx3 = b2 & 0x3F;
-
-        out.push(TABLE[idx0 as usize] as char);
-        out.push(TABLE[idx1 as usize] as char);
-        if i + 1 < data.len() {
-            out.push(TABLE[idx2 as usize] as char);
-        } else {
-            out.push('=');
-        }
-        if i + 2 < data.len() {
-            out.push(TABLE[idx3 as usize] as char);
-        } else {
-            out.push('=');
-        }
-
-        i += 3;
-    }
-    out
-}
-
 fn sha1(data: &[u8]) -> [u8; 20] {
     let mut h0: u32 = 0x67452301;
     let mut h1: u32 = 0xEFCDAB89;
@@ -149,7 +238,7 @@ fn sha1(data: &[u8]) -> [u8; 20] {
         }
 
         let (mut a, mut b, mut c, mut d, mut e) = (h0, h1, h2, h3, h4);
-        for i in 0..80 {
+        for (i, word) in w.iter().enumerate() {
             let (f, k) = match i {
                 0..=19 => ((b & c) | ((!b) & d), 0x5A827999),
                 20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
@@ -161,7 +250,7 @@ fn sha1(data: &[u8]) -> [u8; 20] {
                 .wrapping_add(f)
                 .wrapping_add(e)
                 .wrapping_add(k)
-                .wrapping_add(w[i]);
+                .wrapping_add(*word);
             e = d;
             d = c;
             c = b.rotate_left(30);
@@ -184,3 +273,147 @@ fn sha1(data: &[u8]) -> [u8; 20] {
     out[16..20].copy_from_slice(&h4.to_be_bytes());
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_upgrade_problem() {
+        let with_key = |key: &str| HashMap::from([("sec-websocket-key".to_string(), key.to_string())]);
+        assert_eq!(upgrade_problem("GET", &with_key("dGhlIHNhbXBsZSBub25jZQ==")), None);
+        assert_eq!(upgrade_problem("GET", &HashMap::new()), Some("Missing Sec-WebSocket-Key header"));
+        assert_eq!(upgrade_problem("GET", &with_key("c2hvcnQ=")), Some("Sec-WebSocket-Key must be a base64-encoded 16-byte nonce"));
+        assert_eq!(upgrade_problem("GET", &with_key("not base64!")), Some("Sec-WebSocket-Key must be a base64-encoded 16-byte nonce"));
+        assert_eq!(upgrade_problem("POST", &with_key("dGhlIHNhbXBsZSBub25jZQ==")), Some("WebSocket upgrades must use GET"));
+    }
+
+    /// Client frames must be masked; a zero mask keeps the payload readable.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() < 126);
+        let mut frame = vec![(if fin { 0x80 } else { 0 }) | opcode, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_frame_length_encoding_at_boundaries() {
+        // (payload length, header length): 7-bit, 16-bit and 64-bit length forms
+        for (len, header) in [(0, 2), (125, 2), (126, 4), (65535, 4), (65536, 10)] {
+            let payload: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let mut buf = Vec::new();
+            write_frame(&mut buf, 0x2, &payload).unwrap();
+            assert_eq!(buf.len(), header + len, "length {}", len);
+            assert_eq!(buf[0], 0x82);
+
+            let frame = read_frame(&mut buf.as_slice()).unwrap();
+            assert!(frame.fin);
+            assert_eq!(frame.opcode, 0x2);
+            assert_eq!(frame.payload, payload, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_binary_frame_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let data = [0u8, 159, 146, 150, 255, 1];
+        write_binary(&mut server, &data).unwrap();
+        let frame = read_frame(&mut client).unwrap();
+        assert_eq!((frame.fin, frame.opcode, frame.payload.as_slice()), (true, 0x2, &data[..]));
+    }
+
+    #[test]
+    fn test_close_frame_carries_code_and_reason() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        write_close(&mut server, CLOSE_RESTART, "Service restart").unwrap();
+        let frame = read_frame(&mut client).unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, 0x8);
+        assert_eq!(&frame.payload[..2], &[0x03, 0xF4]);
+        assert_eq!(&frame.payload[2..], b"Service restart");
+
+        reply_close(&mut server, &[]).unwrap();
+        assert_eq!(read_frame(&mut client).unwrap().payload, CLOSE_NORMAL.to_be_bytes());
+    }
+
+    #[test]
+    fn test_close_reason_fits_control_frame() {
+        let payload = close_payload(CLOSE_NORMAL, &"é".repeat(100));
+        assert!(payload.len() <= 125);
+        assert!(std::str::from_utf8(&payload[2..]).is_ok());
+        assert_eq!(payload.len(), 124);
+    }
+
+    #[test]
+    fn test_accept_key_matches_rfc_example() {
+        assert_eq!(websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_handshake_echoes_a_supported_protocol() {
+        let supported = vec!["rpw.v2".to_string(), "rpw.v1".to_string()];
+        let mut headers = HashMap::new();
+        headers.insert("sec-websocket-key".to_string(), "dGhlIHNhbXBsZSBub25jZQ==".to_string());
+        headers.insert("sec-websocket-protocol".to_string(), "graphql-ws, rpw.v1 ,rpw.v2".to_string());
+        let mut out = Vec::new();
+        // The client's order of preference decides between supported ones
+        assert_eq!(handshake(&mut out, &headers, &supported).unwrap().as_deref(), Some("rpw.v1"));
+        let response = String::from_utf8(out).unwrap();
+        assert!(response.contains("\r\nSec-WebSocket-Protocol: rpw.v1\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"));
+
+        headers.insert("sec-websocket-protocol".to_string(), "graphql-ws, RPW.V1".to_string());
+        let mut out = Vec::new();
+        assert_eq!(handshake(&mut out, &headers, &supported).unwrap(), None);
+        assert!(!String::from_utf8(out).unwrap().contains("Sec-WebSocket-Protocol"));
+        assert_eq!(select_protocol(None, &supported), None);
+    }
+
+    #[test]
+    fn test_echo_roundtrip_with_fragments_and_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut headers = HashMap::new();
+        headers.insert("sec-websocket-key".to_string(), "dGhlIHNhbXBsZSBub25jZQ==".to_string());
+        assert_eq!(handshake(&mut server, &headers, &[]).unwrap(), None);
+        let echo = thread::spawn(move || echo(server));
+
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut response = [0u8; 129];
+        client.read_exact(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 101 Switching Protocols"));
+
+        client.write_all(&client_frame(true, 0x1, b"hello")).unwrap();
+        let frame = read_frame(&mut client).unwrap();
+        assert_eq!((frame.fin, frame.opcode, frame.payload.as_slice()), (true, 0x1, &b"hello"[..]));
+
+        // A ping between fragments is answered right away; the message after reassembly.
+        client.write_all(&client_frame(false, 0x1, b"frag")).unwrap();
+        client.write_all(&client_frame(true, 0x9, b"p")).unwrap();
+        client.write_all(&client_frame(true, 0x0, b"mented")).unwrap();
+        let pong = read_frame(&mut client).unwrap();
+        assert_eq!((pong.opcode, pong.payload.as_slice()), (0xA, &b"p"[..]));
+        let frame = read_frame(&mut client).unwrap();
+        assert_eq!(frame.payload, b"fragmented");
+
+        client.write_all(&client_frame(true, 0x2, &[0, 255, 7])).unwrap();
+        let frame = read_frame(&mut client).unwrap();
+        assert_eq!((frame.opcode, frame.payload.as_slice()), (0x2, &[0u8, 255, 7][..]));
+
+        client.write_all(&client_frame(true, 0x8, &1001u16.to_be_bytes())).unwrap();
+        let close = read_frame(&mut client).unwrap();
+        assert_eq!((close.opcode, close.payload.as_slice()), (0x8, &1001u16.to_be_bytes()[..]));
+        echo.join().unwrap();
+    }
+}